@@ -0,0 +1,124 @@
+// POSIX access ACLs (Linux only). The kernel stores a file's ACL in the
+// `system.posix_acl_access` extended attribute as a version number and
+// a list of (tag, permissions, id) entries. We render it in the text
+// form used by `getfacl`, one comma-separated entry per tag and id, in
+// a fixed order so that equal ACLs always compare equal. A file with no
+// ACL attribute is rendered as the minimal ACL of its permission bits.
+
+use std::fs::File;
+use std::io;
+
+const ACL_VERSION: u32 = 2;
+
+const ACL_USER_OBJ: u16 = 0x01;
+const ACL_USER: u16 = 0x02;
+const ACL_GROUP_OBJ: u16 = 0x04;
+const ACL_GROUP: u16 = 0x08;
+const ACL_MASK: u16 = 0x10;
+const ACL_OTHER: u16 = 0x20;
+
+// Returns the file's ACL, or `None` where ACLs aren't supported
+#[cfg(target_os = "linux")]
+pub(crate) fn file_acl(file: &File) -> io::Result<Option<String>> {
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::io::AsRawFd;
+
+    let name = b"system.posix_acl_access\0";
+    let mut buffer = vec![0u8; 128];
+    loop {
+        let len = unsafe {
+            libc::fgetxattr(
+                file.as_raw_fd(),
+                name.as_ptr() as *const libc::c_char,
+                buffer.as_mut_ptr() as *mut libc::c_void,
+                buffer.len())
+        };
+        if len >= 0 {
+            buffer.truncate(len as usize);
+            return parse_acl(&buffer).map(Some);
+        }
+        let err = io::Error::last_os_error();
+        match err.raw_os_error() {
+            Some(libc::ENODATA) | Some(libc::ENOTSUP) => {
+                let mode = file.metadata()?.permissions().mode();
+                return Ok(Some(format!(
+                    "user::{},group::{},other::{}",
+                    permissions(mode >> 6),
+                    permissions(mode >> 3),
+                    permissions(mode))));
+            }
+            Some(libc::ERANGE) => buffer.resize(buffer.len() * 2, 0),
+            _ => return Err(err),
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn file_acl(_file: &File) -> io::Result<Option<String>> {
+    Ok(None)
+}
+
+// Renders the low three bits as `rwx`
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn permissions(bits: u32) -> String {
+    [(4, 'r'), (2, 'w'), (1, 'x')].iter()
+        .map(|&(bit, c)| if bits & bit != 0 { c } else { '-' })
+        .collect()
+}
+
+fn invalid_acl() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "malformed POSIX ACL")
+}
+
+// Decodes the extended attribute (all fields little-endian)
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_acl(bytes: &[u8]) -> io::Result<String> {
+    if bytes.len() < 4 || !(bytes.len() - 4).is_multiple_of(8) {
+        return Err(invalid_acl());
+    }
+    let u16_at = |i: usize| u16::from_le_bytes([bytes[i], bytes[i + 1]]);
+    let u32_at = |i: usize| u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+    if u32_at(0) != ACL_VERSION {
+        return Err(invalid_acl());
+    }
+
+    let mut entries = Vec::new();
+    for offset in (4..bytes.len()).step_by(8) {
+        let (tag, perm, id) = (u16_at(offset), u16_at(offset + 2), u32_at(offset + 4));
+        let (kind, qualifier) = match tag {
+            ACL_USER_OBJ => ("user", None),
+            ACL_USER => ("user", Some(id)),
+            ACL_GROUP_OBJ => ("group", None),
+            ACL_GROUP => ("group", Some(id)),
+            ACL_MASK => ("mask", None),
+            ACL_OTHER => ("other", None),
+            _ => return Err(invalid_acl()),
+        };
+        entries.push(((tag, qualifier), format!(
+            "{}:{}:{}",
+            kind,
+            qualifier.map(|id| id.to_string()).unwrap_or_default(),
+            permissions(u32::from(perm)))));
+    }
+    entries.sort();
+    Ok(entries.into_iter().map(|(_, entry)| entry).collect::<Vec<_>>().join(","))
+}
+
+// Whether a rendered ACL has entries beyond those for the owner, group
+// and others, which the permission bits alone would give (an extended
+// ACL always has a mask)
+pub(crate) fn is_extended(acl: &str) -> bool {
+    acl.split(',').any(|entry| entry.starts_with("mask:"))
+}
+
+// Describes how one rendered ACL differs from another, as entries
+// prefixed with `-` (only in the old ACL) or `+` (only in the new one)
+pub(crate) fn acl_changes(old: &str, new: &str) -> Vec<String> {
+    let entries = |acl: &str| -> Vec<String> {
+        acl.split(',').filter(|entry| !entry.is_empty()).map(str::to_owned).collect()
+    };
+    let (old, new) = (entries(old), entries(new));
+    let removed = old.iter().filter(|entry| !new.contains(entry)).map(|entry| format!("-{}", entry));
+    let added = new.iter().filter(|entry| !old.contains(entry)).map(|entry| format!("+{}", entry));
+    removed.chain(added).collect()
+}
@@ -0,0 +1,84 @@
+// Export of a database as the tag files of a BagIt bag (RFC 8493), for
+// ingest by digital preservation systems. Everything comes from the
+// stored metrics, so no payload file is read: the payload itself must
+// be placed under `data/` in the bag by other means.
+
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::database::{path_to_bytes, Database, ManifestAlgorithm};
+use crate::error;
+
+// The algorithms to write payload manifests for, with their names in
+// the manifest file names
+const ALGORITHMS: [(ManifestAlgorithm, &str); 2] = [
+    (ManifestAlgorithm::Sha256, "sha256"),
+    (ManifestAlgorithm::Sha512, "sha512"),
+];
+
+// Percent-encodes the characters that can't appear literally in a
+// manifest path: CR, LF and `%` itself
+fn encode_path(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for c in path.chars() {
+        match c {
+            '\r' => encoded.push_str("%0D"),
+            '\n' => encoded.push_str("%0A"),
+            '%' => encoded.push_str("%25"),
+            c => encoded.push(c),
+        }
+    }
+    encoded
+}
+
+impl Database {
+    /// Writes the tag files of a BagIt bag (RFC 8493) for the tree into
+    /// `dir`, creating it if needed: `bagit.txt`, `bag-info.txt` with
+    /// the `Payload-Oxum` (unless some file's size is unknown) and a
+    /// `manifest-<algorithm>.txt` for each of SHA2-256 and SHA2-512
+    /// that every file has a usable hash of (see `write_manifest`).
+    /// Fails with `Error::MissingHash` if there is none, and with
+    /// `Error::NonUtf8Path` on a path that the UTF-8 manifests can't
+    /// hold.
+    pub fn export_bagit(&self, dir: impl AsRef<Path>) -> Result<(), error::Error> {
+        let dir = dir.as_ref();
+        let mut files = Vec::new();
+        self.for_each_file(|path, metrics| files.push((path.to_owned(), metrics.clone())));
+
+        let mut paths = Vec::with_capacity(files.len());
+        for (path, _) in files.iter() {
+            match String::from_utf8(path_to_bytes(path)) {
+                Ok(name) => paths.push(encode_path(&format!("data/{}", name))),
+                Err(_) => return Err(error::Error::NonUtf8Path(path.clone())),
+            }
+        }
+        let hashed = |algorithm| files.iter().all(|(_, metrics)| metrics.manifest_hash(algorithm).is_some());
+        let manifests: Vec<_> = ALGORITHMS.iter().filter(|(algorithm, _)| hashed(*algorithm)).collect();
+        if manifests.is_empty() {
+            let (algorithm, _) = ALGORITHMS[0];
+            let (path, _) = files.iter()
+                .find(|(_, metrics)| metrics.manifest_hash(algorithm).is_none())
+                .expect("an empty tree has every hash");
+            return Err(error::Error::MissingHash { path: path.clone(), algorithm: algorithm.name() });
+        }
+
+        fs::create_dir_all(dir)?;
+        fs::write(dir.join("bagit.txt"), "BagIt-Version: 1.0\nTag-File-Character-Encoding: UTF-8\n")?;
+        let mut info = String::new();
+        if files.iter().all(|(_, metrics)| metrics.size_known()) {
+            let octets: u64 = files.iter().map(|(_, metrics)| metrics.size()).sum();
+            info.push_str(&format!("Payload-Oxum: {}.{}\n", octets, files.len()));
+        }
+        fs::write(dir.join("bag-info.txt"), info)?;
+        for (algorithm, name) in manifests {
+            let mut w = BufWriter::new(File::create(dir.join(format!("manifest-{}.txt", name)))?);
+            for ((_, metrics), path) in files.iter().zip(paths.iter()) {
+                let hash = metrics.manifest_hash(*algorithm).expect("checked above");
+                writeln!(w, "{}  {}", hash, path)?;
+            }
+            w.flush()?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,239 @@
+// Streaming implementation of the BLAKE3 hash (unkeyed, 32-byte output)
+// From the specification at https://github.com/BLAKE3-team/BLAKE3-specs
+
+const BLOCK_LEN: usize = 64;
+const CHUNK_LEN: usize = 1024;
+
+const CHUNK_START: u32 = 1 << 0;
+const CHUNK_END: u32 = 1 << 1;
+const PARENT: u32 = 1 << 2;
+const ROOT: u32 = 1 << 3;
+
+const IV: [u32; 8] = [
+    0x6A09_E667, 0xBB67_AE85, 0x3C6E_F372, 0xA54F_F53A,
+    0x510E_527F, 0x9B05_688C, 0x1F83_D9AB, 0x5BE0_CD19,
+];
+
+const MSG_PERMUTATION: [usize; 16] = [2, 6, 3, 10, 7, 0, 4, 13, 1, 11, 12, 5, 9, 14, 15, 8];
+
+fn g(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize, mx: u32, my: u32) {
+    state[a] = state[a].wrapping_add(state[b]).wrapping_add(mx);
+    state[d] = (state[d] ^ state[a]).rotate_right(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_right(12);
+    state[a] = state[a].wrapping_add(state[b]).wrapping_add(my);
+    state[d] = (state[d] ^ state[a]).rotate_right(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_right(7);
+}
+
+fn round(state: &mut [u32; 16], m: &[u32; 16]) {
+    // Mix the columns
+    g(state, 0, 4, 8, 12, m[0], m[1]);
+    g(state, 1, 5, 9, 13, m[2], m[3]);
+    g(state, 2, 6, 10, 14, m[4], m[5]);
+    g(state, 3, 7, 11, 15, m[6], m[7]);
+    // Mix the diagonals
+    g(state, 0, 5, 10, 15, m[8], m[9]);
+    g(state, 1, 6, 11, 12, m[10], m[11]);
+    g(state, 2, 7, 8, 13, m[12], m[13]);
+    g(state, 3, 4, 9, 14, m[14], m[15]);
+}
+
+fn compress(
+    chaining_value: &[u32; 8],
+    block_words: &[u32; 16],
+    counter: u64,
+    block_len: u32,
+    flags: u32,
+) -> [u32; 16] {
+    let mut state = [
+        chaining_value[0], chaining_value[1], chaining_value[2], chaining_value[3],
+        chaining_value[4], chaining_value[5], chaining_value[6], chaining_value[7],
+        IV[0], IV[1], IV[2], IV[3],
+        counter as u32, (counter >> 32) as u32, block_len, flags,
+    ];
+    let mut block = *block_words;
+    for i in 0..7 {
+        round(&mut state, &block);
+        if i < 6 {
+            let mut permuted = [0; 16];
+            for (word, &source) in permuted.iter_mut().zip(MSG_PERMUTATION.iter()) {
+                *word = block[source];
+            }
+            block = permuted;
+        }
+    }
+    for i in 0..8 {
+        state[i] ^= state[i + 8];
+        state[i + 8] ^= chaining_value[i];
+    }
+    state
+}
+
+fn first_8_words(words: [u32; 16]) -> [u32; 8] {
+    let mut first = [0; 8];
+    first.copy_from_slice(&words[..8]);
+    first
+}
+
+fn words_from_le_bytes(bytes: &[u8; BLOCK_LEN]) -> [u32; 16] {
+    let mut words = [0; 16];
+    for (word, chunk) in words.iter_mut().zip(bytes.chunks_exact(4)) {
+        *word = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+    }
+    words
+}
+
+// The inputs to a compression that hasn't happened yet, so that the
+// root node can be compressed with the ROOT flag
+struct Output {
+    chaining_value: [u32; 8],
+    block_words: [u32; 16],
+    counter: u64,
+    block_len: u32,
+    flags: u32,
+}
+
+impl Output {
+    fn chaining_value(&self) -> [u32; 8] {
+        first_8_words(compress(
+            &self.chaining_value, &self.block_words, self.counter, self.block_len, self.flags))
+    }
+
+    fn root_hash(&self) -> [u8; 32] {
+        let words = compress(
+            &self.chaining_value, &self.block_words, 0, self.block_len, self.flags | ROOT);
+        let mut hash = [0; 32];
+        for (bytes, word) in hash.chunks_exact_mut(4).zip(words.iter()) {
+            bytes.copy_from_slice(&word.to_le_bytes());
+        }
+        hash
+    }
+}
+
+fn parent_output(left: [u32; 8], right: [u32; 8]) -> Output {
+    let mut block_words = [0; 16];
+    block_words[..8].copy_from_slice(&left);
+    block_words[8..].copy_from_slice(&right);
+    Output {
+        chaining_value: IV,
+        block_words,
+        counter: 0,
+        block_len: BLOCK_LEN as u32,
+        flags: PARENT,
+    }
+}
+
+struct ChunkState {
+    chaining_value: [u32; 8],
+    chunk_counter: u64,
+    block: [u8; BLOCK_LEN],
+    block_len: usize,
+    blocks_compressed: usize,
+}
+
+impl ChunkState {
+    fn new(chunk_counter: u64) -> ChunkState {
+        ChunkState {
+            chaining_value: IV,
+            chunk_counter,
+            block: [0; BLOCK_LEN],
+            block_len: 0,
+            blocks_compressed: 0,
+        }
+    }
+
+    fn len(&self) -> usize {
+        BLOCK_LEN * self.blocks_compressed + self.block_len
+    }
+
+    fn start_flag(&self) -> u32 {
+        if self.blocks_compressed == 0 { CHUNK_START } else { 0 }
+    }
+
+    fn input(&mut self, mut input: &[u8]) {
+        while !input.is_empty() {
+            // Only compress a full block once more input arrives, since
+            // the last block of the chunk needs the CHUNK_END flag
+            if self.block_len == BLOCK_LEN {
+                let block_words = words_from_le_bytes(&self.block);
+                self.chaining_value = first_8_words(compress(
+                    &self.chaining_value,
+                    &block_words,
+                    self.chunk_counter,
+                    BLOCK_LEN as u32,
+                    self.start_flag()));
+                self.blocks_compressed += 1;
+                self.block = [0; BLOCK_LEN];
+                self.block_len = 0;
+            }
+            let n = input.len().min(BLOCK_LEN - self.block_len);
+            self.block[self.block_len..self.block_len + n].copy_from_slice(&input[..n]);
+            self.block_len += n;
+            input = &input[n..];
+        }
+    }
+
+    fn output(&self) -> Output {
+        Output {
+            chaining_value: self.chaining_value,
+            block_words: words_from_le_bytes(&self.block),
+            counter: self.chunk_counter,
+            block_len: self.block_len as u32,
+            flags: self.start_flag() | CHUNK_END,
+        }
+    }
+}
+
+pub struct Blake3 {
+    chunk_state: ChunkState,
+    // Chaining values of completed subtrees, one per set bit of the
+    // number of chunks hashed so far, largest first
+    cv_stack: Vec<[u32; 8]>,
+}
+
+impl Default for Blake3 {
+    fn default() -> Blake3 {
+        Blake3 {
+            chunk_state: ChunkState::new(0),
+            cv_stack: Vec::new(),
+        }
+    }
+}
+
+impl Blake3 {
+    fn add_chunk_chaining_value(&mut self, mut chaining_value: [u32; 8], mut total_chunks: u64) {
+        // Merge each completed subtree with its left sibling
+        while total_chunks & 1 == 0 {
+            let left = self.cv_stack.pop().expect("unreachable");
+            chaining_value = parent_output(left, chaining_value).chaining_value();
+            total_chunks >>= 1;
+        }
+        self.cv_stack.push(chaining_value);
+    }
+
+    pub fn input(&mut self, mut input: &[u8]) {
+        while !input.is_empty() {
+            // As with blocks, a full chunk is only finished once more
+            // input arrives, since the last chunk may be the root
+            if self.chunk_state.len() == CHUNK_LEN {
+                let chaining_value = self.chunk_state.output().chaining_value();
+                let total_chunks = self.chunk_state.chunk_counter + 1;
+                self.add_chunk_chaining_value(chaining_value, total_chunks);
+                self.chunk_state = ChunkState::new(total_chunks);
+            }
+            let n = input.len().min(CHUNK_LEN - self.chunk_state.len());
+            self.chunk_state.input(&input[..n]);
+            input = &input[n..];
+        }
+    }
+
+    pub fn result(self) -> [u8; 32] {
+        let mut output = self.chunk_state.output();
+        for left in self.cv_stack.iter().rev() {
+            output = parent_output(*left, output.chaining_value());
+        }
+        output.root_hash()
+    }
+}
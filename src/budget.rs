@@ -0,0 +1,42 @@
+// Caps the total size of the buffers that walker threads have in
+// flight at once. A thread reserves memory before hashing a file and
+// blocks until enough has been released by the others.
+
+use std::sync::{Condvar, Mutex};
+
+pub(crate) struct MemoryBudget {
+    limit: usize,
+    available: Mutex<usize>,
+    released: Condvar,
+}
+
+pub(crate) struct Reservation<'a> {
+    budget: &'a MemoryBudget,
+    amount: usize,
+}
+
+impl MemoryBudget {
+    pub(crate) fn new(limit: usize) -> MemoryBudget {
+        MemoryBudget { limit, available: Mutex::new(limit), released: Condvar::new() }
+    }
+
+    /// Blocks until `amount` bytes are available. Requests larger than
+    /// the whole budget are clamped to it, so that they wait for all
+    /// other reservations to finish rather than forever.
+    pub(crate) fn reserve(&self, amount: usize) -> Reservation<'_> {
+        let amount = amount.min(self.limit);
+        let mut available = self.available.lock().unwrap();
+        while *available < amount {
+            available = self.released.wait(available).unwrap();
+        }
+        *available -= amount;
+        Reservation { budget: self, amount }
+    }
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        *self.budget.available.lock().unwrap() += self.amount;
+        self.budget.released.notify_all();
+    }
+}
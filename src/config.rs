@@ -0,0 +1,131 @@
+// Support for an options file passed with `--config`, so that a team
+// can share the same build options. The file is a JSON object; every
+// key is optional and unknown keys are rejected:
+//
+//     {
+//         "hashes": ["sha2", "blake2b"],
+//         "exclude": ["cache/**", "*.pid"],
+//         "decompress": ["logs/*.gz"],
+//         "max_depth": 16,
+//         "follow_links": false,
+//         "buffer_size": 65536
+//     }
+//
+// The file is never picked up from the scanned tree on its own: whoever
+// can write to that tree could otherwise use `exclude` to hide files
+// from `check`.
+
+use std::fs;
+use std::path::Path;
+
+use crate::database::{BuildOptions, Features};
+use crate::error;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Config {
+    pub features: Option<Features>,
+    pub exclude: Vec<String>,
+    pub decompress: Vec<String>,
+    pub max_depth: Option<usize>,
+    pub follow_links: Option<bool>,
+    pub buffer_size: Option<usize>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Hash {
+    Sha2,
+    Blake2b,
+    Sha256,
+    Sha512,
+    Blake3,
+    Xxh64,
+    Crc32,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    hashes: Option<Vec<Hash>>,
+    #[serde(default)]
+    exclude: Vec<String>,
+    #[serde(default)]
+    decompress: Vec<String>,
+    max_depth: Option<usize>,
+    follow_links: Option<bool>,
+    buffer_size: Option<usize>,
+}
+
+fn features(hashes: &[Hash]) -> Features {
+    let mut features = Features {
+        sha2: false, blake2b: false, sha256: false, sha512: false, blake3: false, xxh64: false,
+        crc32: false,
+    };
+    for hash in hashes {
+        match hash {
+            Hash::Sha2 => features.sha2 = true,
+            Hash::Blake2b => features.blake2b = true,
+            Hash::Sha256 => features.sha256 = true,
+            Hash::Sha512 => features.sha512 = true,
+            Hash::Blake3 => features.blake3 = true,
+            Hash::Xxh64 => features.xxh64 = true,
+            Hash::Crc32 => features.crc32 = true,
+        }
+    }
+    features
+}
+
+impl Config {
+    pub fn parse(text: &str) -> Result<Config, error::Error> {
+        let file: ConfigFile = serde_json::from_str(text)
+            .map_err(|err| error::Error::Config(err.line(), err.to_string()))?;
+        if file.buffer_size == Some(0) {
+            return Err(error::Error::Config(0, "buffer_size must be positive".to_owned()));
+        }
+        Ok(Config {
+            features: file.hashes.as_ref().map(|hashes| features(hashes)),
+            exclude: file.exclude,
+            decompress: file.decompress,
+            max_depth: file.max_depth,
+            follow_links: file.follow_links,
+            buffer_size: file.buffer_size,
+        })
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Config, error::Error> {
+        Config::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Loads a config file for checking `root`, refusing one that
+    /// lives inside `root`, since the tree being checked must not get
+    /// to choose how it is checked.
+    pub fn load_outside(path: impl AsRef<Path>, root: impl AsRef<Path>)
+                        -> Result<Config, error::Error> {
+        let path = path.as_ref();
+        let canonical = fs::canonicalize(path)?;
+        if canonical.starts_with(fs::canonicalize(root)?) {
+            return Err(error::Error::ConfigInTree(path.to_owned()));
+        }
+        Config::load(path)
+    }
+
+    /// Overwrites the options set in this file. Apply the file first,
+    /// then any explicitly requested options, so the latter take
+    /// precedence.
+    pub fn apply(&self, options: &mut BuildOptions) {
+        if let Some(features) = self.features {
+            options.features = features;
+        }
+        options.exclude.extend(self.exclude.iter().cloned());
+        options.decompress.extend(self.decompress.iter().cloned());
+        if self.max_depth.is_some() {
+            options.max_depth = self.max_depth;
+        }
+        if let Some(follow_links) = self.follow_links {
+            options.follow_links = follow_links;
+        }
+        if let Some(buffer_size) = self.buffer_size {
+            options.buffer_size = buffer_size;
+        }
+    }
+}
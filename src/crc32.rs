@@ -0,0 +1,42 @@
+// Streaming implementation of CRC-32 (IEEE 802.3, as used by zip, gzip
+// and `cksfv`), reflected with polynomial 0xEDB88320
+
+const POLYNOMIAL: u32 = 0xEDB8_8320;
+
+const fn make_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ POLYNOMIAL } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+static TABLE: [u32; 256] = make_table();
+
+pub struct Crc32(u32);
+
+impl Default for Crc32 {
+    fn default() -> Crc32 {
+        Crc32(!0)
+    }
+}
+
+impl Crc32 {
+    pub fn input(&mut self, input: &[u8]) {
+        for byte in input {
+            self.0 = TABLE[((self.0 ^ u32::from(*byte)) & 0xFF) as usize] ^ (self.0 >> 8);
+        }
+    }
+
+    pub fn result(self) -> u32 {
+        !self.0
+    }
+}
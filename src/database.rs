@@ -1,31 +1,58 @@
-use std::collections::BTreeMap;
+use std::borrow::Cow;
+use std::collections::{btree_map, BTreeMap, BTreeSet, HashMap};
 use std::cmp::Ordering;
+use std::convert::TryFrom;
 use std::default::Default;
-use std::fs::File;
-use std::io::{Read, Write};
-use std::path::{Path, PathBuf};
-use std::sync::{Arc, Mutex};
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufRead, IsTerminal, Read, Seek, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, UNIX_EPOCH};
 
 use digest::{Input, FixedOutput, VariableOutput};
+use globset::{Glob, GlobSet, GlobSetBuilder};
 use ignore::{WalkBuilder, WalkState};
+use ignore::overrides::{Override, OverrideBuilder};
 use time;
 
 use serde_json;
 
 use flate2::Compression;
-use flate2::read::GzDecoder;
+use flate2::read::{GzDecoder, MultiGzDecoder};
 use flate2::write::GzEncoder;
 
 use sha2;
 use blake2;
 
+use unicode_normalization::UnicodeNormalization;
+
+use crate::acl;
 use crate::base64;
+use crate::blake3;
+use crate::budget::MemoryBudget;
+#[cfg(unix)]
+use crate::dirfd;
 use crate::error;
+use crate::html;
+use crate::mmap;
+use crate::ospath;
+use crate::progress::{InteractiveProgress, ProgressCallback};
+use crate::ratelimit::{RateLimiter, Throttled};
+use crate::treehash;
+use crate::crc32;
+use crate::xxhash;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Features {
     pub sha2: bool,
     pub blake2b: bool,
+    pub sha256: bool,
+    pub sha512: bool,
+    pub blake3: bool,
+    pub xxh64: bool,
+    pub crc32: bool,
 }
 
 impl Default for Features {
@@ -33,17 +60,491 @@ impl Default for Features {
         Features {
             sha2: true,
             blake2b: false,
+            sha256: false,
+            sha512: false,
+            blake3: false,
+            xxh64: false,
+            crc32: false,
         }
     }
 }
 
 impl Features {
+    /// Only the XXH64 checksum, for quick scans that need to catch bit
+    /// rot but not deliberate tampering
+    pub fn fast() -> Features {
+        Features {
+            sha2: false,
+            blake2b: false,
+            sha256: false,
+            sha512: false,
+            blake3: false,
+            xxh64: true,
+            crc32: false,
+        }
+    }
+
     fn infer_from_database_checksum(checksum: &DatabaseChecksum) -> Features {
         Features {
             sha2: checksum.sha2.is_some(),
             blake2b: checksum.blake2b.is_some(),
+            sha256: checksum.sha256.is_some(),
+            sha512: checksum.sha512.is_some(),
+            blake3: checksum.blake3.is_some(),
+            xxh64: checksum.xxh64.is_some(),
+            crc32: checksum.crc32.is_some(),
+        }
+    }
+
+    // Whether both enable at least one hash of the same kind
+    fn shares_hash(&self, other: &Features) -> bool {
+        let (a, b) = (self, other);
+        (a.sha2 && b.sha2) || (a.blake2b && b.blake2b) ||
+            (a.sha256 && b.sha256) || (a.sha512 && b.sha512) || (a.blake3 && b.blake3) ||
+            (a.xxh64 && b.xxh64) || (a.crc32 && b.crc32)
+    }
+
+    fn cryptographic(self) -> Features {
+        Features { xxh64: false, crc32: false, ..self }
+    }
+
+    fn union(self, other: Features) -> Features {
+        Features {
+            sha2: self.sha2 || other.sha2,
+            blake2b: self.blake2b || other.blake2b,
+            sha256: self.sha256 || other.sha256,
+            sha512: self.sha512 || other.sha512,
+            blake3: self.blake3 || other.blake3,
+            xxh64: self.xxh64 || other.xxh64,
+            crc32: self.crc32 || other.crc32,
+        }
+    }
+
+    fn intersection(self, other: Features) -> Features {
+        Features {
+            sha2: self.sha2 && other.sha2,
+            blake2b: self.blake2b && other.blake2b,
+            sha256: self.sha256 && other.sha256,
+            sha512: self.sha512 && other.sha512,
+            blake3: self.blake3 && other.blake3,
+            xxh64: self.xxh64 && other.xxh64,
+            crc32: self.crc32 && other.crc32,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BuildOptions {
+    pub features: Features,
+    pub threads: usize,
+    pub verbose: bool,
+    /// Store all path keys case folded (lowercased), so that
+    /// databases built on case-insensitive and case-sensitive file
+    /// systems compare cleanly. If two names in a directory fold to
+    /// the same key, the build fails with `Error::DuplicatePath`.
+    pub fold_case: bool,
+    /// Only store the tree under this directory (relative to the
+    /// root), with paths relative to it, e.g. `.zfs/snapshot/name` to
+    /// compare a snapshot against a database built from the live file
+    /// system. Exclude globs and `max_depth` count from it too, so that
+    /// both databases get the same keys. The root's name is recorded.
+    pub strip_prefix_components: Option<PathBuf>,
+    /// Globs (relative to the root) of paths to leave out of the database
+    pub exclude: Vec<String>,
+    pub max_depth: Option<usize>,
+    pub follow_links: bool,
+    /// Size of the buffer used to read each file, from 1 byte up to
+    /// `MAX_BUFFER_SIZE`. Larger buffers mean fewer reads, which pays
+    /// off on spinning disks and network mounts.
+    pub buffer_size: usize,
+    /// Globs (relative to the root) of gzip files whose decompressed
+    /// contents should be hashed, so that recompressing a file does
+    /// not register as a change
+    pub decompress: Vec<String>,
+    /// Store each file's inode number (Unix only), so that renames
+    /// can later be matched up by identity rather than by content
+    pub record_inode: bool,
+    /// Store each file's POSIX access ACL (Linux only), so that changes
+    /// to who can access a file show up in diffs
+    pub record_acl: bool,
+    /// Show a status line with the number of files hashed, the
+    /// throughput and the current path, updated a few times per second
+    /// on a terminal (or as an occasional log line otherwise). The line
+    /// goes to stderr. Ignored unless built with the `progress` feature.
+    pub progress: bool,
+    /// Compute each hash over the file's database key (its path
+    /// relative to the root) followed by its contents, so identical
+    /// contents at a different path hash differently. Hashes made
+    /// this way can't be checked with `sha256sum` and similar tools.
+    pub bind_paths: bool,
+    /// Cap on the total size of the read buffers in use at once across
+    /// all threads. Each file needs a buffer of `buffer_size` bytes (or
+    /// its own size, if smaller), and threads wait for memory to free
+    /// up before starting on a file that would exceed the budget.
+    pub memory_budget: Option<usize>,
+    /// Limit the average rate of reads from disk, across all threads,
+    /// so that scanning a busy machine doesn't starve its workload
+    pub max_read_bytes_per_sec: Option<u64>,
+    /// Leave out files modified after this time, in seconds since the
+    /// Unix epoch, e.g. to keep files still being written out of a
+    /// baseline. The cutoff is checked against the mtime seen during
+    /// the walk (without opening the file), so a file modified while
+    /// it is being hashed is still included, with whatever contents
+    /// were read.
+    pub modified_before: Option<i64>,
+    /// Store only the first this many bytes of each hash, to save space
+    /// in databases meant only for change detection. A hash cut to `n`
+    /// bytes is expected to see its first accidental collision after
+    /// about 2^(4n) files: 2^64 for 16 bytes, but only 2^32 (about four
+    /// billion) for 8. Shorter hashes are also that much easier to
+    /// collide on purpose. Truncated hashes are never compared against
+    /// hashes of a different length: checks and `Database::diff_files`
+    /// fail with `Error::TruncationMismatch` instead.
+    pub truncate_hashes: Option<usize>,
+    /// Also store the SHA-256 of each file's first 4 KiB, computed from
+    /// the first read at almost no cost. Diffs compare it even in fast
+    /// mode, and report files whose first block is unchanged although
+    /// later content differs.
+    pub first_block_hash: bool,
+    /// Also store this many leading bytes of each file (at most
+    /// `MAX_PREVIEW_LEN`), so that a diff can show what a truncated or
+    /// overwritten file used to begin with. Previews are never compared.
+    pub preview_len: usize,
+    /// Also store a tree hash of every file at least this large: the
+    /// file is hashed in chunks of `tree_hash_chunk_size` bytes on up
+    /// to `threads` threads at once, so one huge file doesn't pin one
+    /// core. Whole-file hashes, if any `features` are enabled, take a
+    /// second sequential pass; disable them to read such files once.
+    /// Decompressed and path-bound files never get a tree hash.
+    pub tree_hash_threshold: Option<u64>,
+    /// The chunk size of tree hashes. Tree hashes are only comparable
+    /// if they were made with the same chunk size, so keep the default
+    /// unless every database to be compared uses another.
+    pub tree_hash_chunk_size: u64,
+    /// Read files by mapping them into memory (Linux only; ignored
+    /// elsewhere), which saves a read call per buffer. Files that can't
+    /// be mapped, such as empty and special files, are read as usual,
+    /// and a file truncated while mapped fails with an I/O error. The
+    /// metrics are the same either way.
+    pub mmap: bool,
+}
+
+pub const DEFAULT_BUFFER_SIZE: usize = 64 << 10;
+pub const MAX_BUFFER_SIZE: usize = 1 << 30;
+pub const DEFAULT_TREE_HASH_CHUNK_SIZE: u64 = 64 << 20;
+pub const MAX_PREVIEW_LEN: usize = 256;
+
+impl Default for BuildOptions {
+    fn default() -> BuildOptions {
+        BuildOptions {
+            features: Features::default(),
+            threads: 1,
+            verbose: false,
+            fold_case: false,
+            strip_prefix_components: None,
+            exclude: Vec::new(),
+            max_depth: None,
+            follow_links: false,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            decompress: Vec::new(),
+            record_inode: false,
+            record_acl: false,
+            progress: false,
+            bind_paths: false,
+            memory_budget: None,
+            max_read_bytes_per_sec: None,
+            modified_before: None,
+            truncate_hashes: None,
+            first_block_hash: false,
+            preview_len: 0,
+            tree_hash_threshold: None,
+            tree_hash_chunk_size: DEFAULT_TREE_HASH_CHUNK_SIZE,
+            mmap: false,
+        }
+    }
+}
+
+impl BuildOptions {
+    pub(crate) fn hasher(&self) -> Result<Hasher, error::Error> {
+        if self.truncate_hashes == Some(0) {
+            return Err(error::Error::InvalidHashTruncation(0));
+        }
+        if self.buffer_size == 0 || self.buffer_size > MAX_BUFFER_SIZE {
+            return Err(error::Error::InvalidBufferSize(self.buffer_size));
+        }
+        if self.preview_len > MAX_PREVIEW_LEN {
+            return Err(error::Error::InvalidPreviewLength(self.preview_len));
+        }
+        if self.tree_hash_threshold.is_some() && self.tree_hash_chunk_size == 0 {
+            return Err(error::Error::InvalidChunkSize(0));
+        }
+        let decompress = if self.decompress.is_empty() {
+            None
+        } else {
+            let mut builder = GlobSetBuilder::new();
+            for glob in self.decompress.iter() {
+                builder.add(Glob::new(glob)?);
+            }
+            Some(builder.build()?)
+        };
+        Ok(Hasher {
+            features: self.features,
+            buffer_size: self.buffer_size,
+            decompress,
+            record_inode: self.record_inode,
+            record_acl: self.record_acl,
+            truncate_hashes: self.truncate_hashes,
+            first_block_hash: self.first_block_hash,
+            preview_len: self.preview_len,
+            tree_hash_threshold: self.tree_hash_threshold,
+            tree_hash_chunk_size: self.tree_hash_chunk_size,
+            threads: self.threads,
+            mmap: self.mmap,
+            bind_paths: self.bind_paths,
+            fold_case: self.fold_case,
+            budget: self.memory_budget.map(|limit| Arc::new(MemoryBudget::new(limit))),
+            limiter: self.max_read_bytes_per_sec.map(|rate| Arc::new(RateLimiter::new(rate))),
+            links: Arc::default(),
+            previous: None,
+        })
+    }
+
+    // The directory whose contents a build of `root` stores under their
+    // paths relative to it
+    fn scan_root(&self, root: &Path) -> PathBuf {
+        match self.strip_prefix_components {
+            Some(ref prefix) => root.join(prefix),
+            None => root.to_owned(),
+        }
+    }
+
+    pub(crate) fn walk_builder(&self, root: &Path) -> Result<WalkBuilder, error::Error> {
+        self.subtree_walk_builder(root, Path::new(""))
+    }
+
+    // Walks only the tree under `start` (relative to the root), with
+    // depths and excludes still counted from the root
+    fn subtree_walk_builder(&self, root: &Path, start: &Path) -> Result<WalkBuilder, error::Error> {
+        // Joining an empty path would add a trailing slash, which fails
+        // for a root that is a file
+        let mut builder = if start == Path::new("") {
+            WalkBuilder::new(root)
+        } else {
+            WalkBuilder::new(root.join(start))
+        };
+        let depth = start.components().count();
+        builder
+            .max_depth(self.max_depth.map(|max| max.saturating_sub(depth)))
+            .follow_links(self.follow_links);
+        if let Some(overrides) = self.exclude_overrides(root)? {
+            builder.overrides(overrides);
+        }
+        Ok(builder)
+    }
+
+    fn exclude_overrides(&self, root: &Path) -> Result<Option<Override>, error::Error> {
+        if self.exclude.is_empty() {
+            return Ok(None);
+        }
+        let mut overrides = OverrideBuilder::new(root);
+        for glob in self.exclude.iter() {
+            overrides.add(&format!("!{}", glob))?;
+        }
+        Ok(Some(overrides.build()?))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DiffMode {
+    /// Compare sizes and all hashes available in both databases
+    #[default]
+    Full,
+    /// Compare only sizes, the (non-cryptographic) XXH64 hash and
+    /// first-block hashes
+    Fast,
+}
+
+/// When `show_diff` colors its report: removed entries red, added
+/// green, changed yellow, and suspicious findings bold red
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorMode {
+    Always,
+    /// Only when printing to stdout, and stdout is a terminal. Reports
+    /// written to any other sink are never colored.
+    #[default]
+    Auto,
+    Never,
+}
+
+impl ColorMode {
+    /// `Always` or `Never`, depending on whether output to stdout is to
+    /// be colored
+    pub fn for_stdout(self) -> ColorMode {
+        match self {
+            ColorMode::Auto if io::stdout().is_terminal() => ColorMode::Always,
+            ColorMode::Auto => ColorMode::Never,
+            mode => mode,
+        }
+    }
+}
+
+/// Layout of a metadata listing for `Database::diff_metadata_listing`.
+/// Each record holds a file's size in bytes, mtime in seconds since the
+/// Unix epoch (a fractional part is ignored), permission bits in octal
+/// and path relative to the root, separated by single spaces, as printed
+/// by `find DIR -type f -printf '%s %T@ %m %P\n'`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListingFormat {
+    /// One record per line
+    Lines,
+    /// Records terminated by NUL bytes (`-printf '%s %T@ %m %P\0'`),
+    /// for trees with newlines in file names
+    Nul,
+}
+
+/// A Unicode normalization form for path keys, see
+/// `DiffOptions::normalize_paths`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathNormalization {
+    /// Canonical composition, as produced by most Linux and Windows tools
+    Nfc,
+    /// Canonical decomposition, as stored by HFS+ on macOS
+    Nfd,
+}
+
+/// A hash algorithm for `Database::write_manifest`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ManifestAlgorithm {
+    /// SHA2-256, as checked by `sha256sum -c`
+    Sha256,
+    /// SHA2-512, as checked by `sha512sum -c`
+    Sha512,
+}
+
+/// A snapshot of a build in progress, as passed to the callback of
+/// `Database::build_with_progress`
+#[derive(Debug)]
+pub struct Progress<'a> {
+    pub path: &'a Path, // The file just hashed, relative to the root
+    pub files: u64, // Files hashed so far
+    pub bytes: u64, // Bytes hashed so far
+}
+
+/// The progress callback runs once this many files have been hashed
+/// since it last ran, or `PROGRESS_BYTE_INTERVAL` bytes
+pub const PROGRESS_FILE_INTERVAL: u64 = 1000;
+pub const PROGRESS_BYTE_INTERVAL: u64 = 64 << 20;
+
+/// What a build went through. Any error, even on a single file or
+/// directory (e.g. one that can't be read), aborts the build and is
+/// returned instead of these, so a count of the errors encountered
+/// would always be zero and is left out.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct BuildStats {
+    pub files: u64, // Files hashed
+    pub directories: u64, // Directories visited, including the root
+    pub bytes: u64, // Bytes hashed, after decompression where enabled
+    pub allocated: u64, // Bytes allocated on disk to the files hashed (Unix only)
+    pub skipped: u64, // Files left out for being modified after `modified_before`
+    pub elapsed: Duration,
+}
+
+impl BuildStats {
+    /// The average throughput of the build, in bytes per second
+    pub fn bytes_per_sec(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs > 0.0 { self.bytes as f64 / secs } else { 0.0 }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DiffOptions {
+    pub mode: DiffMode,
+    /// Paths (relative to the root) to exclude from the diff. Matching
+    /// entries present on both sides count as unchanged; matching
+    /// entries that were added or removed are not counted at all.
+    pub ignore: Option<GlobSet>,
+    /// Flag files where one cryptographic hash matches but another
+    /// does not. This should never happen, and indicates a bug, an
+    /// attempted collision, or corruption of a database.
+    pub paranoid: bool,
+    /// Flag files whose modification time moved backward, which may
+    /// indicate timestomping. Off by default, since restoring from a
+    /// backup or checking out files can legitimately reset mtimes.
+    pub check_mtime: bool,
+    /// Count files whose modification time differs as changed, even if
+    /// their size and hashes match. Meant for comparisons where there
+    /// are no hashes to go by, such as against a metadata listing.
+    pub compare_mtime: bool,
+    /// List files whose contents are unchanged but whose modification
+    /// time differs, as "content identical, timestamp changed". They
+    /// still don't count as changed.
+    pub show_touched: bool,
+    /// Treat newly added zero-byte files as suspicious, since empty
+    /// files are sometimes dropped as markers by attackers.
+    pub flag_added_empty: bool,
+    /// Treat text files that gained (or lost) non-ASCII bytes as
+    /// suspicious. Off by default, since an accented character or an
+    /// emoji is usually a benign edit. The change is still listed.
+    pub flag_nonascii: bool,
+    /// Don't treat files that gained the setuid, setgid, world-write
+    /// or execute bits as suspicious, e.g. for trees where builds
+    /// legitimately produce executables. Mode changes are still listed.
+    pub ignore_privilege_escalation: bool,
+    /// Report removed files that reappear as added files elsewhere as
+    /// renames, rather than as a removal and an addition, and pairs of
+    /// files that exchanged contents (which makes the diff suspicious).
+    /// See `Database::diff_with_renames` and `Database::swaps`.
+    pub detect_renames: bool,
+    /// Compare only the paths present in both databases, ignoring all
+    /// additions and removals. For trees that are expected to differ
+    /// in their file sets but should agree on the files they share.
+    pub shared_only: bool,
+    /// Prepended to every path in the report (in place of `.` for the
+    /// root), e.g. the absolute path of the scanned tree, so that the
+    /// reported paths can be passed straight to other commands.
+    pub path_prefix: Option<PathBuf>,
+    /// Bring the path keys of both sides to this normalization form
+    /// before matching them up, so that databases of trees whose names
+    /// were written with different normalizations compare cleanly.
+    /// Reported paths (and `ignore` globs) use the normalized names.
+    pub normalize_paths: Option<PathNormalization>,
+    /// In `check`, warn that the wrong root was likely given when more
+    /// than this many times as many files were added as the database
+    /// holds, e.g. a 10-file database checked against a whole disk.
+    pub wrong_root_ratio: Option<u64>,
+    /// Fail with `Error::LikelyWrongRoot` instead of warning, before
+    /// printing any of the report.
+    pub abort_on_wrong_root: bool,
+    /// Instead of the tree of changes, list every changed file grouped
+    /// by `MetricsDiff::classify`, the most alarming tier first.
+    pub group_by_confidence: bool,
+    /// Whether to color the tree of changes with ANSI escapes. Without
+    /// colors, the report is the same in every mode.
+    pub color: ColorMode,
+    /// Only report suspicious entries, leaving out ordinary changes and
+    /// directories without suspicious entries. See
+    /// `EntryDiff::suspicious_only`. The summary still covers all changes.
+    pub suspicious_only: bool,
+}
+
+impl DiffOptions {
+    pub(crate) fn is_ignored(&self, path: &Path) -> bool {
+        self.ignore.as_ref().is_some_and(|ignore| ignore.is_match(path))
+    }
+
+    // The path to report for a path relative to the database root
+    pub(crate) fn display_path(&self, path: &Path) -> PathBuf {
+        match self.path_prefix {
+            Some(ref prefix) => prefix.join(path),
+            None => path.to_owned(),
         }
     }
+
+    // Whether a path present on only one side counts as added or removed
+    pub(crate) fn counts_one_sided(&self, path: &Path) -> bool {
+        !self.shared_only && !self.is_ignored(path)
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -53,16 +554,34 @@ pub struct DatabaseChecksum {
     sha2: Option<HashSum>,
     #[serde(skip_serializing_if = "Option::is_none")]
     blake2b: Option<HashSum>,
+    #[serde(rename = "sha2-256")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sha256: Option<HashSum>,
+    #[serde(rename = "sha2-512")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sha512: Option<HashSum>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    blake3: Option<HashSum>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    xxh64: Option<HashSum>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    crc32: Option<HashSum>,
     size: u64,
+    // Informational only: not covered by the checksum
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    root_name: Option<String>,
 }
 
 impl DatabaseChecksum {
     fn diff(&self, new: &Self) -> bool {
-        let changed = self.size != new.size;
-        let changed = changed || (self.sha2.is_some() && new.sha2.is_some() && self.sha2 != new.sha2);
-        let changed = changed ||
-            (self.blake2b.is_some() && new.blake2b.is_some() && self.blake2b != new.blake2b);
-        changed
+        self.size != new.size ||
+            hash_changed(&self.sha2, &new.sha2) ||
+            hash_changed(&self.blake2b, &new.blake2b) ||
+            hash_changed(&self.sha256, &new.sha256) ||
+            hash_changed(&self.sha512, &new.sha512) ||
+            hash_changed(&self.blake3, &new.blake3) ||
+            hash_changed(&self.xxh64, &new.xxh64) ||
+            hash_changed(&self.crc32, &new.crc32)
     }
 }
 
@@ -71,18 +590,232 @@ impl From<Metrics> for DatabaseChecksum {
         DatabaseChecksum {
             sha2: metrics.sha2,
             blake2b: metrics.blake2b,
+            sha256: metrics.sha256,
+            sha512: metrics.sha512,
+            blake3: metrics.blake3,
+            xxh64: metrics.xxh64,
+            crc32: metrics.crc32,
             size: metrics.size,
+            root_name: None,
         }
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
-pub struct Database(Entry);
+// The second field is the name of the directory or file the database
+// was built from. It is stored in the header rather than the contents,
+// so it takes no part in serialization or equality.
+#[derive(Debug, Clone, Default)]
+pub struct Database(Entry, Option<String>);
+
+impl PartialEq for Database {
+    fn eq(&self, other: &Database) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl Eq for Database {}
+
+impl serde::Serialize for Database {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Database {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Database, D::Error> {
+        Ok(Database(serde::Deserialize::deserialize(deserializer)?, None))
+    }
+}
 
+// Files far outnumber directories, so boxing the metrics would only
+// add an allocation per file
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Entry {
     Directory(BTreeMap<PathBuf, Entry>),
     File(Metrics),
+    /// A symbolic link, recorded without following it
+    Symlink { target: PathBuf },
+    /// A FIFO, socket or device node, recorded without opening it
+    Special(SpecialKind),
+}
+
+/// The kind of an `Entry::Special`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SpecialKind {
+    Fifo,
+    Socket,
+    BlockDevice,
+    CharDevice,
+}
+
+impl SpecialKind {
+    #[cfg(unix)]
+    fn of(file_type: fs::FileType) -> Option<SpecialKind> {
+        use std::os::unix::fs::FileTypeExt;
+        if file_type.is_fifo() {
+            Some(SpecialKind::Fifo)
+        } else if file_type.is_socket() {
+            Some(SpecialKind::Socket)
+        } else if file_type.is_block_device() {
+            Some(SpecialKind::BlockDevice)
+        } else if file_type.is_char_device() {
+            Some(SpecialKind::CharDevice)
+        } else {
+            None
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn of(_file_type: fs::FileType) -> Option<SpecialKind> {
+        None
+    }
+}
+
+impl fmt::Display for SpecialKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            SpecialKind::Fifo => "fifo",
+            SpecialKind::Socket => "socket",
+            SpecialKind::BlockDevice => "block device",
+            SpecialKind::CharDevice => "character device",
+        })
+    }
+}
+
+/// Anomalies in a file name of the kind used to disguise a file, such
+/// as `invoice\u{202E}fdp.exe` or a trailing space, as a set of flags
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NameAnomalies(u8);
+
+impl NameAnomalies {
+    /// Control characters, such as a newline
+    pub const CONTROL: NameAnomalies = NameAnomalies(1);
+    /// Bidirectional overrides, embeddings, isolates or marks
+    pub const BIDI: NameAnomalies = NameAnomalies(2);
+    /// Whitespace at the start or end
+    pub const WHITESPACE: NameAnomalies = NameAnomalies(4);
+    /// UTF-16 surrogates missing their other half (Windows only)
+    pub const LONE_SURROGATE: NameAnomalies = NameAnomalies(8);
+
+    /// Analyzes a name as the platform stores it. Bytes that are not
+    /// UTF-8 are not anomalies by themselves.
+    pub fn of(name: &OsStr) -> NameAnomalies {
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStrExt;
+            NameAnomalies::of_chars(String::from_utf8_lossy(name.as_bytes()).chars().map(Some))
+        }
+        #[cfg(windows)]
+        {
+            use std::os::windows::ffi::OsStrExt;
+            NameAnomalies::of_chars(std::char::decode_utf16(name.encode_wide()).map(Result::ok))
+        }
+        #[cfg(not(any(unix, windows)))]
+        {
+            NameAnomalies::of_chars(name.to_string_lossy().chars().map(Some))
+        }
+    }
+
+    // Characters of the name, with `None` for a lone surrogate
+    fn of_chars(chars: impl Iterator<Item = Option<char>>) -> NameAnomalies {
+        let mut anomalies = NameAnomalies::default();
+        let (mut first, mut last) = (None, None);
+        for c in chars {
+            anomalies = anomalies | match c {
+                None => NameAnomalies::LONE_SURROGATE,
+                Some(c) if c.is_control() => NameAnomalies::CONTROL,
+                Some('\u{061C}' | '\u{200E}' | '\u{200F}' |
+                     '\u{202A}'..='\u{202E}' | '\u{2066}'..='\u{2069}') => NameAnomalies::BIDI,
+                Some(_) => NameAnomalies::default(),
+            };
+            first = first.or(Some(c));
+            last = Some(c);
+        }
+        if [first, last].iter().any(|c| matches!(c, Some(Some(c)) if c.is_whitespace())) {
+            anomalies = anomalies | NameAnomalies::WHITESPACE;
+        }
+        anomalies
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn contains(self, other: NameAnomalies) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// A description of each anomaly, e.g. "control characters"
+    pub fn descriptions(self) -> Vec<&'static str> {
+        [
+            (NameAnomalies::CONTROL, "control characters"),
+            (NameAnomalies::BIDI, "bidirectional text controls"),
+            (NameAnomalies::WHITESPACE, "leading or trailing whitespace"),
+            (NameAnomalies::LONE_SURROGATE, "unpaired UTF-16 surrogates"),
+        ].iter().filter(|(flag, _)| self.contains(*flag)).map(|(_, description)| *description).collect()
+    }
+}
+
+impl std::ops::BitOr for NameAnomalies {
+    type Output = NameAnomalies;
+
+    fn bitor(self, other: NameAnomalies) -> NameAnomalies {
+        NameAnomalies(self.0 | other.0)
+    }
+}
+
+/// A coarse content type, sniffed from the first bytes of a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FileType {
+    Elf,
+    Pe,
+    Pdf,
+    Zip,
+    Png,
+    Jpeg,
+    Gzip,
+    Text,
+    Unknown,
+}
+
+impl fmt::Display for FileType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            FileType::Elf => "ELF executable",
+            FileType::Pe => "PE executable",
+            FileType::Pdf => "PDF document",
+            FileType::Zip => "ZIP archive",
+            FileType::Png => "PNG image",
+            FileType::Jpeg => "JPEG image",
+            FileType::Gzip => "gzip data",
+            FileType::Text => "text",
+            FileType::Unknown => "unknown",
+        })
+    }
+}
+
+/// The line endings used in a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LineEndings {
+    Lf,
+    Crlf,
+    Mixed,
+    NoNewlines,
+    /// The file is binary (has NUL bytes)
+    NotApplicable,
+}
+
+impl fmt::Display for LineEndings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            LineEndings::Lf => "LF",
+            LineEndings::Crlf => "CRLF",
+            LineEndings::Mixed => "mixed",
+            LineEndings::NoNewlines => "no newlines",
+            LineEndings::NotApplicable => "binary",
+        })
+    }
 }
 
 impl Default for Entry {
@@ -98,455 +831,4883 @@ pub struct Metrics {
     sha2: Option<HashSum>,
     #[serde(skip_serializing_if = "Option::is_none")]
     blake2b: Option<HashSum>,
+    #[serde(rename = "sha2-256")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sha256: Option<HashSum>,
+    #[serde(rename = "sha2-512")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sha512: Option<HashSum>, // Full-width SHA-512, for archival use
+    #[serde(skip_serializing_if = "Option::is_none")]
+    blake3: Option<HashSum>, // Absent in older databases
+    #[serde(skip_serializing_if = "Option::is_none")]
+    xxh64: Option<HashSum>, // Non-cryptographic, for change detection only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    crc32: Option<HashSum>, // Non-cryptographic, to compare with legacy manifests
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    first_block: Option<HashSum>, // SHA-256 of the first 4 KiB, if requested
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tree_hash: Option<HashSum>, // SHA-256 tree hash of large files, if requested
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tree_chunk_size: Option<u64>, // The chunk size of `tree_hash`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tree_chunks: Option<Vec<HashSum>>, // The chunk hashes under `tree_hash`, for incremental rebuilds
     size: u64,      // File size
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    allocated: Option<u64>, // Bytes allocated on disk (Unix only; absent in older databases)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    preview: Option<Preview>, // Leading bytes of the contents, if requested
     nul: bool,      // Does the file contain a NUL byte?
     nonascii: bool, // Does the file contain non-ASCII bytes?
+    #[serde(skip_serializing_if = "Option::is_none")]
+    utf8: Option<bool>, // Is the file valid UTF-8? (Absent in older databases)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    entropy: Option<u16>, // Shannon entropy in thousandths of a bit per byte
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    line_endings: Option<LineEndings>, // Absent in older databases
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    newlines: Option<u64>, // Number of LF bytes (text files only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    crlf: Option<u64>, // How many of those follow a CR (text files only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    filetype: Option<FileType>, // Sniffed from the first bytes (absent in older databases)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mode: Option<u32>, // Permission bits (Unix only), including setuid/setgid/sticky
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    uid: Option<u32>, // Owning user (Unix only; absent in older databases)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    gid: Option<u32>, // Owning group (Unix only; absent in older databases)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mtime: Option<i64>, // Modification time, in seconds since the Unix epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    mtime_nsec: Option<u32>, // Nanoseconds past `mtime` (absent in older databases)
+    #[serde(default, skip_serializing_if = "is_false")]
+    decompressed: bool, // Are the metrics of the gzip-decompressed contents?
+    #[serde(skip_serializing_if = "Option::is_none")]
+    inode: Option<u64>, // Inode number (Unix only, if requested), for rename detection
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    dev: Option<u64>, // Device holding `inode` (Unix only, if requested)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    nlink: Option<u64>, // Number of hard links (Unix only; absent in older databases)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    acl: Option<String>, // POSIX access ACL (Linux only, if requested), as for `getfacl`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    truncated: Option<usize>, // Length in bytes that hashes were cut to, if any
+    #[serde(default, skip_serializing_if = "is_false")]
+    hash_only: bool, // Are only the hashes and size known? (e.g. from a sidecar file)
+    #[serde(default, skip_serializing_if = "is_false")]
+    size_unknown: bool, // Is even the size unknown? (e.g. from `sha256sum` output)
+    #[serde(default, skip_serializing_if = "is_false")]
+    path_bound: bool, // Do the hashes cover the path as well as the contents?
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
-pub struct HashSum(#[serde(with = "base64")] Vec<u8>);
+// Renders bytes as ASCII, with escapes such as `\n` and `\xff` for
+// anything unprintable
+fn printable(bytes: &[u8]) -> String {
+    bytes.iter().flat_map(|&byte| std::ascii::escape_default(byte)).map(char::from).collect()
+}
 
-#[derive(Default)]
-struct EngineSize(u64);
-impl EngineSize {
-    fn input(&mut self, input: &[u8]) {
-        self.0 += input.len() as u64;
-    }
-    fn result(self) -> u64 {
-        self.0
+// Whether the change in size is all down to CRs added or removed before
+// the same number of LFs, as when converting between LF and CRLF
+fn line_endings_only(old: &Metrics, new: &Metrics) -> bool {
+    match ((old.newlines, old.crlf), (new.newlines, new.crlf)) {
+        ((Some(old_lf), Some(old_crlf)), (Some(new_lf), Some(new_crlf))) => {
+            old_lf == new_lf && old_crlf != new_crlf &&
+                new.size as i128 - old.size as i128 == new_crlf as i128 - old_crlf as i128
+        }
+        _ => false,
     }
 }
 
-#[derive(Default)]
-struct EngineNul(bool);
-impl EngineNul {
-    fn input(&mut self, input: &[u8]) {
-        self.0 = self.0 || input.iter().any(|x| *x == 0);
-    }
-    fn result(self) -> bool {
-        self.0
-    }
+fn is_false(value: &bool) -> bool {
+    !*value
 }
 
-#[derive(Default)]
-struct EngineNonascii(bool);
-impl EngineNonascii {
-    fn input(&mut self, input: &[u8]) {
-        self.0 = self.0 || input.iter().any(|x| x & 0x80 != 0);
+// Renders a byte count in binary units, e.g. "1.5 KiB"
+pub(crate) fn human_size(size: u64) -> String {
+    const UNITS: &[&str] = &["KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+    if size < 1024 {
+        return format!("{} B", size);
     }
-    fn result(self) -> bool {
-        self.0
+    let mut value = size as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
     }
+    format!("{:.1} {}", value, UNITS[unit])
 }
 
-struct Engines {
-    sha2: Option<sha2::Sha512Trunc256>,
-    blake2b: Option<blake2::VarBlake2b>,
-    size: EngineSize,
-    nul: EngineNul,
-    nonascii: EngineNonascii,
+// Renders permission bits as e.g. "rwxr-sr-x"
+fn symbolic_mode(mode: u32) -> String {
+    let special = [(0o4000, 's'), (0o2000, 's'), (0o1000, 't')];
+    let mut result = String::with_capacity(9);
+    for (i, (special_bit, special_char)) in special.iter().enumerate() {
+        let bits = mode >> (6 - 3 * i);
+        result.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+        result.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+        result.push(match (bits & 0o1 != 0, mode & special_bit != 0) {
+            (true, true) => *special_char,
+            (false, true) => special_char.to_ascii_uppercase(),
+            (true, false) => 'x',
+            (false, false) => '-',
+        });
+    }
+    result
 }
 
-impl Engines {
-    fn new(features: Features) -> Engines {
-        Engines {
-            sha2: if features.sha2 {
-                Some(sha2::Sha512Trunc256::default())
-            } else {
-                None
-            },
-            blake2b: if features.blake2b {
-                Some(blake2::VarBlake2b::new(32).unwrap())
-            } else {
-                None
-            },
-            size: EngineSize::default(),
-            nul: EngineNul::default(),
-            nonascii: EngineNonascii::default(),
-         }
+// Describes the dangerous permission bits set in `new` but not in `old`.
+// A file only becomes executable if no one could execute it before:
+// `chmod o+x` on a program already runnable by its owner isn't news.
+fn gained_privileges(old: u32, new: u32) -> Vec<&'static str> {
+    let gained = new & !old;
+    let bits = [
+        (0o4000, "gained setuid bit"),
+        (0o2000, "gained setgid bit"),
+        (0o0002, "became world-writable"),
+    ];
+    let mut reasons: Vec<&'static str> =
+        bits.iter().filter(|(bit, _)| gained & bit != 0).map(|(_, reason)| *reason).collect();
+    if old & 0o111 == 0 && new & 0o111 != 0 {
+        reasons.push("became executable");
     }
+    reasons
 }
 
-impl Engines {
-    fn input(&mut self, input: &[u8]) {
-        self.sha2.iter_mut().for_each(|e| e.input(input));
-        self.blake2b.iter_mut().for_each(|e| e.input(input));
-        self.size.input(input);
-        self.nul.input(input);
-        self.nonascii.input(input);
-    }
-    fn result(self) -> Metrics {
+impl Metrics {
+    /// Constructs metrics directly, e.g. to exercise `diff` without
+    /// files on disk. Metrics not given here are left unrecorded.
+    pub fn new(
+        size: u64,
+        sha2: Option<HashSum>,
+        blake2b: Option<HashSum>,
+        nul: bool,
+        nonascii: bool,
+    ) -> Metrics {
         Metrics {
-            sha2: self.sha2.map(|e| HashSum(Vec::from(e.fixed_result().as_slice()))),
-            blake2b: self.blake2b.map(|e| HashSum(
-                e.vec_result())),
-            size: self.size.result(),
-            nul: self.nul.result(),
-            nonascii: self.nonascii.result(),
+            sha2,
+            blake2b,
+            sha256: None,
+            sha512: None,
+            blake3: None,
+            xxh64: None,
+            crc32: None,
+            first_block: None,
+            tree_hash: None,
+            tree_chunk_size: None,
+            tree_chunks: None,
+            size,
+            allocated: None,
+            preview: None,
+            nul,
+            nonascii,
+            utf8: None,
+            entropy: None,
+            line_endings: None,
+            newlines: None,
+            crlf: None,
+            filetype: None,
+            mode: None,
+            uid: None,
+            gid: None,
+            mtime: None,
+            mtime_nsec: None,
+            decompressed: false,
+            inode: None,
+            dev: None,
+            nlink: None,
+            acl: None,
+            truncated: None,
+            hash_only: false,
+            size_unknown: false,
+            path_bound: false,
         }
     }
-}
 
-fn compute_metrics(path: impl AsRef<Path>, features: Features) -> Result<Metrics, error::Error> {
-    let mut f = File::open(path)?;
+    /// Computes the metrics of data already in memory, such as
+    /// rendered or decrypted contents, as if it had been read from a
+    /// file. Metadata (mode, mtime, inode) is left unrecorded.
+    pub fn from_bytes(data: &[u8], features: Features) -> Metrics {
+        let mut engines = Engines::new(features);
+        engines.input(data);
+        engines.result()
+    }
 
-    let mut engines = Engines::new(features);
+    // False for files imported without a size, whose `size` is 0
+    pub(crate) fn size_known(&self) -> bool {
+        !self.size_unknown
+    }
 
-    let mut buffer = [0; 4096];
-    loop {
-        let n = f.read(&mut buffer[..])?;
-        if n == 0 { break }
-        engines.input(&buffer[0..n]);
+    pub fn size(&self) -> u64 {
+        self.size
     }
-    Ok(engines.result())
-}
 
-trait BTreeMapExt<K, V> where K: Ord, V: Default {
-    fn get_default(&mut self, key: K) -> &mut V;
-}
+    /// The sniffed content type, or `None` if not recorded
+    pub fn filetype(&self) -> Option<FileType> {
+        self.filetype
+    }
 
-impl<K, V> BTreeMapExt<K, V> for BTreeMap<K, V>
-where
-    K: Ord + Clone,
-    V: Default,
-{
-    fn get_default(&mut self, key: K) -> &mut V {
-        self.entry(key).or_insert_with(|| V::default())
+    /// The line endings used in the file, or `None` if not recorded
+    pub fn line_endings(&self) -> Option<LineEndings> {
+        self.line_endings
+    }
+
+    /// The bytes allocated to the file on disk, or `None` if not
+    /// recorded. Less than `size` for sparse files.
+    pub fn allocated(&self) -> Option<u64> {
+        self.allocated
+    }
+
+    /// The leading bytes of the contents, if a preview was stored
+    pub fn preview(&self) -> Option<&[u8]> {
+        self.preview.as_ref().map(|preview| &preview.0[..])
+    }
+
+    /// The Shannon entropy of the contents, in thousandths of a bit
+    /// per byte, or `None` if empty or not recorded
+    pub fn entropy(&self) -> Option<u16> {
+        self.entropy
+    }
+
+    /// The CRC-32 of the contents, if recorded in full and not bound
+    /// to the path or taken after decompression
+    pub fn crc32(&self) -> Option<u32> {
+        match &self.crc32 {
+            Some(HashSum(bytes)) if bytes.len() == 4 && !self.path_bound && !self.decompressed => {
+                Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+            }
+            _ => None,
+        }
+    }
+
+    // Rejects hashes whose length doesn't match their algorithm. Such a
+    // hash can only come from corruption that still parses as JSON.
+    fn check_hash_lengths(&self, path: &Path) -> Result<(), error::Error> {
+        let hashes = [
+            ("sha2-512/256", &self.sha2, 32),
+            ("blake2b", &self.blake2b, 32),
+            ("sha2-256", &self.sha256, 32),
+            ("sha2-512", &self.sha512, 64),
+            ("blake3", &self.blake3, 32),
+            ("xxh64", &self.xxh64, 8),
+            ("crc32", &self.crc32, 4),
+            ("first_block", &self.first_block, 32),
+            ("tree_hash", &self.tree_hash, 32),
+        ];
+        let chunks = self.tree_chunks.iter().flatten().map(|hash| ("tree_chunks", Some(hash), 32));
+        let hashes = hashes.iter().map(|&(algorithm, hash, expected)| (algorithm, hash.as_ref(), expected));
+        for (algorithm, hash, expected) in hashes.chain(chunks) {
+            let expected = self.truncated.map_or(expected, |len| len.min(expected));
+            if let Some(HashSum(bytes)) = hash {
+                if bytes.len() != expected {
+                    return Err(error::Error::InvalidHashLength {
+                        path: path.to_owned(),
+                        algorithm,
+                        expected,
+                        got: bytes.len(),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Renders the stored permission bits in `ls -l` style
+    /// (e.g. `rwsr-xr-x`), or `None` if the mode was not recorded.
+    pub fn mode_symbolic(&self) -> Option<String> {
+        self.mode.map(symbolic_mode)
+    }
+
+    // Sparse means at least a block's worth fewer bytes allocated than
+    // the file's size, which leaves out small files stored inline
+    fn is_sparse(&self) -> Option<bool> {
+        self.allocated.map(|allocated| allocated.saturating_add(4096) <= self.size)
+    }
+
+    // Text means no NUL bytes and valid UTF-8, or None if UTF-8
+    // validity wasn't recorded (in databases predating the check)
+    fn is_text(&self) -> Option<bool> {
+        self.utf8.map(|utf8| !self.nul && utf8)
+    }
+
+    pub fn diff(&self, new: &Metrics, options: &DiffOptions) -> MetricsDiff {
+        let old = self;
+        let tree = old.tree_hashes(new);
+        // Metrics of decompressed and raw contents can't be compared, so
+        // a file switching between the two is simply reported as changed
+        let comparable = old.decompressed == new.decompressed &&
+            old.path_bound == new.path_bound &&
+            (old.truncated == new.truncated || !old.shares_hash(new));
+        // Content flags are unknown for hash-only metrics
+        let flags = comparable && !old.hash_only && !new.hash_only;
+        let sizes = !old.size_unknown && !new.size_unknown;
+        let changed = !comparable ||
+            (sizes && old.size != new.size) ||
+            hash_changed(&old.xxh64, &new.xxh64) ||
+            hash_changed(&old.crc32, &new.crc32) ||
+            hash_changed(&old.first_block, &new.first_block);
+        let changed = changed || (options.mode == DiffMode::Full && (
+            hash_changed(&old.sha2, &new.sha2) ||
+            hash_changed(&old.blake2b, &new.blake2b) ||
+            hash_changed(&old.sha256, &new.sha256) ||
+            hash_changed(&old.sha512, &new.sha512) ||
+            hash_changed(&old.blake3, &new.blake3) ||
+            hash_changed(tree.0, tree.1)));
+        let changed = changed || (options.compare_mtime && match (old.mtime, new.mtime) {
+            (Some(old), Some(new)) => old != new,
+            _ => false,
+        });
+        MetricsDiff {
+            changed_content: changed,
+            later_content_changed: changed && comparable &&
+                old.first_block.is_some() && old.first_block == new.first_block,
+            zeroed: comparable && sizes && old.size > 0 && new.size == 0,
+            changed_nul: flags && old.nul != new.nul,
+            changed_nonascii: flags && old.nonascii != new.nonascii,
+            nonascii_flagged: options.flag_nonascii && flags && old.nonascii != new.nonascii,
+            changed_utf8_validity: flags && old.utf8 == Some(true) && new.utf8 == Some(false),
+            changed_filetype: match (old.filetype, new.filetype) {
+                // Covered by `text_to_binary` and `binary_to_text`
+                (Some(FileType::Text), Some(FileType::Unknown)) |
+                (Some(FileType::Unknown), Some(FileType::Text)) => None,
+                (Some(old), Some(new)) if flags && old != new => Some((old, new)),
+                _ => None,
+            },
+            changed_line_endings: if changed && flags && line_endings_only(old, new) {
+                old.line_endings.zip(new.line_endings)
+            } else {
+                None
+            },
+            changed_mode: match (old.mode, new.mode) {
+                (Some(old), Some(new)) if old != new => Some((old, new)),
+                _ => None,
+            },
+            changed_owner: match ((old.uid, old.gid), (new.uid, new.gid)) {
+                ((Some(old_uid), Some(old_gid)), (Some(new_uid), Some(new_gid)))
+                    if (old_uid, old_gid) != (new_uid, new_gid) =>
+                {
+                    Some(((old_uid, old_gid), (new_uid, new_gid)))
+                }
+                _ => None,
+            },
+            entropy_jump: flags && match (old.entropy, new.entropy) {
+                (Some(old_entropy), Some(new_entropy)) => {
+                    old_entropy < ENTROPY_LOW && new_entropy >= ENTROPY_HIGH &&
+                        new.size.abs_diff(old.size) <= old.size / 8 + 4096
+                }
+                _ => false,
+            },
+            original_preview: if changed { old.preview().map(<[u8]>::to_vec) } else { None },
+            changed_sparseness: match (old.is_sparse(), new.is_sparse()) {
+                (Some(old), Some(new)) if old != new && !changed => Some(new),
+                _ => None,
+            },
+            hardlink_broken: match (old.nlink, new.nlink) {
+                (Some(old), Some(1)) if old > 1 => Some(old),
+                _ => None,
+            },
+            privilege_escalation: !options.ignore_privilege_escalation && match (old.mode, new.mode) {
+                (Some(old), Some(new)) => !gained_privileges(old, new).is_empty(),
+                _ => false,
+            },
+            hash_disagreement: options.paranoid && comparable && hashes_disagree(old, new),
+            no_common_hash: old.has_hash() && new.has_hash() && !old.shares_hash(new),
+            weak_evidence: options.mode == DiffMode::Full && old.shares_hash(new) && tree.0.is_none() &&
+                !old.features().cryptographic().shares_hash(&new.features().cryptographic()),
+            tree_chunk_mismatch: old.tree_hash.is_some() && new.tree_hash.is_some() &&
+                old.tree_chunk_size != new.tree_chunk_size,
+            text_to_binary: flags && old.is_text() == Some(true) && new.is_text() == Some(false),
+            binary_to_text: flags && old.is_text() == Some(false) && new.is_text() == Some(true),
+            mtime_went_backward: options.check_mtime && match (old.mtime, new.mtime) {
+                (Some(old), Some(new)) => new < old,
+                _ => false,
+            },
+            mtime_changed: match (old.mtime, new.mtime) {
+                (Some(old), Some(new)) => old != new,
+                _ => false,
+            },
+            // Within a second, a legitimate rewrite can keep the same
+            // mtime, so only nanosecond precision can tell
+            frozen_mtime: changed && comparable && old.mtime.is_some() && old.mtime == new.mtime &&
+                old.mtime_nsec.is_some() && old.mtime_nsec == new.mtime_nsec,
+            // A file without an extended ACL is rendered from its mode,
+            // so its changes are already reported as mode changes
+            changed_acl: match (&old.acl, &new.acl) {
+                (Some(old), Some(new)) => old != new && (acl::is_extended(old) || acl::is_extended(new)),
+                _ => false,
+            },
+            acl_changes: match (&old.acl, &new.acl) {
+                (Some(old), Some(new)) if acl::is_extended(old) || acl::is_extended(new) => {
+                    acl::acl_changes(old, new)
+                }
+                _ => Vec::new(),
+            },
+            new_file: if changed { Some(new.new_file()) } else { None },
+        }
+    }
+
+    fn new_file(&self) -> NewFile {
+        NewFile {
+            size: self.size,
+            sha256: self.manifest_hash(ManifestAlgorithm::Sha256).cloned(),
+        }
+    }
+
+    // The hash of the algorithm, if it is one that `sha256sum` and
+    // similar tools would compute for the file
+    pub(crate) fn manifest_hash(&self, algorithm: ManifestAlgorithm) -> Option<&HashSum> {
+        if self.truncated.is_some() || self.path_bound || self.decompressed {
+            return None;
+        }
+        match algorithm {
+            ManifestAlgorithm::Sha256 => self.sha256.as_ref(),
+            ManifestAlgorithm::Sha512 => self.sha512.as_ref(),
+        }
+    }
+
+    // Cuts every hash down to at most `len` bytes
+    fn truncate_hashes(mut self, len: Option<usize>) -> Metrics {
+        if let Some(len) = len {
+            let mut hashes = [
+                &mut self.sha2, &mut self.blake2b, &mut self.sha256, &mut self.sha512, &mut self.blake3,
+                &mut self.xxh64, &mut self.crc32,
+                &mut self.first_block, &mut self.tree_hash,
+            ];
+            for hash in hashes.iter_mut() {
+                if let Some(HashSum(bytes)) = &mut **hash {
+                    bytes.truncate(len);
+                }
+            }
+            // Chunk hashes are only of use at full length
+            self.tree_chunks = None;
+            self.truncated = Some(len);
+        }
+        self
+    }
+
+    // Fails if the hashes of both sides were computed in ways that
+    // can never agree, so that comparing them would report a change
+    // whatever the contents
+    fn ensure_comparable(&self, new: &Metrics, path: &Path) -> Result<(), error::Error> {
+        if self.decompressed != new.decompressed {
+            Err(error::Error::DecompressionMismatch { path: path.to_owned(), recorded: self.decompressed })
+        } else if self.path_bound != new.path_bound {
+            Err(error::Error::PathBindingMismatch { path: path.to_owned(), recorded: self.path_bound })
+        } else if self.truncated != new.truncated && self.shares_hash(new) {
+            Err(error::Error::TruncationMismatch {
+                path: path.to_owned(),
+                recorded: self.truncated,
+                current: new.truncated,
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    // Whether both sides recorded at least one hash of the same kind
+    fn shares_hash(&self, other: &Metrics) -> bool {
+        self.features().shares_hash(&other.features()) || self.tree_hashes(other).0.is_some()
+    }
+
+    // The tree hashes of both sides, or none if they can't be compared
+    // because either is missing or their chunk sizes differ
+    fn tree_hashes<'a>(&'a self, other: &'a Metrics) -> (&'a Option<HashSum>, &'a Option<HashSum>) {
+        match (&self.tree_hash, &other.tree_hash) {
+            (Some(_), Some(_)) if self.tree_chunk_size == other.tree_chunk_size => {
+                (&self.tree_hash, &other.tree_hash)
+            }
+            _ => (&None, &None),
+        }
+    }
+
+    fn has_hash(&self) -> bool {
+        self.shares_hash(self)
+    }
+
+    // The features needed to recompute every hash stored here
+    fn features(&self) -> Features {
+        Features {
+            sha2: self.sha2.is_some(),
+            blake2b: self.blake2b.is_some(),
+            sha256: self.sha256.is_some(),
+            sha512: self.sha512.is_some(),
+            blake3: self.blake3.is_some(),
+            xxh64: self.xxh64.is_some(),
+            crc32: self.crc32.is_some(),
+        }
+    }
+}
+
+impl fmt::Display for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.size_unknown {
+            writeln!(f, "size:         unknown")?;
+        } else {
+            writeln!(f, "size:         {} ({} bytes)", human_size(self.size), self.size)?;
+        }
+        let hashes = [
+            ("sha2-512/256", &self.sha2),
+            ("blake2b", &self.blake2b),
+            ("sha2-256", &self.sha256),
+            ("sha2-512", &self.sha512),
+            ("blake3", &self.blake3),
+            ("xxh64", &self.xxh64),
+            ("crc32", &self.crc32),
+            ("first-block", &self.first_block),
+            ("tree-hash", &self.tree_hash),
+        ];
+        for (name, hash) in hashes.iter() {
+            if let Some(hash) = hash {
+                writeln!(f, "{:<13} {}", format!("{}:", name), hash)?;
+            }
+        }
+        if let Some(chunk_size) = self.tree_chunk_size {
+            writeln!(f, "tree-chunks:  {} ({} bytes)", human_size(chunk_size), chunk_size)?;
+        }
+        if let Some(mode) = self.mode_symbolic() {
+            writeln!(f, "mode:         {}", mode)?;
+        }
+        writeln!(f, "nul:          {}", self.nul)?;
+        write!(f, "nonascii:     {}", self.nonascii)?;
+        if let Some(utf8) = self.utf8 {
+            write!(f, "\nutf8:         {}", utf8)?;
+        }
+        if self.decompressed {
+            write!(f, "\ndecompressed: true")?;
+        }
+        if self.path_bound {
+            write!(f, "\npath-bound:   true")?;
+        }
+        if let Some(len) = self.truncated {
+            write!(f, "\ntruncated:    {} bytes", len)?;
+        }
+        if let Some(ref acl) = self.acl {
+            write!(f, "\nacl:          {}", acl)?;
+        }
+        Ok(())
+    }
+}
+
+// The raw bytes of a preview, base64-encoded like hashes
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Preview(#[serde(with = "base64")] Vec<u8>);
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct HashSum(#[serde(with = "base64")] Vec<u8>);
+
+// A hash can only be compared if both sides computed it
+fn hash_changed(old: &Option<HashSum>, new: &Option<HashSum>) -> bool {
+    match (old, new) {
+        (Some(old), Some(new)) => old != new,
+        _ => false,
+    }
+}
+
+// True if both files have the same size and agree on at least one
+// hash, with no hash disagreeing
+fn same_content(old: &Metrics, new: &Metrics) -> bool {
+    let pairs = [
+        (&old.sha2, &new.sha2),
+        (&old.blake2b, &new.blake2b),
+        (&old.sha256, &new.sha256),
+        (&old.sha512, &new.sha512),
+        (&old.blake3, &new.blake3),
+        (&old.xxh64, &new.xxh64),
+        (&old.crc32, &new.crc32),
+        old.tree_hashes(new),
+    ];
+    let mut matched = false;
+    for (old, new) in pairs.iter() {
+        if let (Some(old), Some(new)) = (old, new) {
+            if old != new {
+                return false;
+            }
+            matched = true;
+        }
+    }
+    let sizes = old.size == new.size || old.size_unknown || new.size_unknown;
+    matched && sizes && old.decompressed == new.decompressed &&
+        !old.path_bound && !new.path_bound
+}
+
+// True if some cryptographic hash computed on both sides matches
+// while another one does not
+fn hashes_disagree(old: &Metrics, new: &Metrics) -> bool {
+    let pairs = [
+        (&old.sha2, &new.sha2),
+        (&old.blake2b, &new.blake2b),
+        (&old.sha256, &new.sha256),
+        (&old.sha512, &new.sha512),
+        (&old.blake3, &new.blake3),
+        old.tree_hashes(new),
+    ];
+    let mut matched = false;
+    let mut mismatched = false;
+    for (old, new) in pairs.iter() {
+        if let (Some(old), Some(new)) = (old, new) {
+            matched = matched || old == new;
+            mismatched = mismatched || old != new;
+        }
+    }
+    matched && mismatched
+}
+
+impl From<Vec<u8>> for HashSum {
+    fn from(bytes: Vec<u8>) -> HashSum {
+        HashSum(bytes)
+    }
+}
+
+impl fmt::Display for HashSum {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for b in self.0.iter() {
+            write!(f, "{:02x}", b)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct EngineSize(u64);
+impl EngineSize {
+    fn input(&mut self, input: &[u8]) {
+        self.0 += input.len() as u64;
+    }
+    fn result(self) -> u64 {
+        self.0
+    }
+}
+
+#[derive(Default)]
+struct EngineNul(bool);
+impl EngineNul {
+    fn input(&mut self, input: &[u8]) {
+        self.0 = self.0 || input.contains(&0);
+    }
+    fn result(self) -> bool {
+        self.0
+    }
+}
+
+#[derive(Default)]
+struct EngineNonascii(bool);
+impl EngineNonascii {
+    fn input(&mut self, input: &[u8]) {
+        self.0 = self.0 || input.iter().any(|x| x & 0x80 != 0);
+    }
+    fn result(self) -> bool {
+        self.0
+    }
+}
+
+const FIRST_BLOCK_LEN: usize = 4096;
+
+// Hashes the first FIRST_BLOCK_LEN bytes, however the reads split them
+#[derive(Default)]
+struct EngineFirstBlock(Vec<u8>);
+impl EngineFirstBlock {
+    fn input(&mut self, input: &[u8]) {
+        let n = input.len().min(FIRST_BLOCK_LEN - self.0.len());
+        self.0.extend_from_slice(&input[..n]);
+    }
+    fn result(self) -> HashSum {
+        let mut engine = sha2::Sha256::default();
+        engine.input(&self.0);
+        HashSum(Vec::from(engine.fixed_result().as_slice()))
+    }
+}
+
+// Validates UTF-8 incrementally. A multi-byte sequence may be split
+// across inputs, so an incomplete tail is carried over to the next one.
+struct EngineUtf8 {
+    valid: bool,
+    pending: Vec<u8>,
+}
+impl Default for EngineUtf8 {
+    fn default() -> EngineUtf8 {
+        EngineUtf8 { valid: true, pending: Vec::new() }
+    }
+}
+impl EngineUtf8 {
+    fn input(&mut self, input: &[u8]) {
+        if !self.valid {
+            return;
+        }
+        let mut data = std::mem::take(&mut self.pending);
+        let input = if data.is_empty() {
+            input
+        } else {
+            data.extend_from_slice(input);
+            &data[..]
+        };
+        if let Err(err) = std::str::from_utf8(input) {
+            match err.error_len() {
+                Some(_) => self.valid = false,
+                None => self.pending = input[err.valid_up_to()..].to_vec(),
+            }
+        }
+    }
+    fn result(self) -> bool {
+        self.valid && self.pending.is_empty()
+    }
+}
+
+// Counts every byte value, for the Shannon entropy of the contents
+struct EngineEntropy([u64; 256]);
+impl Default for EngineEntropy {
+    fn default() -> EngineEntropy {
+        EngineEntropy([0; 256])
+    }
+}
+impl EngineEntropy {
+    fn input(&mut self, input: &[u8]) {
+        for &byte in input {
+            self.0[byte as usize] += 1;
+        }
+    }
+    // In thousandths of a bit per byte, from 0 to 8000, or `None` if
+    // there were no bytes at all
+    fn result(self) -> Option<u16> {
+        let total: u64 = self.0.iter().sum();
+        if total == 0 {
+            return None;
+        }
+        let entropy: f64 = self.0.iter().filter(|&&count| count > 0).map(|&count| {
+            let p = count as f64 / total as f64;
+            -p * p.log2()
+        }).sum();
+        Some((entropy * 1000.0).round() as u16)
+    }
+}
+
+// Counts line feeds, and those preceded by a carriage return, which
+// may have ended the previous input
+#[derive(Default)]
+struct EngineNewlines {
+    lf: u64,
+    crlf: u64,
+    after_cr: bool,
+}
+impl EngineNewlines {
+    fn input(&mut self, input: &[u8]) {
+        for &byte in input {
+            if byte == b'\n' {
+                self.lf += 1;
+                self.crlf += self.after_cr as u64;
+            }
+            self.after_cr = byte == b'\r';
+        }
+    }
+    // Counts aren't kept for binary files, whose "lines" mean nothing
+    fn result(self, nul: bool) -> (Option<LineEndings>, Option<u64>, Option<u64>) {
+        if nul {
+            return (Some(LineEndings::NotApplicable), None, None);
+        }
+        let line_endings = match (self.lf, self.crlf) {
+            (0, _) => LineEndings::NoNewlines,
+            (_, 0) => LineEndings::Lf,
+            (lf, crlf) if lf == crlf => LineEndings::Crlf,
+            _ => LineEndings::Mixed,
+        };
+        (Some(line_endings), Some(self.lf), Some(self.crlf))
+    }
+}
+
+const MAGIC_LEN: usize = 8;
+
+// Sniffs the content type from the first MAGIC_LEN bytes, falling back
+// on the text flags of the whole file
+#[derive(Default)]
+struct EngineFileType(Vec<u8>);
+impl EngineFileType {
+    fn input(&mut self, input: &[u8]) {
+        let n = input.len().min(MAGIC_LEN - self.0.len());
+        self.0.extend_from_slice(&input[..n]);
+    }
+    fn result(self, nul: bool, utf8: bool) -> FileType {
+        const MAGIC: &[(&[u8], FileType)] = &[
+            (b"\x7fELF", FileType::Elf),
+            (b"MZ", FileType::Pe),
+            (b"%PDF-", FileType::Pdf),
+            (b"PK\x03\x04", FileType::Zip),
+            (b"PK\x05\x06", FileType::Zip),
+            (b"PK\x07\x08", FileType::Zip),
+            (b"\x89PNG\r\n\x1a\n", FileType::Png),
+            (b"\xff\xd8\xff", FileType::Jpeg),
+            (b"\x1f\x8b", FileType::Gzip),
+        ];
+        match MAGIC.iter().find(|(magic, _)| self.0.starts_with(magic)) {
+            Some((_, filetype)) => *filetype,
+            None if !nul && utf8 => FileType::Text,
+            None => FileType::Unknown,
+        }
+    }
+}
+
+struct Engines {
+    sha2: Option<sha2::Sha512Trunc256>,
+    blake2b: Option<blake2::VarBlake2b>,
+    sha256: Option<sha2::Sha256>,
+    sha512: Option<sha2::Sha512>,
+    blake3: Option<blake3::Blake3>,
+    xxh64: Option<xxhash::Xxh64>,
+    crc32: Option<crc32::Crc32>,
+    first_block: Option<EngineFirstBlock>,
+    size: EngineSize,
+    nul: EngineNul,
+    nonascii: EngineNonascii,
+    utf8: EngineUtf8,
+    entropy: EngineEntropy,
+    newlines: EngineNewlines,
+    filetype: EngineFileType,
+}
+
+impl Engines {
+    fn new(features: Features) -> Engines {
+        Engines {
+            sha2: if features.sha2 {
+                Some(sha2::Sha512Trunc256::default())
+            } else {
+                None
+            },
+            blake2b: if features.blake2b {
+                Some(blake2::VarBlake2b::new(32).unwrap())
+            } else {
+                None
+            },
+            sha256: if features.sha256 {
+                Some(sha2::Sha256::default())
+            } else {
+                None
+            },
+            sha512: if features.sha512 {
+                Some(sha2::Sha512::default())
+            } else {
+                None
+            },
+            blake3: if features.blake3 {
+                Some(blake3::Blake3::default())
+            } else {
+                None
+            },
+            xxh64: if features.xxh64 {
+                Some(xxhash::Xxh64::default())
+            } else {
+                None
+            },
+            crc32: if features.crc32 {
+                Some(crc32::Crc32::default())
+            } else {
+                None
+            },
+            first_block: None,
+            size: EngineSize::default(),
+            nul: EngineNul::default(),
+            nonascii: EngineNonascii::default(),
+            utf8: EngineUtf8::default(),
+            entropy: EngineEntropy::default(),
+            newlines: EngineNewlines::default(),
+            filetype: EngineFileType::default(),
+         }
+    }
+}
+
+impl Engines {
+    fn input_hashes(&mut self, input: &[u8]) {
+        self.sha2.iter_mut().for_each(|e| e.input(input));
+        self.blake2b.iter_mut().for_each(|e| e.input(input));
+        self.sha256.iter_mut().for_each(|e| e.input(input));
+        self.sha512.iter_mut().for_each(|e| e.input(input));
+        self.blake3.iter_mut().for_each(|e| e.input(input));
+        self.xxh64.iter_mut().for_each(|e| e.input(input));
+        self.crc32.iter_mut().for_each(|e| e.input(input));
+    }
+    // Only what a `DatabaseChecksum` keeps: the hashes and the size
+    fn input_checksum(&mut self, input: &[u8]) {
+        self.input_hashes(input);
+        self.size.input(input);
+    }
+    fn input(&mut self, input: &[u8]) {
+        self.input_hashes(input);
+        self.first_block.iter_mut().for_each(|e| e.input(input));
+        self.size.input(input);
+        self.nul.input(input);
+        self.nonascii.input(input);
+        self.utf8.input(input);
+        self.entropy.input(input);
+        self.newlines.input(input);
+        self.filetype.input(input);
+    }
+    fn result(self) -> Metrics {
+        let nul = self.nul.result();
+        let utf8 = self.utf8.result();
+        let (line_endings, newlines, crlf) = self.newlines.result(nul);
+        Metrics {
+            sha2: self.sha2.map(|e| HashSum(Vec::from(e.fixed_result().as_slice()))),
+            blake2b: self.blake2b.map(|e| HashSum(
+                e.vec_result())),
+            sha256: self.sha256.map(|e| HashSum(Vec::from(e.fixed_result().as_slice()))),
+            sha512: self.sha512.map(|e| HashSum(Vec::from(e.fixed_result().as_slice()))),
+            blake3: self.blake3.map(|e| HashSum(e.result().to_vec())),
+            xxh64: self.xxh64.map(|e| HashSum(e.result().to_be_bytes().to_vec())),
+            crc32: self.crc32.map(|e| HashSum(e.result().to_be_bytes().to_vec())),
+            first_block: self.first_block.map(|e| e.result()),
+            tree_hash: None,
+            tree_chunk_size: None,
+            tree_chunks: None,
+            size: self.size.result(),
+            allocated: None,
+            preview: None,
+            nul,
+            nonascii: self.nonascii.result(),
+            utf8: Some(utf8),
+            entropy: self.entropy.result(),
+            line_endings,
+            newlines,
+            crlf,
+            filetype: Some(self.filetype.result(nul, utf8)),
+            mode: None,
+            uid: None,
+            gid: None,
+            mtime: None,
+            mtime_nsec: None,
+            decompressed: false,
+            inode: None,
+            dev: None,
+            nlink: None,
+            acl: None,
+            truncated: None,
+            hash_only: false,
+            size_unknown: false,
+            path_bound: false,
+        }
+    }
+}
+
+#[cfg(unix)]
+fn file_mode(metadata: &fs::Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    Some(metadata.permissions().mode() & 0o7777)
+}
+
+#[cfg(not(unix))]
+fn file_mode(_metadata: &fs::Metadata) -> Option<u32> {
+    None
+}
+
+#[cfg(unix)]
+fn file_inode(metadata: &fs::Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(metadata.ino())
+}
+
+#[cfg(not(unix))]
+fn file_inode(_metadata: &fs::Metadata) -> Option<u64> {
+    None
+}
+
+// `blocks` counts 512-byte units, whatever the file system's block size
+#[cfg(unix)]
+fn file_allocated(metadata: &fs::Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(metadata.blocks() * 512)
+}
+
+#[cfg(not(unix))]
+fn file_allocated(_metadata: &fs::Metadata) -> Option<u64> {
+    None
+}
+
+// The device, inode number and link count of the file
+#[cfg(unix)]
+fn file_links(metadata: &fs::Metadata) -> Option<(u64, u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino(), metadata.nlink()))
+}
+
+#[cfg(not(unix))]
+fn file_links(_metadata: &fs::Metadata) -> Option<(u64, u64, u64)> {
+    None
+}
+
+#[cfg(unix)]
+fn file_owner(metadata: &fs::Metadata) -> (Option<u32>, Option<u32>) {
+    use std::os::unix::fs::MetadataExt;
+    (Some(metadata.uid()), Some(metadata.gid()))
+}
+
+#[cfg(not(unix))]
+fn file_owner(_metadata: &fs::Metadata) -> (Option<u32>, Option<u32>) {
+    (None, None)
+}
+
+fn file_mtime(metadata: &fs::Metadata) -> Option<i64> {
+    let modified = metadata.modified().ok()?;
+    Some(match modified.duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_secs() as i64,
+        Err(before) => -(before.duration().as_secs() as i64),
+    })
+}
+
+// The nanoseconds past `file_mtime`. Left out before the epoch, where
+// `file_mtime` rounds toward it.
+fn file_mtime_nsec(metadata: &fs::Metadata) -> Option<u32> {
+    let modified = metadata.modified().ok()?;
+    modified.duration_since(UNIX_EPOCH).ok().map(|since| since.subsec_nanos())
+}
+
+// Whether a walked file falls after the `modified_before` cutoff. Files
+// without a readable mtime are kept.
+fn modified_after(entry: &ignore::DirEntry, cutoff: Option<i64>) -> bool {
+    match cutoff {
+        Some(cutoff) => entry.metadata().ok().as_ref()
+            .and_then(file_mtime)
+            .is_some_and(|mtime| mtime > cutoff),
+        None => false,
+    }
+}
+
+// Windows APIs reject paths longer than MAX_PATH (260 characters)
+// unless they carry the `\\?\` prefix, which in turn requires an
+// absolute path with no `.` or `..` components
+#[cfg(windows)]
+pub(crate) fn extended_length_path(path: &Path) -> Cow<'_, Path> {
+    const MAX_PATH: usize = 260;
+    if path.as_os_str().len() < MAX_PATH {
+        return Cow::Borrowed(path);
+    }
+    let absolute = match std::path::absolute(path) {
+        Ok(absolute) => absolute,
+        Err(_) => return Cow::Borrowed(path),
+    };
+    let absolute = match absolute.into_os_string().into_string() {
+        Ok(absolute) => absolute,
+        Err(_) => return Cow::Borrowed(path),
+    };
+    Cow::Owned(PathBuf::from(if absolute.starts_with(r"\\?\") {
+        absolute
+    } else if let Some(unc) = absolute.strip_prefix(r"\\") {
+        format!(r"\\?\UNC\{}", unc)
+    } else {
+        format!(r"\\?\{}", absolute)
+    }))
+}
+
+#[cfg(not(windows))]
+pub(crate) fn extended_length_path(path: &Path) -> Cow<'_, Path> {
+    Cow::Borrowed(path)
+}
+
+// No point allocating more than the file needs, plus a byte to see EOF
+fn buffer_len(buffer_size: usize, file_size: u64) -> usize {
+    let file_size = usize::try_from(file_size).unwrap_or(usize::MAX);
+    buffer_size.min(file_size.saturating_add(1)).max(1)
+}
+
+fn hash_reader(
+    mut r: impl Read,
+    features: Features,
+    buffer_len: usize,
+    bound_path: Option<&[u8]>,
+    first_block: bool,
+) -> io::Result<Metrics> {
+    let mut engines = Engines::new(features);
+    if first_block {
+        engines.first_block = Some(EngineFirstBlock::default());
+    }
+    if let Some(bound_path) = bound_path {
+        engines.input_hashes(bound_path);
+    }
+
+    let mut buffer = vec![0; buffer_len];
+    loop {
+        let n = r.read(&mut buffer[..])?;
+        if n == 0 { break }
+        engines.input(&buffer[0..n]);
+    }
+    Ok(engines.result())
+}
+
+// The bytes hashed ahead of the contents in path-bound mode: the
+// components of the database key joined with `/`, then a NUL
+fn bound_path_bytes(path: &Path) -> Vec<u8> {
+    let components: Vec<_> = path.iter().map(|c| c.to_string_lossy()).collect();
+    let mut bytes = components.join("/").into_bytes();
+    bytes.push(0);
+    bytes
+}
+
+// With `decompress`, hashes the gzip-decompressed contents instead,
+// falling back to the raw bytes if the file turns out not to be gzip.
+// With `bound_path`, the hashes cover that path followed by the
+// contents, though the size and content flags are of the contents
+// alone. With `limiter`, reads from disk are paced to its rate. With
+// `first_block`, the hash of the first 4 KiB of the contents is stored.
+fn compute_metrics(
+    path: impl AsRef<Path>,
+    features: Features,
+    buffer_size: usize,
+    decompress: bool,
+    bound_path: Option<&Path>,
+    limiter: Option<&RateLimiter>,
+    first_block: bool,
+) -> Result<Metrics, error::Error> {
+    let path = extended_length_path(path.as_ref());
+    file_metrics(
+        File::open(&path)?, features, buffer_size, decompress, bound_path, limiter, first_block)
+}
+
+// As `compute_metrics`, for a file that is already open
+fn file_metrics(
+    f: File,
+    features: Features,
+    buffer_size: usize,
+    decompress: bool,
+    bound_path: Option<&Path>,
+    limiter: Option<&RateLimiter>,
+    first_block: bool,
+) -> Result<Metrics, error::Error> {
+    let metadata = f.metadata()?;
+    // A duplicate descriptor, to reread the raw bytes if they turn out
+    // not to be gzip
+    let rewind = if decompress { Some(f.try_clone()?) } else { None };
+    let f = Throttled::new(f, limiter);
+    let mode = file_mode(&metadata);
+    let mtime = file_mtime(&metadata);
+    let mtime_nsec = file_mtime_nsec(&metadata);
+    let path_bound = bound_path.is_some();
+    let bound_path = bound_path.map(bound_path_bytes);
+    let bound_path = bound_path.as_deref();
+    let raw_len = buffer_len(buffer_size, metadata.len());
+
+    let metrics = if decompress {
+        // The decompressed size isn't known up front
+        match hash_reader(
+            MultiGzDecoder::new(f), features, buffer_size.max(1), bound_path, first_block) {
+            Ok(metrics) => Metrics { decompressed: true, ..metrics },
+            Err(ref err) if err.kind() == io::ErrorKind::InvalidInput ||
+                err.kind() == io::ErrorKind::InvalidData =>
+            {
+                let mut f = rewind.expect("unreachable");
+                f.seek(io::SeekFrom::Start(0))?;
+                hash_reader(Throttled::new(f, limiter), features, raw_len, bound_path, first_block)?
+            }
+            Err(err) => return Err(err.into()),
+        }
+    } else {
+        hash_reader(f, features, raw_len, bound_path, first_block)?
+    };
+    let (uid, gid) = file_owner(&metadata);
+    Ok(Metrics { mode, uid, gid, mtime, mtime_nsec, path_bound, ..metrics })
+}
+
+// Everything needed to compute the metrics of walked files, in a form
+// that can be handed to each walker thread
+#[derive(Clone)]
+pub(crate) struct Hasher {
+    features: Features,
+    buffer_size: usize,
+    decompress: Option<GlobSet>,
+    record_inode: bool,
+    record_acl: bool,
+    truncate_hashes: Option<usize>,
+    first_block_hash: bool,
+    preview_len: usize,
+    tree_hash_threshold: Option<u64>,
+    tree_hash_chunk_size: u64,
+    threads: usize,
+    mmap: bool,
+    bind_paths: bool,
+    fold_case: bool,
+    budget: Option<Arc<MemoryBudget>>,
+    limiter: Option<Arc<RateLimiter>>,
+    // Metrics of files with several hard links, by device and inode
+    links: Arc<Mutex<HashMap<(u64, u64), Metrics>>>,
+    // An earlier database of the same tree, whose chunk hashes may be
+    // reused for files that only grew since
+    previous: Option<Arc<Database>>,
+}
+
+impl Hasher {
+    fn compute(&self, path: &Path, short_path: &Path) -> Result<Metrics, error::Error> {
+        self.compute_file(File::open(extended_length_path(path))?, short_path)
+    }
+
+    // Everything about the file is read through the open descriptor,
+    // so it can't be swapped for another file part way through
+    pub(crate) fn compute_file(&self, mut file: File, short_path: &Path) -> Result<Metrics, error::Error> {
+        let metadata = file.metadata()?;
+        let _reservation = self.budget.as_ref()
+            .map(|budget| budget.reserve(buffer_len(self.buffer_size, metadata.len())));
+        let decompress = self.decompress.as_ref().is_some_and(|globs| globs.is_match(short_path));
+        // Bind to the key the file will be stored under
+        let bound_path = match (self.bind_paths, self.fold_case) {
+            (false, _) => None,
+            (true, false) => Some(short_path.to_owned()),
+            (true, true) => Some(fold_path_case(short_path)),
+        };
+        // Other links to an inode already hashed share its metrics,
+        // unless the hashes depend on the path
+        let links = file_links(&metadata);
+        let shared = links.filter(|&(_, _, nlink)| nlink > 1 && !decompress && bound_path.is_none())
+            .map(|(dev, ino, _)| (dev, ino));
+        if let Some(metrics) = shared.and_then(|key| self.links.lock().unwrap().get(&key).cloned()) {
+            return Ok(metrics);
+        }
+        let acl = if self.record_acl { acl::file_acl(&file)? } else { None };
+        let preview = if self.preview_len > 0 {
+            let mut preview = Vec::with_capacity(self.preview_len);
+            (&file).take(self.preview_len as u64).read_to_end(&mut preview)?;
+            file.seek(io::SeekFrom::Start(0))?;
+            Some(Preview(preview))
+        } else {
+            None
+        };
+        let tree = self.tree_hash_threshold.is_some_and(|threshold| metadata.len() >= threshold) &&
+            !decompress && bound_path.is_none();
+        let mapping = if self.mmap && !tree && !decompress {
+            mmap::Mapping::new(&file, metadata.len())
+        } else {
+            None
+        };
+        let mut metrics = if tree {
+            self.tree_metrics(file, &metadata, short_path)?
+        } else if let Some(mapping) = mapping {
+            self.mapped_metrics(&mapping, &metadata, bound_path.as_deref())?
+        } else {
+            file_metrics(
+                file, self.features, self.buffer_size, decompress, bound_path.as_deref(),
+                self.limiter.as_deref(), self.first_block_hash)?
+        };
+        if self.record_inode {
+            metrics.inode = file_inode(&metadata);
+            metrics.dev = links.map(|(dev, _, _)| dev);
+        }
+        metrics.nlink = links.map(|(_, _, nlink)| nlink);
+        metrics.allocated = file_allocated(&metadata);
+        metrics.acl = acl;
+        metrics.preview = preview;
+        let metrics = metrics.truncate_hashes(self.truncate_hashes);
+        if let Some(key) = shared {
+            self.links.lock().unwrap().insert(key, metrics.clone());
+        }
+        Ok(metrics)
+    }
+}
+
+impl Hasher {
+    // As `file_metrics`, reading the raw contents through a mapping
+    fn mapped_metrics(
+        &self,
+        mapping: &mmap::Mapping,
+        metadata: &fs::Metadata,
+        bound_path: Option<&Path>,
+    ) -> Result<Metrics, error::Error> {
+        let mut engines = Engines::new(self.features);
+        if self.first_block_hash {
+            engines.first_block = Some(EngineFirstBlock::default());
+        }
+        if let Some(bound_path) = bound_path {
+            engines.input_hashes(&bound_path_bytes(bound_path));
+        }
+        mapping.for_each_slice(self.buffer_size, |slice| {
+            if let Some(limiter) = self.limiter.as_deref() {
+                limiter.consume(slice.len());
+            }
+            engines.input(slice);
+        })?;
+        let (uid, gid) = file_owner(metadata);
+        Ok(Metrics {
+            mode: file_mode(metadata),
+            uid,
+            gid,
+            mtime: file_mtime(metadata),
+            mtime_nsec: file_mtime_nsec(metadata),
+            path_bound: bound_path.is_some(),
+            ..engines.result()
+        })
+    }
+
+    fn tree_metrics(
+        &self,
+        mut file: File,
+        metadata: &fs::Metadata,
+        short_path: &Path,
+    ) -> Result<Metrics, error::Error> {
+        let chunk_size = self.tree_hash_chunk_size;
+        let whole_file = self.features.shares_hash(&self.features) || self.first_block_hash;
+        // Hashes over the whole file can't be resumed, so only a file
+        // summarized by its tree hash alone can skip its earlier chunks
+        let previous = if whole_file { None } else { self.previous_grown(metadata, short_path) };
+        let reused = match previous {
+            Some(previous) => {
+                let complete = (previous.size / chunk_size) as usize;
+                &previous.tree_chunks.as_ref().expect("checked by previous_grown")[..complete]
+            }
+            None => &[],
+        };
+        let reused: Vec<Vec<u8>> = reused.iter().map(|HashSum(hash)| hash.clone()).collect();
+        let mut tree = treehash::tree_hash(
+            &file, metadata.len(), chunk_size, &reused, self.buffer_size, self.threads,
+            self.limiter.as_deref())?;
+        if let (Some(previous), false) = (previous, reused.is_empty()) {
+            tree.nul = tree.nul || previous.nul;
+            tree.nonascii = tree.nonascii || previous.nonascii;
+        }
+        let metrics = if whole_file {
+            file.seek(io::SeekFrom::Start(0))?;
+            file_metrics(
+                file, self.features, self.buffer_size, false, None, self.limiter.as_deref(),
+                self.first_block_hash)?
+        } else {
+            // UTF-8 validity and line endings can't be told chunk by
+            // chunk, so they're unknown
+            let (uid, gid) = file_owner(metadata);
+            Metrics {
+                size: tree.size,
+                nul: tree.nul,
+                nonascii: tree.nonascii,
+                utf8: None,
+                line_endings: None,
+                newlines: None,
+                crlf: None,
+                filetype: None,
+                mode: file_mode(metadata),
+                uid,
+                gid,
+                mtime: file_mtime(metadata),
+                mtime_nsec: file_mtime_nsec(metadata),
+                ..Engines::new(self.features).result()
+            }
+        };
+        Ok(Metrics {
+            tree_hash: Some(HashSum(tree.root)),
+            tree_chunk_size: Some(chunk_size),
+            tree_chunks: Some(tree.chunks.into_iter().map(HashSum).collect()),
+            ..metrics
+        })
+    }
+
+    // The metrics of the file in the previous database, if they have a
+    // chunk map of the same chunk size and the file has since only grown
+    // (it is no smaller, and its mtime did not move backward). The
+    // chunks it had then are trusted to be unchanged.
+    fn previous_grown(&self, metadata: &fs::Metadata, short_path: &Path) -> Option<&Metrics> {
+        let key = if self.fold_case { fold_path_case(short_path) } else { short_path.to_owned() };
+        let previous = match self.previous.as_ref()?.0.lookup(&key)? {
+            Entry::File(metrics) => metrics,
+            _ => return None,
+        };
+        let grown = metadata.len() >= previous.size &&
+            matches!((previous.mtime, file_mtime(metadata)), (Some(old), Some(new)) if new >= old);
+        let usable = previous.tree_chunk_size == Some(self.tree_hash_chunk_size) &&
+            previous.tree_chunks.as_ref().is_some_and(|chunks| {
+                chunks.len() as u64 == previous.size.div_ceil(self.tree_hash_chunk_size)
+            }) &&
+            previous.truncated.is_none() && !previous.decompressed && !previous.path_bound;
+        if grown && usable { Some(previous) } else { None }
+    }
+}
+
+fn fold_name_case(name: &OsStr) -> OsString {
+    match name.to_str() {
+        Some(name) => name.to_lowercase().into(),
+        None => name.to_ascii_lowercase(),
+    }
+}
+
+pub(crate) fn fold_path_case(path: &Path) -> PathBuf {
+    path.iter().map(fold_name_case).collect()
+}
+
+trait BTreeMapExt<K, V> where K: Ord, V: Default {
+    fn get_default(&mut self, key: K) -> &mut V;
+}
+
+impl<K, V> BTreeMapExt<K, V> for BTreeMap<K, V>
+where
+    K: Ord + Clone,
+    V: Default,
+{
+    fn get_default(&mut self, key: K) -> &mut V {
+        self.entry(key).or_insert_with(|| V::default())
+    }
+}
+
+impl Entry {
+    pub fn is_dir(&self) -> bool {
+        matches!(self, Entry::Directory(_))
+    }
+
+    pub fn is_file(&self) -> bool {
+        matches!(self, Entry::File(_))
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        matches!(self, Entry::Symlink { .. })
+    }
+
+    /// The file's metrics, or `None` for a directory, symlink or
+    /// special file
+    pub fn as_metrics(&self) -> Option<&Metrics> {
+        match self {
+            Entry::File(metrics) => Some(metrics),
+            Entry::Directory(_) | Entry::Symlink { .. } | Entry::Special(_) => None,
+        }
+    }
+
+    /// The directory's entries keyed by name, or `None` for anything
+    /// else
+    pub fn children(&self) -> Option<&BTreeMap<PathBuf, Entry>> {
+        match self {
+            Entry::Directory(entries) => Some(entries),
+            Entry::File(_) | Entry::Symlink { .. } | Entry::Special(_) => None,
+        }
+    }
+
+    fn kind(&self) -> EntryKind {
+        match self {
+            Entry::Directory(_) => EntryKind::Directory,
+            Entry::File(_) => EntryKind::File,
+            Entry::Symlink { .. } => EntryKind::Symlink,
+            Entry::Special(kind) => EntryKind::Special(*kind),
+        }
+    }
+
+    // Returns false if the path collides with an existing entry.
+    fn insert(&mut self, path: &Path, file: Entry, fold_case: bool) -> bool {
+        // Inner nodes in the tree should always be directories. If
+        // the node is not a directory, that means we are inserting a
+        // duplicate file. The directory walker never produces
+        // duplicates on its own, but they can still be observed when
+        // names are case folded (e.g. `README` and `readme`).
+        match self {
+            Entry::Directory(entries) => {
+                let mut components = path.components();
+                let count = components.clone().count();
+                let first = components.next().expect("unreachable").as_os_str();
+                let first = if fold_case {
+                    PathBuf::from(fold_name_case(first))
+                } else {
+                    PathBuf::from(first)
+                };
+                let rest = components.as_path();
+                if count > 1 {
+                    let subentry = entries.get_default(first);
+                    subentry.insert(rest, file, fold_case)
+                } else {
+                    match entries.entry(first) {
+                        btree_map::Entry::Occupied(_) => false,
+                        btree_map::Entry::Vacant(slot) => {
+                            slot.insert(file);
+                            true
+                        }
+                    }
+                }
+            }
+            Entry::File(_) | Entry::Symlink { .. } | Entry::Special(_) => false,
+        }
+    }
+
+    // Makes sure there is a directory at `path`, creating it (and any
+    // missing parents) if needed. Walked directories land on the same
+    // nodes that `insert` creates implicitly for their files, whichever
+    // comes first, so each directory is recorded once. Returns false if
+    // something other than a directory is in the way.
+    fn insert_directory(&mut self, path: &Path, fold_case: bool) -> bool {
+        match self {
+            Entry::Directory(entries) => {
+                let mut components = path.components();
+                let first = match components.next() {
+                    Some(first) => first.as_os_str(),
+                    None => return true,
+                };
+                let first = if fold_case {
+                    PathBuf::from(fold_name_case(first))
+                } else {
+                    PathBuf::from(first)
+                };
+                entries.get_default(first).insert_directory(components.as_path(), fold_case)
+            }
+            Entry::File(_) | Entry::Symlink { .. } | Entry::Special(_) => false,
+        }
+    }
+
+    // Writes the mtree spec lines of this entry and everything under
+    // it, in full-path form, assuming `/set type=file`
+    fn write_mtree(&self, path: &Path, w: &mut dyn Write) -> io::Result<()> {
+        let name = if path == Path::new("") {
+            ".".to_owned()
+        } else {
+            format!("./{}", vis_mtree(&path_to_bytes(path)))
+        };
+        match self {
+            Entry::Directory(entries) => {
+                writeln!(w, "{} type=dir", name)?;
+                for (key, entry) in entries.iter() {
+                    entry.write_mtree(&path.join(key), w)?;
+                }
+            }
+            Entry::File(metrics) => {
+                write!(w, "{}", name)?;
+                if metrics.size_known() {
+                    write!(w, " size={}", metrics.size)?;
+                }
+                if let Some(hash) = metrics.manifest_hash(ManifestAlgorithm::Sha256) {
+                    write!(w, " sha256digest={}", hash)?;
+                }
+                if let Some(hash) = metrics.manifest_hash(ManifestAlgorithm::Sha512) {
+                    write!(w, " sha512digest={}", hash)?;
+                }
+                if let Some(mode) = metrics.mode {
+                    write!(w, " mode={:o}", mode & 0o7777)?;
+                }
+                if let Some(uid) = metrics.uid {
+                    write!(w, " uid={}", uid)?;
+                }
+                if let Some(gid) = metrics.gid {
+                    write!(w, " gid={}", gid)?;
+                }
+                if let Some(mtime) = metrics.mtime {
+                    write!(w, " time={}.0", mtime)?;
+                }
+                writeln!(w)?;
+            }
+            Entry::Symlink { target } => {
+                writeln!(w, "{} type=link link={}", name, vis_mtree(&path_to_bytes(target)))?;
+            }
+            Entry::Special(kind) => writeln!(w, "{} type={}", name, mtree_special_type(*kind))?,
+        }
+        Ok(())
+    }
+
+    fn to_human_json(&self) -> serde_json::Value {
+        match self {
+            Entry::Symlink { target } => {
+                let mut link = serde_json::Map::new();
+                link.insert("symlink".to_owned(), target.to_string_lossy().into_owned().into());
+                serde_json::Value::Object(link)
+            }
+            Entry::Special(kind) => {
+                let mut special = serde_json::Map::new();
+                special.insert("special".to_owned(), kind.to_string().into());
+                serde_json::Value::Object(special)
+            }
+            Entry::Directory(entries) => serde_json::Value::Object(
+                entries
+                    .iter()
+                    .map(|(key, entry)| (key.to_string_lossy().into_owned(), entry.to_human_json()))
+                    .collect()),
+            Entry::File(metrics) => {
+                let mut file = serde_json::Map::new();
+                file.insert("size".to_owned(), metrics.size.into());
+                let hashes = [
+                    ("sha2-512/256", &metrics.sha2),
+                    ("blake2b", &metrics.blake2b),
+                    ("sha2-256", &metrics.sha256),
+                    ("sha2-512", &metrics.sha512),
+                    ("blake3", &metrics.blake3),
+                    ("xxh64", &metrics.xxh64),
+                    ("crc32", &metrics.crc32),
+                    ("first_block", &metrics.first_block),
+                    ("tree_hash", &metrics.tree_hash),
+                ];
+                for (name, hash) in hashes.iter() {
+                    if let Some(hash) = hash {
+                        file.insert((*name).to_owned(), hash.to_string().into());
+                    }
+                }
+                file.insert("nul".to_owned(), metrics.nul.into());
+                file.insert("nonascii".to_owned(), metrics.nonascii.into());
+                if let Some(utf8) = metrics.utf8 {
+                    file.insert("utf8".to_owned(), utf8.into());
+                }
+                if let Some(mode) = metrics.mode {
+                    file.insert("mode".to_owned(), format!("{:04o}", mode).into());
+                }
+                if let Some(uid) = metrics.uid {
+                    file.insert("uid".to_owned(), uid.into());
+                }
+                if let Some(gid) = metrics.gid {
+                    file.insert("gid".to_owned(), gid.into());
+                }
+                if let Some(mtime) = metrics.mtime {
+                    file.insert("mtime".to_owned(), mtime.into());
+                }
+                if let Some(ref acl) = metrics.acl {
+                    file.insert("acl".to_owned(), acl.clone().into());
+                }
+                serde_json::Value::Object(file)
+            }
+        }
+    }
+
+    fn count_files(&self) -> u64 {
+        let mut count = 0;
+        self.for_each_file(Path::new(""), &mut |_, _| count += 1);
+        count
+    }
+
+    // The diff of this entry, found only in the new tree under `name`
+    fn added(&self, name: &Path) -> EntryDiff {
+        EntryDiff::Added {
+            kind: self.kind(),
+            files: self.count_files(),
+            anomalies: NameAnomalies::of(name.as_os_str()),
+            nested_anomalies: self.nested_anomalies(),
+            new_files: self.new_files(),
+        }
+    }
+
+    // The anomalies in the names of everything below this entry, by
+    // path relative to it
+    fn nested_anomalies(&self) -> BTreeMap<PathBuf, NameAnomalies> {
+        let mut found = BTreeMap::new();
+        self.collect_anomalies(Path::new(""), &mut found);
+        found
+    }
+
+    fn collect_anomalies(&self, path: &Path, found: &mut BTreeMap<PathBuf, NameAnomalies>) {
+        if let Entry::Directory(entries) = self {
+            for (key, entry) in entries.iter() {
+                let path = path.join(key);
+                let anomalies = NameAnomalies::of(key.as_os_str());
+                if !anomalies.is_empty() {
+                    found.insert(path.clone(), anomalies);
+                }
+                entry.collect_anomalies(&path, found);
+            }
+        }
+    }
+
+    // The number of names with anomalies in this entry, found only in
+    // the new tree under `name`, and below it
+    fn count_anomalies(&self, name: &Path) -> u64 {
+        !NameAnomalies::of(name.as_os_str()).is_empty() as u64 + self.nested_anomalies().len() as u64
+    }
+
+    fn new_files(&self) -> BTreeMap<PathBuf, NewFile> {
+        let mut files = BTreeMap::new();
+        self.for_each_file(Path::new(""), &mut |path, metrics| {
+            files.insert(path.to_owned(), metrics.new_file());
+        });
+        files
+    }
+
+    // The diff of this entry, found only in the old tree
+    fn removed(&self) -> EntryDiff {
+        EntryDiff::Removed { kind: self.kind(), files: self.count_files() }
+    }
+
+    // Whether this entry, found at `path`, is a zero-byte file or a
+    // directory with one in it that isn't ignored
+    fn has_empty_file(&self, path: &Path, options: &DiffOptions) -> bool {
+        let mut found = false;
+        self.for_each_file(path, &mut |path, metrics| {
+            found = found || (metrics.size == 0 && !options.is_ignored(path));
+        });
+        found
+    }
+
+    fn for_each_file<F>(&self, path: &Path, f: &mut F)
+    where
+        F: FnMut(&Path, &Metrics),
+    {
+        match self {
+            Entry::Directory(entries) => {
+                for (key, entry) in entries.iter() {
+                    entry.for_each_file(&path.join(key), f);
+                }
+            }
+            Entry::File(metrics) => f(path, metrics),
+            Entry::Symlink { .. } | Entry::Special(_) => (),
+        }
+    }
+
+    // Returns the total size of the files under this entry, recording
+    // the total of each directory along the way
+    fn directory_sizes(&self, path: &Path, sizes: &mut BTreeMap<PathBuf, u64>) -> u64 {
+        match self {
+            Entry::Directory(entries) => {
+                let total = entries.iter()
+                    .map(|(key, entry)| entry.directory_sizes(&path.join(key), sizes))
+                    .sum();
+                sizes.insert(path.to_owned(), total);
+                total
+            }
+            Entry::File(metrics) => metrics.size,
+            Entry::Symlink { .. } | Entry::Special(_) => 0,
+        }
+    }
+
+    fn lookup(&self, path: &Path) -> Option<&Entry> {
+        match self {
+            Entry::Directory(entries) => {
+                let mut components = path.components();
+                let count = components.clone().count();
+                let first = match components.next() {
+                    Some(first) => Path::new(first.as_os_str()).to_owned(),
+                    None => return Some(self),
+                };
+                let rest = components.as_path().to_owned();
+                if count > 1 {
+                    entries.get(&first).and_then(
+                        |subentry| subentry.lookup(&rest))
+                } else {
+                    entries.get(&first)
+                }
+            }
+            // Only directories have children
+            Entry::File(_) | Entry::Symlink { .. } | Entry::Special(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum EntryDiff {
+    Directory(#[serde(with = "ospath::map")] BTreeMap<PathBuf, EntryDiff>, DirectoryDiff),
+    File(MetricsDiff),
+    /// A symlink on both sides, changed if the targets differ
+    Symlink {
+        #[serde(with = "ospath")]
+        old_target: PathBuf,
+        #[serde(with = "ospath")]
+        new_target: PathBuf,
+    },
+    /// A special file of the same kind on both sides, so unchanged
+    Special(SpecialKind),
+    /// An entry of one kind replaced one of another, e.g. a file
+    /// replaced a directory or a symlink. `new_files` holds the files
+    /// in the new entry, by path relative to it (empty for a file).
+    KindChanged {
+        was: EntryKind,
+        now: EntryKind,
+        #[serde(with = "ospath::map")]
+        new_files: BTreeMap<PathBuf, NewFile>,
+    },
+    /// An entry only in the new tree, with the number of regular files
+    /// in it (1 for a file), any anomalies in its name and in the names
+    /// below it (by path relative to it) and the files themselves, as
+    /// for `KindChanged`
+    Added {
+        kind: EntryKind,
+        files: u64,
+        anomalies: NameAnomalies,
+        #[serde(with = "ospath::map")]
+        nested_anomalies: BTreeMap<PathBuf, NameAnomalies>,
+        #[serde(with = "ospath::map")]
+        new_files: BTreeMap<PathBuf, NewFile>,
+    },
+    /// An entry only in the old tree, with the number of regular files
+    /// in it
+    Removed { kind: EntryKind, files: u64 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EntryKind {
+    Directory,
+    File,
+    Symlink,
+    Special(SpecialKind),
+}
+
+impl EntryKind {
+    /// Whether a change from `was` to `now` swaps a regular file for a
+    /// FIFO, socket or device node, or the other way around
+    pub fn is_suspicious_change(was: EntryKind, now: EntryKind) -> bool {
+        matches!((was, now),
+                 (EntryKind::File, EntryKind::Special(_)) | (EntryKind::Special(_), EntryKind::File))
+    }
+}
+
+// E.g. "directory -> file"
+fn kind_change(was: EntryKind, now: EntryKind) -> &'static str {
+    match (was, now) {
+        (EntryKind::Directory, EntryKind::File) => "directory -> file",
+        (EntryKind::Directory, EntryKind::Symlink) => "directory -> symlink",
+        (EntryKind::File, EntryKind::Directory) => "file -> directory",
+        (EntryKind::File, EntryKind::Symlink) => "file -> symlink",
+        (EntryKind::Symlink, EntryKind::Directory) => "symlink -> directory",
+        (EntryKind::Symlink, EntryKind::File) => "symlink -> file",
+        (EntryKind::File, EntryKind::Special(_)) => "file -> special file",
+        (EntryKind::Special(_), EntryKind::File) => "special file -> file",
+        (_, EntryKind::Special(_)) => "replaced by a special file",
+        (EntryKind::Special(_), _) => "special file replaced",
+        _ => "unchanged",
+    }
+}
+
+impl fmt::Display for EntryKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            EntryKind::Directory => "directory",
+            EntryKind::File => "file",
+            EntryKind::Symlink => "symlink",
+            EntryKind::Special(kind) => return kind.fmt(f),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DirectoryDiff {
+    pub added: u64,
+    pub removed: u64,
+    pub changed: u64,
+    pub unchanged: u64,
+    pub added_empty: u64, // Added entries that are, or contain, zero-byte files
+    pub suspicious: u64, // Suspicious files, including empty ones if flagged
+    pub touched: u64, // Unchanged files whose mtime differs
+    pub added_dirs: u64, // Directories among the added entries
+    pub removed_dirs: u64, // Directories among the removed entries
+}
+
+/// `MetricsDiff::entropy_jump` is set when a file's entropy, in
+/// thousandths of a bit per byte, rises from below `ENTROPY_LOW` to at
+/// least `ENTROPY_HIGH`, as encrypting it would do
+pub const ENTROPY_LOW: u16 = 7000;
+pub const ENTROPY_HIGH: u16 = 7900;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MetricsDiff {
+    pub changed_content: bool,
+    pub later_content_changed: bool, // Changed, but with the same first-block hash
+    pub zeroed: bool,
+    pub changed_nul: bool,
+    pub changed_nonascii: bool, // Gained or lost non-ASCII bytes, reported for information
+    pub nonascii_flagged: bool, // `changed_nonascii` with `flag_nonascii`, so suspicious
+    pub changed_utf8_validity: bool, // Was valid UTF-8, but no longer is
+    pub changed_line_endings: Option<(LineEndings, LineEndings)>, // Explains the content change
+    pub changed_filetype: Option<(FileType, FileType)>, // Old and new sniffed content types
+    pub changed_mode: Option<(u32, u32)>, // Old and new permission bits
+    pub changed_owner: Option<((u32, u32), (u32, u32))>, // Old and new uid and gid
+    pub hardlink_broken: Option<u64>, // Old link count, if the file had others but now has none
+    pub original_preview: Option<Vec<u8>>, // The old preview, if stored and the content changed
+    pub changed_sparseness: Option<bool>, // Whether now sparse, if that changed but the content didn't
+    pub entropy_jump: bool, // Entropy rose to near the maximum at a similar size (encrypted?)
+    pub privilege_escalation: bool, // Gained setuid, setgid or world-write, or became executable
+    pub hash_disagreement: bool, // Only computed in paranoid mode
+    pub no_common_hash: bool, // No hash recorded on both sides, so contents weren't compared
+    pub weak_evidence: bool, // Only non-cryptographic hashes in common, in full mode
+    pub tree_chunk_mismatch: bool, // Both sides have tree hashes, but of different chunk sizes
+    pub text_to_binary: bool,
+    pub binary_to_text: bool,
+    pub mtime_went_backward: bool, // Only computed with `check_mtime`
+    pub mtime_changed: bool, // Only computed if both sides recorded mtimes
+    pub frozen_mtime: bool, // Content changed, but the mtime did not, to the nanosecond
+    pub changed_acl: bool, // Only computed if both sides recorded ACLs, one of them extended
+    pub acl_changes: Vec<String>, // ACL entries removed (`-`) and added (`+`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub new_file: Option<NewFile>, // Only if the content changed, for `EntryDiff::to_backup_manifest`
+}
+
+impl MetricsDiff {
+    /// Short descriptions of every suspicious change to the file
+    pub fn suspicious_reasons(&self) -> Vec<&'static str> {
+        let reasons = [
+            (self.hash_disagreement, "hashes disagree (some match, others do not)"),
+            (self.zeroed, "file was truncated"),
+            (self.changed_nul, "original had no NUL bytes, but now does"),
+            (self.nonascii_flagged, "non-ASCII bytes were added or removed"),
+            (self.text_to_binary, "text file became binary"),
+            // Already implied for text files
+            (self.changed_utf8_validity && !self.text_to_binary, "was valid UTF-8, but no longer is"),
+            (self.mtime_went_backward, "mtime moved backward (timestomping?)"),
+            (self.entropy_jump, "entropy jumped to near maximum (encrypted?)"),
+            (self.changed_filetype.is_some(), "file type changed"),
+        ];
+        let mut reasons: Vec<_> =
+            reasons.iter().filter(|(flag, _)| *flag).map(|(_, reason)| *reason).collect();
+        reasons.extend(self.gained_privileges());
+        reasons
+    }
+
+    /// The dangerous permission bits gained, e.g. "gained setuid bit"
+    pub fn gained_privileges(&self) -> Vec<&'static str> {
+        match self.changed_mode {
+            Some((old, new)) if self.privilege_escalation => gained_privileges(old, new),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Whether the contents, permissions, owner, hard links, allocation
+    /// or ACL changed. Files whose contents couldn't be verified, for
+    /// lack of a hash algorithm in common, count as changed too.
+    pub fn is_changed(&self) -> bool {
+        self.changed_content || self.changed_mode.is_some() || self.changed_owner.is_some() ||
+            self.hardlink_broken.is_some() || self.changed_sparseness.is_some() || self.changed_acl ||
+            self.no_common_hash
+    }
+
+    pub fn is_suspicious(&self) -> bool {
+        self.privilege_escalation || self.zeroed || self.changed_nul || self.nonascii_flagged ||
+            self.hash_disagreement || self.text_to_binary || self.changed_utf8_validity ||
+            self.mtime_went_backward || self.entropy_jump || self.changed_filetype.is_some()
+    }
+
+    /// Sorts the change into a confidence tier, for triage
+    pub fn classify(&self) -> ChangeClass {
+        if self.tamper_reasons().is_empty() {
+            if self.is_changed() || self.is_suspicious() {
+                ChangeClass::ProbableChange
+            } else if self.mtime_changed {
+                ChangeClass::Benign
+            } else {
+                ChangeClass::Unchanged
+            }
+        } else {
+            ChangeClass::DefiniteTamper
+        }
+    }
+
+    // Why the change is classified as `DefiniteTamper`, if it is
+    fn tamper_reasons(&self) -> Vec<&'static str> {
+        let mut reasons = Vec::new();
+        if self.changed_nul && self.text_to_binary {
+            reasons.push("text file gained NUL bytes");
+        }
+        reasons.extend(self.gained_privileges().into_iter().filter(|reason| {
+            *reason == "gained setuid bit" || *reason == "gained setgid bit"
+        }));
+        if self.frozen_mtime {
+            reasons.push("content changed, but mtime did not");
+        }
+        reasons
+    }
+}
+
+/// How alarming a change is, from `MetricsDiff::classify`. Tiers sort
+/// with the most alarming first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ChangeClass {
+    /// A text file gained NUL bytes, a setuid or setgid bit was gained,
+    /// or the content changed while the mtime stayed the same
+    DefiniteTamper,
+    /// Any other change to the content, permissions or ACL
+    ProbableChange,
+    /// Only the mtime changed
+    Benign,
+    Unchanged,
+}
+
+impl fmt::Display for ChangeClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ChangeClass::DefiniteTamper => "definite tamper",
+            ChangeClass::ProbableChange => "probable change",
+            ChangeClass::Benign => "benign",
+            ChangeClass::Unchanged => "unchanged",
+        })
+    }
+}
+
+/// Renders a diff in some output format
+pub trait DiffFormatter {
+    fn format(&self, diff: &EntryDiff, w: &mut dyn Write) -> io::Result<()>;
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VerificationResult {
+    pub missing: Vec<PathBuf>,    // Expected, but not in the database
+    pub mismatched: Vec<PathBuf>, // Present, but with a different hash
+    pub unhashed: Vec<PathBuf>,   // Present, but built without SHA-256
+    pub extra: Vec<PathBuf>,      // In the database, but not expected
+}
+
+/// A database read by `Database::from_hashdeep`, along with the hash
+/// columns of the input that it had no place for (e.g. `md5`), in the
+/// order they appeared
+#[derive(Debug, Clone)]
+pub struct HashdeepImport {
+    pub database: Database,
+    pub ignored_columns: Vec<String>,
+}
+
+impl VerificationResult {
+    pub fn is_ok(&self) -> bool {
+        self.missing.is_empty() && self.mismatched.is_empty() &&
+            self.unhashed.is_empty() && self.extra.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerifyStatus {
+    Ok,      // Present on disk and unchanged
+    Changed, // Present on disk, but contents (or mode) differ
+    Missing, // In the database, but not on disk
+    Added,   // On disk, but not in the database
+}
+
+// Walks the live tree lazily, then reports missing files at the end
+struct VerifyIter<'a> {
+    database: &'a Database,
+    root: PathBuf,
+    walk: ignore::Walk,
+    seen: BTreeSet<PathBuf>,
+    missing: Option<std::vec::IntoIter<PathBuf>>,
+}
+
+impl<'a> VerifyIter<'a> {
+    fn verify_file(&self, path: &Path, short_path: &Path) -> Result<VerifyStatus, error::Error> {
+        match self.database.0.lookup(short_path) {
+            Some(Entry::File(old)) => {
+                let bound_path = if old.path_bound { Some(short_path) } else { None };
+                let new = compute_metrics(
+                    path, old.features(), DEFAULT_BUFFER_SIZE, old.decompressed, bound_path, None,
+                    old.first_block.is_some())?
+                    .truncate_hashes(old.truncated);
+                let diff = old.diff(&new, &DiffOptions::default());
+                Ok(if diff.is_changed() {
+                    VerifyStatus::Changed
+                } else {
+                    VerifyStatus::Ok
+                })
+            }
+            Some(Entry::Directory(_)) | Some(Entry::Symlink { .. }) | Some(Entry::Special(_)) => {
+                Ok(VerifyStatus::Changed)
+            }
+            None => Ok(VerifyStatus::Added),
+        }
+    }
+}
+
+impl<'a> Iterator for VerifyIter<'a> {
+    type Item = Result<(PathBuf, VerifyStatus), error::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(ref mut missing) = self.missing {
+                return missing.next().map(|path| Ok((path, VerifyStatus::Missing)));
+            }
+            match self.walk.next() {
+                Some(Err(err)) => return Some(Err(err.into())),
+                Some(Ok(entry)) => {
+                    if !entry.file_type().is_some_and(|t| t.is_file()) {
+                        continue;
+                    }
+                    let short_path = match short_path(entry.path(), &self.root) {
+                        Ok(short_path) => short_path.to_owned(),
+                        Err(err) => return Some(Err(err.into())),
+                    };
+                    let status = self.verify_file(entry.path(), &short_path);
+                    self.seen.insert(short_path.clone());
+                    return Some(status.map(|status| (short_path, status)));
+                }
+                None => {
+                    let mut missing = Vec::new();
+                    let seen = &self.seen;
+                    self.database.0.for_each_file(Path::new(""), &mut |path, _| {
+                        if !seen.contains(path) {
+                            missing.push(path.to_owned());
+                        }
+                    });
+                    self.missing = Some(missing.into_iter());
+                }
+            }
+        }
+    }
+}
+
+/// The result of checking a legacy CRC-32 manifest against a database
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ManifestReport {
+    pub matched: Vec<PathBuf>,
+    pub mismatched: Vec<PathBuf>,
+    pub only_in_manifest: Vec<PathBuf>, // Listed, but not a file in the database
+    pub only_in_database: Vec<PathBuf>,
+    pub unchecked: Vec<PathBuf>, // In both, but no usable CRC-32 was recorded
+    pub malformed: Vec<(usize, String)>, // Line number (from 1) and contents
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rename {
+    pub from: PathBuf,
+    pub to: PathBuf,
+    /// Matched by inode rather than by content. The content may have
+    /// changed as well.
+    pub same_inode: bool,
+}
+
+/// A file to back up after a diff: one that was added or changed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackupEntry {
+    pub path: PathBuf,
+    pub size: u64,
+    pub sha256: HashSum,
+}
+
+/// What a backup manifest needs to know about a file on the new side
+/// of a diff
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NewFile {
+    pub size: u64,
+    // Absent unless built with `Features::sha256`, or if the hash isn't
+    // of the plain contents (see `write_manifest`)
+    pub sha256: Option<HashSum>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DiffSummary {
+    NoChanges,
+    Changes,
+    Suspicious,
+}
+
+/// The counts of a diff or check, as returned by `EntryDiff::outcome`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CheckOutcome {
+    pub clean: bool, // Nothing was added, removed or changed, and nothing is suspicious
+    pub changed: u64,
+    pub added: u64,
+    pub removed: u64,
+    pub suspicious: u64,
+}
+
+/// The report written by `Database::diff_json`, listing every path
+/// that was added, removed or changed, in path order. Added and removed
+/// directories are listed once, without their contents.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiffReport {
+    pub summary: DiffSummary,
+    pub changed: u64,
+    pub added: u64,
+    pub removed: u64,
+    pub suspicious: u64,
+    pub paths: Vec<PathReport>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PathReport {
+    #[serde(with = "ospath")]
+    pub path: PathBuf, // Relative to the root
+    pub status: PathStatus,
+    pub suspicious: Vec<String>, // Reasons the entry is suspicious, if it is
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PathStatus {
+    Added,
+    Removed,
+    Changed,
+}
+
+impl EntryDiff {
+    /// Returns the smallest set of directories that together contain
+    /// every change: a directory is listed if an entry directly inside
+    /// it was added, removed or changed, and otherwise its changed
+    /// subdirectories are searched. No path is listed along with one
+    /// of its ancestors. Paths are relative to the root, which is the
+    /// empty path.
+    pub fn changed_subtree_roots(&self) -> Vec<PathBuf> {
+        let mut roots = Vec::new();
+        self.collect_changed_subtree_roots(&mut PathBuf::new(), &mut roots);
+        roots
+    }
+
+    fn collect_changed_subtree_roots(&self, path: &mut PathBuf, roots: &mut Vec<PathBuf>) {
+        let changes = |diff: &DirectoryDiff| diff.added + diff.removed + diff.changed;
+        match self {
+            EntryDiff::Directory(entries, diff) => {
+                let nested: u64 = entries.values()
+                    .map(|entry| match entry {
+                        EntryDiff::Directory(_, diff) => changes(diff),
+                        _ => 0,
+                    })
+                    .sum();
+                if changes(diff) > nested {
+                    roots.push(path.clone());
+                } else if nested > 0 {
+                    for (key, entry) in entries.iter() {
+                        path.push(key);
+                        entry.collect_changed_subtree_roots(path, roots);
+                        path.pop();
+                    }
+                }
+            }
+            // Changed files are covered by their parent directory
+            EntryDiff::File(_) | EntryDiff::Symlink { .. } | EntryDiff::Special(_) |
+            EntryDiff::KindChanged { .. } | EntryDiff::Added { .. } | EntryDiff::Removed { .. } => (),
+        }
+    }
+
+    /// A one-line summary for logs, e.g. "integrity check of /opt/app:
+    /// 3 changed, 0 added, 1 removed, 2 suspicious". The counts come
+    /// from the root, so the tree is not walked again.
+    pub fn summary_line(&self, root: &Path) -> String {
+        let outcome = self.outcome();
+        format!("integrity check of {}: {} changed, {} added, {} removed, {} suspicious",
+                root.display(), outcome.changed, outcome.added, outcome.removed, outcome.suspicious)
+    }
+
+    /// Whether the diff is clean, with the counts that decide it. Like
+    /// `summary_line`, this only looks at the root.
+    pub fn outcome(&self) -> CheckOutcome {
+        let (changed, added, removed, suspicious) = match self {
+            EntryDiff::Directory(_, diff) => (diff.changed, diff.added, diff.removed, diff.suspicious),
+            EntryDiff::File(diff) => (
+                diff.is_changed() as u64,
+                0,
+                0,
+                diff.is_suspicious() as u64,
+            ),
+            EntryDiff::Symlink { old_target, new_target } => ((old_target != new_target) as u64, 0, 0, 0),
+            EntryDiff::Special(_) => (0, 0, 0, 0),
+            EntryDiff::Added { .. } => (0, 1, 0, self.added_anomalies(Path::new("")).len() as u64),
+            EntryDiff::Removed { .. } => (0, 0, 1, 0),
+            EntryDiff::KindChanged { was, now, .. } => {
+                (1, 0, 0, EntryKind::is_suspicious_change(*was, *now) as u64)
+            }
+        };
+        CheckOutcome {
+            clean: changed == 0 && added == 0 && removed == 0 && suspicious == 0,
+            changed,
+            added,
+            removed,
+            suspicious,
+        }
+    }
+
+    /// The per-path report of the diff, as written by
+    /// `Database::diff_json`. Only diffs that keep the whole tree, such
+    /// as those of `Database::diff`, list added and removed entries.
+    pub fn to_report(&self) -> DiffReport {
+        let CheckOutcome { changed, added, removed, suspicious, .. } = self.outcome();
+        let mut paths = Vec::new();
+        self.collect_report(&mut PathBuf::new(), &mut paths);
+        paths.sort_by(|a, b| a.path.cmp(&b.path));
+        DiffReport { summary: self.summarize_diff(), changed, added, removed, suspicious, paths }
+    }
+
+    fn collect_report(&self, path: &mut PathBuf, paths: &mut Vec<PathReport>) {
+        let mut push = |path: &Path, status, suspicious: Vec<&str>| paths.push(PathReport {
+            path: path.to_owned(),
+            status,
+            suspicious: suspicious.into_iter().map(String::from).collect(),
+        });
+        match self {
+            EntryDiff::Directory(entries, _) => {
+                for (key, entry) in entries.iter() {
+                    path.push(key);
+                    entry.collect_report(path, paths);
+                    path.pop();
+                }
+            }
+            EntryDiff::File(diff) => {
+                if diff.is_changed() || diff.is_suspicious() {
+                    push(path, PathStatus::Changed, diff.suspicious_reasons());
+                }
+            }
+            EntryDiff::Symlink { old_target, new_target } => {
+                if old_target != new_target {
+                    push(path, PathStatus::Changed, Vec::new());
+                }
+            }
+            EntryDiff::KindChanged { was, now, .. } => {
+                let reasons = if EntryKind::is_suspicious_change(*was, *now) {
+                    vec!["regular file swapped with a special file"]
+                } else {
+                    Vec::new()
+                };
+                push(path, PathStatus::Changed, reasons);
+            }
+            EntryDiff::Added { anomalies, .. } => {
+                push(path, PathStatus::Added, anomalies.descriptions());
+                for (nested, anomalies) in self.added_anomalies(path) {
+                    if nested != *path {
+                        push(&nested, PathStatus::Added, anomalies.descriptions());
+                    }
+                }
+            }
+            EntryDiff::Removed { .. } => push(path, PathStatus::Removed, Vec::new()),
+            EntryDiff::Special(_) => (),
+        }
+    }
+
+    /// For an entry added at `path`, each path in it whose name has
+    /// anomalies, starting with `path` itself, with those anomalies.
+    /// Empty for any other entry.
+    pub fn added_anomalies(&self, path: &Path) -> Vec<(PathBuf, NameAnomalies)> {
+        match self {
+            EntryDiff::Added { anomalies, nested_anomalies, .. } => {
+                let own = Some((path.to_owned(), *anomalies)).filter(|_| !anomalies.is_empty());
+                own.into_iter()
+                    .chain(nested_anomalies.iter().map(|(nested, anomalies)| (path.join(nested), *anomalies)))
+                    .collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// The diff pruned to its suspicious entries, or `None` if there
+    /// are none. Directories keep only the entries with something
+    /// suspicious in them, and their counts cover only those: each
+    /// remaining file or change of kind counts as changed, and each
+    /// suspiciously named entry (or empty file, with
+    /// `flag_added_empty`) as added.
+    pub fn suspicious_only(&self, options: &DiffOptions) -> Option<EntryDiff> {
+        match self {
+            EntryDiff::Directory(entries, diff) => {
+                let entries: BTreeMap<PathBuf, EntryDiff> = entries.iter()
+                    .filter_map(|(key, entry)| Some((key.clone(), entry.suspicious_only(options)?)))
+                    .collect();
+                let added_empty = if options.flag_added_empty { diff.added_empty } else { 0 };
+                let mut stats = DirectoryDiff {
+                    added: added_empty, removed: 0, changed: 0, unchanged: 0, added_empty,
+                    suspicious: added_empty, touched: 0, added_dirs: 0, removed_dirs: 0,
+                };
+                for entry in entries.values() {
+                    match entry {
+                        EntryDiff::Directory(_, nested) => {
+                            stats.added += nested.added;
+                            stats.changed += nested.changed;
+                            stats.added_empty += nested.added_empty;
+                            stats.suspicious += nested.suspicious;
+                        }
+                        EntryDiff::Added { .. } => {
+                            stats.added += 1;
+                            stats.suspicious += entry.added_anomalies(Path::new("")).len() as u64;
+                        }
+                        _ => {
+                            stats.changed += 1;
+                            stats.suspicious += 1;
+                        }
+                    }
+                }
+                if stats.suspicious > 0 {
+                    Some(EntryDiff::Directory(entries, stats))
+                } else {
+                    None
+                }
+            }
+            EntryDiff::File(diff) if diff.is_suspicious() => Some(self.clone()),
+            EntryDiff::KindChanged { was, now, .. } if EntryKind::is_suspicious_change(*was, *now) => {
+                Some(self.clone())
+            }
+            EntryDiff::Added { .. } if !self.added_anomalies(Path::new("")).is_empty() => {
+                Some(self.clone())
+            }
+            _ => None,
+        }
+    }
+
+    // Takes a renamed file, given the components of its path, out of
+    // the added (or removed) entries. Returns `None` if it wasn't
+    // found, and otherwise whether a whole entry was taken out: a file
+    // inside a wholly added or removed directory only lowers that
+    // directory's file count, since the directory is still one-sided.
+    fn forget_one_sided(
+        &mut self,
+        components: &[&OsStr],
+        added: bool,
+        empty: bool,
+        options: &DiffOptions,
+    ) -> Option<bool> {
+        let (entries, diff) = match self {
+            EntryDiff::Directory(entries, diff) => (entries, diff),
+            _ => return None,
+        };
+        let (first, rest) = components.split_first()?;
+        let key = Path::new(first);
+        let whole_entry = match entries.get_mut(key) {
+            Some(EntryDiff::Added { kind: EntryKind::File, .. }) if added && rest.is_empty() => true,
+            Some(EntryDiff::Removed { kind: EntryKind::File, .. }) if !added && rest.is_empty() => true,
+            Some(EntryDiff::Added { kind: EntryKind::Directory, files, .. }) if added => {
+                *files = files.saturating_sub(1);
+                false
+            }
+            Some(EntryDiff::Removed { kind: EntryKind::Directory, files }) if !added => {
+                *files = files.saturating_sub(1);
+                false
+            }
+            Some(nested @ EntryDiff::Directory(..)) => nested.forget_one_sided(rest, added, empty, options)?,
+            _ => return None,
+        };
+        if whole_entry {
+            if rest.is_empty() {
+                entries.remove(key);
+            }
+            if added {
+                diff.added -= 1;
+            } else {
+                diff.removed -= 1;
+            }
+        }
+        // A directory with the file still counts as added, and still
+        // has an empty file in it
+        if whole_entry && added && empty {
+            diff.added_empty -= 1;
+            if options.flag_added_empty {
+                diff.suspicious -= 1;
+            }
+        }
+        Some(whole_entry)
+    }
+
+    /// The number of files present on both sides that have no hash
+    /// algorithm in common, so that only their sizes (and content
+    /// flags) could be compared
+    pub fn files_without_common_hash(&self) -> u64 {
+        match self {
+            EntryDiff::Directory(entries, _) => {
+                entries.values().map(EntryDiff::files_without_common_hash).sum()
+            }
+            EntryDiff::File(diff) => diff.no_common_hash as u64,
+            EntryDiff::Symlink { .. } | EntryDiff::Special(_) | EntryDiff::KindChanged { .. } |
+            EntryDiff::Added { .. } | EntryDiff::Removed { .. } => 0,
+        }
+    }
+
+    /// Lists the files that were added or changed in content, with
+    /// their new sizes and SHA2-256 hashes, in path order: what an
+    /// incremental backup needs to copy. Needs a diff that kept its
+    /// entries, as `Database::diff` does. Fails up front with
+    /// `Error::Sha256Required` if any of those files has no plain
+    /// SHA2-256 hash, which only databases built with `--sha256`
+    /// (`Features::sha256`) record: the default `sha2` hash is
+    /// SHA2-512/256.
+    pub fn to_backup_manifest(&self) -> Result<Vec<BackupEntry>, error::Error> {
+        let mut files = Vec::new();
+        self.collect_new_files(&mut PathBuf::new(), &mut files);
+        if let Some((path, _)) = files.iter().find(|(_, file)| file.sha256.is_none()) {
+            return Err(error::Error::Sha256Required(path.clone()));
+        }
+        Ok(files.into_iter()
+           .map(|(path, file)| BackupEntry {
+               path,
+               size: file.size,
+               sha256: file.sha256.clone().expect("checked above"),
+           })
+           .collect())
+    }
+
+    fn collect_new_files<'a>(&'a self, path: &mut PathBuf, files: &mut Vec<(PathBuf, &'a NewFile)>) {
+        match self {
+            EntryDiff::Directory(entries, _) => {
+                for (key, entry) in entries.iter() {
+                    path.push(key);
+                    entry.collect_new_files(path, files);
+                    path.pop();
+                }
+            }
+            EntryDiff::File(diff) => {
+                if let Some(ref file) = diff.new_file {
+                    files.push((path.clone(), file));
+                }
+            }
+            EntryDiff::Added { new_files, .. } | EntryDiff::KindChanged { new_files, .. } => {
+                for (name, file) in new_files.iter() {
+                    // A file is listed under the empty path, which `join`
+                    // would turn into a trailing separator
+                    let full = if name.as_os_str().is_empty() { path.clone() } else { path.join(name) };
+                    files.push((full, file));
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// The paths of the files counted by `files_without_common_hash`,
+    /// relative to the root of the diff
+    pub fn paths_without_common_hash(&self) -> Vec<PathBuf> {
+        let mut paths = Vec::new();
+        self.collect_without_common_hash(&mut PathBuf::new(), &mut paths);
+        paths
+    }
+
+    fn collect_without_common_hash(&self, path: &mut PathBuf, paths: &mut Vec<PathBuf>) {
+        match self {
+            EntryDiff::Directory(entries, _) => {
+                for (key, entry) in entries.iter() {
+                    path.push(key);
+                    entry.collect_without_common_hash(path, paths);
+                    path.pop();
+                }
+            }
+            EntryDiff::File(diff) if diff.no_common_hash => paths.push(path.clone()),
+            _ => (),
+        }
+    }
+
+    /// The number of files present on both sides whose only hashes in
+    /// common are the non-cryptographic XXH64 or CRC-32, e.g. because
+    /// either database was built with `Features::fast()`. Always zero
+    /// in fast mode, which ignores the other hashes anyway.
+    pub fn files_with_weak_evidence(&self) -> u64 {
+        match self {
+            EntryDiff::Directory(entries, _) => {
+                entries.values().map(EntryDiff::files_with_weak_evidence).sum()
+            }
+            EntryDiff::File(diff) => diff.weak_evidence as u64,
+            EntryDiff::Symlink { .. } | EntryDiff::Special(_) | EntryDiff::KindChanged { .. } |
+            EntryDiff::Added { .. } | EntryDiff::Removed { .. } => 0,
+        }
+    }
+
+    /// The number of files whose tree hashes could not be compared
+    /// because the databases were built with different chunk sizes.
+    pub fn files_with_tree_chunk_mismatch(&self) -> u64 {
+        match self {
+            EntryDiff::Directory(entries, _) => {
+                entries.values().map(EntryDiff::files_with_tree_chunk_mismatch).sum()
+            }
+            EntryDiff::File(diff) => diff.tree_chunk_mismatch as u64,
+            EntryDiff::Symlink { .. } | EntryDiff::Special(_) | EntryDiff::KindChanged { .. } |
+            EntryDiff::Added { .. } | EntryDiff::Removed { .. } => 0,
+        }
+    }
+
+    /// Writes the human-readable report of the diff, as shown by
+    /// `ick diff` and `ick check`, and returns its summary.
+    pub fn report(&self, options: &DiffOptions, mut w: impl Write) -> Result<DiffSummary, error::Error> {
+        let root = options.path_prefix.as_deref().unwrap_or_else(|| Path::new("."));
+        let filtered;
+        let shown = if options.suspicious_only {
+            filtered = self.suspicious_only(options);
+            filtered.as_ref()
+        } else {
+            Some(self)
+        };
+        match shown {
+            Some(shown) if options.group_by_confidence => shown.show_diff_by_confidence(root, &mut w)?,
+            Some(shown) => shown.show_diff(root, 0, options, &mut w)?,
+            None => (),
+        }
+        let incomparable = self.paths_without_common_hash();
+        if !incomparable.is_empty() {
+            writeln!(w, "warning: {} file(s) not verified: no hash algorithm in common",
+                     incomparable.len())?;
+            for path in incomparable.iter() {
+                match options.path_prefix {
+                    Some(ref prefix) => writeln!(w, "##> {}", prefix.join(path).display())?,
+                    None => writeln!(w, "##> {}", path.display())?,
+                }
+            }
+        }
+        let weak = self.files_with_weak_evidence();
+        if weak > 0 {
+            writeln!(w, "warning: {} file(s) compared only by non-cryptographic \
+                         checksums, which can't detect deliberate tampering", weak)?;
+        }
+        let mismatched = self.files_with_tree_chunk_mismatch();
+        if mismatched > 0 {
+            writeln!(w, "warning: {} file(s) have tree hashes of different chunk sizes, \
+                         which were not compared", mismatched)?;
+        }
+        Ok(self.summarize_diff())
+    }
+
+    fn show_diff_by_confidence(&self, root: &Path, w: &mut dyn Write) -> io::Result<()> {
+        let mut files = Vec::new();
+        self.collect_classified(&mut root.to_owned(), &mut files);
+        files.sort_by_key(|(_, class, _)| *class);
+        let mut tier = None;
+        for (path, class, reasons) in files {
+            if tier != Some(class) {
+                writeln!(w, "{}:", class)?;
+                tier = Some(class);
+            }
+            writeln!(w, "  {}", path.display())?;
+            for reason in reasons {
+                writeln!(w, "  > {}", reason)?;
+            }
+        }
+        if let EntryDiff::Directory(_, diff) = self {
+            writeln!(w, "{} added, {} removed", diff.added, diff.removed)?;
+        }
+        Ok(())
+    }
+
+    // Appends every entry that changed in any way, in path order, with
+    // its tier and the reasons for a `DefiniteTamper` one
+    fn collect_classified(
+        &self,
+        path: &mut PathBuf,
+        files: &mut Vec<(PathBuf, ChangeClass, Vec<&'static str>)>,
+    ) {
+        match self {
+            EntryDiff::Directory(entries, _) => {
+                for (key, entry) in entries.iter() {
+                    path.push(key);
+                    entry.collect_classified(path, files);
+                    path.pop();
+                }
+            }
+            EntryDiff::File(diff) => {
+                let class = diff.classify();
+                if class != ChangeClass::Unchanged {
+                    files.push((path.clone(), class, diff.tamper_reasons()));
+                }
+            }
+            EntryDiff::Symlink { old_target, new_target } => {
+                if old_target != new_target {
+                    files.push((path.clone(), ChangeClass::ProbableChange, vec!["symlink target changed"]));
+                }
+            }
+            EntryDiff::Special(_) => (),
+            EntryDiff::KindChanged { was, now, .. } => {
+                files.push((path.clone(), ChangeClass::ProbableChange, vec![kind_change(*was, *now)]));
+            }
+            EntryDiff::Added { .. } => {
+                for (path, anomalies) in self.added_anomalies(path) {
+                    files.push((path, ChangeClass::DefiniteTamper, anomalies.descriptions()));
+                }
+            }
+            // Counted after the list
+            EntryDiff::Removed { .. } => (),
+        }
+    }
+
+    fn show_diff(
+        &self,
+        path: &Path,
+        depth: usize,
+        options: &DiffOptions,
+        w: &mut dyn Write,
+    ) -> io::Result<()> {
+        let palette = Palette::new(options.color == ColorMode::Always);
+        match self {
+            EntryDiff::Directory(entries, diff) => {
+                if diff.changed > 0 || diff.added > 0 || diff.removed > 0 || diff.suspicious > 0 ||
+                    (options.show_touched && diff.touched > 0)
+                {
+                    writeln!(w, "{}{}: {}, {}, {}, {} unchanged",
+                             "| ".repeat(depth),
+                             path.display(),
+                             palette.paint(diff.changed > 0, palette.changed,
+                                           format!("{} changed", diff.changed)),
+                             palette.paint(diff.added > 0, palette.added,
+                                           format!("{} added{}", diff.added,
+                                                   breakdown(diff.added_empty, diff.added_dirs))),
+                             palette.paint(diff.removed > 0, palette.removed,
+                                           format!("{} removed{}", diff.removed,
+                                                   breakdown(0, diff.removed_dirs))),
+                             diff.unchanged)?;
+                    if options.flag_added_empty && diff.added_empty > 0 {
+                        writeln!(w, "{}{}> suspicious: added empty file(s){}",
+                                 "##".repeat(depth),
+                                 palette.suspicious,
+                                 palette.reset)?;
+                    }
+                    for (key, entry) in entries.iter() {
+                        entry.show_diff(key, depth+1, options, w)?;
+                    }
+                }
+            }
+            EntryDiff::File(diff) => {
+                if diff.zeroed || diff.changed_nul || diff.changed_nonascii ||
+                    diff.changed_mode.is_some() || diff.changed_owner.is_some() ||
+                    diff.hardlink_broken.is_some() || diff.changed_sparseness.is_some() ||
+                    diff.changed_acl || diff.hash_disagreement ||
+                    diff.text_to_binary || diff.changed_utf8_validity ||
+                    diff.changed_line_endings.is_some() || diff.changed_filetype.is_some() ||
+                    diff.mtime_went_backward || diff.later_content_changed ||
+                    diff.entropy_jump ||
+                    (options.show_touched && diff.mtime_changed && !diff.changed_content)
+                {
+                    writeln!(w, "{}{}{} changed{}",
+                             "| ".repeat(depth),
+                             palette.changed,
+                             path.display(),
+                             palette.reset)?;
+                    if diff.hash_disagreement {
+                        writeln!(w, "{}{}> WARNING: hashes disagree (some match, others do not); \
+                                  possible collision attack or database corruption{}",
+                                 "##".repeat(depth),
+                                 palette.suspicious,
+                                 palette.reset)?;
+                    }
+                    for reason in diff.gained_privileges() {
+                        writeln!(w, "{}{}> suspicious: {}{}",
+                                 "##".repeat(depth), palette.suspicious, reason, palette.reset)?;
+                    }
+                    if let Some((old, new)) = diff.changed_mode {
+                        writeln!(w, "{}> mode changed from {} to {}",
+                                 "##".repeat(depth),
+                                 symbolic_mode(old),
+                                 symbolic_mode(new))?;
+                    }
+                    if let Some(((old_uid, old_gid), (new_uid, new_gid))) = diff.changed_owner {
+                        writeln!(w, "{}> owner changed from {}:{} to {}:{}",
+                                 "##".repeat(depth),
+                                 old_uid, old_gid, new_uid, new_gid)?;
+                    }
+                    match diff.changed_sparseness {
+                        Some(true) => writeln!(w, "{}> content identical, but now sparse",
+                                               "##".repeat(depth))?,
+                        Some(false) => writeln!(w, "{}> content identical, but no longer sparse",
+                                                "##".repeat(depth))?,
+                        None => (),
+                    }
+                    if let Some(links) = diff.hardlink_broken {
+                        writeln!(w, "{}> hard link broken (was one of {} links)",
+                                 "##".repeat(depth),
+                                 links)?;
+                    }
+                    if diff.changed_acl {
+                        writeln!(w, "{}> ACL changed: {}",
+                                 "##".repeat(depth),
+                                 diff.acl_changes.join(", "))?;
+                    }
+                    if let Some((old, new)) = diff.changed_line_endings {
+                        writeln!(w, "{}> content changed (line endings only?): {} -> {}",
+                                 "##".repeat(depth),
+                                 old,
+                                 new)?;
+                    }
+                    if diff.later_content_changed {
+                        writeln!(w, "{}> first block unchanged, but later content differs",
+                                 "##".repeat(depth))?;
+                    }
+                    if options.show_touched && diff.mtime_changed && !diff.changed_content {
+                        writeln!(w, "{}> content identical, timestamp changed",
+                                 "##".repeat(depth))?;
+                    }
+                    if diff.zeroed {
+                        writeln!(w, "{}{}> suspicious: file was truncated{}",
+                                 "##".repeat(depth),
+                                 palette.suspicious,
+                                 palette.reset)?;
+                        if let Some(ref preview) = diff.original_preview {
+                            writeln!(w, "{}> original began with: {}",
+                                     "##".repeat(depth),
+                                     printable(preview))?;
+                        }
+                    }
+                    if diff.changed_nul {
+                        writeln!(w, "{}{}> suspicious: original had no NUL bytes, but now does{}",
+                                 "##".repeat(depth),
+                                 palette.suspicious,
+                                 palette.reset)?;
+                    }
+                    if diff.nonascii_flagged {
+                        writeln!(w, "{}{}> suspicious: non-ASCII bytes were added or removed{}",
+                                 "##".repeat(depth),
+                                 palette.suspicious,
+                                 palette.reset)?;
+                    } else if diff.changed_nonascii {
+                        writeln!(w, "{}> non-ASCII bytes were added or removed",
+                                 "##".repeat(depth))?;
+                    }
+                    if diff.text_to_binary {
+                        writeln!(w, "{}{}> suspicious: text file became binary{}",
+                                 "##".repeat(depth),
+                                 palette.suspicious,
+                                 palette.reset)?;
+                    } else if diff.changed_utf8_validity {
+                        writeln!(w, "{}{}> suspicious: was valid UTF-8, but no longer is{}",
+                                 "##".repeat(depth),
+                                 palette.suspicious,
+                                 palette.reset)?;
+                    }
+                    if diff.mtime_went_backward {
+                        writeln!(w, "{}{}> suspicious: mtime moved backward (timestomping?){}",
+                                 "##".repeat(depth),
+                                 palette.suspicious,
+                                 palette.reset)?;
+                    }
+                    if diff.entropy_jump {
+                        writeln!(w, "{}{}> suspicious: entropy jumped to near maximum (encrypted?){}",
+                                 "##".repeat(depth),
+                                 palette.suspicious,
+                                 palette.reset)?;
+                    }
+                    if let Some((old, new)) = diff.changed_filetype {
+                        writeln!(w, "{}{}> suspicious: file type changed from {} to {}{}",
+                                 "##".repeat(depth),
+                                 palette.suspicious,
+                                 old,
+                                 new,
+                                 palette.reset)?;
+                    }
+                }
+            }
+            EntryDiff::Symlink { old_target, new_target } => {
+                if old_target != new_target {
+                    writeln!(w, "{}{}{} changed{}",
+                             "| ".repeat(depth),
+                             palette.changed,
+                             path.display(),
+                             palette.reset)?;
+                    writeln!(w, "{}> symlink target changed from {} to {}",
+                             "##".repeat(depth),
+                             old_target.display(),
+                             new_target.display())?;
+                }
+            }
+            EntryDiff::Special(_) => (),
+            EntryDiff::KindChanged { was, now, .. } => {
+                writeln!(w, "{}{}kind changed at {}: {} -> {}{}",
+                         "| ".repeat(depth),
+                         palette.changed,
+                         path.display(),
+                         was,
+                         now,
+                         palette.reset)?;
+                if EntryKind::is_suspicious_change(*was, *now) {
+                    writeln!(w, "{}{}> suspicious: regular file swapped with a special file{}",
+                             "##".repeat(depth),
+                             palette.suspicious,
+                             palette.reset)?;
+                }
+            }
+            EntryDiff::Added { kind, files, anomalies, .. } => {
+                writeln!(w, "{}{}+ {}{}",
+                         "| ".repeat(depth),
+                         palette.added,
+                         listed_name(path, *kind, *files, !anomalies.is_empty()),
+                         palette.reset)?;
+                if !anomalies.is_empty() {
+                    writeln!(w, "{}{}> suspicious: name has {}{}",
+                             "##".repeat(depth),
+                             palette.suspicious,
+                             anomalies.descriptions().join(", "),
+                             palette.reset)?;
+                }
+                for (nested, anomalies) in self.added_anomalies(path) {
+                    if nested != path {
+                        writeln!(w, "{}{}> suspicious: {:?} has {}{}",
+                                 "##".repeat(depth),
+                                 palette.suspicious,
+                                 nested,
+                                 anomalies.descriptions().join(", "),
+                                 palette.reset)?;
+                    }
+                }
+            }
+            EntryDiff::Removed { kind, files } => {
+                writeln!(w, "{}{}- {}{}",
+                         "| ".repeat(depth),
+                         palette.removed,
+                         listed_name(path, *kind, *files, false),
+                         palette.reset)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn summarize_diff(&self) -> DiffSummary {
+        match self {
+            EntryDiff::Directory(entries, diff) => {
+                let initial =
+                    if diff.suspicious > 0 {
+                        DiffSummary::Suspicious
+                    } else if diff.changed > 0 || diff.added > 0 || diff.removed > 0 {
+                        DiffSummary::Changes
+                    } else {
+                        DiffSummary::NoChanges
+                    };
+                entries
+                    .values()
+                    .map(|x| x.summarize_diff())
+                    .fold(initial, |acc, x| acc.meet(x))
+            }
+            EntryDiff::File(diff) => {
+                if diff.is_suspicious() {
+                    DiffSummary::Suspicious
+                } else if diff.is_changed() {
+                    DiffSummary::Changes
+                } else {
+                    DiffSummary::NoChanges
+                }
+            }
+            EntryDiff::Symlink { old_target, new_target } => {
+                if old_target != new_target { DiffSummary::Changes } else { DiffSummary::NoChanges }
+            }
+            EntryDiff::Special(_) => DiffSummary::NoChanges,
+            EntryDiff::Added { .. } if !self.added_anomalies(Path::new("")).is_empty() => {
+                DiffSummary::Suspicious
+            }
+            EntryDiff::Added { .. } | EntryDiff::Removed { .. } => DiffSummary::Changes,
+            EntryDiff::KindChanged { was, now, .. } => {
+                if EntryKind::is_suspicious_change(*was, *now) {
+                    DiffSummary::Suspicious
+                } else {
+                    DiffSummary::Changes
+                }
+            }
+        }
+    }
+}
+
+impl DiffSummary {
+    fn meet(self, other: DiffSummary) -> DiffSummary {
+        if self == DiffSummary::Suspicious || other == DiffSummary::Suspicious {
+            DiffSummary::Suspicious
+        } else if self == DiffSummary::Changes || other == DiffSummary::Changes {
+            DiffSummary::Changes
+        } else {
+            DiffSummary::NoChanges
+        }
+    }
+}
+
+impl Entry {
+    fn diff(&self, other: &Entry, options: &DiffOptions) -> EntryDiff {
+        self.diff_visit(other, options, &mut PathBuf::new(), true, &mut |_, _| ())
+    }
+
+    // Computes the diff bottom-up, calling the visitor on each node
+    // after its children. When keep is false, child diffs are
+    // dropped after being visited, so directory nodes only carry
+    // their counters.
+    fn diff_visit<F>(
+        &self,
+        other: &Entry,
+        options: &DiffOptions,
+        path: &mut PathBuf,
+        keep: bool,
+        visitor: &mut F,
+    ) -> EntryDiff
+    where
+        F: FnMut(&Path, &EntryDiff),
+    {
+        let diff = match (self, other) {
+            (Entry::Directory(old), Entry::Directory(new)) => {
+                let mut entries = BTreeMap::default();
+                let mut added = 0;
+                let mut removed = 0;
+                let mut changed = 0;
+                let mut unchanged = 0;
+                let mut added_empty = 0;
+                let mut suspicious = 0;
+                let mut touched = 0;
+                let mut added_dirs = 0;
+                let mut removed_dirs = 0;
+
+                let old = normalized_entries(old, options.normalize_paths);
+                let new = normalized_entries(new, options.normalize_paths);
+                let mut old_iter = old.iter().map(|(key, value)| (key.as_ref(), *value));
+                let mut new_iter = new.iter().map(|(key, value)| (key.as_ref(), *value));
+                let mut old_entry = old_iter.next();
+                let mut new_entry = new_iter.next();
+                while old_entry.is_some() && new_entry.is_some() {
+                    let (old_key, old_value) = old_entry.unwrap();
+                    let (new_key, new_value) = new_entry.unwrap();
+                    match old_key.cmp(new_key) {
+                        Ordering::Less => {
+                            if options.counts_one_sided(&path.join(old_key)) {
+                                removed += 1;
+                                removed_dirs += old_value.is_dir() as u64;
+                                if keep {
+                                    entries.insert(old_key.to_owned(), old_value.removed());
+                                }
+                            }
+                            old_entry = old_iter.next();
+                        }
+                        Ordering::Greater => {
+                            if options.counts_one_sided(&path.join(new_key)) {
+                                let empty = new_value.has_empty_file(&path.join(new_key), options) as u64;
+                                added += 1;
+                                added_dirs += new_value.is_dir() as u64;
+                                added_empty += empty;
+                                if options.flag_added_empty {
+                                    suspicious += empty;
+                                }
+                                suspicious += new_value.count_anomalies(new_key);
+                                if keep {
+                                    entries.insert(new_key.to_owned(), new_value.added(new_key));
+                                }
+                            }
+                            new_entry = new_iter.next();
+                        }
+                        Ordering::Equal if options.is_ignored(&path.join(old_key)) => {
+                            old_value.for_each_file(old_key, &mut |_, _| unchanged += 1);
+                            old_entry = old_iter.next();
+                            new_entry = new_iter.next();
+                        }
+                        Ordering::Equal => {
+                            path.push(old_key);
+                            let diff = old_value.diff_visit(new_value, options, path, keep, visitor);
+                            path.pop();
+                            match diff {
+                                EntryDiff::Directory(_, ref stats) => {
+                                    added += stats.added;
+                                    removed += stats.removed;
+                                    changed += stats.changed;
+                                    unchanged += stats.unchanged;
+                                    added_empty += stats.added_empty;
+                                    suspicious += stats.suspicious;
+                                    touched += stats.touched;
+                                    added_dirs += stats.added_dirs;
+                                    removed_dirs += stats.removed_dirs;
+                                }
+                                EntryDiff::File(ref stats) => {
+                                    if stats.is_changed() {
+                                        changed += 1;
+                                    } else {
+                                        unchanged += 1;
+                                        touched += stats.mtime_changed as u64;
+                                    }
+                                    if stats.is_suspicious() {
+                                        suspicious += 1;
+                                    }
+                                }
+                                EntryDiff::Symlink { ref old_target, ref new_target } => {
+                                    if old_target != new_target {
+                                        changed += 1;
+                                    } else {
+                                        unchanged += 1;
+                                    }
+                                }
+                                EntryDiff::Special(_) => {
+                                    unchanged += 1;
+                                }
+                                EntryDiff::KindChanged { was, now, .. } => {
+                                    changed += 1;
+                                    if EntryKind::is_suspicious_change(was, now) {
+                                        suspicious += 1;
+                                    }
+                                }
+                                EntryDiff::Added { .. } | EntryDiff::Removed { .. } => {
+                                    unreachable!("entries on both sides are neither added nor removed")
+                                }
+                            }
+                            if keep {
+                                entries.insert(old_key.to_owned(), diff);
+                            }
+                            old_entry = old_iter.next();
+                            new_entry = new_iter.next();
+                        }
+                    }
+                }
+                // The loop exits with the current entry of the longer
+                // side still pending, so it must be counted here too
+                for (key, value) in old_entry.into_iter().chain(old_iter) {
+                    if options.counts_one_sided(&path.join(key)) {
+                        removed += 1;
+                        removed_dirs += value.is_dir() as u64;
+                        if keep {
+                            entries.insert(key.to_owned(), value.removed());
+                        }
+                    }
+                }
+                for (key, value) in new_entry.into_iter().chain(new_iter) {
+                    if options.counts_one_sided(&path.join(key)) {
+                        let empty = value.has_empty_file(&path.join(key), options) as u64;
+                        added += 1;
+                        added_dirs += value.is_dir() as u64;
+                        added_empty += empty;
+                        if options.flag_added_empty {
+                            suspicious += empty;
+                        }
+                        suspicious += value.count_anomalies(key);
+                        if keep {
+                            entries.insert(key.to_owned(), value.added(key));
+                        }
+                    }
+                }
+                EntryDiff::Directory(
+                    entries,
+                    DirectoryDiff {
+                        added, removed, changed, unchanged, added_empty, suspicious, touched,
+                        added_dirs, removed_dirs,
+                    })
+            },
+            (Entry::File(old), Entry::File(new)) => EntryDiff::File(old.diff(new, options)),
+            (Entry::Symlink { target: old }, Entry::Symlink { target: new }) => {
+                EntryDiff::Symlink { old_target: old.clone(), new_target: new.clone() }
+            }
+            (Entry::Special(old), Entry::Special(new)) if old == new => EntryDiff::Special(*old),
+            (old, new) => {
+                EntryDiff::KindChanged { was: old.kind(), now: new.kind(), new_files: new.new_files() }
+            }
+        };
+        visitor(path, &diff);
+        diff
+    }
+}
+
+// An added or removed entry as listed by `show_diff`, e.g. "docs/ (3
+// files)". Names with anomalies are quoted and escaped, as they may
+// hide in plain text.
+fn listed_name(path: &Path, kind: EntryKind, files: u64, quote: bool) -> String {
+    let name = if quote { format!("{:?}", path) } else { path.display().to_string() };
+    match (kind, files) {
+        (EntryKind::Directory, 1) => format!("{}/ (1 file)", name),
+        (EntryKind::Directory, files) => format!("{}/ ({} files)", name, files),
+        _ => name,
+    }
+}
+
+// ANSI escapes for `show_diff`, all empty when not coloring
+struct Palette {
+    changed: &'static str,
+    added: &'static str,
+    removed: &'static str,
+    suspicious: &'static str,
+    reset: &'static str,
+}
+
+impl Palette {
+    fn new(color: bool) -> Palette {
+        if color {
+            Palette {
+                changed: "\x1b[33m",
+                added: "\x1b[32m",
+                removed: "\x1b[31m",
+                suspicious: "\x1b[1;31m",
+                reset: "\x1b[0m",
+            }
+        } else {
+            Palette { changed: "", added: "", removed: "", suspicious: "", reset: "" }
+        }
+    }
+
+    // The text in the given color, if `when` holds
+    fn paint(&self, when: bool, color: &str, text: String) -> String {
+        if when && !color.is_empty() {
+            format!("{}{}{}", color, text, self.reset)
+        } else {
+            text
+        }
+    }
+}
+
+// How many of the added or removed entries were empty files and
+// directories, e.g. " (1 empty, 2 directories)", or nothing if none were
+fn breakdown(empty: u64, dirs: u64) -> String {
+    let mut parts = Vec::new();
+    if empty > 0 {
+        parts.push(format!("{} empty", empty));
+    }
+    match dirs {
+        0 => (),
+        1 => parts.push("1 directory".to_owned()),
+        dirs => parts.push(format!("{} directories", dirs)),
+    }
+    if parts.is_empty() {
+        String::new()
+    } else {
+        format!(" ({})", parts.join(", "))
+    }
+}
+
+// The entries of a directory in key order, with each key normalized to
+// `form`, if given. Names that aren't UTF-8 are left alone. If two names
+// normalize to the same key, the one already in normal form keeps it
+// and the other is matched under its original name.
+fn normalized_entries(
+    entries: &BTreeMap<PathBuf, Entry>,
+    form: Option<PathNormalization>,
+) -> Vec<(Cow<'_, Path>, &Entry)> {
+    let form = match form {
+        Some(form) => form,
+        None => return entries.iter().map(|(key, value)| (Cow::Borrowed(key.as_path()), value)).collect(),
+    };
+    let normalize = |key: &Path| -> Option<PathBuf> {
+        let name = key.to_str()?;
+        Some(PathBuf::from(match form {
+            PathNormalization::Nfc => name.nfc().collect::<String>(),
+            PathNormalization::Nfd => name.nfd().collect::<String>(),
+        }))
+    };
+    let mut normalized = BTreeMap::new();
+    let mut pending = Vec::new();
+    for (key, value) in entries.iter() {
+        match normalize(key) {
+            Some(ref normal) if normal != key => pending.push((normal.clone(), key, value)),
+            _ => { normalized.insert(Cow::Borrowed(key.as_path()), value); }
+        }
+    }
+    for (normal, key, value) in pending {
+        if normalized.contains_key(normal.as_path()) {
+            normalized.insert(Cow::Borrowed(key.as_path()), value);
+        } else {
+            normalized.insert(Cow::Owned(normal), value);
+        }
+    }
+    normalized.into_iter().collect()
+}
+
+const SEP : u8 = 0x0a; // separator \n (byte 0x0a) used in JSON encoding
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b]; // Leading bytes of every database file
+
+pub const DEFAULT_COMPRESSION_LEVEL: u32 = 6; // As `flate2::Compression::default()`
+pub const MAX_COMPRESSION_LEVEL: u32 = 9;
+
+// The hash that files are looked up by, given the algorithms recorded:
+// the first of SHA2-512/256, BLAKE3, SHA2-512, SHA2-256, BLAKE2b and XXH64
+fn index_hash(features: Features) -> fn(&Metrics) -> &Option<HashSum> {
+    if features.sha2 {
+        |metrics| &metrics.sha2
+    } else if features.blake3 {
+        |metrics| &metrics.blake3
+    } else if features.sha512 {
+        |metrics| &metrics.sha512
+    } else if features.sha256 {
+        |metrics| &metrics.sha256
+    } else if features.blake2b {
+        |metrics| &metrics.blake2b
+    } else {
+        |metrics| &metrics.xxh64
+    }
+}
+
+// Computes the key under which a walked file is stored in the database
+fn short_path<'a>(path: &'a Path, root: &Path) -> Result<&'a Path, std::path::StripPrefixError> {
+    if path == root {
+        Ok(Path::new(path.file_name().expect("unreachable")))
+    } else {
+        path.strip_prefix(root)
+    }
+}
+
+// The path to record for a directory met during the walk, or `None` for
+// the root itself
+fn directory_path<'a>(path: &'a Path, root: &Path) -> Result<Option<&'a Path>, std::path::StripPrefixError> {
+    let relative = path.strip_prefix(root)?;
+    Ok(if relative == Path::new("") { None } else { Some(relative) })
+}
+
+// The name recorded for a database root. `.` and other paths without a
+// final component fall back to the name of the directory they resolve to.
+fn root_name(root: &Path) -> Option<String> {
+    match root.file_name() {
+        Some(name) => Some(name.to_string_lossy().into_owned()),
+        None => fs::canonicalize(root).ok()?
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned()),
+    }
+}
+
+// Walks the tree under `start`, relative to the root, hashing each
+// file, and passes every entry found to `visit` with its path relative
+// to the root. Directories are passed as empty ones. With several
+// threads, entries are collected first and passed in path order.
+pub(crate) fn walk_tree(
+root: &Path,
+start: &Path,
+options: &BuildOptions,
+callback: Option<ProgressCallback>,
+previous: Option<Arc<Database>>,
+visit: &mut dyn FnMut(&Path, Entry) -> Result<(), error::Error>,
+) -> Result<BuildStats, error::Error> {
+let root = options.scan_root(root);
+    let hasher = Hasher { previous, ..options.hasher()? };
+    let callback = callback.map(|callback| Arc::new(Mutex::new(callback)));
+    let threads = options.threads;
+    let stats = Arc::new(Mutex::new(BuildStats::default()));
+    let start_time_ns = time::precise_time_ns();
+    let progress = if cfg!(feature = "progress") && options.progress {
+        Some(Arc::new(Mutex::new(InteractiveProgress::new(start_time_ns))))
+    } else {
+        None
+    };
+
+    let parallel = threads > 1;
+    if parallel {
+        // Workers only hash, sending their results (or the error that
+        // stopped them) here, so that the tree is filled in path order
+        let (sender, receiver) = mpsc::channel();
+        options.subtree_walk_builder(&root, start)?.threads(threads).build_parallel().run(|| {
+            let stats = stats.clone();
+            let sender = sender.clone();
+            let root = root.clone();
+            let hasher = hasher.clone();
+            let progress = progress.clone();
+            let callback = callback.clone();
+            let modified_before = options.modified_before;
+            Box::new(move |entry| {
+                let result = entry.map_err(error::Error::from).and_then(|entry| {
+                    if entry.file_type().is_some_and(|t| t.is_dir()) {
+                        stats.lock().unwrap().directories += 1;
+                        let path = directory_path(entry.path(), &root)?;
+                        return Ok(path.map(|path| (path.to_owned(), Entry::default())));
+                    }
+                    let symlink = entry.file_type().is_some_and(|t| t.is_symlink());
+                    let special = entry.file_type().and_then(SpecialKind::of);
+                    let file = entry.file_type().is_some_and(|t| t.is_file());
+                    if !symlink && special.is_none() && !file {
+                        return Ok(None);
+                    }
+                    if modified_after(&entry, modified_before) {
+                        stats.lock().unwrap().skipped += 1;
+                        return Ok(None);
+                    }
+                    let short_path = short_path(entry.path(), &root)?;
+                    if symlink {
+                        let target = fs::read_link(entry.path())?;
+                        return Ok(Some((short_path.to_owned(), Entry::Symlink { target })));
+                    }
+                    if let Some(kind) = special {
+                        return Ok(Some((short_path.to_owned(), Entry::Special(kind))));
+                    }
+                    let metrics = hasher.compute(entry.path(), short_path)?;
+                    let mut stats = stats.lock().unwrap();
+                    stats.files += 1;
+                    stats.bytes += metrics.size;
+                    stats.allocated += metrics.allocated.unwrap_or(0);
+                    if let Some(ref progress) = progress {
+                        progress.lock().unwrap().file_done(short_path, stats.bytes);
+                    }
+                    if let Some(ref callback) = callback {
+                        callback.lock().unwrap().file_done(short_path, stats.bytes);
+                    }
+                    Ok(Some((short_path.to_owned(), Entry::File(metrics))))
+                });
+                let state = if result.is_ok() { WalkState::Continue } else { WalkState::Quit };
+                if let Some(result) = result.transpose() {
+                    sender.send(result).expect("receiver outlives the walk");
+                }
+                state
+            })
+        });
+        drop(sender);
+        let mut files = receiver.into_iter().collect::<Result<Vec<_>, _>>()?;
+        files.sort_by(|(a, _), (b, _)| a.cmp(b));
+        for (path, entry) in files {
+            visit(&path, entry)?;
+        }
+    } else {
+        let stats = &mut *stats.lock().unwrap();
+        for entry in options.subtree_walk_builder(&root, start)?.build() {
+            let entry = entry?;
+            if entry.file_type().is_some_and(|t| t.is_dir()) {
+                stats.directories += 1;
+                if let Some(path) = directory_path(entry.path(), &root)? {
+                    visit(path, Entry::default())?;
+                }
+            } else if entry.file_type().is_some_and(|t| t.is_symlink()) {
+                if modified_after(&entry, options.modified_before) {
+                    stats.skipped += 1;
+                    continue;
+                }
+                let short_path = short_path(entry.path(), &root)?;
+                let target = fs::read_link(entry.path())?;
+                visit(short_path, Entry::Symlink { target })?;
+            } else if let Some(kind) = entry.file_type().and_then(SpecialKind::of) {
+                // Never opened: opening a FIFO would block until a writer came
+                if modified_after(&entry, options.modified_before) {
+                    stats.skipped += 1;
+                    continue;
+                }
+                let short_path = short_path(entry.path(), &root)?;
+                visit(short_path, Entry::Special(kind))?;
+            } else if entry.file_type().is_some_and(|t| t.is_file()) {
+                if modified_after(&entry, options.modified_before) {
+                    stats.skipped += 1;
+                    continue;
+                }
+                let short_path = short_path(entry.path(), &root)?;
+                let metrics = hasher.compute(entry.path(), short_path)?;
+                stats.files += 1;
+                stats.bytes += metrics.size;
+                stats.allocated += metrics.allocated.unwrap_or(0);
+                if let Some(ref progress) = progress {
+                    progress.lock().unwrap().file_done(short_path, stats.bytes);
+                }
+                if let Some(ref callback) = callback {
+                    callback.lock().unwrap().file_done(short_path, stats.bytes);
+                }
+                visit(short_path, Entry::File(metrics))?;
+            }
+        }
+    }
+    let stop_time_ns = time::precise_time_ns();
+    if let Some(progress) = progress {
+        progress.lock().unwrap().finish();
+    }
+    let mut stats = *stats.lock().unwrap();
+    stats.elapsed = Duration::from_nanos(stop_time_ns - start_time_ns);
+    Ok(stats)
+}
+
+fn parse_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len()).step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(unix)]
+fn bytes_to_path(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    PathBuf::from(OsStr::from_bytes(bytes))
+}
+
+#[cfg(not(unix))]
+fn bytes_to_path(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
+
+// A path as raw bytes, with `/` as the separator
+#[cfg(unix)]
+pub(crate) fn path_to_bytes(path: &Path) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes().to_vec()
+}
+
+#[cfg(not(unix))]
+pub(crate) fn path_to_bytes(path: &Path) -> Vec<u8> {
+    path.to_string_lossy().replace('\\', "/").into_bytes()
+}
+
+// Encodes a name for an mtree spec as libarchive does, with every
+// byte that is not printable ASCII, or is a space, `#`, `=` or `\`, as
+// a backslash and three octal digits
+fn vis_mtree(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len());
+    for &byte in bytes {
+        if byte > b' ' && byte < 0x7f && !matches!(byte, b'#' | b'=' | b'\\') {
+            encoded.push(char::from(byte));
+        } else {
+            encoded.push_str(&format!("\\{:03o}", byte));
+        }
+    }
+    encoded
+}
+
+// Decodes a name from an mtree spec: octal escapes as written by
+// `vis_mtree`, and the C-style `\\`, `\s` (space), `\t`, `\n` and `\r`
+fn unvis_mtree(encoded: &[u8]) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(encoded.len());
+    let mut bytes = encoded.iter().copied();
+    while let Some(byte) = bytes.next() {
+        if byte != b'\\' {
+            decoded.push(byte);
+            continue;
+        }
+        decoded.push(match bytes.next()? {
+            b'\\' => b'\\',
+            b's' => b' ',
+            b't' => b'\t',
+            b'n' => b'\n',
+            b'r' => b'\r',
+            digit @ b'0'..=b'7' => {
+                let mut value = u32::from(digit - b'0');
+                for _ in 0..2 {
+                    match bytes.next()? {
+                        digit @ b'0'..=b'7' => value = value * 8 + u32::from(digit - b'0'),
+                        _ => return None,
+                    }
+                }
+                u8::try_from(value).ok()?
+            }
+            _ => return None,
+        });
+    }
+    Some(decoded)
+}
+
+// The value of an mtree `type` keyword for a special file
+fn mtree_special_type(kind: SpecialKind) -> &'static str {
+    match kind {
+        SpecialKind::Fifo => "fifo",
+        SpecialKind::Socket => "socket",
+        SpecialKind::BlockDevice => "block",
+        SpecialKind::CharDevice => "char",
+    }
+}
+
+impl ManifestAlgorithm {
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            ManifestAlgorithm::Sha256 => "sha2-256",
+            ManifestAlgorithm::Sha512 => "sha2-512",
+        }
+    }
+}
+
+// Writes a line of `sha256sum` output, escaping the name if it has a
+// newline, carriage return or backslash
+fn write_manifest_line(w: &mut impl Write, hash: &HashSum, path: &Path) -> io::Result<()> {
+    let name = path_to_bytes(path);
+    if name.iter().any(|byte| matches!(byte, b'\\' | b'\n' | b'\r')) {
+        write!(w, "\\{}  ", hash)?;
+        for &byte in name.iter() {
+            match byte {
+                b'\\' => w.write_all(b"\\\\")?,
+                b'\n' => w.write_all(b"\\n")?,
+                b'\r' => w.write_all(b"\\r")?,
+                byte => w.write_all(&[byte])?,
+            }
+        }
+    } else {
+        write!(w, "{}  ", hash)?;
+        w.write_all(&name)?;
+    }
+    w.write_all(b"\n")
+}
+
+// Writes a line of hashdeep output. The name comes last, so it needs
+// no escaping for commas.
+fn write_hashdeep_line(w: &mut impl Write, size: u64, hash: &HashSum, path: &Path) -> io::Result<()> {
+    write!(w, "{},{},", size, hash)?;
+    w.write_all(&path_to_bytes(path))?;
+    w.write_all(b"\n")
+}
+
+// Undoes the escaping of a name in `sha256sum` output, or returns
+// `None` for an unknown escape
+fn unescape_sha256sum_name(name: &[u8]) -> Option<Vec<u8>> {
+    let mut bytes = name.iter();
+    let mut unescaped = Vec::with_capacity(name.len());
+    while let Some(&byte) = bytes.next() {
+        unescaped.push(match byte {
+            b'\\' => match bytes.next()? {
+                b'\\' => b'\\',
+                b'n' => b'\n',
+                b'r' => b'\r',
+                _ => return None,
+            },
+            byte => byte,
+        });
+    }
+    Some(unescaped)
+}
+
+// Drops `.` components so that `./a/b` and `a/b` name the same entry
+fn normalize_path(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|c| *c != Component::CurDir)
+        .collect()
+}
+
+// The key of a file named in an imported manifest, which must stay
+// within the root: absolute paths, `..` components and empty names
+// are rejected
+fn manifest_path(name: &[u8]) -> Result<PathBuf, error::Error> {
+    let path = normalize_path(&bytes_to_path(name));
+    if path.has_root() || path == Path::new("") ||
+        path.components().any(|c| c == Component::ParentDir)
+    {
+        return Err(error::Error::ParseError);
+    }
+    Ok(path)
+}
+
+impl Database {
+    pub(crate) fn insert(
+        &mut self,
+        path: &Path,
+        entry: Entry,
+        fold_case: bool,
+    ) -> Result<(), error::Error> {
+        if self.0.insert(path, entry, fold_case) {
+            Ok(())
+        } else {
+            Err(error::Error::DuplicatePath(path.to_owned()))
+        }
+    }
+
+    pub(crate) fn insert_directory(&mut self, path: &Path, fold_case: bool) -> Result<(), error::Error> {
+        if self.0.insert_directory(path, fold_case) {
+            Ok(())
+        } else {
+            Err(error::Error::DuplicatePath(path.to_owned()))
+        }
+    }
+
+    pub fn lookup(&self, path: &Path) -> Option<&Entry> {
+        self.0.lookup(path)
+    }
+
+    /// Returns true if the database has an entry (file or directory)
+    /// at `path`, relative to the database root. A leading `./` and
+    /// trailing slash are ignored.
+    pub fn contains<P: AsRef<Path>>(&self, path: P) -> bool {
+        self.0.lookup(&normalize_path(path.as_ref())).is_some()
+    }
+
+    pub fn contains_dir<P: AsRef<Path>>(&self, path: P) -> bool {
+        matches!(self.0.lookup(&normalize_path(path.as_ref())), Some(Entry::Directory(_)))
+    }
+
+    pub fn contains_file<P: AsRef<Path>>(&self, path: P) -> bool {
+        matches!(self.0.lookup(&normalize_path(path.as_ref())), Some(Entry::File(_)))
+    }
+
+    pub(crate) fn for_each_file<F>(&self, mut f: F)
+    where
+        F: FnMut(&Path, &Metrics),
+    {
+        self.0.for_each_file(Path::new(""), &mut f)
+    }
+
+    /// Returns the total size of the files under each directory,
+    /// recursively, like `du`. The root is listed under the empty path.
+    pub fn directory_sizes(&self) -> BTreeMap<PathBuf, u64> {
+        let mut sizes = BTreeMap::new();
+        self.0.directory_sizes(Path::new(""), &mut sizes);
+        sizes
+    }
+
+    /// Maps the content hash of every file to the paths of all files
+    /// with that content, to find duplicates or look up a file's
+    /// contents regardless of where it is stored. Only one algorithm is
+    /// used, the first recorded of SHA2-512/256, BLAKE3, SHA2-512,
+    /// SHA2-256, BLAKE2b and XXH64; files without it, and files whose
+    /// hashes are bound to their paths, are left out. The index is
+    /// computed afresh on every call, so callers should keep it around.
+    pub fn build_hash_index(&self) -> BTreeMap<HashSum, Vec<PathBuf>> {
+        let hash = index_hash(self.features());
+        let mut index: BTreeMap<HashSum, Vec<PathBuf>> = BTreeMap::new();
+        self.0.for_each_file(Path::new(""), &mut |path, metrics| {
+            if let (Some(sum), false) = (hash(metrics), metrics.path_bound) {
+                index.entry(sum.clone()).or_default().push(path.to_owned());
+            }
+        });
+        index
+    }
+
+    /// Checks that every stored hash has the length its algorithm
+    /// produces. `load_json` runs this on every database it loads.
+    pub fn check_hash_lengths(&self) -> Result<(), error::Error> {
+        let mut result = Ok(());
+        self.for_each_file(|path, metrics| {
+            if result.is_ok() {
+                result = metrics.check_hash_lengths(path);
+            }
+        });
+        result
+    }
+
+    /// Renders the tree as nested JSON objects keyed by file name,
+    /// with hex-encoded hashes. Unlike `dump_json`, this is intended
+    /// for consumption by other tools and cannot be loaded back.
+    pub fn to_human_json(&self) -> serde_json::Value {
+        self.0.to_human_json()
+    }
+
+    pub fn diff(&self, other: &Database) -> EntryDiff {
+        self.diff_with_options(other, &DiffOptions::default())
+    }
+
+    pub fn diff_with_options(&self, other: &Database, options: &DiffOptions) -> EntryDiff {
+        self.0.diff(&other.0, options)
+    }
+
+    /// Fails if a file present in both databases had its hashes
+    /// truncated to different lengths, or computed over decompressed
+    /// contents or bound to its path on one side only. Such hashes
+    /// never match, so diffing them would report every file as changed.
+    /// `check` and `diff_files` run this before diffing; `diff` and
+    /// its variants don't, and report such files as changed.
+    pub fn ensure_comparable(&self, other: &Database) -> Result<(), error::Error> {
+        let mut result = Ok(());
+        self.0.for_each_file(Path::new(""), &mut |path, old| {
+            if result.is_ok() {
+                if let Some(Entry::File(new)) = other.0.lookup(path) {
+                    result = old.ensure_comparable(new, path);
+                }
+            }
+        });
+        result
+    }
+
+    /// Compares only the directory at `path` (relative to the database
+    /// root) in both databases. Paths in the diff are relative to that
+    /// directory, while ignore globs still match from the root. Fails
+    /// with `Error::NotInDatabase` if either database lacks the path,
+    /// and with `Error::NotADirectory` if it is anything else than a
+    /// directory on either side.
+    pub fn diff_subtree(&self, other: &Database, path: impl AsRef<Path>) -> Result<EntryDiff, error::Error> {
+        self.diff_subtree_with_options(other, path, &DiffOptions::default())
+    }
+
+    pub fn diff_subtree_with_options(
+        &self,
+        other: &Database,
+        path: impl AsRef<Path>,
+        options: &DiffOptions,
+    ) -> Result<EntryDiff, error::Error> {
+        let mut path = normalize_path(path.as_ref());
+        let old = self.subtree(&path)?;
+        let new = other.subtree(&path)?;
+        self.ensure_comparable(other)?;
+        Ok(old.diff_visit(new, options, &mut path, true, &mut |_, _| ()))
+    }
+
+    fn subtree(&self, path: &PathBuf) -> Result<&Entry, error::Error> {
+        match self.0.lookup(path) {
+            Some(entry @ Entry::Directory(_)) => Ok(entry),
+            Some(_) => Err(error::Error::NotADirectory(path.to_owned())),
+            None => Err(error::Error::NotInDatabase(path.to_owned())),
+        }
+    }
+
+    pub fn diff_ignoring(&self, other: &Database, ignore: &GlobSet) -> EntryDiff {
+        let options = DiffOptions { ignore: Some(ignore.clone()), ..DiffOptions::default() };
+        self.diff_with_options(other, &options)
+    }
+
+    /// Computes the diff against `other` without building the full
+    /// `EntryDiff` tree, instead passing each node to `visitor` as
+    /// soon as it has been computed. Nodes are visited children
+    /// first; paths are relative to the database root, which is
+    /// visited last with an empty path. Directory nodes carry their
+    /// aggregate counters, but not their children.
+    pub fn diff_visit<F>(&self, other: &Database, options: &DiffOptions, mut visitor: F)
+    where
+        F: FnMut(&Path, &EntryDiff),
+    {
+        self.0.diff_visit(&other.0, options, &mut PathBuf::new(), false, &mut visitor);
+    }
+
+    /// Compares the database against a manifest of expected SHA-256
+    /// sums (hex-encoded, keyed by path relative to the database
+    /// root), as published by e.g. `sha256sum`. The database must
+    /// have been built with the `sha256` feature.
+    pub fn verify_against_expected(
+        &self,
+        expected: &BTreeMap<PathBuf, String>,
+    ) -> VerificationResult {
+        let expected: BTreeMap<_, _> = expected
+            .iter()
+            .map(|(path, hash)| (normalize_path(path), hash.trim().to_ascii_lowercase()))
+            .collect();
+
+        let mut result = VerificationResult::default();
+        let mut seen = 0;
+        self.0.for_each_file(Path::new(""), &mut |path, metrics| {
+            match expected.get(path) {
+                Some(hash) => {
+                    seen += 1;
+                    match metrics.sha256 {
+                        Some(ref actual) => if actual.to_string() != *hash {
+                            result.mismatched.push(path.to_owned());
+                        },
+                        None => result.unhashed.push(path.to_owned()),
+                    }
+                }
+                None => result.extra.push(path.to_owned()),
+            }
+        });
+        if seen < expected.len() {
+            for path in expected.keys() {
+                match self.0.lookup(path) {
+                    Some(Entry::File(_)) => (),
+                    _ => result.missing.push(path.clone()),
+                }
+            }
+        }
+        result
+    }
+
+    /// Builds a database from per-file sidecars, where `file + suffix`
+    /// (e.g. `image.iso.sha256`) holds the hex-encoded SHA-256 of
+    /// `file`, optionally followed by a file name as in `sha256sum`
+    /// output. Files without a sidecar are left out, and sidecars are
+    /// not themselves recorded. Only hashes and sizes are stored, so
+    /// diff the result against a tree built with the `sha256` feature
+    /// (excluding the sidecars).
+    pub fn from_sidecars<P: AsRef<Path>>(root: P, suffix: &str) -> Result<Database, error::Error> {
+        let root = root.as_ref();
+        let mut database = Database::default();
+        for entry in BuildOptions::default().walk_builder(root)?.build() {
+            let entry = entry?;
+            if !entry.file_type().is_some_and(|t| t.is_file()) ||
+                entry.file_name().to_string_lossy().ends_with(suffix)
+            {
+                continue;
+            }
+            let mut sidecar = entry.path().as_os_str().to_owned();
+            sidecar.push(suffix);
+            let contents = match fs::read_to_string(&sidecar) {
+                Ok(contents) => contents,
+                Err(ref err) if err.kind() == io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(err.into()),
+            };
+            let hash = contents.split_whitespace().next()
+                .and_then(parse_hex)
+                .filter(|hash| hash.len() == 32)
+                .ok_or(error::Error::ParseError)?;
+            let metrics = Metrics {
+                sha256: Some(HashSum::from(hash)),
+                hash_only: true,
+                ..Metrics::new(entry.metadata()?.len(), None, None, false, false)
+            };
+            database.insert(short_path(entry.path(), root)?, Entry::File(metrics), false)?;
+        }
+        Ok(database)
+    }
+
+    /// Builds a database from the output of `sha256sum` (or `shasum -a
+    /// 256`): lines of a hex-encoded SHA-256, then two spaces (or a
+    /// space and `*`, in binary mode) and a path relative to the
+    /// database root. A line starting with a backslash has its name
+    /// escaped, with `\\`, `\n` and `\r` standing for a backslash, a
+    /// newline and a carriage return. Lines may end with CRLF. Paths
+    /// must stay within the root. Nothing but the hashes is known,
+    /// not even sizes, so a diff against a tree built with the `sha256`
+    /// feature compares the SHA-256 alone.
+    pub fn from_sha256sum_file(path: impl AsRef<Path>) -> Result<Database, error::Error> {
+        Database::from_sha256sum(File::open(path)?)
+    }
+
+    pub fn from_sha256sum<R: Read>(mut reader: R) -> Result<Database, error::Error> {
+        let mut contents = Vec::new();
+        reader.read_to_end(&mut contents)?;
+
+        let mut database = Database::default();
+        for line in contents.split(|&byte| byte == b'\n') {
+            // A carriage return in a name is escaped, so one at the end
+            // of the line comes from a manifest saved with CRLF endings
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            if line.is_empty() {
+                continue;
+            }
+            let (escaped, line) = match line.strip_prefix(b"\\") {
+                Some(line) => (true, line),
+                None => (false, line),
+            };
+            if line.len() < 64 {
+                return Err(error::Error::ParseError);
+            }
+            let (hex, rest) = line.split_at(64);
+            let name = match rest {
+                [b' ', b' ', name @ ..] | [b' ', b'*', name @ ..] if !name.is_empty() => name,
+                _ => return Err(error::Error::ParseError),
+            };
+            let hash = std::str::from_utf8(hex).ok()
+                .and_then(parse_hex)
+                .ok_or(error::Error::ParseError)?;
+            let name = if escaped {
+                unescape_sha256sum_name(name).ok_or(error::Error::ParseError)?
+            } else {
+                name.to_vec()
+            };
+            let path = manifest_path(&name)?;
+            let metrics = Metrics {
+                sha256: Some(HashSum::from(hash)),
+                hash_only: true,
+                size_unknown: true,
+                ..Metrics::new(0, None, None, false, false)
+            };
+            database.insert(&path, Entry::File(metrics), false)?;
+        }
+        Ok(database)
+    }
+
+    /// Writes a manifest of every file's hash, in the format checked by
+    /// `sha256sum -c` (or `sha512sum -c`): the hex-encoded hash, two
+    /// spaces and the path relative to the database root, in path
+    /// order. Names with a newline, carriage return or backslash are
+    /// escaped as coreutils does, with the line starting with a
+    /// backslash. Fails with `Error::MissingHash` on a file without a
+    /// hash of the algorithm that the tools could reproduce: one that
+    /// was not computed, or was truncated, path-bound or computed over
+    /// decompressed contents.
+    pub fn write_manifest<W: Write>(
+        &self,
+        mut w: W,
+        algorithm: ManifestAlgorithm,
+    ) -> Result<(), error::Error> {
+        let mut result = Ok(());
+        self.0.for_each_file(Path::new(""), &mut |path, metrics| {
+            if result.is_err() {
+                return;
+            }
+            result = match metrics.manifest_hash(algorithm) {
+                Some(hash) => write_manifest_line(&mut w, hash, path).map_err(error::Error::from),
+                None => Err(error::Error::MissingHash {
+                    path: path.to_owned(),
+                    algorithm: algorithm.name(),
+                }),
+            };
+        });
+        result
+    }
+
+    /// Writes the tree as an mtree(8) spec, with one line per entry by
+    /// its full path. Files carry their `size` and, where recorded,
+    /// `sha256digest`, `sha512digest`, `mode`, `uid`, `gid` and `time`
+    /// keywords; their `type=file` is given once by a `/set` line.
+    /// Names are escaped as libarchive does (see `from_mtree`).
+    pub fn export_mtree<W: Write>(&self, mut w: W) -> Result<(), error::Error> {
+        writeln!(w, "#mtree")?;
+        writeln!(w, "/set type=file")?;
+        self.0.write_mtree(Path::new(""), &mut w)?;
+        Ok(())
+    }
+
+    /// Builds a database from an mtree(8) spec, in either the full-path
+    /// form written by `export_mtree` and `bsdtar --format=mtree` or the
+    /// classic form of `mtree -c`, where names are relative to the last
+    /// directory and `..` goes back up. `/set` and `/unset` lines are
+    /// followed. The `type`, `size`, `sha256digest`, `sha512digest`,
+    /// `mode`, `uid`, `gid`, `time` and `link` keywords are read, and
+    /// all others ignored; files are stored with only what these
+    /// record. Names may use octal and C-style backslash escapes.
+    pub fn from_mtree<R: Read>(mut reader: R) -> Result<Database, error::Error> {
+        let mut contents = Vec::new();
+        reader.read_to_end(&mut contents)?;
+
+        // A line ending in a backslash continues on the next one
+        let mut lines = Vec::new();
+        let mut pending: Vec<u8> = Vec::new();
+        for line in contents.split(|&byte| byte == b'\n') {
+            pending.extend_from_slice(line);
+            if pending.last() == Some(&b'\\') {
+                pending.pop();
+                pending.push(b' ');
+            } else {
+                lines.push(std::mem::take(&mut pending));
+            }
+        }
+        lines.push(pending);
+
+        let mut database = Database::default();
+        let mut defaults: BTreeMap<Vec<u8>, Vec<u8>> = BTreeMap::new();
+        let mut cwd = PathBuf::new();
+        for line in lines.iter() {
+            let mut words = line.split(|byte| byte.is_ascii_whitespace()).filter(|word| !word.is_empty());
+            let first = match words.next() {
+                Some(first) if !first.starts_with(b"#") => first,
+                _ => continue,
+            };
+            let mut pairs = Vec::new();
+            for word in words {
+                match word.iter().position(|&byte| byte == b'=') {
+                    Some(index) => pairs.push((word[..index].to_vec(), word[index + 1..].to_vec())),
+                    None if first == b"/unset" => pairs.push((word.to_vec(), Vec::new())),
+                    None => return Err(error::Error::ParseError),
+                }
+            }
+            match first {
+                b"/set" => {
+                    defaults.extend(pairs);
+                    continue;
+                }
+                b"/unset" => {
+                    for (keyword, _) in pairs {
+                        if keyword == b"all" {
+                            defaults.clear();
+                        } else {
+                            defaults.remove(&keyword);
+                        }
+                    }
+                    continue;
+                }
+                b".." => {
+                    cwd.pop();
+                    continue;
+                }
+                _ => (),
+            }
+
+            let name = unvis_mtree(first).ok_or(error::Error::ParseError)?;
+            let full = name.contains(&b'/');
+            let path = if full {
+                normalize_path(&bytes_to_path(&name))
+            } else {
+                normalize_path(&cwd.join(bytes_to_path(&name)))
+            };
+            if path.has_root() || path.components().any(|c| c == Component::ParentDir) {
+                return Err(error::Error::ParseError);
+            }
+            let mut keywords = defaults.clone();
+            keywords.extend(pairs);
+            let keyword = |name: &str| {
+                keywords.get(name.as_bytes())
+                    .map(|value| std::str::from_utf8(value).map_err(|_| error::Error::ParseError))
+                    .transpose()
+            };
+            let number = |name: &str, radix: u32| -> Result<Option<u64>, error::Error> {
+                keyword(name)?.map(|value| {
+                    // Only whole seconds are kept of a `time`
+                    let value = value.split('.').next().unwrap_or("");
+                    u64::from_str_radix(value, radix).map_err(|_| error::Error::ParseError)
+                }).transpose()
+            };
+            let hash = |name: &str, len: usize| -> Result<Option<HashSum>, error::Error> {
+                keyword(name)?.map(|value| {
+                    parse_hex(value).filter(|hash| hash.len() == len).map(HashSum::from)
+                        .ok_or(error::Error::ParseError)
+                }).transpose()
+            };
+            let small = |value: Option<u64>| {
+                value.map(|value| u32::try_from(value).map_err(|_| error::Error::ParseError)).transpose()
+            };
+
+            let entry = match keyword("type")?.unwrap_or("file") {
+                "dir" => {
+                    if !full {
+                        cwd = path.clone();
+                    }
+                    database.insert_directory(&path, false)?;
+                    continue;
+                }
+                "file" => {
+                    let size = number("size", 10)?;
+                    let mtime = number("time", 10)?
+                        .map(|time| i64::try_from(time).map_err(|_| error::Error::ParseError))
+                        .transpose()?;
+                    Entry::File(Metrics {
+                        sha256: hash("sha256digest", 32)?.or(hash("sha256", 32)?),
+                        sha512: hash("sha512digest", 64)?.or(hash("sha512", 64)?),
+                        mode: small(number("mode", 8)?)?,
+                        uid: small(number("uid", 10)?)?,
+                        gid: small(number("gid", 10)?)?,
+                        mtime,
+                        hash_only: true,
+                        size_unknown: size.is_none(),
+                        ..Metrics::new(size.unwrap_or(0), None, None, false, false)
+                    })
+                }
+                "link" => {
+                    let target = keywords.get(&b"link"[..]).ok_or(error::Error::ParseError)?;
+                    let target = unvis_mtree(target).ok_or(error::Error::ParseError)?;
+                    Entry::Symlink { target: bytes_to_path(&target) }
+                }
+                "fifo" => Entry::Special(SpecialKind::Fifo),
+                "socket" => Entry::Special(SpecialKind::Socket),
+                "block" => Entry::Special(SpecialKind::BlockDevice),
+                "char" => Entry::Special(SpecialKind::CharDevice),
+                _ => return Err(error::Error::ParseError),
+            };
+            if path == Path::new("") {
+                return Err(error::Error::ParseError);
+            }
+            database.insert(&path, entry, false)?;
+        }
+        Ok(database)
+    }
+
+    /// Writes the file list in hashdeep's audit format, with a
+    /// `size,sha256,filename` header, as `hashdeep -c sha256 -l` would
+    /// for the tree. Fails with `Error::MissingHash` on a file without
+    /// a usable SHA2-256 hash (see `write_manifest`), and with
+    /// `Error::UnknownSize` on one imported without its size.
+    pub fn write_hashdeep<W: Write>(&self, mut w: W) -> Result<(), error::Error> {
+        writeln!(w, "%%%% HASHDEEP-1.0")?;
+        writeln!(w, "%%%% size,sha256,filename")?;
+        writeln!(w, "## Written by integrity-checker")?;
+        writeln!(w, "##")?;
+        let mut result = Ok(());
+        self.0.for_each_file(Path::new(""), &mut |path, metrics| {
+            if result.is_err() {
+                return;
+            }
+            result = match metrics.manifest_hash(ManifestAlgorithm::Sha256) {
+                _ if !metrics.size_known() => Err(error::Error::UnknownSize(path.to_owned())),
+                Some(hash) => {
+                    write_hashdeep_line(&mut w, metrics.size, hash, path).map_err(error::Error::from)
+                }
+                None => Err(error::Error::MissingHash {
+                    path: path.to_owned(),
+                    algorithm: ManifestAlgorithm::Sha256.name(),
+                }),
+            };
+        });
+        result
+    }
+
+    /// Builds a database from a hashdeep (or md5deep) audit file, so
+    /// that it can be diffed against a fresh scan. The columns are
+    /// given by a `%%%% size,...,filename` header line, or are
+    /// hashdeep's default `size,md5,sha256,filename` without one; the
+    /// file name comes last and may itself contain commas. `##`
+    /// comment lines are skipped. Only the `size` and `sha256` columns
+    /// are stored: the others are listed in `ignored_columns` rather
+    /// than failing. Paths must be relative, as with `hashdeep -l`, and
+    /// must not leave the root through `..`.
+    pub fn from_hashdeep<R: Read>(mut reader: R) -> Result<HashdeepImport, error::Error> {
+        let mut contents = Vec::new();
+        reader.read_to_end(&mut contents)?;
+
+        let mut columns: Vec<String> = vec!["size".into(), "md5".into(), "sha256".into(), "filename".into()];
+        let mut database = Database::default();
+        for line in contents.split(|&byte| byte == b'\n') {
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            if line.is_empty() || line.starts_with(b"##") {
+                continue;
+            }
+            if let Some(header) = line.strip_prefix(b"%%%%") {
+                let header = std::str::from_utf8(header).map_err(|_| error::Error::ParseError)?.trim();
+                if header.starts_with("HASHDEEP-") {
+                    continue;
+                }
+                columns = header.split(',').map(|column| column.trim().to_ascii_lowercase()).collect();
+                if columns.last().map(String::as_str) != Some("filename") {
+                    return Err(error::Error::ParseError);
+                }
+                continue;
+            }
+
+            let fields: Vec<&[u8]> = line.splitn(columns.len(), |&byte| byte == b',').collect();
+            if fields.len() != columns.len() {
+                return Err(error::Error::ParseError);
+            }
+            let (mut size, mut sha256) = (None, None);
+            for (column, field) in columns.iter().zip(fields.iter()) {
+                let field = || std::str::from_utf8(field).map_err(|_| error::Error::ParseError);
+                match column.as_str() {
+                    "size" => size = Some(field()?.parse().map_err(|_| error::Error::ParseError)?),
+                    "sha256" => {
+                        let hash = parse_hex(field()?).filter(|hash| hash.len() == 32);
+                        sha256 = Some(HashSum::from(hash.ok_or(error::Error::ParseError)?));
+                    }
+                    _ => (),
+                }
+            }
+            let path = manifest_path(fields[columns.len() - 1])?;
+            let metrics = Metrics {
+                sha256,
+                hash_only: true,
+                size_unknown: size.is_none(),
+                ..Metrics::new(size.unwrap_or(0), None, None, false, false)
+            };
+            database.insert(&path, Entry::File(metrics), false)?;
+        }
+        let ignored_columns = columns.into_iter()
+            .filter(|column| !matches!(column.as_str(), "size" | "sha256" | "filename"))
+            .collect();
+        Ok(HashdeepImport { database, ignored_columns })
+    }
+
+    /// Compares the database against a listing of file metadata (see
+    /// `ListingFormat`), e.g. from a remote host where the tool isn't
+    /// installed. Without hashes, only sizes, mtimes and permission
+    /// bits are compared, so an edit that keeps all three goes unseen.
+    /// Only regular files are compared, as only they are listed.
+    pub fn diff_metadata_listing<R: Read>(
+        &self,
+        mut reader: R,
+        format: ListingFormat,
+    ) -> Result<EntryDiff, error::Error> {
+        let mut contents = Vec::new();
+        reader.read_to_end(&mut contents)?;
+        let terminator = match format {
+            ListingFormat::Lines => b'\n',
+            ListingFormat::Nul => b'\0',
+        };
+
+        let mut listing = Database::default();
+        for record in contents.split(|&byte| byte == terminator) {
+            if record.is_empty() {
+                continue;
+            }
+            let mut fields = record.splitn(4, |&byte| byte == b' ');
+            let mut field = || {
+                fields.next().and_then(|field| std::str::from_utf8(field).ok())
+                    .ok_or(error::Error::ParseError)
+            };
+            let size = field()?.parse().map_err(|_| error::Error::ParseError)?;
+            let mtime = field()?.split('.').next().unwrap_or("")
+                .parse().map_err(|_| error::Error::ParseError)?;
+            let mode = u32::from_str_radix(field()?, 8).map_err(|_| error::Error::ParseError)?;
+            let path = match fields.next() {
+                Some(path) if !path.is_empty() => normalize_path(&bytes_to_path(path)),
+                _ => return Err(error::Error::ParseError),
+            };
+            let metrics = Metrics {
+                mode: Some(mode),
+                mtime: Some(mtime),
+                hash_only: true,
+                ..Metrics::new(size, None, None, false, false)
+            };
+            listing.insert(&path, Entry::File(metrics), false)?;
+        }
+
+        // The listing only has regular files, so symlinks, special files
+        // and empty directories must not count as removed
+        let mut files = Database::default();
+        self.for_each_file(|path, metrics| {
+            files.insert(path, Entry::File(metrics.clone()), false).expect("paths in a database are unique");
+        });
+        let options = DiffOptions { compare_mtime: true, ..DiffOptions::default() };
+        Ok(files.diff_with_options(&listing, &options))
+    }
+
+    /// Checks a single file, read from `reader` (e.g. a member of an
+    /// archive of the tree), against the entry stored at `member_path`.
+    /// The stream is hashed with the algorithms recorded for the entry.
+    /// Since a stream has no mode or mtime, only contents are compared.
+    pub fn check_archive_member<R: Read>(
+        &self,
+        member_path: &Path,
+        reader: R,
+    ) -> Result<MetricsDiff, error::Error> {
+        let member_path = normalize_path(member_path);
+        let old = match self.0.lookup(&member_path) {
+            Some(Entry::File(old)) => old,
+            _ => return Err(error::Error::NotInDatabase(member_path)),
+        };
+        let bound_path = if old.path_bound { Some(bound_path_bytes(&member_path)) } else { None };
+        let features = old.features();
+        let bound_path = bound_path.as_deref();
+        let first_block = old.first_block.is_some();
+        let new = if old.decompressed {
+            hash_reader(
+                MultiGzDecoder::new(reader), features, DEFAULT_BUFFER_SIZE, bound_path, first_block)?
+        } else {
+            hash_reader(reader, features, DEFAULT_BUFFER_SIZE, bound_path, first_block)?
+        };
+        let new = Metrics { decompressed: old.decompressed, path_bound: old.path_bound, ..new }
+            .truncate_hashes(old.truncated);
+        Ok(old.diff(&new, &DiffOptions::default()))
+    }
+
+    /// Checks a legacy manifest of `path<TAB>crc32` lines, with the
+    /// CRC-32 in hexadecimal, against the CRC-32s recorded in the
+    /// database (see `Features::crc32`). Blank lines and lines starting
+    /// with `#` are skipped. Problems with individual lines are
+    /// collected into the report; only I/O errors abort.
+    pub fn verify_crc32_manifest(&self, manifest: impl BufRead) -> Result<ManifestReport, error::Error> {
+        let mut report = ManifestReport::default();
+        let mut listed = BTreeSet::new();
+        for (index, line) in manifest.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let parsed = line.rsplit_once('\t').and_then(|(path, crc)| {
+                let crc = crc.trim();
+                if crc.is_empty() || crc.len() > 8 || !crc.bytes().all(|b| b.is_ascii_hexdigit()) {
+                    return None;
+                }
+                Some((normalize_path(Path::new(path)), u32::from_str_radix(crc, 16).ok()?))
+            });
+            let (path, crc) = match parsed {
+                Some(parsed) => parsed,
+                None => {
+                    report.malformed.push((index + 1, line));
+                    continue;
+                }
+            };
+            match self.0.lookup(&path) {
+                Some(Entry::File(metrics)) => match metrics.crc32() {
+                    Some(recorded) if recorded == crc => report.matched.push(path.clone()),
+                    Some(_) => report.mismatched.push(path.clone()),
+                    None => report.unchecked.push(path.clone()),
+                },
+                _ => report.only_in_manifest.push(path.clone()),
+            }
+            listed.insert(path);
+        }
+        self.for_each_file(|path, _| {
+            if !listed.contains(path) {
+                report.only_in_database.push(path.to_owned());
+            }
+        });
+        Ok(report)
+    }
+
+    /// Returns the files recorded in the database that no longer
+    /// exist under `root`. Only paths are compared: the live tree is
+    /// walked, but no file under it is opened.
+    pub fn missing_files(&self, root: impl AsRef<Path>) -> Result<Vec<PathBuf>, error::Error> {
+        let root = root.as_ref();
+        let mut live = BTreeSet::new();
+        for entry in BuildOptions::default().walk_builder(root)?.build() {
+            let entry = entry?;
+            if entry.file_type().is_some_and(|t| t.is_file()) {
+                live.insert(short_path(entry.path(), root)?.to_owned());
+            }
+        }
+        let mut missing = Vec::new();
+        self.0.for_each_file(Path::new(""), &mut |path, _| {
+            if !live.contains(path) {
+                missing.push(path.to_owned());
+            }
+        });
+        Ok(missing)
+    }
+
+    /// Verifies the files under `root` one at a time, as the caller
+    /// pulls results. Files found on disk are reported in walk order,
+    /// followed by any files missing from disk. Each file is hashed
+    /// only with the algorithms recorded for it in the database.
+    pub fn verify_iter<'a>(
+        &'a self,
+        root: impl AsRef<Path>,
+    ) -> impl Iterator<Item = Result<(PathBuf, VerifyStatus), error::Error>> + 'a {
+        VerifyIter {
+            database: self,
+            root: root.as_ref().to_owned(),
+            walk: WalkBuilder::new(&root).build(),
+            seen: BTreeSet::new(),
+            missing: None,
+        }
+    }
+
+    pub fn build(
+        root: impl AsRef<Path>,
+        features: Features,
+        threads: usize,
+        verbose: bool,
+    ) -> Result<Database, error::Error> {
+        let options = BuildOptions { features, threads, verbose, ..BuildOptions::default() };
+        Database::build_with_options(root, &options)
+    }
+
+    pub fn build_with_options(
+        root: impl AsRef<Path>,
+        options: &BuildOptions,
+    ) -> Result<Database, error::Error> {
+        let (database, stats) = Database::build_with_stats(root, options)?;
+        if options.verbose {
+            println!("Database::build took {:.3} seconds on {} threads, read {} bytes, {:.1} MB/s",
+                     stats.elapsed.as_secs_f64(),
+                     options.threads,
+                     stats.bytes,
+                     stats.bytes_per_sec()/1e6);
+            if stats.allocated > 0 {
+                println!("{} logical, {} allocated", human_size(stats.bytes), human_size(stats.allocated));
+            }
+        }
+        Ok(database)
+    }
+
+    /// As `build_with_options`, also returning statistics about the
+    /// build. `verbose` is ignored, so nothing is printed.
+    pub fn build_with_stats(
+        root: impl AsRef<Path>,
+        options: &BuildOptions,
+    ) -> Result<(Database, BuildStats), error::Error> {
+        Database::build_with_callback(root, Path::new(""), options, None, None)
+    }
+
+    /// As `build_with_stats`, but files that only grew since `previous`
+    /// was built (they are no smaller, and their mtime did not move
+    /// backward) have the chunks they had then taken from its chunk
+    /// map rather than read again, which makes rescanning append-only
+    /// files such as logs nearly free. This only applies to files
+    /// summarized by their tree hash alone (see `tree_hash_threshold`),
+    /// i.e. built with no other hash algorithm and without
+    /// `first_block_hash`, since other hashes can't be resumed; all
+    /// other files are hashed in full. Changes within the reused chunks
+    /// go unseen, so use this to keep a database current, not to check
+    /// a tree that may have been tampered with.
+    pub fn build_incremental(
+        root: impl AsRef<Path>,
+        previous: &Database,
+        options: &BuildOptions,
+    ) -> Result<(Database, BuildStats), error::Error> {
+        let previous = Some(Arc::new(previous.clone()));
+        Database::build_with_callback(root, Path::new(""), options, None, previous)
     }
-}
 
-impl Entry {
-    fn insert(&mut self, path: PathBuf, file: Entry) {
-        // Inner nodes in the tree should always be directories. If
-        // the node is not a directory, that means we are inserting a
-        // duplicate file. However, this function is only called from
-        // the directory walker, which makes it impossible to observe
-        // any duplicates. (And the database, after construction, is
-        // always immutable.)
-        match self {
-            Entry::Directory(entries) => {
-                let mut components = path.components();
-                let count = components.clone().count();
-                let first = Path::new(components.next().expect("unreachable").as_os_str()).to_owned();
-                let rest = components.as_path().to_owned();
-                if count > 1 {
-                    let subentry = entries.get_default(first);
-                    subentry.insert(rest, file);
-                } else {
-                    match entries.insert(first, file) {
-                        Some(_) => unreachable!(), // See above
-                        None => (),
-                    }
-                }
+    /// As `build_with_stats`, calling `callback` as files are hashed,
+    /// at most once per `PROGRESS_FILE_INTERVAL` files or
+    /// `PROGRESS_BYTE_INTERVAL` bytes. With several threads, it runs on
+    /// whichever thread hashed the file, one call at a time. A panic in
+    /// the callback propagates out of the build, so no database is
+    /// returned at all rather than a partial one.
+    pub fn build_with_progress(
+        root: impl AsRef<Path>,
+        options: &BuildOptions,
+        callback: impl FnMut(&Progress) + Send + 'static,
+    ) -> Result<(Database, BuildStats), error::Error> {
+        let callback = Some(ProgressCallback::new(Box::new(callback)));
+        Database::build_with_callback(root, Path::new(""), options, callback, None)
+    }
+
+    // Only the tree under `start`, relative to the root, is walked
+    fn build_with_callback(
+        root: impl AsRef<Path>,
+        start: &Path,
+        options: &BuildOptions,
+        callback: Option<ProgressCallback>,
+        previous: Option<Arc<Database>>,
+    ) -> Result<(Database, BuildStats), error::Error> {
+        let mut database = Database::default();
+        let stats = walk_tree(root.as_ref(), start, options, callback, previous, &mut |path, entry| {
+            if entry.is_dir() {
+                database.insert_directory(path, options.fold_case)
+            } else {
+                database.insert(path, entry, options.fold_case)
             }
-            Entry::File(_) => unreachable!()
-        }
+        })?;
+        database.1 = root_name(root.as_ref());
+        Ok((database, stats))
     }
 
-    fn lookup(&self, path: &PathBuf) -> Option<&Entry> {
-        match self {
-            Entry::Directory(entries) => {
-                let mut components = path.components();
-                let count = components.clone().count();
-                let first = Path::new(components.next().expect("unreachable").as_os_str()).to_owned();
-                let rest = components.as_path().to_owned();
-                if count > 1 {
-                    entries.get(&first).and_then(
-                        |subentry| subentry.lookup(&rest))
-                } else {
-                    entries.get(&first)
-                }
+    /// Builds a database of the tree under an already open directory,
+    /// visiting it with `openat`-style calls relative to descriptors
+    /// held open throughout, so that an attacker who swaps a directory
+    /// for a symbolic link during the walk can't redirect it outside the
+    /// tree. Symbolic links are never followed (whatever `follow_links`
+    /// says), the walk runs on one thread, and, unlike with
+    /// `build_with_options`, `.gitignore` and `.ignore` files are not
+    /// read. No root name is recorded.
+    #[cfg(unix)]
+    pub fn build_from_dir_fd(
+        dir: std::os::unix::io::BorrowedFd<'_>,
+        options: &BuildOptions,
+    ) -> Result<Database, error::Error> {
+        let hasher = options.hasher()?;
+        let exclude = options.exclude_overrides(Path::new(""))?;
+        let walk = dirfd::DirFdWalk {
+            max_depth: options.max_depth,
+            exclude: exclude.as_ref(),
+            prefix: options.strip_prefix_components.as_deref(),
+        };
+        let mut database = Database::default();
+        let mut total_bytes = 0;
+        let mut progress = if cfg!(feature = "progress") && options.progress {
+            Some(InteractiveProgress::new(time::precise_time_ns()))
+        } else {
+            None
+        };
+        walk.run(dir, &mut |path, file| {
+            let modified = options.modified_before.is_some_and(|cutoff| {
+                file.metadata().ok().as_ref().and_then(file_mtime).is_some_and(|mtime| mtime > cutoff)
+            });
+            if modified {
+                return Ok(());
+            }
+            let short_path = short_path(path, Path::new(""))?;
+            let metrics = hasher.compute_file(file, short_path)?;
+            total_bytes += metrics.size;
+            if let Some(ref mut progress) = progress {
+                progress.file_done(short_path, total_bytes);
             }
-            Entry::File(_) => unreachable!()
+            database.insert(short_path, Entry::File(metrics), options.fold_case)
+        })?;
+        if let Some(progress) = progress {
+            progress.finish();
         }
+        Ok(database)
     }
-}
 
-#[derive(Debug)]
-pub enum EntryDiff {
-    Directory(BTreeMap<PathBuf, EntryDiff>, DirectoryDiff),
-    File(MetricsDiff),
-    KindChanged,
-}
+    /// The final component of the path the database was built from,
+    /// whether that was a directory or a single file. Databases loaded
+    /// from files written before the name was recorded return `None`.
+    pub fn root_name(&self) -> Option<&str> {
+        self.1.as_deref()
+    }
 
-#[derive(Debug)]
-pub struct DirectoryDiff {
-    added: u64,
-    removed: u64,
-    changed: u64,
-    unchanged: u64,
-}
+    /// Renders the diff as a self-contained HTML report
+    pub fn diff_html(&self, other: &Database) -> String {
+        let mut output = Vec::new();
+        html::HtmlFormatter.format(&self.diff(other), &mut output)
+            .expect("writing to a Vec cannot fail");
+        String::from_utf8(output).expect("report is valid UTF-8")
+    }
 
-#[derive(Debug)]
-pub struct MetricsDiff {
-    changed_content: bool,
-    zeroed: bool,
-    changed_nul: bool,
-    changed_nonascii: bool,
-}
+    /// Prints the diff against `other` to stdout, and panics if that
+    /// fails (e.g. on a closed pipe). Use `show_diff_to` to write it
+    /// elsewhere, or to handle write errors.
+    pub fn show_diff(&self, other: &Database) -> DiffSummary {
+        self.show_diff_with_options(other, &DiffOptions::default())
+    }
 
-#[derive(Debug, PartialEq)]
-pub enum DiffSummary {
-    NoChanges,
-    Changes,
-    Suspicious,
-}
+    pub fn show_diff_with_options(&self, other: &Database, options: &DiffOptions) -> DiffSummary {
+        let stdout = io::stdout();
+        let options = DiffOptions { color: options.color.for_stdout(), ..options.clone() };
+        self.show_diff_to_with_options(other, &options, &mut stdout.lock())
+            .expect("failed to write to stdout")
+    }
 
-impl EntryDiff {
-    fn show_diff(&self, path: &PathBuf, depth: usize) {
-        match self {
-            EntryDiff::Directory(entries, diff) => {
-                if diff.changed > 0 || diff.added > 0 || diff.removed > 0 {
-                    println!("{}{}: {} changed, {} added, {} removed, {} unchanged",
-                             "| ".repeat(depth),
-                             path.display(),
-                             diff.changed,
-                             diff.added,
-                             diff.removed,
-                             diff.unchanged);
-                    for (key, entry) in entries.iter() {
-                        entry.show_diff(key, depth+1);
-                    }
-                }
+    pub fn show_diff_to<W: Write>(
+        &self,
+        other: &Database,
+        writer: W,
+    ) -> Result<DiffSummary, error::Error> {
+        self.show_diff_to_with_options(other, &DiffOptions::default(), writer)
+    }
+
+    /// Writes the diff against `other` as a `DiffReport` in JSON, for
+    /// consumption by other tools, and returns its summary. Renames are
+    /// not detected.
+    pub fn diff_json<W: Write>(
+        &self,
+        other: &Database,
+        options: &DiffOptions,
+        writer: W,
+    ) -> Result<DiffSummary, error::Error> {
+        let report = self.diff_with_options(other, options).to_report();
+        serde_json::to_writer_pretty(writer, &report)?;
+        Ok(report.summary)
+    }
+
+    pub fn show_diff_to_with_options<W: Write>(
+        &self,
+        other: &Database,
+        options: &DiffOptions,
+        mut writer: W,
+    ) -> Result<DiffSummary, error::Error> {
+        let mut summary;
+        if options.detect_renames {
+            let (diff, renames) = self.diff_with_renames(other, options);
+            summary = diff.report(options, &mut writer)?;
+            if !renames.is_empty() {
+                summary = summary.meet(DiffSummary::Changes);
             }
-            EntryDiff::File(diff) => {
-                if diff.zeroed || diff.changed_nul || diff.changed_nonascii {
-                    println!("{}{} changed",
-                             "| ".repeat(depth),
-                             path.display());
-                    if diff.zeroed {
-                        println!("{}> suspicious: file was truncated",
-                                 "##".repeat(depth));
-                    }
-                    if diff.changed_nul {
-                        println!("{}> suspicious: original had no NUL bytes, but now does",
-                                 "##".repeat(depth));
-                    }
-                    if diff.changed_nonascii {
-                        println!("{}> suspicious: original had no non-ASCII bytes, but now does",
-                                 "##".repeat(depth));
-                    }
-                }
+            for rename in renames {
+                writeln!(writer, "{} renamed to {}{}",
+                         options.display_path(&rename.from).display(),
+                         options.display_path(&rename.to).display(),
+                         if rename.same_inode { " (same inode)" } else { "" })?;
+            }
+            for (first, second) in self.swaps(other, options) {
+                writeln!(writer, "> suspicious: content swapped between {} and {}",
+                         options.display_path(&first).display(),
+                         options.display_path(&second).display())?;
+                summary = DiffSummary::Suspicious;
             }
-            EntryDiff::KindChanged => {
+        } else {
+            summary = self.diff_with_options(other, options).report(options, &mut writer)?;
+        }
+        if let (Some(old), Some(new)) = (self.root_name(), other.root_name()) {
+            if old != new {
+                writeln!(writer, "warning: comparing databases of differently named roots {} and {}",
+                         old, new)?;
             }
         }
+        Ok(summary)
     }
 
-    fn summarize_diff(&self) -> DiffSummary {
-        match self {
-            EntryDiff::Directory(entries, diff) => {
-                let initial =
-                    if diff.changed > 0 || diff.added > 0 || diff.removed > 0 {
-                        DiffSummary::Changes
-                    } else {
-                        DiffSummary::NoChanges
-                    };
-                entries
-                    .values()
-                    .map(|x| x.summarize_diff())
-                    .fold(initial, |acc, x| acc.meet(x))
+    /// Finds pairs of files, present in both databases, that exchanged
+    /// contents: each file now has what the other had before. Each
+    /// pair is reported once, in path order.
+    pub fn swaps(&self, other: &Database, options: &DiffOptions) -> Vec<(PathBuf, PathBuf)> {
+        let mut changed = Vec::new();
+        self.0.for_each_file(Path::new(""), &mut |path, old| {
+            if options.is_ignored(path) {
+                return;
             }
-            EntryDiff::File(diff) => {
-                if diff.zeroed || diff.changed_nul || diff.changed_nonascii {
-                    DiffSummary::Suspicious
-                } else if diff.changed_content {
-                    DiffSummary::Changes
-                } else {
-                    DiffSummary::NoChanges
+            if let Some(Entry::File(new)) = other.0.lookup(path) {
+                if old.diff(new, options).changed_content {
+                    changed.push((path.to_owned(), old.clone(), new.clone()));
                 }
             }
-            EntryDiff::KindChanged => {
-                DiffSummary::Changes
+        });
+
+        // Only files of the same size can have swapped
+        let mut by_size: BTreeMap<u64, Vec<usize>> = BTreeMap::new();
+        for (index, (_, old, _)) in changed.iter().enumerate() {
+            by_size.entry(old.size).or_default().push(index);
+        }
+        let mut swaps = Vec::new();
+        for (index, (path, old, new)) in changed.iter().enumerate() {
+            for &other_index in by_size.get(&new.size).into_iter().flatten() {
+                let (other_path, other_old, other_new) = &changed[other_index];
+                if other_index > index &&
+                    same_content(other_old, new) && same_content(old, other_new)
+                {
+                    swaps.push((path.clone(), other_path.clone()));
+                }
             }
         }
+        swaps
     }
-}
 
-impl DiffSummary {
-    fn meet(self, other: DiffSummary) -> DiffSummary {
-        if self == DiffSummary::Suspicious || other == DiffSummary::Suspicious {
-            DiffSummary::Suspicious
-        } else if self == DiffSummary::Changes || other == DiffSummary::Changes {
-            DiffSummary::Changes
-        } else {
-            DiffSummary::NoChanges
-        }
-    }
-}
+    /// Pairs files removed since this database with files added in
+    /// `other`. Files are matched by device and inode where both
+    /// databases recorded them, and otherwise by identical size and
+    /// hashes. Each
+    /// file is used in at most one pair; among files of identical
+    /// content, removed and added files are paired in path order. An
+    /// added file whose content was also kept at a path present on both
+    /// sides may just be a copy of it, so it is never matched by content.
+    pub fn renames(&self, other: &Database, options: &DiffOptions) -> Vec<Rename> {
+        let only_in = |a: &Database, b: &Database| {
+            let mut files = Vec::new();
+            a.0.for_each_file(Path::new(""), &mut |path, metrics| {
+                if options.counts_one_sided(path) &&
+                    !matches!(b.0.lookup(path), Some(Entry::File(_)))
+                {
+                    files.push(Some((path.to_owned(), metrics.clone())));
+                }
+            });
+            files
+        };
+        let mut removed = only_in(self, other);
+        let mut added = only_in(other, self);
 
-impl Entry {
-    fn diff(&self, other: &Entry) -> EntryDiff {
-        match (self, other) {
-            (Entry::Directory(old), Entry::Directory(new)) => {
-                let mut entries = BTreeMap::default();
-                let mut added = 0;
-                let mut removed = 0;
-                let mut changed = 0;
-                let mut unchanged = 0;
+        // Added files by device and inode, since inode numbers are only
+        // unique within one device, and by content hash. Indexes stay in
+        // path order, so that the first candidate still unpaired wins.
+        let identity = |metrics: &Metrics| Some((metrics.dev?, metrics.inode?));
+        let hash = index_hash(self.features().intersection(other.features()));
+        let mut by_identity: BTreeMap<(u64, u64), Vec<usize>> = BTreeMap::new();
+        let mut by_hash: BTreeMap<HashSum, Vec<usize>> = BTreeMap::new();
+        let mut unhashed = Vec::new();
+        let all: Vec<usize> = (0..added.len()).collect();
+        for (index, file) in added.iter().enumerate() {
+            let (_, metrics) = file.as_ref().expect("nothing is paired yet");
+            if let Some(identity) = identity(metrics) {
+                by_identity.entry(identity).or_default().push(index);
+            }
+            match hash(metrics) {
+                Some(sum) => by_hash.entry(sum.clone()).or_default().push(index),
+                None => unhashed.push(index),
+            }
+        }
 
-                let mut old_iter = old.iter();
-                let mut new_iter = new.iter();
-                let mut old_entry = old_iter.next();
-                let mut new_entry = new_iter.next();
-                while old_entry.is_some() && new_entry.is_some() {
-                    let (old_key, old_value) = old_entry.unwrap();
-                    let (new_key, new_value) = new_entry.unwrap();
-                    match old_key.cmp(new_key) {
-                        Ordering::Less => {
-                            removed += 1;
-                            old_entry = old_iter.next();
-                        }
-                        Ordering::Greater => {
-                            added += 1;
-                            new_entry = new_iter.next();
-                        }
-                        Ordering::Equal => {
-                            let diff = old_value.diff(new_value);
-                            match diff {
-                                EntryDiff::Directory(_, ref stats) => {
-                                    added += stats.added;
-                                    removed += stats.removed;
-                                    changed += stats.changed;
-                                    unchanged += stats.unchanged;
-                                }
-                                EntryDiff::File(ref stats) => {
-                                    if stats.changed_content {
-                                        changed += 1;
-                                    } else {
-                                        unchanged += 1;
-                                    }
-                                }
-                                EntryDiff::KindChanged => {
-                                    changed += 1;
-                                }
-                            }
-                            entries.insert(old_key.clone(), diff);
-                            old_entry = old_iter.next();
-                            new_entry = new_iter.next();
-                        }
-                    }
+        let mut renames = Vec::new();
+        let mut pair = |removed: &mut Vec<Option<(PathBuf, Metrics)>>,
+                        same_inode: bool,
+                        candidates: &dyn Fn(&Metrics) -> Vec<usize>,
+                        matches: &dyn Fn(&Metrics, &Metrics) -> bool| {
+            for old in removed.iter_mut() {
+                let found = match old {
+                    Some((_, old)) => candidates(old).into_iter().find(|&index| {
+                        added[index].as_ref().is_some_and(|(_, new)| matches(old, new))
+                    }),
+                    None => None,
+                };
+                if let Some(index) = found {
+                    let (from, _) = old.take().unwrap();
+                    let (to, _) = added[index].take().unwrap();
+                    renames.push(Rename { from, to, same_inode });
                 }
-                removed += old_iter.count() as u64;
-                added += new_iter.count() as u64;
-                EntryDiff::Directory(
-                    entries,
-                    DirectoryDiff { added, removed, changed, unchanged })
-            },
-            (Entry::File(old), Entry::File(new)) => {
-                let changed = old.size != new.size;
-                let changed = changed ||
-                    (old.sha2.is_some() && new.sha2.is_some() && old.sha2 != new.sha2);
-                let changed = changed ||
-                    (old.blake2b.is_some() && new.blake2b.is_some() && old.blake2b != new.blake2b);
-                EntryDiff::File(
-                    MetricsDiff {
-                        changed_content: changed,
-                        zeroed: old.size > 0 && new.size == 0,
-                        changed_nul: old.nul != new.nul,
-                        changed_nonascii: old.nonascii != new.nonascii,
-                    }
-                )
-            },
-            (_, _) => EntryDiff::KindChanged,
-        }
+            }
+        };
+        let mut kept: BTreeMap<u64, Vec<Metrics>> = BTreeMap::new();
+        self.0.for_each_file(Path::new(""), &mut |path, metrics| {
+            if matches!(other.0.lookup(path), Some(Entry::File(_))) {
+                kept.entry(metrics.size).or_default().push(metrics.clone());
+            }
+        });
+        let copied = |new: &Metrics| {
+            kept.get(&new.size).into_iter().flatten().any(|old| same_content(old, new))
+        };
+
+        pair(&mut removed, true, &|old| {
+            identity(old).and_then(|identity| by_identity.get(&identity)).cloned().unwrap_or_default()
+        }, &|_, _| true);
+        // Inode numbers are only comparable within one file system, so
+        // fall back to content for files that could not be matched.
+        // Files without the indexed hash may still share another one.
+        pair(&mut removed, false, &|old| match hash(old) {
+            Some(sum) => {
+                let mut candidates: Vec<usize> =
+                    by_hash.get(sum).into_iter().flatten().chain(&unhashed).copied().collect();
+                candidates.sort_unstable();
+                candidates
+            }
+            None => all.clone(),
+        }, &|old, new| same_content(old, new) && !copied(new));
+        renames.sort_by(|a, b| a.from.cmp(&b.from));
+        renames
     }
-}
 
-const SEP : u8 = 0x0a; // separator \n (byte 0x0a) used in JSON encoding
+    /// The diff against `other`, with the files paired by `renames`
+    /// taken out of its added and removed entries and counts, along
+    /// with those renames. A rename to a suspicious name is left as an
+    /// addition and a removal, so that the name is still flagged.
+    pub fn diff_with_renames(&self, other: &Database, options: &DiffOptions) -> (EntryDiff, Vec<Rename>) {
+        let mut diff = self.diff_with_options(other, options);
+        let mut renames = self.renames(other, options);
+        renames.retain(|rename| {
+            rename.to.file_name().is_none_or(|name| NameAnomalies::of(name).is_empty())
+        });
+        for rename in &renames {
+            let empty = matches!(other.0.lookup(&rename.to), Some(Entry::File(metrics)) if metrics.size == 0);
+            let from: Vec<&OsStr> = rename.from.iter().collect();
+            let to: Vec<&OsStr> = rename.to.iter().collect();
+            diff.forget_one_sided(&from, false, false, options);
+            diff.forget_one_sided(&to, true, empty, options);
+        }
+        (diff, renames)
+    }
 
-impl Database {
-    fn insert(&mut self, path: PathBuf, entry: Entry) {
-        self.0.insert(path, entry);
+    pub fn check(
+        &self,
+        root: impl AsRef<Path>,
+        features: Features,
+        threads: usize
+    ) -> Result<DiffSummary, error::Error> {
+        let options = BuildOptions { features, threads, ..BuildOptions::default() };
+        self.check_with_options(root, &options, &DiffOptions::default())
     }
 
-    pub fn lookup(&self, path: &PathBuf) -> Option<&Entry> {
-        self.0.lookup(path)
+    /// The hash algorithms recorded for at least one file
+    pub fn features(&self) -> Features {
+        let mut features = Features {
+            sha2: false, blake2b: false, sha256: false, sha512: false, blake3: false, xxh64: false,
+            crc32: false,
+        };
+        self.0.for_each_file(Path::new(""), &mut |_, metrics| {
+            features = features.union(metrics.features());
+        });
+        features
     }
 
-    pub fn diff(&self, other: &Database) -> EntryDiff {
-        self.0.diff(&other.0)
+    pub fn check_with_options(
+        &self,
+        root: impl AsRef<Path>,
+        build_options: &BuildOptions,
+        diff_options: &DiffOptions,
+    ) -> Result<DiffSummary, error::Error> {
+        let (summary, _) = self.check_with_stats(root, build_options, diff_options)?;
+        Ok(summary)
     }
 
-    pub fn build(
+    /// As `check_with_options`, also returning statistics about the
+    /// rebuild of the current tree, i.e. how much was read again
+    pub fn check_with_stats(
+        &self,
         root: impl AsRef<Path>,
-        features: Features,
-        threads: usize,
-        verbose: bool,
-    ) -> Result<Database, error::Error> {
-        let total_bytes = Arc::new(Mutex::new(0));
-        let database = Arc::new(Mutex::new(Database::default()));
-        let start_time_ns = time::precise_time_ns();
-
-        let parallel = threads > 1;
-        if parallel {
-            WalkBuilder::new(&root).threads(threads).build_parallel().run(|| {
-                let total_bytes = total_bytes.clone();
-                let database = database.clone();
-                let root = root.as_ref().to_owned();
-                Box::new(move |entry| {
-                    let entry = entry.unwrap(); // ?
-                    if entry.file_type().map_or(false, |t| t.is_file()) {
-                        let metrics = compute_metrics(entry.path(), features).unwrap(); // ?
-                        *total_bytes.lock().unwrap() += metrics.size;
-                        let result = Entry::File(metrics);
-                        let short_path = if entry.path() == root {
-                            Path::new(entry.path().file_name().expect("unreachable"))
-                        } else {
-                            entry.path().strip_prefix(&root).unwrap() // ?
-                        };
-                        database.lock().unwrap().insert(short_path.to_owned(), result);
-                    }
-                    WalkState::Continue
-                })
-            });
-        } else {
-            let ref mut total_bytes = *total_bytes.lock().unwrap();
-            let ref mut database = *database.lock().unwrap();
-            for entry in WalkBuilder::new(&root).build() {
-                let entry = entry?;
-                if entry.file_type().map_or(false, |t| t.is_file()) {
-                    let metrics = compute_metrics(entry.path(), features)?;
-                    *total_bytes += metrics.size;
-                    let result = Entry::File(metrics);
-                    let short_path = if entry.path() == root.as_ref() {
-                        Path::new(entry.path().file_name().expect("unreachable"))
-                    } else {
-                        entry.path().strip_prefix(&root)?
-                    };
-                    database.insert(short_path.to_owned(), result);
-                }
+        build_options: &BuildOptions,
+        diff_options: &DiffOptions,
+    ) -> Result<(DiffSummary, BuildStats), error::Error> {
+        let (current, stats) = self.rebuild_for_check(root, Path::new(""), build_options)?;
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        if let Some((on_disk, in_database)) = self.likely_wrong_root(&current, diff_options) {
+            if diff_options.abort_on_wrong_root {
+                return Err(error::Error::LikelyWrongRoot { on_disk, in_database });
             }
+            writeln!(stdout, "warning: likely wrong root: {} files on disk vs {} in database",
+                     on_disk, in_database)?;
         }
-        let stop_time_ns = time::precise_time_ns();
-        if verbose {
-            let total_bytes = *total_bytes.lock().unwrap();
-            println!("Database::build took {:.3} seconds on {} threads, read {} bytes, {:.1} MB/s",
-                     (stop_time_ns - start_time_ns) as f64/1e9,
-                     threads,
-                     total_bytes,
-                     total_bytes as f64/((stop_time_ns - start_time_ns) as f64/1e3));
+        let diff_options = DiffOptions { color: diff_options.color.for_stdout(), ..diff_options.clone() };
+        let summary = self.show_diff_to_with_options(&current, &diff_options, stdout)?;
+        Ok((summary, stats))
+    }
+
+    /// As `check_with_options`, but returns the diff instead of printing
+    /// it, so that callers can inspect it, then print it with
+    /// `EntryDiff::report` if they like. A likely wrong root still fails
+    /// with `abort_on_wrong_root`, but otherwise isn't warned about.
+    /// Renames are not detected, as they are not part of the diff.
+    pub fn check_quiet(
+        &self,
+        root: impl AsRef<Path>,
+        build_options: &BuildOptions,
+        diff_options: &DiffOptions,
+    ) -> Result<EntryDiff, error::Error> {
+        let (current, _) = self.rebuild_for_check(root, Path::new(""), build_options)?;
+        if diff_options.abort_on_wrong_root {
+            if let Some((on_disk, in_database)) = self.likely_wrong_root(&current, diff_options) {
+                return Err(error::Error::LikelyWrongRoot { on_disk, in_database });
+            }
         }
-        let ref database = *database.lock().unwrap();
-        Ok(database.clone())
+        Ok(self.diff_with_options(&current, diff_options))
     }
 
-    pub fn show_diff(&self, other: &Database) -> DiffSummary {
-        let diff = self.diff(other);
-        diff.show_diff(&Path::new(".").to_owned(), 0);
-        diff.summarize_diff()
+    /// As `check_quiet`, but returns only whether the tree is clean and
+    /// the counts of changes, e.g. for an exit code
+    pub fn check_outcome(
+        &self,
+        root: impl AsRef<Path>,
+        build_options: &BuildOptions,
+        diff_options: &DiffOptions,
+    ) -> Result<CheckOutcome, error::Error> {
+        Ok(self.check_quiet(root, build_options, diff_options)?.outcome())
     }
 
-    pub fn check(
+    /// As `check_with_options`, but only for the directory at `path`
+    /// (relative to the database root): only the files under it are
+    /// walked and hashed, and the diff is printed as by `diff_subtree`,
+    /// with paths shown under `path`. With `strip_prefix_components`,
+    /// the directory is looked for under the prefix on disk. Renames
+    /// are not detected, and the root is never reported as likely wrong.
+    pub fn check_subtree(
         &self,
         root: impl AsRef<Path>,
-        features: Features,
-        threads: usize
+        path: impl AsRef<Path>,
+        build_options: &BuildOptions,
+        diff_options: &DiffOptions,
     ) -> Result<DiffSummary, error::Error> {
+        let path = normalize_path(path.as_ref());
+        // Fail before walking anything if there is nothing to compare to
+        self.subtree(&path)?;
+        let (current, _) = self.rebuild_for_check(root, &path, build_options)?;
+        let diff = self.diff_subtree_with_options(&current, &path, diff_options)?;
+        let diff_options = DiffOptions {
+            color: diff_options.color.for_stdout(),
+            path_prefix: Some(diff_options.display_path(&path)),
+            ..diff_options.clone()
+        };
+        let stdout = io::stdout();
+        diff.report(&diff_options, stdout.lock())
+    }
+
+    fn rebuild_for_check(
+        &self,
+        root: impl AsRef<Path>,
+        start: &Path,
+        build_options: &BuildOptions,
+    ) -> Result<(Database, BuildStats), error::Error> {
+        // Rebuilding with none of the recorded algorithms would report
+        // every file as changed (or, by size alone, as unchanged)
+        let recorded = self.features();
+        let requested = build_options.features;
+        if recorded.shares_hash(&recorded) && requested.shares_hash(&requested) &&
+            !recorded.shares_hash(&requested)
+        {
+            return Err(error::Error::FeatureMismatch { recorded, requested });
+        }
         // FIXME: This is non-interactive, but vastly more simple than
         // trying to implement the same functionality interactively.
-        let other = Database::build(root, features, threads, false)?;
-        Ok(self.show_diff(&other))
+        let (current, stats) = Database::build_with_callback(root, start, build_options, None, None)?;
+        self.ensure_comparable(&current)?;
+        Ok((current, stats))
+    }
+
+    /// Whether `current` added more files than `wrong_root_ratio` times
+    /// the number of files in this database, and if so, the numbers of
+    /// files in `current` and in this database
+    pub fn likely_wrong_root(&self, current: &Database, options: &DiffOptions) -> Option<(u64, u64)> {
+        let ratio = options.wrong_root_ratio?;
+        let (mut in_database, mut on_disk, mut added) = (0, 0, 0);
+        self.0.for_each_file(Path::new(""), &mut |_, _| in_database += 1);
+        current.0.for_each_file(Path::new(""), &mut |path, _| {
+            on_disk += 1;
+            if options.counts_one_sided(path) &&
+                !matches!(self.0.lookup(path), Some(Entry::File(_)))
+            {
+                added += 1;
+            }
+        });
+        if added > ratio.saturating_mul(in_database.max(1)) {
+            Some((on_disk, in_database))
+        } else {
+            None
+        }
+    }
+
+    /// Checks against an already built database of the current tree,
+    /// rather than walking the file system again. This lets one
+    /// current state be checked against several stored baselines.
+    pub fn check_against(&self, current: &Database) -> EntryDiff {
+        self.check_against_with_options(current, &DiffOptions::default())
+    }
+
+    pub fn check_against_with_options(
+        &self,
+        current: &Database,
+        options: &DiffOptions,
+    ) -> EntryDiff {
+        self.diff_with_options(current, options)
+    }
+
+    /// Loads the database stored at `path`, recognizing its format by
+    /// its contents rather than its name. The gzipped JSON written by
+    /// `dump_json` is the only format so far; anything else fails with
+    /// `Error::UnrecognizedFormat`. Errors are wrapped in `Error::Load`
+    /// along with the path.
+    pub fn load_file(path: impl AsRef<Path>) -> Result<Database, error::Error> {
+        let path = path.as_ref();
+        let load = || {
+            let mut file = File::open(path)?;
+            let mut magic = Vec::new();
+            (&mut file).take(GZIP_MAGIC.len() as u64).read_to_end(&mut magic)?;
+            if magic != GZIP_MAGIC {
+                return Err(error::Error::UnrecognizedFormat);
+            }
+            file.seek(io::SeekFrom::Start(0))?;
+            Database::load_json(file)
+        };
+        load().map_err(|cause| error::Error::Load { path: path.to_owned(), cause: Box::new(cause) })
+    }
+
+    /// Loads the databases stored at `old` and `new` with `load_file`,
+    /// and returns the diff between them along with its summary
+    pub fn diff_files(
+        old: impl AsRef<Path>,
+        new: impl AsRef<Path>,
+    ) -> Result<(EntryDiff, DiffSummary), error::Error> {
+        Database::diff_files_with_options(old, new, &DiffOptions::default())
+    }
+
+    pub fn diff_files_with_options(
+        old: impl AsRef<Path>,
+        new: impl AsRef<Path>,
+        options: &DiffOptions,
+    ) -> Result<(EntryDiff, DiffSummary), error::Error> {
+        let old = Database::load_file(old)?;
+        let new = Database::load_file(new)?;
+        old.ensure_comparable(&new)?;
+        let diff = old.diff_with_options(&new, options);
+        let summary = diff.summarize_diff();
+        Ok((diff, summary))
     }
 
     pub fn load_json(r: impl Read) -> Result<Database, error::Error> {
@@ -570,7 +5731,7 @@ impl Database {
 
         // Compute actual checksums of database
         let mut engines = Engines::new(features);
-        engines.input(&bytes[index+1..]);
+        engines.input_checksum(&bytes[index+1..]);
         let actual: DatabaseChecksum = engines.result().into();
 
         if expected.diff(&actual) {
@@ -578,13 +5739,35 @@ impl Database {
         }
 
         // Continue decoding database
-        Ok(serde_json::from_slice(&bytes[index+1..])?)
+        let entry = serde_json::from_slice(&bytes[index+1..])?;
+        let database = Database(entry, expected.root_name);
+        database.check_hash_lengths()?;
+        Ok(database)
     }
 
     pub fn dump_json<W>(&self, w: W, features: Features) -> Result<W, error::Error>
     where
         W: Write
     {
+        self.dump_json_with_level(w, features, DEFAULT_COMPRESSION_LEVEL)
+    }
+
+    /// Like `dump_json`, but with an explicit gzip compression level
+    /// from 0 (none, fastest) to 9 (smallest, slowest). `dump_json`
+    /// uses `DEFAULT_COMPRESSION_LEVEL`, a balance of the two.
+    pub fn dump_json_with_level<W>(
+        &self,
+        w: W,
+        features: Features,
+        level: u32,
+    ) -> Result<W, error::Error>
+    where
+        W: Write
+    {
+        if level > MAX_COMPRESSION_LEVEL {
+            return Err(error::Error::InvalidCompressionLevel(level));
+        }
+
         // Important: The encoded JSON **must not** contain the separator,
         // or else the format will break
 
@@ -593,15 +5776,16 @@ impl Database {
 
         // Compute checksums of encoded JSON
         let mut engines = Engines::new(features);
-        engines.input(&db_json[..]);
-        let checksum: DatabaseChecksum = engines.result().into();
+        engines.input_checksum(&db_json[..]);
+        let mut checksum: DatabaseChecksum = engines.result().into();
+        checksum.root_name = self.1.clone();
         let checksum_json = serde_json::to_vec(&checksum)?;
 
         // Make sure encoded JSON does not include separator
         assert!(!checksum_json.contains(&SEP));
 
         // Write checksum, separator and database
-        let mut e = GzEncoder::new(w, Compression::best());
+        let mut e = GzEncoder::new(w, Compression::new(level));
         e.write_all(&checksum_json[..])?;
         e.write_all(&vec![SEP][..])?;
         e.write_all(&db_json)?;
@@ -609,17 +5793,17 @@ impl Database {
     }
 }
 
-// impl std::fmt::Display for Database {
-//     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-//         for (path, entry) in self.0.iter() {
-//             match entry {
-//                 &Entry::File(ref hashes) => {
-//                     let hash: Vec<_> = hashes.sha2.0.iter().map(
-//                         |b| format!("{:02x}", b)).collect();
-//                     writeln!(f, "{} {}", hash.join(""), Path::new(path).display())?
-//                 }
-//             }
-//         }
-//         Ok(())
-//     }
-// }
+// A `sha256sum` manifest of the files that have a usable SHA2-256
+// hash, as written by `write_manifest`. Other files are left out
+// rather than failing, since formatting can't report which.
+impl fmt::Display for Database {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut manifest = Vec::new();
+        self.0.for_each_file(Path::new(""), &mut |path, metrics| {
+            if let Some(hash) = metrics.manifest_hash(ManifestAlgorithm::Sha256) {
+                write_manifest_line(&mut manifest, hash, path).expect("writing to a Vec can't fail");
+            }
+        });
+        f.write_str(&String::from_utf8_lossy(&manifest))
+    }
+}
@@ -0,0 +1,178 @@
+// Walks a tree starting from an open directory descriptor (Unix only).
+// Every entry is looked up with `fstatat` and opened with `openat`
+// relative to the descriptor of its parent, which is held open for as
+// long as its entries are visited, and symbolic links are never
+// followed (`O_NOFOLLOW`). So once the walk has started, renaming
+// directories or swapping one for a symbolic link can't lead it outside
+// the tree it was given.
+
+use std::ffi::{CStr, CString, OsStr};
+use std::fs::File;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, BorrowedFd, FromRawFd, IntoRawFd, OwnedFd};
+use std::path::{Component, Path, PathBuf};
+
+use ignore::overrides::Override;
+
+use crate::error;
+
+pub(crate) struct DirFdWalk<'a> {
+    pub(crate) max_depth: Option<usize>,
+    pub(crate) exclude: Option<&'a Override>,
+    pub(crate) prefix: Option<&'a Path>, // Walk only this directory under the root
+}
+
+impl<'a> DirFdWalk<'a> {
+    // Calls `visit` with the path (relative to `root`, or to `prefix`
+    // in it) and an open handle of every regular file in the tree.
+    // Hidden entries (whose names start with `.`) are skipped, as in
+    // the path-based walk, but may be named in `prefix`.
+    pub(crate) fn run<F>(&self, root: BorrowedFd<'_>, visit: &mut F) -> Result<(), error::Error>
+    where
+        F: FnMut(&Path, File) -> Result<(), error::Error>,
+    {
+        let mut root = root.try_clone_to_owned()?;
+        for component in self.prefix.iter().flat_map(|prefix| prefix.components()) {
+            let name = match component {
+                Component::CurDir => continue,
+                Component::Normal(name) => CString::new(name.as_bytes())
+                    .map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?,
+                _ => return Err(io::Error::from(io::ErrorKind::InvalidInput).into()),
+            };
+            root = openat(&root, &name, libc::O_DIRECTORY)?;
+        }
+        self.walk(&root, &mut PathBuf::new(), 0, visit)
+    }
+
+    fn walk<F>(
+        &self,
+        dir: &OwnedFd,
+        path: &mut PathBuf,
+        depth: usize,
+        visit: &mut F,
+    ) -> Result<(), error::Error>
+    where
+        F: FnMut(&Path, File) -> Result<(), error::Error>,
+    {
+        if self.max_depth.is_some_and(|max_depth| depth >= max_depth) {
+            return Ok(());
+        }
+        for name in read_dir_names(dir)? {
+            if name.as_bytes()[0] == b'.' {
+                continue;
+            }
+            path.push(OsStr::from_bytes(name.as_bytes()));
+            let result = self.visit_entry(dir, &name, path, depth, visit);
+            path.pop();
+            result?;
+        }
+        Ok(())
+    }
+
+    fn visit_entry<F>(
+        &self,
+        dir: &OwnedFd,
+        name: &CStr,
+        path: &mut PathBuf,
+        depth: usize,
+        visit: &mut F,
+    ) -> Result<(), error::Error>
+    where
+        F: FnMut(&Path, File) -> Result<(), error::Error>,
+    {
+        let mut stat = unsafe { std::mem::zeroed::<libc::stat>() };
+        if unsafe {
+            libc::fstatat(dir.as_raw_fd(), name.as_ptr(), &mut stat, libc::AT_SYMLINK_NOFOLLOW)
+        } != 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        let is_dir = match stat.st_mode & libc::S_IFMT {
+            libc::S_IFDIR => true,
+            libc::S_IFREG => false,
+            _ => return Ok(()), // Symbolic links, devices, sockets, etc.
+        };
+        if self.exclude.is_some_and(|exclude| exclude.matched(&*path, is_dir).is_ignore()) {
+            return Ok(());
+        }
+        let flags = if is_dir { libc::O_DIRECTORY } else { libc::O_NONBLOCK };
+        let fd = openat(dir, name, flags)?;
+        if is_dir {
+            self.walk(&fd, path, depth + 1, visit)
+        } else {
+            // The entry may have been replaced since `fstatat`
+            let file = File::from(fd);
+            if file.metadata()?.is_file() {
+                visit(path, file)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+fn openat(dir: &OwnedFd, name: &CStr, flags: libc::c_int) -> io::Result<OwnedFd> {
+    let flags = flags | libc::O_RDONLY | libc::O_NOFOLLOW | libc::O_CLOEXEC;
+    let fd = unsafe { libc::openat(dir.as_raw_fd(), name.as_ptr(), flags) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+// Lists the names in a directory, other than `.` and `..`, in order
+fn read_dir_names(dir: &OwnedFd) -> io::Result<Vec<CString>> {
+    // `fdopendir` takes ownership of its descriptor, and reads from the
+    // shared file offset, so give it a fresh descriptor of the directory
+    let dot = CStr::from_bytes_with_nul(b".\0").expect("unreachable");
+    let fd = openat(dir, dot, libc::O_DIRECTORY)?.into_raw_fd();
+    let stream = unsafe { libc::fdopendir(fd) };
+    if stream.is_null() {
+        let err = io::Error::last_os_error();
+        unsafe { libc::close(fd) };
+        return Err(err);
+    }
+
+    let mut names = Vec::new();
+    let result = loop {
+        // `readdir` signals errors only through errno
+        set_errno(0);
+        let entry = unsafe { libc::readdir(stream) };
+        if entry.is_null() {
+            let err = io::Error::last_os_error();
+            break if err.raw_os_error() == Some(0) { Ok(()) } else { Err(err) };
+        }
+        let name = unsafe { CStr::from_ptr((*entry).d_name.as_ptr()) };
+        if name.to_bytes() != b"." && name.to_bytes() != b".." {
+            names.push(name.to_owned());
+        }
+    };
+    unsafe { libc::closedir(stream) };
+    result?;
+    names.sort();
+    Ok(names)
+}
+
+#[cfg(any(target_os = "linux", target_os = "redox", target_os = "emscripten"))]
+fn set_errno(value: libc::c_int) {
+    unsafe { *libc::__errno_location() = value };
+}
+
+#[cfg(any(target_os = "android", target_os = "netbsd", target_os = "openbsd"))]
+fn set_errno(value: libc::c_int) {
+    unsafe { *libc::__errno() = value };
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios", target_os = "freebsd", target_os = "dragonfly"))]
+fn set_errno(value: libc::c_int) {
+    unsafe { *libc::__error() = value };
+}
+
+#[cfg(any(target_os = "solaris", target_os = "illumos"))]
+fn set_errno(value: libc::c_int) {
+    unsafe { *libc::___errno() = value };
+}
+
+#[cfg(target_os = "haiku")]
+fn set_errno(value: libc::c_int) {
+    unsafe { *libc::_errnop() = value };
+}
@@ -1,4 +1,5 @@
 use std;
+use ::globset;
 use ::ignore;
 use serde_json;
 
@@ -7,9 +8,59 @@ pub enum Error {
     Io(std::io::Error),
     StripPrefix(std::path::StripPrefixError),
     Ignore(ignore::Error),
+    Glob(globset::Error),
     Json(serde_json::Error),
     ChecksumMismatch,
     ParseError,
+    DuplicatePath(std::path::PathBuf),
+    InvalidCompressionLevel(u32),
+    InvalidHashTruncation(usize),
+    InvalidChunkSize(u64),
+    InvalidBufferSize(usize),
+    InvalidPreviewLength(usize),
+    Config(usize, String), // Line number and message
+    ConfigInTree(std::path::PathBuf), // A config file inside the tree it would check
+    NotInDatabase(std::path::PathBuf),
+    NotADirectory(std::path::PathBuf),
+    NonUtf8Path(std::path::PathBuf), // For formats that only hold UTF-8 paths
+    UnknownSize(std::path::PathBuf), // For formats that need every file's size
+    UnrecognizedFormat, // Not a database in any format we can read
+    Load {
+        path: std::path::PathBuf, // The database file that failed to load
+        cause: Box<Error>,
+    },
+    FeatureMismatch {
+        recorded: crate::database::Features, // Hashes stored in the database
+        requested: crate::database::Features, // Hashes asked for by the check
+    },
+    TruncationMismatch {
+        path: std::path::PathBuf,
+        recorded: Option<usize>, // Length hashes were cut to in the database
+        current: Option<usize>, // Length hashes were cut to on the other side
+    },
+    DecompressionMismatch {
+        path: std::path::PathBuf,
+        recorded: bool, // Whether the database hashed the decompressed contents
+    },
+    PathBindingMismatch {
+        path: std::path::PathBuf,
+        recorded: bool, // Whether the database hashes cover the path
+    },
+    LikelyWrongRoot {
+        on_disk: u64, // Files found under the checked root
+        in_database: u64,
+    },
+    Sha256Required(std::path::PathBuf), // Rebuild with --sha256 to list this file for backup
+    MissingHash {
+        path: std::path::PathBuf,
+        algorithm: &'static str, // The algorithm asked for
+    },
+    InvalidHashLength {
+        path: std::path::PathBuf,
+        algorithm: &'static str,
+        expected: usize, // In bytes
+        got: usize,
+    },
 }
 
 impl From<std::io::Error> for Error {
@@ -30,6 +81,12 @@ impl From<ignore::Error> for Error {
     }
 }
 
+impl From<globset::Error> for Error {
+    fn from(err: globset::Error) -> Error {
+        Error::Glob(err)
+    }
+}
+
 impl From<serde_json::Error> for Error {
     fn from(err: serde_json::Error) -> Error {
         Error::Json(err)
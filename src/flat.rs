@@ -0,0 +1,143 @@
+// A flat alternative to the nested `Database` tree: a single vector of
+// (path, metrics) pairs sorted by path. This needs one allocation per
+// file rather than one per directory level, and iterates in order
+// without chasing pointers. Since `Path` ordering compares component by
+// component, the files under any directory form a contiguous range,
+// which is what makes the tree-style accessors below cheap.
+
+use std::path::{Path, PathBuf};
+use std::cmp::Ordering;
+
+use crate::database::{self, BuildOptions, Database, DiffOptions, Entry, Metrics, MetricsDiff};
+use crate::error;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FlatDatabase(Vec<(PathBuf, Metrics)>);
+
+#[derive(Debug)]
+pub enum FlatDiff {
+    Added,
+    Removed,
+    Changed(MetricsDiff),
+}
+
+impl FlatDatabase {
+    /// Builds the database with the same walk as
+    /// `Database::build_with_options`, so both agree on every file,
+    /// without building the tree. Only files are kept: symlinks,
+    /// special files and empty directories are left out.
+    pub fn build_with_options(
+        root: impl AsRef<Path>,
+        options: &BuildOptions,
+    ) -> Result<FlatDatabase, error::Error> {
+        let mut entries = Vec::new();
+        database::walk_tree(root.as_ref(), Path::new(""), options, None, None, &mut |path, entry| {
+            if let Entry::File(metrics) = entry {
+                let path = if options.fold_case { database::fold_path_case(path) } else { path.to_owned() };
+                entries.push((path, metrics));
+            }
+            Ok(())
+        })?;
+        entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+        // Only case folding can make two files land on the same path
+        if let Some(pair) = entries.windows(2).find(|pair| pair[0].0 == pair[1].0) {
+            return Err(error::Error::DuplicatePath(pair[1].0.clone()));
+        }
+        Ok(FlatDatabase(entries))
+    }
+
+    pub fn from_database(database: &Database) -> FlatDatabase {
+        // The tree is visited in sorted order, so no sort is needed
+        let mut entries = Vec::new();
+        database.for_each_file(|path, metrics| entries.push((path.to_owned(), metrics.clone())));
+        FlatDatabase(entries)
+    }
+
+    pub fn to_database(&self) -> Database {
+        let mut database = Database::default();
+        for (path, metrics) in self.0.iter() {
+            database.insert(path, Entry::File(metrics.clone()), false)
+                .expect("paths in a flat database are unique");
+        }
+        database
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Path, &Metrics)> {
+        self.0.iter().map(|(path, metrics)| (path.as_path(), metrics))
+    }
+
+    pub fn lookup(&self, path: &Path) -> Option<&Metrics> {
+        self.0
+            .binary_search_by(|(key, _)| key.as_path().cmp(path))
+            .ok()
+            .map(|index| &self.0[index].1)
+    }
+
+    /// Returns all files under the directory `path`, recursively. An
+    /// empty path returns every file.
+    pub fn directory(&self, path: &Path) -> &[(PathBuf, Metrics)] {
+        let start = self.0.partition_point(|(key, _)| key.as_path() < path);
+        let len = self.0[start..].partition_point(|(key, _)| key.starts_with(path));
+        &self.0[start..start + len]
+    }
+
+    /// Compares two databases with a merge join over the sorted
+    /// entries. Files that are unchanged and not suspicious are
+    /// omitted from the result.
+    pub fn diff(&self, other: &FlatDatabase, options: &DiffOptions) -> Vec<(PathBuf, FlatDiff)> {
+        let mut result = Vec::new();
+        let mut old_iter = self.0.iter().peekable();
+        let mut new_iter = other.0.iter().peekable();
+        loop {
+            let order = match (old_iter.peek(), new_iter.peek()) {
+                (Some((old_path, _)), Some((new_path, _))) => old_path.cmp(new_path),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => break,
+            };
+            let path = match order {
+                Ordering::Less | Ordering::Equal => &old_iter.peek().unwrap().0,
+                Ordering::Greater => &new_iter.peek().unwrap().0,
+            };
+            if options.is_ignored(path) {
+                match order {
+                    Ordering::Less => { old_iter.next(); }
+                    Ordering::Greater => { new_iter.next(); }
+                    Ordering::Equal => { old_iter.next(); new_iter.next(); }
+                }
+                continue;
+            }
+            match order {
+                Ordering::Less => {
+                    let (path, _) = old_iter.next().unwrap();
+                    if !options.shared_only {
+                        result.push((path.clone(), FlatDiff::Removed));
+                    }
+                }
+                Ordering::Greater => {
+                    let (path, _) = new_iter.next().unwrap();
+                    if !options.shared_only {
+                        result.push((path.clone(), FlatDiff::Added));
+                    }
+                }
+                Ordering::Equal => {
+                    let (path, old) = old_iter.next().unwrap();
+                    let (_, new) = new_iter.next().unwrap();
+                    let diff = old.diff(new, options);
+                    if diff.is_changed() || diff.is_suspicious() {
+                        result.push((path.clone(), FlatDiff::Changed(diff)));
+                    }
+                }
+            }
+        }
+        result
+    }
+}
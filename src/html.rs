@@ -0,0 +1,160 @@
+// Renders a diff as a self-contained HTML report, for sharing results
+// with people who won't read the terminal output. Directories become
+// collapsible `<details>` elements (no JavaScript needed), and all
+// suspicious findings are repeated in a section at the top.
+
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::database::{DiffFormatter, EntryDiff, EntryKind};
+
+pub struct HtmlFormatter;
+
+const STYLE: &str = "\
+body { font-family: sans-serif; margin: 2em; }
+.suspicious { color: #b00020; }
+.changed { color: #b35c00; }
+.added { color: #1b7a1b; }
+.removed { color: #b00020; }
+.summary { color: #555; }
+.findings { border: 2px solid #b00020; padding: 0.5em 1em; margin-bottom: 1em; }
+details { margin-left: 1.5em; }
+ul { margin: 0.2em 0; }
+code { font-size: 1.1em; }";
+
+pub fn escape_html(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '"' => result.push_str("&quot;"),
+            '\'' => result.push_str("&#39;"),
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+fn escape_path(path: &Path) -> String {
+    escape_html(&path.to_string_lossy())
+}
+
+fn collect_findings(diff: &EntryDiff, path: &mut PathBuf, findings: &mut Vec<(PathBuf, String)>) {
+    match diff {
+        EntryDiff::Directory(entries, _) => {
+            for (key, entry) in entries.iter() {
+                path.push(key);
+                collect_findings(entry, path, findings);
+                path.pop();
+            }
+        }
+        EntryDiff::File(diff) => {
+            for reason in diff.suspicious_reasons() {
+                findings.push((path.clone(), reason.to_owned()));
+            }
+        }
+        EntryDiff::KindChanged { was, now, .. } if EntryKind::is_suspicious_change(*was, *now) => {
+            findings.push((path.clone(), "regular file swapped with a special file".to_owned()));
+        }
+        EntryDiff::Added { .. } => {
+            for (path, anomalies) in diff.added_anomalies(path) {
+                findings.push((path, format!("added with a name that has {}",
+                                             anomalies.descriptions().join(", "))));
+            }
+        }
+        EntryDiff::Symlink { .. } | EntryDiff::Special(_) | EntryDiff::KindChanged { .. } |
+        EntryDiff::Removed { .. } => (),
+    }
+}
+
+fn write_tree(diff: &EntryDiff, name: &Path, w: &mut dyn Write) -> io::Result<()> {
+    match diff {
+        EntryDiff::Directory(entries, stats) => {
+            if stats.changed == 0 && stats.added == 0 && stats.removed == 0 && stats.suspicious == 0 {
+                return Ok(());
+            }
+            writeln!(w, "<details open><summary><code>{}/</code> \
+                         <span class=\"summary\">{} changed, {} added, {} removed, \
+                         {} unchanged</span></summary>",
+                     escape_path(name), stats.changed, stats.added, stats.removed,
+                     stats.unchanged)?;
+            writeln!(w, "<ul>")?;
+            for (key, entry) in entries.iter() {
+                write_tree(entry, key, w)?;
+            }
+            writeln!(w, "</ul></details>")?;
+        }
+        EntryDiff::File(diff) => {
+            let reasons = diff.suspicious_reasons();
+            if !reasons.is_empty() {
+                writeln!(w, "<li class=\"suspicious\"><code>{}</code> suspicious: {}</li>",
+                         escape_path(name), escape_html(&reasons.join("; ")))?;
+            } else if diff.is_changed() {
+                writeln!(w, "<li class=\"changed\"><code>{}</code> changed</li>",
+                         escape_path(name))?;
+            }
+        }
+        EntryDiff::Symlink { old_target, new_target } => {
+            if old_target != new_target {
+                writeln!(w, "<li class=\"changed\"><code>{}</code> symlink target changed \
+                             from <code>{}</code> to <code>{}</code></li>",
+                         escape_path(name), escape_path(old_target), escape_path(new_target))?;
+            }
+        }
+        EntryDiff::Special(_) => (),
+        EntryDiff::KindChanged { .. } => {
+            writeln!(w, "<li class=\"changed\"><code>{}</code> changed type</li>",
+                     escape_path(name))?;
+        }
+        EntryDiff::Added { anomalies, .. } if !anomalies.is_empty() => {
+            writeln!(w, "<li class=\"suspicious\"><code>{}</code> added, suspicious: name has {}</li>",
+                     escape_html(&format!("{:?}", name)), escape_html(&anomalies.descriptions().join(", ")))?;
+        }
+        EntryDiff::Added { .. } if !diff.added_anomalies(name).is_empty() => {
+            writeln!(w, "<li class=\"suspicious\"><code>{}</code> added, suspicious: names in it \
+                         have anomalies</li>", escape_path(name))?;
+        }
+        EntryDiff::Added { .. } => {
+            writeln!(w, "<li class=\"added\"><code>{}</code> added</li>", escape_path(name))?;
+        }
+        EntryDiff::Removed { .. } => {
+            writeln!(w, "<li class=\"removed\"><code>{}</code> removed</li>", escape_path(name))?;
+        }
+    }
+    Ok(())
+}
+
+impl DiffFormatter for HtmlFormatter {
+    fn format(&self, diff: &EntryDiff, w: &mut dyn Write) -> io::Result<()> {
+        writeln!(w, "<!DOCTYPE html>")?;
+        writeln!(w, "<html><head><meta charset=\"utf-8\">")?;
+        writeln!(w, "<title>Integrity check report</title>")?;
+        writeln!(w, "<style>\n{}\n</style></head><body>", STYLE)?;
+        writeln!(w, "<h1>Integrity check report</h1>")?;
+
+        let mut findings = Vec::new();
+        collect_findings(diff, &mut PathBuf::new(), &mut findings);
+        if !findings.is_empty() {
+            writeln!(w, "<div class=\"findings\"><h2 class=\"suspicious\">\
+                         Suspicious findings ({})</h2><ul>", findings.len())?;
+            for (path, reason) in findings.iter() {
+                writeln!(w, "<li><code>{}</code>: {}</li>",
+                         escape_path(path), escape_html(reason))?;
+            }
+            writeln!(w, "</ul></div>")?;
+        }
+
+        writeln!(w, "<h2>Changes</h2>")?;
+        match diff {
+            EntryDiff::Directory(_, stats)
+                if stats.changed == 0 && stats.added == 0 && stats.removed == 0 && stats.suspicious == 0 =>
+            {
+                writeln!(w, "<p>No changes.</p>")?;
+            }
+            _ => write_tree(diff, Path::new("."), w)?,
+        }
+        writeln!(w, "</body></html>")
+    }
+}
@@ -1,6 +1,24 @@
 #[macro_use]
 extern crate serde_derive;
 
+pub mod config;
 pub mod database;
 pub mod error;
+pub mod flat;
+pub mod html;
+#[cfg(any(test, feature = "testutil"))]
+pub mod testutil;
+mod acl;
+mod bagit;
 mod base64;
+mod blake3;
+mod budget;
+mod crc32;
+#[cfg(unix)]
+mod dirfd;
+mod mmap;
+mod ospath;
+mod progress;
+mod ratelimit;
+mod treehash;
+mod xxhash;
@@ -4,27 +4,40 @@ extern crate clap;
 use std::ffi::OsString;
 use std::fs::{File, OpenOptions};
 use std::io::{self, Write};
+use std::path::PathBuf;
 
-use integrity_checker::database::{Features, Database, DiffSummary};
+use globset::{Glob, GlobSetBuilder};
+
+use integrity_checker::config::Config;
+use integrity_checker::database::{
+    BuildOptions, ColorMode, Features, Database, DiffMode, DiffOptions, DiffSummary, PathNormalization,
+    DEFAULT_COMPRESSION_LEVEL, MAX_COMPRESSION_LEVEL,
+};
 use integrity_checker::error;
 
 enum Action {
     Build {
         db_path: OsString,
         dir_path: OsString,
-        features: Features,
-        threads: usize,
+        options: BuildOptions,
         force: bool,
+        compression_level: u32,
+        previous_path: Option<OsString>,
     },
     Check {
         db_path: OsString,
         dir_path: OsString,
-        features: Features,
-        threads: usize,
+        options: BuildOptions,
+        diff_options: DiffOptions,
     },
     Diff {
         old_path: OsString,
         new_path: OsString,
+        options: DiffOptions,
+    },
+    Missing {
+        db_path: OsString,
+        dir_path: OsString,
     },
     SelfCheck { db_path: OsString },
 }
@@ -39,15 +52,28 @@ fn validate_usize(s: String) -> Result<(), String> {
     s.parse::<usize>().map(|_| ()).map_err(|e| e.to_string())
 }
 
+fn validate_i64(s: String) -> Result<(), String> {
+    s.parse::<i64>().map(|_| ()).map_err(|e| e.to_string())
+}
+
+fn validate_compression_level(s: String) -> Result<(), String> {
+    match s.parse::<u32>() {
+        Ok(level) if level <= MAX_COMPRESSION_LEVEL => Ok(()),
+        Ok(_) => Err(format!("must be at most {}", MAX_COMPRESSION_LEVEL)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
 trait DefaultFlags {
     fn add_default_flags(self) -> Self;
+    fn add_diff_flags(self) -> Self;
 }
 
 impl<'a, 'b> DefaultFlags for clap::App<'a, 'b> {
     fn add_default_flags(self) -> Self {
-        self
+        let mut app = self
             .arg(clap::Arg::with_name("threads")
-                 .help("Number of threads to use")
+                 .help("Number of threads to use (default: number of logical CPUs)")
                  .short("j").long("threads")
                  .takes_value(true)
                  .validator(validate_usize))
@@ -67,12 +93,202 @@ impl<'a, 'b> DefaultFlags for clap::App<'a, 'b> {
                  .help("Disable use of BLAKE2b algorithm")
                  .long("no-blake2")
                  .overrides_with("blake2"))
+            .arg(clap::Arg::with_name("sha256")
+                 .help("Enable use of SHA2-256 algorithm")
+                 .long("sha256")
+                 .overrides_with("no-sha256"))
+            .arg(clap::Arg::with_name("no-sha256")
+                 .help("Disable use of SHA2-256 algorithm")
+                 .long("no-sha256")
+                 .overrides_with("sha256"))
+            .arg(clap::Arg::with_name("sha512")
+                 .help("Enable use of SHA2-512 algorithm (full 512-bit digests)")
+                 .long("sha512")
+                 .overrides_with("no-sha512"))
+            .arg(clap::Arg::with_name("no-sha512")
+                 .help("Disable use of SHA2-512 algorithm")
+                 .long("no-sha512")
+                 .overrides_with("sha512"))
+            .arg(clap::Arg::with_name("blake3")
+                 .help("Enable use of BLAKE3 algorithm")
+                 .long("blake3")
+                 .overrides_with("no-blake3"))
+            .arg(clap::Arg::with_name("no-blake3")
+                 .help("Disable use of BLAKE3 algorithm")
+                 .long("no-blake3")
+                 .overrides_with("blake3"))
+            .arg(clap::Arg::with_name("xxh64")
+                 .help("Enable use of XXH64 (non-cryptographic) algorithm")
+                 .long("xxh64")
+                 .overrides_with("no-xxh64"))
+            .arg(clap::Arg::with_name("no-xxh64")
+                 .help("Disable use of XXH64 (non-cryptographic) algorithm")
+                 .long("no-xxh64")
+                 .overrides_with("xxh64"))
+            .arg(clap::Arg::with_name("crc32")
+                 .help("Enable use of CRC-32 checksum (for legacy manifests)")
+                 .long("crc32")
+                 .overrides_with("no-crc32"))
+            .arg(clap::Arg::with_name("no-crc32")
+                 .help("Disable use of CRC-32 checksum")
+                 .long("no-crc32")
+                 .overrides_with("crc32"))
+            .arg(clap::Arg::with_name("fold-case")
+                 .help("Store path names case folded (lowercase)")
+                 .long("fold-case"))
+            .arg(clap::Arg::with_name("config")
+                 .help("Read options from this file (for check, it must be outside <path>)")
+                 .long("config")
+                 .takes_value(true))
+            .arg(clap::Arg::with_name("exclude")
+                 .help("Leave paths matching a glob out of the database")
+                 .long("exclude")
+                 .takes_value(true)
+                 .multiple(true)
+                 .number_of_values(1))
+            .arg(clap::Arg::with_name("decompress")
+                 .help("Hash the decompressed contents of gzip files matching a glob")
+                 .long("decompress")
+                 .takes_value(true)
+                 .multiple(true)
+                 .number_of_values(1))
+            .arg(clap::Arg::with_name("max-depth")
+                 .help("Descend at most this many directories below the root")
+                 .long("max-depth")
+                 .takes_value(true)
+                 .validator(validate_usize))
+            .arg(clap::Arg::with_name("memory-budget")
+                 .help("Limit the read buffers in use across all threads to this many bytes")
+                 .long("memory-budget")
+                 .takes_value(true)
+                 .validator(validate_usize))
+            .arg(clap::Arg::with_name("buffer-size")
+                 .help("Read files through a buffer of this many bytes (default: 64 KiB)")
+                 .long("buffer-size")
+                 .takes_value(true)
+                 .validator(validate_usize))
+            .arg(clap::Arg::with_name("mmap")
+                 .help("Read files by mapping them into memory (Linux only)")
+                 .long("mmap"))
+            .arg(clap::Arg::with_name("max-read-rate")
+                 .help("Limit reads from disk to this many bytes per second")
+                 .long("max-read-rate")
+                 .takes_value(true)
+                 .validator(validate_usize))
+            .arg(clap::Arg::with_name("truncate-hashes")
+                 .help("Store only this many leading bytes of each hash (smaller, but weaker)")
+                 .long("truncate-hashes")
+                 .takes_value(true)
+                 .validator(validate_usize))
+            .arg(clap::Arg::with_name("modified-before")
+                 .help("Leave out files modified after this time (seconds since the Unix epoch)")
+                 .long("modified-before")
+                 .takes_value(true)
+                 .validator(validate_i64))
+            .arg(clap::Arg::with_name("follow-links")
+                 .help("Follow symbolic links")
+                 .long("follow-links"))
+            .arg(clap::Arg::with_name("bind-paths")
+                 .help("Hash each file's path along with its contents (not compatible with sha256sum)")
+                 .long("bind-paths"))
+            .arg(clap::Arg::with_name("record-inode")
+                 .help("Store inode numbers, to detect renames of modified files")
+                 .long("record-inode"))
+            .arg(clap::Arg::with_name("record-acl")
+                 .help("Store POSIX ACLs (Linux only), to detect changes in who can access files")
+                 .long("record-acl"))
+            .arg(clap::Arg::with_name("first-block-hash")
+                 .help("Also store a hash of each file's first 4 KiB, for quick triage of changes")
+                 .long("first-block-hash"))
+            .arg(clap::Arg::with_name("preview-bytes")
+                 .help("Also store up to this many leading bytes of each file (at most 256)")
+                 .long("preview-bytes")
+                 .takes_value(true)
+                 .validator(validate_usize))
+            .arg(clap::Arg::with_name("tree-hash-threshold")
+                 .help("Also hash files of at least this many bytes in chunks, on several threads")
+                 .long("tree-hash-threshold")
+                 .takes_value(true)
+                 .validator(validate_usize))
+            .arg(clap::Arg::with_name("tree-hash-chunk-size")
+                 .help("Chunk size of tree hashes, in bytes (default: 64 MiB)")
+                 .long("tree-hash-chunk-size")
+                 .takes_value(true)
+                 .validator(validate_usize))
+            .arg(clap::Arg::with_name("strip-prefix")
+                 .help("Scan only this directory under the root, e.g. .zfs/snapshot/name")
+                 .long("strip-prefix")
+                 .takes_value(true));
+        if cfg!(feature = "progress") {
+            app = app.arg(clap::Arg::with_name("progress")
+                          .help("Show a status line on stderr while hashing")
+                          .long("progress"));
+        }
+        app
     }
-}
 
-fn parse_features(matches: &clap::ArgMatches) -> Features {
-    let defaults = Features::default();
+    fn add_diff_flags(self) -> Self {
+        self
+            .arg(clap::Arg::with_name("fast")
+                 .help("Compare only sizes and XXH64 hashes (databases need --xxh64)")
+                 .long("fast"))
+            .arg(clap::Arg::with_name("group-by-confidence")
+                 .help("List changed files grouped by confidence tier, most alarming first")
+                 .long("group-by-confidence"))
+            .arg(clap::Arg::with_name("suspicious-only")
+                 .help("Report only suspicious files, leaving out ordinary changes")
+                 .long("suspicious-only"))
+            .arg(clap::Arg::with_name("paranoid")
+                 .help("Flag files where one hash matches but another does not")
+                 .long("paranoid"))
+            .arg(clap::Arg::with_name("check-mtime")
+                 .help("Flag files whose modification time moved backward")
+                 .long("check-mtime"))
+            .arg(clap::Arg::with_name("compare-mtime")
+                 .help("Count files whose modification time differs as changed")
+                 .long("compare-mtime"))
+            .arg(clap::Arg::with_name("ignore-privilege-escalation")
+                 .help("Don't treat files that gained setuid, setgid, world-write or execute bits as suspicious")
+                 .long("ignore-privilege-escalation"))
+            .arg(clap::Arg::with_name("show-touched")
+                 .help("List files whose contents are unchanged but whose modification time differs")
+                 .long("show-touched"))
+            .arg(clap::Arg::with_name("flag-added-empty")
+                 .help("Treat newly added empty files as suspicious")
+                 .long("flag-added-empty"))
+            .arg(clap::Arg::with_name("flag-nonascii")
+                 .help("Treat files that gained non-ASCII bytes as suspicious")
+                 .long("flag-nonascii"))
+            .arg(clap::Arg::with_name("path-prefix")
+                 .help("Prepend this path to every reported path")
+                 .long("path-prefix")
+                 .takes_value(true))
+            .arg(clap::Arg::with_name("shared-only")
+                 .help("Compare only files present on both sides, ignoring additions and removals")
+                 .long("shared-only"))
+            .arg(clap::Arg::with_name("color")
+                 .help("Color the report: always, never, or auto (only on a terminal)")
+                 .long("color")
+                 .takes_value(true)
+                 .possible_values(&["always", "auto", "never"]))
+            .arg(clap::Arg::with_name("normalize-paths")
+                 .help("Normalize file names on both sides to a Unicode form before comparing")
+                 .long("normalize-paths")
+                 .takes_value(true)
+                 .possible_values(&["nfc", "nfd"]))
+            .arg(clap::Arg::with_name("renames")
+                 .help("Report renamed files, and files that swapped contents")
+                 .long("renames"))
+            .arg(clap::Arg::with_name("ignore")
+                 .help("Exclude paths matching a glob from the comparison")
+                 .long("ignore")
+                 .takes_value(true)
+                 .multiple(true)
+                 .number_of_values(1))
+    }
+}
 
+fn parse_features(matches: &clap::ArgMatches, defaults: Features) -> Features {
     let sha2 = if matches.is_present("sha2") {
         true
     } else if matches.is_present("no-sha2") {
@@ -89,17 +305,159 @@ fn parse_features(matches: &clap::ArgMatches) -> Features {
         defaults.blake2b
     };
 
-    Features { sha2: sha2, blake2b: blake2b }
+    let sha256 = if matches.is_present("sha256") {
+        true
+    } else if matches.is_present("no-sha256") {
+        false
+    } else {
+        defaults.sha256
+    };
+
+    let sha512 = if matches.is_present("sha512") {
+        true
+    } else if matches.is_present("no-sha512") {
+        false
+    } else {
+        defaults.sha512
+    };
+
+    let blake3 = if matches.is_present("blake3") {
+        true
+    } else if matches.is_present("no-blake3") {
+        false
+    } else {
+        defaults.blake3
+    };
+
+    let xxh64 = if matches.is_present("xxh64") {
+        true
+    } else if matches.is_present("no-xxh64") {
+        false
+    } else {
+        defaults.xxh64
+    };
+
+    let crc32 = if matches.is_present("crc32") {
+        true
+    } else if matches.is_present("no-crc32") {
+        false
+    } else {
+        defaults.crc32
+    };
+
+    Features { sha2, blake2b, sha256, sha512, blake3, xxh64, crc32 }
 }
 
 fn parse_threads(matches: &clap::ArgMatches) -> usize {
     match matches.value_of("threads") {
-        None => 1, // FIXME: Pick a reasonable number of threads
+        None => std::thread::available_parallelism().map_or(1, |threads| threads.get()),
         Some(threads) => threads.parse().unwrap(),
     }
 }
 
-fn parse_args() -> Action {
+fn parse_build_options(matches: &clap::ArgMatches, check: bool)
+                       -> Result<BuildOptions, error::Error> {
+    // Options from the config file apply first, so that flags override them
+    let config = match matches.value_of_os("config") {
+        Some(path) if check => Some(Config::load_outside(path, matches.value_of_os("path").unwrap())?),
+        Some(path) => Some(Config::load(path)?),
+        None => None,
+    };
+    let mut options = BuildOptions::default();
+    if let Some(config) = config {
+        config.apply(&mut options);
+    }
+
+    options.features = parse_features(matches, options.features);
+    options.threads = parse_threads(matches);
+    options.fold_case = matches.is_present("fold-case");
+    options.strip_prefix_components = matches.value_of_os("strip-prefix").map(PathBuf::from);
+    if let Some(globs) = matches.values_of("exclude") {
+        options.exclude.extend(globs.map(String::from));
+    }
+    if let Some(globs) = matches.values_of("decompress") {
+        options.decompress.extend(globs.map(String::from));
+    }
+    if let Some(depth) = matches.value_of("max-depth") {
+        options.max_depth = Some(depth.parse().unwrap());
+    }
+    if matches.is_present("follow-links") {
+        options.follow_links = true;
+    }
+    options.record_inode = matches.is_present("record-inode");
+    options.record_acl = matches.is_present("record-acl");
+    options.first_block_hash = matches.is_present("first-block-hash");
+    if let Some(len) = matches.value_of("preview-bytes") {
+        options.preview_len = len.parse().unwrap();
+    }
+    options.progress = matches.is_present("progress");
+    options.bind_paths = matches.is_present("bind-paths");
+    options.mmap = matches.is_present("mmap");
+    if let Some(budget) = matches.value_of("memory-budget") {
+        options.memory_budget = Some(budget.parse().unwrap());
+    }
+    if let Some(size) = matches.value_of("buffer-size") {
+        options.buffer_size = size.parse().unwrap();
+    }
+    if let Some(rate) = matches.value_of("max-read-rate") {
+        options.max_read_bytes_per_sec = Some(rate.parse().unwrap());
+    }
+    if let Some(len) = matches.value_of("truncate-hashes") {
+        options.truncate_hashes = Some(len.parse().unwrap());
+    }
+    if let Some(threshold) = matches.value_of("tree-hash-threshold") {
+        options.tree_hash_threshold = Some(threshold.parse().unwrap());
+    }
+    if let Some(chunk_size) = matches.value_of("tree-hash-chunk-size") {
+        options.tree_hash_chunk_size = chunk_size.parse().unwrap();
+    }
+    if let Some(cutoff) = matches.value_of("modified-before") {
+        options.modified_before = Some(cutoff.parse().unwrap());
+    }
+    Ok(options)
+}
+
+fn parse_diff_options(matches: &clap::ArgMatches) -> Result<DiffOptions, error::Error> {
+    let ignore = match matches.values_of("ignore") {
+        None => None,
+        Some(patterns) => {
+            let mut builder = GlobSetBuilder::new();
+            for pattern in patterns {
+                builder.add(Glob::new(pattern)?);
+            }
+            Some(builder.build()?)
+        }
+    };
+    Ok(DiffOptions {
+        mode: if matches.is_present("fast") { DiffMode::Fast } else { DiffMode::Full },
+        ignore,
+        paranoid: matches.is_present("paranoid"),
+        check_mtime: matches.is_present("check-mtime"),
+        compare_mtime: matches.is_present("compare-mtime"),
+        show_touched: matches.is_present("show-touched"),
+        flag_added_empty: matches.is_present("flag-added-empty"),
+        flag_nonascii: matches.is_present("flag-nonascii"),
+        ignore_privilege_escalation: matches.is_present("ignore-privilege-escalation"),
+        detect_renames: matches.is_present("renames"),
+        shared_only: matches.is_present("shared-only"),
+        group_by_confidence: matches.is_present("group-by-confidence"),
+        suspicious_only: matches.is_present("suspicious-only"),
+        path_prefix: matches.value_of_os("path-prefix").map(PathBuf::from),
+        normalize_paths: match matches.value_of("normalize-paths") {
+            Some("nfc") => Some(PathNormalization::Nfc),
+            Some("nfd") => Some(PathNormalization::Nfd),
+            _ => None,
+        },
+        color: match matches.value_of("color") {
+            Some("always") => ColorMode::Always,
+            Some("never") => ColorMode::Never,
+            _ => ColorMode::Auto,
+        },
+        ..DiffOptions::default()
+    })
+}
+
+fn parse_args() -> Result<Action, error::Error> {
     let matches = clap::App::new("Integrity Checker")
         .version(crate_version!())
         .setting(clap::AppSettings::SubcommandRequiredElseHelp)
@@ -116,6 +474,15 @@ fn parse_args() -> Action {
                     .arg(clap::Arg::with_name("force")
                          .help("Overwrite existing file")
                          .short("f").long("force"))
+                    .arg(clap::Arg::with_name("compression-level")
+                         .help("Gzip compression level, from 0 (fastest) to 9 (smallest); the default is 6")
+                         .long("compression-level")
+                         .takes_value(true)
+                         .validator(validate_compression_level))
+                    .arg(clap::Arg::with_name("previous")
+                         .help("Reuse chunk hashes of files that only grew since this database")
+                         .long("previous")
+                         .takes_value(true))
                     .add_default_flags())
         .subcommand(clap::SubCommand::with_name("check")
                     .about("Check an integrity database against a directory")
@@ -127,7 +494,17 @@ fn parse_args() -> Action {
                          .help("Path of file or directory to scan")
                          .required(true)
                          .index(2))
-                    .add_default_flags())
+                    .arg(clap::Arg::with_name("wrong-root-ratio")
+                         .help("Warn of a likely wrong root when over this many times as many \
+                                files were added as the database holds (0 disables)")
+                         .long("wrong-root-ratio")
+                         .takes_value(true)
+                         .default_value("100"))
+                    .arg(clap::Arg::with_name("abort-on-wrong-root")
+                         .help("Fail instead of warning when the root is likely wrong")
+                         .long("abort-on-wrong-root"))
+                    .add_default_flags()
+                    .add_diff_flags())
         .subcommand(clap::SubCommand::with_name("diff")
                     .about("Compare two integrity databases")
                     .arg(clap::Arg::with_name("old")
@@ -137,6 +514,17 @@ fn parse_args() -> Action {
                     .arg(clap::Arg::with_name("new")
                          .help("Path of new integrity database")
                          .required(true)
+                         .index(2))
+                    .add_diff_flags())
+        .subcommand(clap::SubCommand::with_name("missing")
+                    .about("List files in an integrity database that are missing from a directory")
+                    .arg(clap::Arg::with_name("database")
+                         .help("Path of integrity database to read")
+                         .required(true)
+                         .index(1))
+                    .arg(clap::Arg::with_name("path")
+                         .help("Path of file or directory to scan")
+                         .required(true)
                          .index(2)))
         .subcommand(clap::SubCommand::with_name("selfcheck")
                     .about("Check the internal consistency of an integrity database")
@@ -150,35 +538,55 @@ fn parse_args() -> Action {
                     \n    2       Suspicious changes \
                     \n   -1       Error")
         .get_matches();
-    match matches.subcommand() {
+    Ok(match matches.subcommand() {
         ("build", Some(submatches)) => Action::Build {
             db_path: submatches.value_of_os("database").unwrap().to_owned(),
             dir_path: submatches.value_of_os("path").unwrap().to_owned(),
-            features: parse_features(submatches),
-            threads: parse_threads(submatches),
+            options: BuildOptions {
+                verbose: true,
+                ..parse_build_options(submatches, false)?
+            },
             force: submatches.is_present("force"),
+            compression_level: match submatches.value_of("compression-level") {
+                None => DEFAULT_COMPRESSION_LEVEL,
+                Some(level) => level.parse().unwrap(),
+            },
+            previous_path: submatches.value_of_os("previous").map(OsString::from),
         },
         ("check", Some(submatches)) => Action::Check {
             db_path: submatches.value_of_os("database").unwrap().to_owned(),
             dir_path: submatches.value_of_os("path").unwrap().to_owned(),
-            features: parse_features(submatches),
-            threads: parse_threads(submatches),
+            options: parse_build_options(submatches, true)?,
+            diff_options: DiffOptions {
+                wrong_root_ratio: match submatches.value_of("wrong-root-ratio").unwrap() {
+                    "0" => None,
+                    ratio => Some(ratio.parse().unwrap()),
+                },
+                abort_on_wrong_root: submatches.is_present("abort-on-wrong-root"),
+                ..parse_diff_options(submatches)?
+            },
         },
         ("diff", Some(submatches)) => Action::Diff {
             old_path: submatches.value_of_os("old").unwrap().to_owned(),
             new_path: submatches.value_of_os("new").unwrap().to_owned(),
+            options: parse_diff_options(submatches)?,
+        },
+        ("missing", Some(submatches)) => Action::Missing {
+            db_path: submatches.value_of_os("database").unwrap().to_owned(),
+            dir_path: submatches.value_of_os("path").unwrap().to_owned(),
         },
         ("selfcheck", Some(submatches)) => Action::SelfCheck {
             db_path: submatches.value_of_os("database").unwrap().to_owned(),
         },
         _ => unreachable!(),
-    }
+    })
 }
 
 fn driver() -> Result<ActionSummary, error::Error> {
-    let action = parse_args();
+    let action = parse_args()?;
     match action {
-        Action::Build { db_path, dir_path, features, threads, force } => {
+        Action::Build { db_path, dir_path, options, force, compression_level, previous_path } => {
+            let previous = previous_path.map(Database::load_file).transpose()?;
             // Truncate only when force is set
             let f = OpenOptions::new()
                 .write(true)
@@ -187,22 +595,42 @@ fn driver() -> Result<ActionSummary, error::Error> {
                 .create_new(!force)
                 .open(&db_path)?;
 
-            let database = Database::build(&dir_path, features, threads, true)?;
-            database.dump_json(f, features)?;
+            let database = match previous {
+                Some(previous) => Database::build_incremental(&dir_path, &previous, &options)?.0,
+                None => Database::build_with_options(&dir_path, &options)?,
+            };
+            database.dump_json_with_level(f, options.features, compression_level)?;
 
             Ok(ActionSummary::Built)
         }
-        Action::Check { db_path, dir_path, features, threads } => {
+        Action::Check { db_path, dir_path, options, diff_options } => {
             let f = File::open(&db_path)?;
             let database = Database::load_json(f)?;
-            Ok(ActionSummary::Diff(database.check(&dir_path, features, threads)?))
+            Ok(ActionSummary::Diff(
+                database.check_with_options(&dir_path, &options, &diff_options)?))
         }
-        Action::Diff { old_path, new_path } => {
-            let f_old = File::open(&old_path)?;
-            let f_new = File::open(&new_path)?;
-            let old = Database::load_json(f_old)?;
-            let new = Database::load_json(f_new)?;
-            Ok(ActionSummary::Diff(old.show_diff(&new)))
+        Action::Diff { old_path, new_path, options } => {
+            let old = Database::load_file(&old_path)?;
+            let new = Database::load_file(&new_path)?;
+            old.ensure_comparable(&new)?;
+            let stdout = io::stdout();
+            let options = DiffOptions { color: options.color.for_stdout(), ..options };
+            Ok(ActionSummary::Diff(old.show_diff_to_with_options(&new, &options, stdout.lock())?))
+        }
+        Action::Missing { db_path, dir_path } => {
+            let f = File::open(&db_path)?;
+            let database = Database::load_json(f)?;
+            let missing = database.missing_files(&dir_path)?;
+            let stdout = io::stdout();
+            let mut stdout = stdout.lock();
+            for path in missing.iter() {
+                writeln!(stdout, "{}", path.display())?;
+            }
+            Ok(ActionSummary::Diff(if missing.is_empty() {
+                DiffSummary::NoChanges
+            } else {
+                DiffSummary::Changes
+            }))
         }
         Action::SelfCheck { db_path } => {
             let f = File::open(&db_path)?;
@@ -221,7 +649,7 @@ fn main() {
            ActionSummary::Diff(DiffSummary::Suspicious) => 2,
        },
        Err(err) => {
-           writeln!(io::stderr(), "error: {:?}", err).unwrap();
+           eprintln!("error: {:?}", err);
            -1
        },
     });
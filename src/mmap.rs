@@ -0,0 +1,159 @@
+// Hashing of memory-mapped files (Linux only), which saves a read call
+// per buffer on large files. If the file is truncated while mapped,
+// touching the pages past its new end raises SIGBUS. Our handler looks
+// the faulting address up among the live mappings; if it belongs to
+// one, it maps a page of zeros over it and marks the mapping as
+// truncated, so the access can resume and the hash is then discarded.
+// Faults anywhere else go to the previous handler, as if we had never
+// installed ours.
+
+#[cfg(target_os = "linux")]
+pub(crate) use self::linux::Mapping;
+#[cfg(not(target_os = "linux"))]
+pub(crate) use self::other::Mapping;
+
+#[cfg(not(target_os = "linux"))]
+mod other {
+    use std::fs::File;
+    use std::io;
+
+    pub(crate) enum Mapping {}
+
+    impl Mapping {
+        pub(crate) fn new(_file: &File, _len: u64) -> Option<Mapping> {
+            None
+        }
+
+        pub(crate) fn for_each_slice(&self, _slice_len: usize, _f: impl FnMut(&[u8])) -> io::Result<()> {
+            match *self {}
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::convert::TryFrom;
+    use std::fs::File;
+    use std::io;
+    use std::os::unix::io::AsRawFd;
+    use std::ptr;
+    use std::slice;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::{Once, OnceLock};
+
+    // A mapped address range, as seen by the signal handler
+    struct Slot {
+        used: AtomicBool,
+        start: AtomicUsize,
+        end: AtomicUsize,
+        truncated: AtomicBool,
+    }
+
+    #[allow(clippy::declare_interior_mutable_const)]
+    const FREE: Slot = Slot {
+        used: AtomicBool::new(false),
+        start: AtomicUsize::new(0),
+        end: AtomicUsize::new(0),
+        truncated: AtomicBool::new(false),
+    };
+
+    // Files are only mapped while a slot is free, one per thread at most
+    static SLOTS: [Slot; 64] = [FREE; 64];
+    static PAGE_SIZE: AtomicUsize = AtomicUsize::new(0);
+    static PREVIOUS: OnceLock<libc::sigaction> = OnceLock::new();
+    static INSTALL: Once = Once::new();
+
+    extern "C" fn on_sigbus(_signal: libc::c_int, info: *mut libc::siginfo_t, _context: *mut libc::c_void) {
+        let addr = unsafe { (*info).si_addr() } as usize;
+        let page_size = PAGE_SIZE.load(Ordering::Relaxed);
+        for slot in SLOTS.iter() {
+            let (start, end) = (slot.start.load(Ordering::Acquire), slot.end.load(Ordering::Acquire));
+            if start <= addr && addr < end {
+                let page = (addr & !(page_size - 1)) as *mut libc::c_void;
+                let zeros = unsafe {
+                    libc::mmap(page, page_size, libc::PROT_READ,
+                               libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_FIXED, -1, 0)
+                };
+                if zeros != libc::MAP_FAILED {
+                    slot.truncated.store(true, Ordering::Release);
+                    return;
+                }
+            }
+        }
+        // Not ours: the fault repeats under the previous handler
+        if let Some(previous) = PREVIOUS.get() {
+            unsafe { libc::sigaction(libc::SIGBUS, previous, ptr::null_mut()) };
+        }
+    }
+
+    fn install_handler() -> bool {
+        INSTALL.call_once(|| unsafe {
+            PAGE_SIZE.store(libc::sysconf(libc::_SC_PAGESIZE) as usize, Ordering::Relaxed);
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = on_sigbus as *const () as libc::sighandler_t;
+            action.sa_flags = libc::SA_SIGINFO | libc::SA_NODEFER;
+            libc::sigemptyset(&mut action.sa_mask);
+            let mut previous: libc::sigaction = std::mem::zeroed();
+            if libc::sigaction(libc::SIGBUS, &action, &mut previous) == 0 {
+                let _ = PREVIOUS.set(previous);
+            }
+        });
+        PREVIOUS.get().is_some()
+    }
+
+    pub(crate) struct Mapping {
+        ptr: *mut libc::c_void,
+        len: usize,
+        slot: &'static Slot,
+    }
+
+    impl Mapping {
+        // Maps the first `len` bytes of the file, or returns `None` if
+        // it can't be mapped: empty and special files, files too large
+        // for the address space, or too many mappings at once
+        pub(crate) fn new(file: &File, len: u64) -> Option<Mapping> {
+            let len = usize::try_from(len).ok().filter(|&len| len > 0)?;
+            if !file.metadata().ok()?.is_file() || !install_handler() {
+                return None;
+            }
+            let slot = SLOTS.iter().find(|slot| {
+                slot.used.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_ok()
+            })?;
+            let ptr = unsafe {
+                libc::mmap(ptr::null_mut(), len, libc::PROT_READ, libc::MAP_PRIVATE, file.as_raw_fd(), 0)
+            };
+            if ptr == libc::MAP_FAILED {
+                slot.used.store(false, Ordering::Release);
+                return None;
+            }
+            unsafe { libc::madvise(ptr, len, libc::MADV_SEQUENTIAL) };
+            slot.truncated.store(false, Ordering::Relaxed);
+            slot.start.store(ptr as usize, Ordering::Release);
+            slot.end.store(ptr as usize + len, Ordering::Release);
+            Some(Mapping { ptr, len, slot })
+        }
+
+        // Passes the contents to `f` in slices of up to `slice_len`
+        // bytes, and fails if the file was truncated meanwhile
+        pub(crate) fn for_each_slice(&self, slice_len: usize, mut f: impl FnMut(&[u8])) -> io::Result<()> {
+            let contents = unsafe { slice::from_raw_parts(self.ptr as *const u8, self.len) };
+            for chunk in contents.chunks(slice_len.max(1)) {
+                f(chunk);
+                if self.slot.truncated.load(Ordering::Acquire) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof, "file was truncated while being hashed"));
+                }
+            }
+            Ok(())
+        }
+    }
+
+    impl Drop for Mapping {
+        fn drop(&mut self) {
+            self.slot.start.store(0, Ordering::Release);
+            self.slot.end.store(0, Ordering::Release);
+            unsafe { libc::munmap(self.ptr, self.len) };
+            self.slot.used.store(false, Ordering::Release);
+        }
+    }
+}
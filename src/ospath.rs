@@ -0,0 +1,85 @@
+// Path encoding adapter for Serde, for paths that may not be UTF-8. A
+// path is written as a string if it is valid UTF-8, and otherwise as
+// `{"base64": ...}` holding its raw bytes. Other platforms than Unix
+// have no raw bytes to fall back on, so their paths are made lossy.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum Encoded {
+    Utf8(String),
+    Bytes { base64: String },
+}
+
+fn encode(path: &Path) -> Encoded {
+    match path.to_str() {
+        Some(path) => Encoded::Utf8(path.to_owned()),
+        None => Encoded::Bytes { base64: base64::encode(&bytes(path)) },
+    }
+}
+
+fn decode<E: de::Error>(encoded: Encoded) -> Result<PathBuf, E> {
+    match encoded {
+        Encoded::Utf8(path) => Ok(PathBuf::from(path)),
+        Encoded::Bytes { base64 } => base64::decode(&base64).map(from_bytes).map_err(E::custom),
+    }
+}
+
+#[cfg(unix)]
+fn bytes(path: &Path) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes().to_vec()
+}
+
+#[cfg(not(unix))]
+fn bytes(path: &Path) -> Vec<u8> {
+    path.to_string_lossy().into_owned().into_bytes()
+}
+
+#[cfg(unix)]
+fn from_bytes(bytes: Vec<u8>) -> PathBuf {
+    use std::os::unix::ffi::OsStringExt;
+    PathBuf::from(std::ffi::OsString::from_vec(bytes))
+}
+
+#[cfg(not(unix))]
+fn from_bytes(bytes: Vec<u8>) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+pub fn serialize<S>(path: &Path, serializer: S) -> Result<S::Ok, S::Error>
+    where S: Serializer
+{
+    encode(path).serialize(serializer)
+}
+
+pub fn deserialize<'de, D>(deserializer: D) -> Result<PathBuf, D::Error>
+    where D: Deserializer<'de>
+{
+    decode(Encoded::deserialize(deserializer)?)
+}
+
+// A map keyed by paths, as a list of `[path, value]` pairs, since JSON
+// object keys can only be strings
+pub mod map {
+    use super::*;
+
+    pub fn serialize<S, T>(map: &BTreeMap<PathBuf, T>, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer, T: Serialize
+    {
+        serializer.collect_seq(map.iter().map(|(path, value)| (encode(path), value)))
+    }
+
+    pub fn deserialize<'de, D, T>(deserializer: D) -> Result<BTreeMap<PathBuf, T>, D::Error>
+        where D: Deserializer<'de>, T: Deserialize<'de>
+    {
+        Vec::<(Encoded, T)>::deserialize(deserializer)?
+            .into_iter()
+            .map(|(path, value)| Ok((decode(path)?, value)))
+            .collect()
+    }
+}
@@ -0,0 +1,125 @@
+// In-place status line for long builds, enabled by the `progress`
+// feature. It goes to stderr, so it never mixes with the report that
+// `check` prints on stdout. On a terminal, the line is redrawn
+// (carriage-return style) at most a few times per second; otherwise an
+// ordinary log line is printed every so often, so that redirected
+// output stays readable. The total amount of work isn't
+// known while the walk is still going, so a terminal gets a spinner
+// rather than a bar with an ETA. Library users can get the same
+// updates through a callback instead.
+
+use std::io::{self, IsTerminal, Write};
+use std::path::Path;
+
+use crate::database::{human_size, Progress, PROGRESS_BYTE_INTERVAL, PROGRESS_FILE_INTERVAL};
+
+const TTY_INTERVAL_NS: u64 = 250_000_000;
+const LOG_INTERVAL_NS: u64 = 10_000_000_000;
+const MAX_PATH_WIDTH: usize = 50;
+const SPINNER: [char; 4] = ['|', '/', '-', '\\'];
+
+pub(crate) struct InteractiveProgress {
+    tty: bool,
+    start_time_ns: u64,
+    last_update_ns: u64,
+    files: u64,
+    updates: usize,
+}
+
+// Keeps the end of the path, which is the most informative part.
+// Control characters are escaped so that a file name can't move the
+// cursor or otherwise garble the terminal.
+fn truncate_path(path: &Path) -> String {
+    let mut escaped = String::new();
+    for c in path.display().to_string().chars() {
+        if c.is_control() {
+            escaped.extend(c.escape_default());
+        } else {
+            escaped.push(c);
+        }
+    }
+    let path = escaped;
+    let len = path.chars().count();
+    if len <= MAX_PATH_WIDTH {
+        path
+    } else {
+        let tail: String = path.chars().skip(len - (MAX_PATH_WIDTH - 3)).collect();
+        format!("...{}", tail)
+    }
+}
+
+impl InteractiveProgress {
+    pub(crate) fn new(start_time_ns: u64) -> InteractiveProgress {
+        InteractiveProgress {
+            tty: io::stderr().is_terminal(),
+            start_time_ns,
+            last_update_ns: start_time_ns,
+            files: 0,
+            updates: 0,
+        }
+    }
+
+    /// Records one more hashed file. `total_bytes` is the running
+    /// total for the whole build.
+    pub(crate) fn file_done(&mut self, path: &Path, total_bytes: u64) {
+        self.files += 1;
+        let now_ns = time::precise_time_ns();
+        let interval = if self.tty { TTY_INTERVAL_NS } else { LOG_INTERVAL_NS };
+        if now_ns - self.last_update_ns < interval {
+            return;
+        }
+        self.last_update_ns = now_ns;
+        self.updates += 1;
+
+        let rate = total_bytes as f64/((now_ns - self.start_time_ns) as f64/1e3);
+        let stderr = io::stderr();
+        let mut stderr = stderr.lock();
+        // Progress is best effort, so failures to write are ignored
+        let _ = if self.tty {
+            write!(stderr, "\r\x1b[K{} {} files, {}, {:.1} MB/s, {}",
+                   SPINNER[self.updates % SPINNER.len()], self.files, human_size(total_bytes), rate,
+                   truncate_path(path))
+                .and_then(|_| stderr.flush())
+        } else {
+            writeln!(stderr, "{} files hashed, read {} bytes, {:.1} MB/s",
+                     self.files, total_bytes, rate)
+        };
+    }
+
+    /// Clears the status line, so that later output starts cleanly
+    pub(crate) fn finish(&self) {
+        if self.tty {
+            let stderr = io::stderr();
+            let mut stderr = stderr.lock();
+            let _ = write!(stderr, "\r\x1b[K").and_then(|_| stderr.flush());
+        }
+    }
+}
+
+// Hands progress to a callback, throttled by the number of files and
+// bytes since the last call rather than by time, which would need a
+// clock read per file
+pub(crate) struct ProgressCallback {
+    callback: Box<dyn FnMut(&Progress) + Send>,
+    files: u64,
+    last_files: u64,
+    last_bytes: u64,
+}
+
+impl ProgressCallback {
+    pub(crate) fn new(callback: Box<dyn FnMut(&Progress) + Send>) -> ProgressCallback {
+        ProgressCallback { callback, files: 0, last_files: 0, last_bytes: 0 }
+    }
+
+    pub(crate) fn file_done(&mut self, path: &Path, total_bytes: u64) {
+        self.files += 1;
+        if self.files - self.last_files < PROGRESS_FILE_INTERVAL &&
+            total_bytes - self.last_bytes < PROGRESS_BYTE_INTERVAL
+        {
+            return;
+        }
+        self.last_files = self.files;
+        self.last_bytes = total_bytes;
+        (self.callback)(&Progress { path, files: self.files, bytes: total_bytes });
+    }
+}
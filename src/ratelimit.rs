@@ -0,0 +1,58 @@
+// Paces reads to a maximum average rate across all threads, so that a
+// scan doesn't starve other workloads of disk bandwidth. This is a
+// token bucket holding up to one second's worth of bytes: reads draw
+// from it, and a read that overdraws it sleeps until the balance would
+// be back to zero.
+
+use std::io::{self, Read};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub(crate) struct RateLimiter {
+    bytes_per_sec: f64,
+    bucket: Mutex<(f64, Instant)>, // Available bytes, as of when
+}
+
+impl RateLimiter {
+    pub(crate) fn new(bytes_per_sec: u64) -> RateLimiter {
+        let bytes_per_sec = bytes_per_sec.max(1) as f64;
+        RateLimiter { bytes_per_sec, bucket: Mutex::new((bytes_per_sec, Instant::now())) }
+    }
+
+    pub(crate) fn consume(&self, bytes: usize) {
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap();
+            let (ref mut available, ref mut updated) = *bucket;
+            let now = Instant::now();
+            let refill = now.duration_since(*updated).as_secs_f64() * self.bytes_per_sec;
+            *available = (*available + refill).min(self.bytes_per_sec) - bytes as f64;
+            *updated = now;
+            if *available < 0.0 { -*available / self.bytes_per_sec } else { 0.0 }
+        };
+        if wait > 0.0 {
+            thread::sleep(Duration::from_secs_f64(wait));
+        }
+    }
+}
+
+pub(crate) struct Throttled<'a, R> {
+    inner: R,
+    limiter: Option<&'a RateLimiter>,
+}
+
+impl<'a, R> Throttled<'a, R> {
+    pub(crate) fn new(inner: R, limiter: Option<&'a RateLimiter>) -> Throttled<'a, R> {
+        Throttled { inner, limiter }
+    }
+}
+
+impl<R: Read> Read for Throttled<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if let Some(limiter) = self.limiter {
+            limiter.consume(n);
+        }
+        Ok(n)
+    }
+}
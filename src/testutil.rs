@@ -0,0 +1,66 @@
+// Synthesizes large directory trees on disk, so that tests and
+// benchmarks can exercise scalability without checked-in fixtures.
+// Trees are pseudo-random but deterministic: the same arguments always
+// produce the same paths and contents.
+
+use std::fs;
+use std::path::Path;
+
+use crate::error;
+
+// SplitMix64, which is plenty for generating fixtures
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        if n == 0 { 0 } else { self.next() % n }
+    }
+}
+
+const FANOUT: u64 = 4;
+const TEXT: &[u8] = b"abcdefghijklmnopqrstuvwxyz      \n";
+
+/// Creates `num_files` files under `root`, in directories nested up to
+/// `depth` levels deep. File sizes are uniformly distributed between
+/// zero and twice `avg_size`. About half the files are ASCII text and
+/// the rest arbitrary binary data.
+pub fn make_tree(
+    root: impl AsRef<Path>,
+    num_files: usize,
+    depth: usize,
+    avg_size: usize,
+) -> Result<(), error::Error> {
+    let mut rng = Rng(0);
+    let mut contents = Vec::new();
+    for i in 0..num_files {
+        let mut path = root.as_ref().to_owned();
+        for _ in 0..rng.below(depth as u64 + 1) {
+            path.push(format!("d{}", rng.below(FANOUT)));
+        }
+        fs::create_dir_all(&path)?;
+        path.push(format!("f{}", i));
+
+        let size = rng.below(2 * avg_size as u64 + 1) as usize;
+        let text = rng.below(2) == 0;
+        contents.clear();
+        while contents.len() < size {
+            let word = rng.next().to_le_bytes();
+            if text {
+                contents.extend(word.iter().map(|b| TEXT[*b as usize % TEXT.len()]));
+            } else {
+                contents.extend_from_slice(&word);
+            }
+        }
+        contents.truncate(size);
+        fs::write(&path, &contents)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,149 @@
+// Tree hash of large files. The file is cut into fixed-size chunks,
+// which are hashed with SHA-256 on several threads at once, and the
+// root is the SHA-256 of the chunk size (as a big-endian u64) followed
+// by every chunk hash in order. The content flags are gathered chunk by
+// chunk too, so a file can be summarized without one sequential pass.
+// Chunk hashes from an earlier scan may be passed in for a prefix of
+// the file that is known not to have changed, and are not read again.
+
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{self, Read};
+use std::thread;
+
+use digest::{FixedOutput, Input};
+
+use crate::ratelimit::{RateLimiter, Throttled};
+
+pub(crate) struct TreeSummary {
+    pub(crate) root: Vec<u8>,
+    pub(crate) chunks: Vec<Vec<u8>>,
+    pub(crate) size: u64,
+    pub(crate) nul: bool,
+    pub(crate) nonascii: bool,
+}
+
+struct ChunkSummary {
+    hash: Vec<u8>,
+    size: u64,
+    nul: bool,
+    nonascii: bool,
+}
+
+// Reads the bytes of `file` from `offset` up to `end` (or EOF), without
+// using or moving the shared file position
+struct RangeReader<'a> {
+    file: &'a File,
+    offset: u64,
+    end: u64,
+}
+
+impl Read for RangeReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = usize::try_from(self.end - self.offset).unwrap_or(usize::MAX);
+        let len = buf.len().min(remaining);
+        if len == 0 {
+            return Ok(0);
+        }
+        let n = read_at(self.file, &mut buf[..len], self.offset)?;
+        self.offset += n as u64;
+        Ok(n)
+    }
+}
+
+#[cfg(unix)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::unix::fs::FileExt::read_at(file, buf, offset)
+}
+
+// Moves the file position, which callers must reset before reading on
+#[cfg(windows)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::windows::fs::FileExt::seek_read(file, buf, offset)
+}
+
+fn hash_chunk(
+    file: &File,
+    offset: u64,
+    chunk_size: u64,
+    buffer: &mut [u8],
+    limiter: Option<&RateLimiter>,
+) -> io::Result<ChunkSummary> {
+    let range = RangeReader { file, offset, end: offset.saturating_add(chunk_size) };
+    let mut reader = Throttled::new(range, limiter);
+    let mut engine = sha2::Sha256::default();
+    let mut summary = ChunkSummary { hash: Vec::new(), size: 0, nul: false, nonascii: false };
+    loop {
+        let n = reader.read(buffer)?;
+        if n == 0 { break }
+        let data = &buffer[..n];
+        engine.input(data);
+        summary.size += n as u64;
+        summary.nul = summary.nul || data.contains(&0);
+        summary.nonascii = summary.nonascii || data.iter().any(|x| x & 0x80 != 0);
+    }
+    summary.hash = Vec::from(engine.fixed_result().as_slice());
+    Ok(summary)
+}
+
+// Hashes the first `len` bytes of the file (fewer if it has shrunk) in
+// chunks of `chunk_size`, spread over up to `threads` threads. The
+// first chunks are taken from `reused` instead, which must only hold
+// hashes of whole chunks; their content flags are left for the caller
+// to fill in.
+pub(crate) fn tree_hash(
+    file: &File,
+    len: u64,
+    chunk_size: u64,
+    reused: &[Vec<u8>],
+    buffer_size: usize,
+    threads: usize,
+    limiter: Option<&RateLimiter>,
+) -> io::Result<TreeSummary> {
+    let chunks = len.div_ceil(chunk_size);
+    let first = (reused.len() as u64).min(chunks);
+    let workers = usize::try_from(chunks - first).unwrap_or(usize::MAX).min(threads.max(1)).max(1);
+    let buffer_len = usize::try_from(chunk_size).unwrap_or(usize::MAX).min(buffer_size.max(1));
+
+    // Worker `w` takes chunks `first + w`, `first + w + workers`, ...
+    let results: Vec<io::Result<Vec<ChunkSummary>>> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers as u64).map(|worker| {
+            scope.spawn(move || {
+                let mut buffer = vec![0; buffer_len];
+                (first + worker..chunks).step_by(workers)
+                    .map(|index| hash_chunk(file, index * chunk_size, chunk_size, &mut buffer, limiter))
+                    .collect()
+            })
+        }).collect();
+        handles.into_iter().map(|handle| handle.join().expect("chunk hasher panicked")).collect()
+    });
+    let mut per_worker = Vec::new();
+    for result in results {
+        per_worker.push(result?.into_iter());
+    }
+
+    let mut root = sha2::Sha256::default();
+    root.input(chunk_size.to_be_bytes());
+    let mut summary = TreeSummary {
+        root: Vec::new(),
+        chunks: Vec::with_capacity(chunks as usize),
+        size: 0,
+        nul: false,
+        nonascii: false,
+    };
+    for hash in reused[..first as usize].iter() {
+        root.input(hash);
+        summary.chunks.push(hash.clone());
+        summary.size += chunk_size;
+    }
+    for index in first..chunks {
+        let chunk = per_worker[((index - first) % workers as u64) as usize].next().expect("unreachable");
+        root.input(&chunk.hash);
+        summary.size += chunk.size;
+        summary.nul = summary.nul || chunk.nul;
+        summary.nonascii = summary.nonascii || chunk.nonascii;
+        summary.chunks.push(chunk.hash);
+    }
+    summary.root = Vec::from(root.fixed_result().as_slice());
+    Ok(summary)
+}
@@ -0,0 +1,126 @@
+// Streaming implementation of the XXH64 non-cryptographic hash
+// From the specification at https://github.com/Cyan4973/xxHash/blob/dev/doc/xxhash_spec.md
+
+const PRIME64_1: u64 = 0x9E37_79B1_85EB_CA87;
+const PRIME64_2: u64 = 0xC2B2_AE3D_27D4_EB4F;
+const PRIME64_3: u64 = 0x1656_67B1_9E37_79F9;
+const PRIME64_4: u64 = 0x85EB_CA77_C2B2_AE63;
+const PRIME64_5: u64 = 0x27D4_EB2F_1656_67C5;
+
+fn round(acc: u64, input: u64) -> u64 {
+    acc.wrapping_add(input.wrapping_mul(PRIME64_2))
+        .rotate_left(31)
+        .wrapping_mul(PRIME64_1)
+}
+
+fn merge_round(acc: u64, val: u64) -> u64 {
+    (acc ^ round(0, val)).wrapping_mul(PRIME64_1).wrapping_add(PRIME64_4)
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    let mut buf = [0; 8];
+    buf.copy_from_slice(&bytes[..8]);
+    u64::from_le_bytes(buf)
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    let mut buf = [0; 4];
+    buf.copy_from_slice(&bytes[..4]);
+    u32::from_le_bytes(buf)
+}
+
+pub struct Xxh64 {
+    acc: [u64; 4],
+    buffer: [u8; 32],
+    buffered: usize,
+    total_len: u64,
+}
+
+impl Default for Xxh64 {
+    fn default() -> Xxh64 {
+        let seed = 0u64;
+        Xxh64 {
+            acc: [
+                seed.wrapping_add(PRIME64_1).wrapping_add(PRIME64_2),
+                seed.wrapping_add(PRIME64_2),
+                seed,
+                seed.wrapping_sub(PRIME64_1),
+            ],
+            buffer: [0; 32],
+            buffered: 0,
+            total_len: 0,
+        }
+    }
+}
+
+impl Xxh64 {
+    fn stripe(&mut self, stripe: &[u8]) {
+        for (i, acc) in self.acc.iter_mut().enumerate() {
+            *acc = round(*acc, read_u64(&stripe[i * 8..]));
+        }
+    }
+
+    pub fn input(&mut self, mut input: &[u8]) {
+        self.total_len += input.len() as u64;
+
+        if self.buffered > 0 {
+            let n = input.len().min(32 - self.buffered);
+            self.buffer[self.buffered..self.buffered + n].copy_from_slice(&input[..n]);
+            self.buffered += n;
+            input = &input[n..];
+            if self.buffered < 32 {
+                return;
+            }
+            let buffer = self.buffer;
+            self.stripe(&buffer);
+            self.buffered = 0;
+        }
+
+        while input.len() >= 32 {
+            self.stripe(&input[..32]);
+            input = &input[32..];
+        }
+
+        self.buffer[..input.len()].copy_from_slice(input);
+        self.buffered = input.len();
+    }
+
+    pub fn result(self) -> u64 {
+        let mut h = if self.total_len >= 32 {
+            let [v1, v2, v3, v4] = self.acc;
+            let h = v1.rotate_left(1)
+                .wrapping_add(v2.rotate_left(7))
+                .wrapping_add(v3.rotate_left(12))
+                .wrapping_add(v4.rotate_left(18));
+            let h = merge_round(h, v1);
+            let h = merge_round(h, v2);
+            let h = merge_round(h, v3);
+            merge_round(h, v4)
+        } else {
+            self.acc[2].wrapping_add(PRIME64_5) // acc[2] is the seed
+        };
+        h = h.wrapping_add(self.total_len);
+
+        let mut rest = &self.buffer[..self.buffered];
+        while rest.len() >= 8 {
+            h ^= round(0, read_u64(rest));
+            h = h.rotate_left(27).wrapping_mul(PRIME64_1).wrapping_add(PRIME64_4);
+            rest = &rest[8..];
+        }
+        if rest.len() >= 4 {
+            h ^= u64::from(read_u32(rest)).wrapping_mul(PRIME64_1);
+            h = h.rotate_left(23).wrapping_mul(PRIME64_2).wrapping_add(PRIME64_3);
+            rest = &rest[4..];
+        }
+        for byte in rest {
+            h ^= u64::from(*byte).wrapping_mul(PRIME64_5);
+            h = h.rotate_left(11).wrapping_mul(PRIME64_1);
+        }
+
+        h ^= h >> 33;
+        h = h.wrapping_mul(PRIME64_2);
+        h ^= h >> 29;
+        h = h.wrapping_mul(PRIME64_3);
+        h ^ (h >> 32)
+    }
+}
@@ -0,0 +1,79 @@
+#![cfg(target_os = "linux")]
+
+use std::ffi::CString;
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+use integrity_checker::database::{BuildOptions, Database, DiffSummary};
+
+use tempfile::tempdir;
+
+// Writes `system.posix_acl_access` directly, since `setfacl` may not
+// be installed. Returns false if the file system doesn't support ACLs.
+fn set_acl(path: &Path, entries: &[(u16, u16, u32)]) -> bool {
+    let mut value = 2u32.to_le_bytes().to_vec();
+    for (tag, perm, id) in entries {
+        value.extend_from_slice(&tag.to_le_bytes());
+        value.extend_from_slice(&perm.to_le_bytes());
+        value.extend_from_slice(&id.to_le_bytes());
+    }
+    let path = CString::new(path.as_os_str().as_bytes()).unwrap();
+    let name = b"system.posix_acl_access\0";
+    let result = unsafe {
+        libc::setxattr(
+            path.as_ptr(),
+            name.as_ptr() as *const libc::c_char,
+            value.as_ptr() as *const libc::c_void,
+            value.len(),
+            0)
+    };
+    result == 0
+}
+
+#[test]
+fn changed_acl() {
+    let dir = tempdir().unwrap();
+    let file = dir.path().join("asdf.txt");
+    fs::write(&file, b"asdf\n").unwrap();
+    fs::set_permissions(&file, fs::Permissions::from_mode(0o644)).unwrap();
+
+    let options = BuildOptions { record_acl: true, ..BuildOptions::default() };
+    let before_db = Database::build_with_options(dir.path(), &options).unwrap();
+    let json = before_db.to_human_json();
+    assert_eq!(json["asdf.txt"]["acl"], "user::rw-,group::r--,other::r--");
+
+    // Without an extended ACL, a chmod is reported once, as a mode change
+    fs::set_permissions(&file, fs::Permissions::from_mode(0o600)).unwrap();
+    let chmod_db = Database::build_with_options(dir.path(), &options).unwrap();
+    let mut output = Vec::new();
+    before_db.show_diff_to(&chmod_db, &mut output).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("> mode changed"), "{}", output);
+    assert!(!output.contains("ACL changed"), "{}", output);
+    fs::set_permissions(&file, fs::Permissions::from_mode(0o644)).unwrap();
+
+    const UNDEFINED: u32 = u32::MAX;
+    let acl = [
+        (0x01, 6, UNDEFINED), // user::rw-
+        (0x02, 4, 1234),      // user:1234:r--
+        (0x04, 4, UNDEFINED), // group::r--
+        (0x10, 4, UNDEFINED), // mask::r--
+        (0x20, 4, UNDEFINED), // other::r--
+    ];
+    if !set_acl(&file, &acl) {
+        return; // No ACL support in this file system
+    }
+
+    let after_db = Database::build_with_options(dir.path(), &options).unwrap();
+    let mut output = Vec::new();
+    let result = before_db.show_diff_to(&after_db, &mut output).unwrap();
+    assert_eq!(result, DiffSummary::Changes);
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("> ACL changed: +user:1234:r--, +mask::r--\n"));
+
+    // Without ACLs on both sides, nothing is compared
+    let plain_db = Database::build_with_options(dir.path(), &BuildOptions::default()).unwrap();
+    assert_eq!(plain_db.show_diff_to(&after_db, Vec::new()).unwrap(), DiffSummary::NoChanges);
+}
@@ -0,0 +1,23 @@
+use std::fs;
+use std::path::Path;
+
+use integrity_checker::database::{Database, Features};
+use integrity_checker::error::Error;
+
+#[test]
+fn check_archive_member() {
+    let threads = 1;
+    let db = Database::build("tests/nochanges/before", Features::default(), threads, false).unwrap();
+    let contents = fs::read("tests/nochanges/before/asdf.txt").unwrap();
+
+    let diff = db.check_archive_member(Path::new("./asdf.txt"), &contents[..]).unwrap();
+    assert!(!diff.changed_content);
+
+    let diff = db.check_archive_member(Path::new("asdf.txt"), &b"tampered\n"[..]).unwrap();
+    assert!(diff.changed_content);
+
+    match db.check_archive_member(Path::new("missing.txt"), &contents[..]) {
+        Err(Error::NotInDatabase(path)) => assert_eq!(path, Path::new("missing.txt")),
+        result => panic!("expected NotInDatabase, got {:?}", result),
+    }
+}
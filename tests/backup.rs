@@ -0,0 +1,67 @@
+use std::fs;
+use std::path::PathBuf;
+
+use integrity_checker::database::{BuildOptions, Database, Features};
+use integrity_checker::error::Error;
+
+use tempfile::tempdir;
+
+#[test]
+fn backup_manifest() {
+    let options = BuildOptions {
+        features: Features {
+            sha2: false, blake2b: false, sha256: true, sha512: false, blake3: false, xxh64: false,
+            crc32: false,
+        },
+        ..BuildOptions::default()
+    };
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("same.txt"), b"same\n").unwrap();
+    fs::write(dir.path().join("edit.txt"), b"before\n").unwrap();
+    fs::write(dir.path().join("gone.txt"), b"gone\n").unwrap();
+    let before = Database::build_with_options(dir.path(), &options).unwrap();
+
+    fs::write(dir.path().join("edit.txt"), b"asdf\n").unwrap();
+    fs::remove_file(dir.path().join("gone.txt")).unwrap();
+    fs::create_dir(dir.path().join("new")).unwrap();
+    fs::write(dir.path().join("new").join("file.txt"), b"qwer\n").unwrap();
+
+    fs::remove_file(dir.path().join("same.txt")).unwrap();
+    fs::create_dir(dir.path().join("same.txt")).unwrap();
+    fs::write(dir.path().join("same.txt").join("inner.txt"), b"zxcv\n").unwrap();
+    let after = Database::build_with_options(dir.path(), &options).unwrap();
+
+    let manifest = before.diff(&after).to_backup_manifest().unwrap();
+    let entries: Vec<_> = manifest.iter()
+        .map(|entry| (entry.path.clone(), entry.size, entry.sha256.to_string()))
+        .collect();
+    assert_eq!(entries, vec![
+        (PathBuf::from("edit.txt"), 5,
+         "d1bc8d3ba4afc7e109612cb73acbdddac052c93025aa1f82942edabb7deb82a1".to_owned()),
+        (PathBuf::from("new/file.txt"), 5,
+         "5f70ae29b3019ec851ef6b664b59d3fd88dda0de5eb58212ddbd97c65c3f8198".to_owned()),
+        (PathBuf::from("same.txt/inner.txt"), 5,
+         "cc0bef0ea3ef368a9c99e35d273abff3a86b7f3811840ddbde90a2fcc6047935".to_owned()),
+    ]);
+}
+
+#[test]
+fn backup_manifest_without_sha256() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("edit.txt"), b"before\n").unwrap();
+    let before = Database::build_with_options(dir.path(), &BuildOptions::default()).unwrap();
+    fs::write(dir.path().join("edit.txt"), b"asdf\n").unwrap();
+    let after = Database::build_with_options(dir.path(), &BuildOptions::default()).unwrap();
+
+    match before.diff(&after).to_backup_manifest() {
+        Err(Error::Sha256Required(path)) => assert_eq!(path, PathBuf::from("edit.txt")),
+        result => panic!("expected Sha256Required, got {:?}", result),
+    }
+
+    // Unchanged files are never asked for a hash, nor carry one
+    fs::write(dir.path().join("edit.txt"), b"before\n").unwrap();
+    let same = Database::build_with_options(dir.path(), &BuildOptions::default()).unwrap();
+    let diff = before.diff(&same);
+    assert_eq!(diff.to_backup_manifest().unwrap(), Vec::new());
+    assert!(!serde_json::to_string(&diff).unwrap().contains("\"new_file\""));
+}
@@ -0,0 +1,37 @@
+use std::fs;
+use std::path::PathBuf;
+
+use integrity_checker::database::{Database, Features};
+use integrity_checker::error::Error;
+
+use tempfile::tempdir;
+
+#[test]
+fn export_bagit() {
+    let dir = tempdir().unwrap();
+    fs::create_dir(dir.path().join("sub")).unwrap();
+    fs::write(dir.path().join("sub").join("b.txt"), b"asdf\n").unwrap();
+    fs::write(dir.path().join("100%\nsure.txt"), b"qwer\n!").unwrap();
+    let features = Features { sha256: true, ..Features::default() };
+    let db = Database::build(dir.path(), features, 1, false).unwrap();
+
+    let bag = tempdir().unwrap();
+    db.export_bagit(bag.path().join("bag")).unwrap();
+    let read = |name| fs::read_to_string(bag.path().join("bag").join(name)).unwrap();
+    assert_eq!(read("bagit.txt"), "BagIt-Version: 1.0\nTag-File-Character-Encoding: UTF-8\n");
+    assert_eq!(read("bag-info.txt"), "Payload-Oxum: 11.2\n");
+    assert_eq!(read("manifest-sha256.txt"), "\
+d2dd0f51b2651cafd0c4e1187a6e2b167cd2531f7c4a78c2e606adc85d5ad391  data/100%25%0Asure.txt
+d1bc8d3ba4afc7e109612cb73acbdddac052c93025aa1f82942edabb7deb82a1  data/sub/b.txt
+");
+    assert!(!bag.path().join("bag").join("manifest-sha512.txt").exists());
+
+    let unhashed = Database::build(dir.path(), Features::default(), 1, false).unwrap();
+    match unhashed.export_bagit(bag.path().join("other")) {
+        Err(Error::MissingHash { path, algorithm }) => {
+            assert_eq!((path, algorithm), (PathBuf::from("100%\nsure.txt"), "sha2-256"));
+        }
+        result => panic!("expected MissingHash, got {:?}", result),
+    }
+    assert!(!bag.path().join("other").exists());
+}
@@ -1,6 +1,21 @@
 use std::path::{Path, PathBuf};
 
-use integrity_checker::database::{Database, DiffSummary, Features};
+use integrity_checker::database::{
+    BuildOptions, CheckOutcome, Database, DiffOptions, DiffSummary, EntryDiff, Features,
+};
+use integrity_checker::error::Error;
+
+fn hashes(features: Features) -> Vec<bool> {
+    let Features { sha2, blake2b, sha256, sha512, blake3, xxh64, crc32 } = features;
+    vec![sha2, blake2b, sha256, sha512, blake3, xxh64, crc32]
+}
+
+// Whether both record hashes, but none in common
+fn mismatch(before_features: Features, after_features: Features) -> bool {
+    let (before, after) = (hashes(before_features), hashes(after_features));
+    before.contains(&true) && after.contains(&true) &&
+        !before.iter().zip(after.iter()).any(|(a, b)| *a && *b)
+}
 
 fn check(root_dir: impl AsRef<Path>, before_features: Features, after_features: Features) -> DiffSummary {
     let mut before_path = PathBuf::from(root_dir.as_ref());
@@ -9,17 +24,36 @@ fn check(root_dir: impl AsRef<Path>, before_features: Features, after_features:
     let mut after_path = PathBuf::from(root_dir.as_ref());
     after_path.push("after");
 
+    let mismatch = mismatch(before_features, after_features);
+
     let threads = 1;
     let before_db = Database::build(&before_path, before_features, threads, false).unwrap();
-    before_db.check(&after_path, after_features, threads).unwrap()
+    match before_db.check(&after_path, after_features, threads) {
+        Ok(result) => {
+            assert!(!mismatch);
+            result
+        }
+        // Checking with none of the recorded hashes is refused, so
+        // compare against a separately built database instead
+        Err(Error::FeatureMismatch { .. }) if mismatch => {
+            let after_db = Database::build(&after_path, after_features, threads, false).unwrap();
+            before_db.check_against(&after_db).report(&DiffOptions::default(), Vec::new()).unwrap()
+        }
+        Err(err) => panic!("unexpected error: {:?}", err),
+    }
 }
 
-const NONE:    Features = Features { sha2: false, blake2b: false };
-const SHA2:    Features = Features { sha2:  true, blake2b: false };
-const BLAKE2B: Features = Features { sha2: false, blake2b: true };
-const ALL:     Features = Features { sha2:  true, blake2b: true };
+const NONE:    Features = Features { sha2: false, blake2b: false, sha256: false, sha512: false, blake3: false, xxh64: false, crc32: false };
+const SHA2:    Features = Features { sha2:  true, blake2b: false, sha256: false, sha512: false, blake3: false, xxh64: false, crc32: false };
+const BLAKE2B: Features = Features { sha2: false, blake2b:  true, sha256: false, sha512: false, blake3: false, xxh64: false, crc32: false };
+const SHA256:  Features = Features { sha2: false, blake2b: false, sha256:  true, sha512: false, blake3: false, xxh64: false, crc32: false };
+const SHA512:  Features = Features { sha2: false, blake2b: false, sha256: false, sha512:  true, blake3: false, xxh64: false, crc32: false };
+const BLAKE3:  Features = Features { sha2: false, blake2b: false, sha256: false, sha512: false, blake3:  true, xxh64: false, crc32: false };
+const XXH64:   Features = Features { sha2: false, blake2b: false, sha256: false, sha512: false, blake3: false, xxh64:  true, crc32: false };
+const CRC32:   Features = Features { sha2: false, blake2b: false, sha256: false, sha512: false, blake3: false, xxh64: false, crc32:  true };
+const ALL:     Features = Features { sha2:  true, blake2b:  true, sha256:  true, sha512:  true, blake3:  true, xxh64:  true, crc32:  true };
 
-const ALL_FEATURES: &[Features] = &[NONE, SHA2, BLAKE2B, ALL];
+const ALL_FEATURES: &[Features] = &[NONE, SHA2, BLAKE2B, SHA256, SHA512, BLAKE3, XXH64, CRC32, ALL];
 
 // These pairs of features share at least one hash in common (and
 // therefore can detect changes even when other metrics don't change).
@@ -28,19 +62,57 @@ const VIABLE_FEATURES: &[(Features, Features)] = &[
     (    ALL,    SHA2),
     (BLAKE2B,     ALL),
     (    ALL, BLAKE2B),
+    ( SHA256,     ALL),
+    (    ALL,  SHA256),
+    ( SHA512,     ALL),
+    (    ALL,  SHA512),
+    ( BLAKE3,     ALL),
+    (    ALL,  BLAKE3),
+    (  XXH64,     ALL),
+    (    ALL,   XXH64),
+    (  CRC32,     ALL),
+    (    ALL,   CRC32),
     (    ALL,     ALL),
 ];
 
-// These pairs of features don't share any common hash (and therefore
-// can't detect changes except when another metric changes).
-const NONVIABLE_FEATURES: &[(Features, Features)] = &[
+// These pairs of features don't share any common hash, because one
+// side has none at all (and therefore can't detect changes except when
+// another metric changes).
+const SIZE_ONLY_FEATURES: &[(Features, Features)] = &[
     (   NONE,    NONE),
     (   NONE,    SHA2),
     (   SHA2,    NONE),
     (   NONE, BLAKE2B),
     (BLAKE2B,    NONE),
+    (   NONE,  SHA256),
+    ( SHA256,    NONE),
+    (   NONE,  SHA512),
+    ( SHA512,    NONE),
+    (   NONE,  BLAKE3),
+    ( BLAKE3,    NONE),
+    (   NONE,   XXH64),
+    (  XXH64,    NONE),
+    (   NONE,   CRC32),
+    (  CRC32,    NONE),
+];
+
+// These pairs of features both record hashes, but none in common, so
+// files can't be verified and are reported as changed.
+const UNVERIFIABLE_FEATURES: &[(Features, Features)] = &[
     (   SHA2, BLAKE2B),
     (BLAKE2B,    SHA2),
+    (   SHA2,  SHA256),
+    ( SHA256,    SHA2),
+    (BLAKE2B,  SHA256),
+    ( SHA256, BLAKE2B),
+    ( SHA256,  SHA512),
+    ( SHA512,  SHA256),
+    (   SHA2,  BLAKE3),
+    ( BLAKE3,    SHA2),
+    (   SHA2,   XXH64),
+    (  XXH64,    SHA2),
+    (  XXH64,   CRC32),
+    (  CRC32,   XXH64),
 ];
 
 #[test]
@@ -48,7 +120,11 @@ fn no_changes() {
     for before_features in ALL_FEATURES {
         for after_features in ALL_FEATURES {
             let result = check("tests/nochanges", *before_features, *after_features);
-            assert_eq!(result, DiffSummary::NoChanges);
+            if mismatch(*before_features, *after_features) {
+                assert_eq!(result, DiffSummary::Changes);
+            } else {
+                assert_eq!(result, DiffSummary::NoChanges);
+            }
         }
     }
 }
@@ -69,10 +145,14 @@ fn changes_edit_no_size_change() {
         let result = check("tests/changes_edit_no_size_change", *before_features, *after_features);
         assert_eq!(result, DiffSummary::Changes);
     }
-    for (before_features, after_features) in NONVIABLE_FEATURES {
+    for (before_features, after_features) in SIZE_ONLY_FEATURES {
         let result = check("tests/changes_edit_no_size_change", *before_features, *after_features);
         assert_eq!(result, DiffSummary::NoChanges);
     }
+    for (before_features, after_features) in UNVERIFIABLE_FEATURES {
+        let result = check("tests/changes_edit_no_size_change", *before_features, *after_features);
+        assert_eq!(result, DiffSummary::Changes);
+    }
 }
 
 #[test]
@@ -91,10 +171,14 @@ fn changes_edit_bin() {
         let result = check("tests/changes_edit_bin", *before_features, *after_features);
         assert_eq!(result, DiffSummary::Changes);
     }
-    for (before_features, after_features) in NONVIABLE_FEATURES {
+    for (before_features, after_features) in SIZE_ONLY_FEATURES {
         let result = check("tests/changes_edit_bin", *before_features, *after_features);
         assert_eq!(result, DiffSummary::NoChanges);
     }
+    for (before_features, after_features) in UNVERIFIABLE_FEATURES {
+        let result = check("tests/changes_edit_bin", *before_features, *after_features);
+        assert_eq!(result, DiffSummary::Changes);
+    }
 }
 
 #[test]
@@ -152,7 +236,151 @@ fn suspicious_nonascii() {
     for before_features in ALL_FEATURES {
         for after_features in ALL_FEATURES {
             let result = check("tests/suspicious_nonascii", *before_features, *after_features);
-            assert_eq!(result, DiffSummary::Suspicious);
+            assert_eq!(result, DiffSummary::Changes);
+        }
+    }
+
+    let threads = 1;
+    let before_db = Database::build("tests/suspicious_nonascii/before", ALL, threads, false).unwrap();
+    let build_options = BuildOptions { features: ALL, threads, ..BuildOptions::default() };
+    let diff_options = DiffOptions { flag_nonascii: true, ..DiffOptions::default() };
+    let result = before_db.check_with_options(
+        "tests/suspicious_nonascii/after", &build_options, &diff_options).unwrap();
+    assert_eq!(result, DiffSummary::Suspicious);
+}
+
+#[test]
+fn check_against_multiple_baselines() {
+    let threads = 1;
+    let current = Database::build("tests/changes_edit/after", ALL, threads, false).unwrap();
+
+    let same = Database::build("tests/changes_edit/after", ALL, threads, false).unwrap();
+    let result = same.check_against(&current).report(&DiffOptions::default(), Vec::new());
+    assert_eq!(result.unwrap(), DiffSummary::NoChanges);
+
+    let older = Database::build("tests/changes_edit/before", ALL, threads, false).unwrap();
+    let mut output = Vec::new();
+    let result = older.check_against(&current).report(&DiffOptions::default(), &mut output);
+    assert_eq!(result.unwrap(), DiffSummary::Changes);
+    assert!(!output.is_empty());
+}
+
+#[test]
+fn check_feature_mismatch() {
+    let threads = 1;
+    let before_db = Database::build("tests/nochanges/before", SHA512, threads, false).unwrap();
+    assert_eq!(before_db.features(), SHA512);
+    match before_db.check("tests/nochanges/after", SHA256, threads) {
+        Err(Error::FeatureMismatch { recorded, requested }) => {
+            assert_eq!((recorded, requested), (SHA512, SHA256));
         }
+        result => panic!("expected FeatureMismatch, got {:?}", result),
+    }
+    let result = before_db.check("tests/nochanges/after", ALL, threads).unwrap();
+    assert_eq!(result, DiffSummary::NoChanges);
+}
+
+#[test]
+fn check_wrong_root() {
+    let small = tempfile::tempdir().unwrap();
+    std::fs::write(small.path().join("asdf.txt"), b"asdf\n").unwrap();
+    let large = tempfile::tempdir().unwrap();
+    for i in 0..12 {
+        std::fs::write(large.path().join(format!("file{}", i)), b"qwer\n").unwrap();
+    }
+
+    let threads = 1;
+    let small_db = Database::build(small.path(), Features::default(), threads, false).unwrap();
+    let large_db = Database::build(large.path(), Features::default(), threads, false).unwrap();
+    let options = DiffOptions { wrong_root_ratio: Some(10), ..DiffOptions::default() };
+    assert_eq!(small_db.likely_wrong_root(&large_db, &options), Some((12, 1)));
+    assert_eq!(large_db.likely_wrong_root(&small_db, &options), None);
+    assert_eq!(small_db.likely_wrong_root(&large_db, &DiffOptions::default()), None);
+
+    let build_options = BuildOptions::default();
+    let options = DiffOptions { abort_on_wrong_root: true, ..options };
+    match small_db.check_with_options(large.path(), &build_options, &options) {
+        Err(Error::LikelyWrongRoot { on_disk, in_database }) => {
+            assert_eq!((on_disk, in_database), (12, 1));
+        }
+        result => panic!("expected LikelyWrongRoot, got {:?}", result),
+    }
+    let result = small_db.check_with_options(small.path(), &build_options, &options).unwrap();
+    assert_eq!(result, DiffSummary::NoChanges);
+}
+
+#[test]
+fn check_quiet() {
+    let threads = 1;
+    let before_db = Database::build("tests/changes_edit/before", SHA2, threads, false).unwrap();
+    let options = BuildOptions { features: SHA2, ..BuildOptions::default() };
+    let diff = before_db
+        .check_quiet("tests/changes_edit/after", &options, &DiffOptions::default())
+        .unwrap();
+    match diff {
+        EntryDiff::Directory(_, ref stats) => {
+            assert_eq!((stats.changed, stats.added, stats.removed, stats.suspicious), (1, 0, 0, 0));
+        }
+        ref diff => panic!("expected a directory diff, got {:?}", diff),
+    }
+    let mut output = Vec::new();
+    assert_eq!(diff.report(&DiffOptions::default(), &mut output).unwrap(), DiffSummary::Changes);
+    assert!(!output.is_empty());
+
+    let options = BuildOptions { features: SHA256, ..BuildOptions::default() };
+    match before_db.check_quiet("tests/changes_edit/after", &options, &DiffOptions::default()) {
+        Err(Error::FeatureMismatch { .. }) => (),
+        result => panic!("expected FeatureMismatch, got {:?}", result),
+    }
+}
+
+#[test]
+fn check_outcome() {
+    let threads = 1;
+    let options = BuildOptions { features: SHA2, ..BuildOptions::default() };
+    let diff_options = DiffOptions::default();
+
+    let before_db = Database::build("tests/nochanges/before", SHA2, threads, false).unwrap();
+    let outcome = before_db.check_outcome("tests/nochanges/after", &options, &diff_options).unwrap();
+    assert_eq!(outcome, CheckOutcome { clean: true, changed: 0, added: 0, removed: 0, suspicious: 0 });
+
+    let before_db = Database::build("tests/suspicious_nul/before", SHA2, threads, false).unwrap();
+    let outcome = before_db.check_outcome("tests/suspicious_nul/after", &options, &diff_options).unwrap();
+    assert_eq!(outcome, CheckOutcome { clean: false, changed: 1, added: 0, removed: 0, suspicious: 1 });
+
+    let before_db = Database::build("tests/changes_new/before", SHA2, threads, false).unwrap();
+    let outcome = before_db.check_outcome("tests/changes_new/after", &options, &diff_options).unwrap();
+    assert!(!outcome.clean);
+    assert_eq!((outcome.changed, outcome.added, outcome.suspicious), (0, 1, 0));
+}
+
+#[test]
+fn check_subtree() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir_all(dir.path().join("projects/alpha")).unwrap();
+    std::fs::create_dir_all(dir.path().join("projects/beta")).unwrap();
+    std::fs::write(dir.path().join("projects/alpha/a.txt"), b"asdf\n").unwrap();
+    std::fs::write(dir.path().join("projects/beta/b.txt"), b"qwer\n").unwrap();
+    let db = Database::build(dir.path(), SHA2, 1, false).unwrap();
+
+    // Changes elsewhere in the tree are neither walked nor reported
+    std::fs::write(dir.path().join("projects/beta/b.txt"), b"QWER\n").unwrap();
+    std::fs::write(dir.path().join("top.txt"), b"zxcv\n").unwrap();
+    let options = BuildOptions { features: SHA2, ..BuildOptions::default() };
+    let diff_options = DiffOptions::default();
+    let result = db.check_subtree(dir.path(), "projects/alpha", &options, &diff_options).unwrap();
+    assert_eq!(result, DiffSummary::NoChanges);
+
+    std::fs::write(dir.path().join("projects/alpha/a.txt"), b"ASDF\n").unwrap();
+    let result = db.check_subtree(dir.path(), "./projects/alpha/", &options, &diff_options).unwrap();
+    assert_eq!(result, DiffSummary::Changes);
+
+    match db.check_subtree(dir.path(), "projects/gamma", &options, &diff_options) {
+        Err(Error::NotInDatabase(path)) => assert_eq!(path, Path::new("projects/gamma")),
+        result => panic!("expected NotInDatabase, got {:?}", result),
+    }
+    match db.check_subtree(dir.path(), "projects/alpha/a.txt", &options, &diff_options) {
+        Err(Error::NotADirectory(path)) => assert_eq!(path, Path::new("projects/alpha/a.txt")),
+        result => panic!("expected NotADirectory, got {:?}", result),
     }
 }
@@ -0,0 +1,63 @@
+use integrity_checker::database::{Database, DiffSummary, Features};
+use integrity_checker::error::Error;
+
+#[test]
+fn compression_levels_roundtrip() {
+    let threads = 1;
+    let features = Features::default();
+    let db = Database::build("tests/nochanges/before", features, threads, false).unwrap();
+
+    let fast = db.dump_json_with_level(Vec::new(), features, 0).unwrap();
+    let best = db.dump_json_with_level(Vec::new(), features, 9).unwrap();
+    assert!(best.len() < fast.len());
+    let balanced = db.dump_json_with_level(Vec::new(), features, 6).unwrap();
+    assert_eq!(db.dump_json(Vec::new(), features).unwrap(), balanced);
+    assert_eq!(Database::load_json(&fast[..]).unwrap(), db);
+    assert_eq!(Database::load_json(&best[..]).unwrap(), db);
+}
+
+#[test]
+fn compression_level_invalid() {
+    let threads = 1;
+    let features = Features::default();
+    let db = Database::build("tests/nochanges/before", features, threads, false).unwrap();
+    match db.dump_json_with_level(Vec::new(), features, 10) {
+        Err(Error::InvalidCompressionLevel(10)) => (),
+        result => panic!("expected InvalidCompressionLevel, got {:?}", result.map(|_| ())),
+    }
+}
+
+#[test]
+fn diff_files() {
+    let threads = 1;
+    let features = Features::default();
+    let dir = tempfile::tempdir().unwrap();
+    let before = Database::build("tests/changes_edit/before", features, threads, false).unwrap();
+    let after = Database::build("tests/changes_edit/after", features, threads, false).unwrap();
+    let before_path = dir.path().join("before.json.gz");
+    let after_path = dir.path().join("after.json.gz");
+    before.dump_json(std::fs::File::create(&before_path).unwrap(), features).unwrap();
+    after.dump_json(std::fs::File::create(&after_path).unwrap(), features).unwrap();
+
+    let (diff, summary) = Database::diff_files(&before_path, &after_path).unwrap();
+    assert_eq!(diff, before.diff(&after));
+    assert_eq!(summary, DiffSummary::Changes);
+
+    let missing = dir.path().join("missing.json.gz");
+    match Database::diff_files(&before_path, &missing) {
+        Err(Error::Load { path, cause }) => {
+            assert_eq!(path, missing);
+            assert!(matches!(*cause, Error::Io(_)), "{:?}", cause);
+        }
+        result => panic!("expected Load, got {:?}", result),
+    }
+    let plain = dir.path().join("plain.json");
+    std::fs::write(&plain, b"{}\n{}").unwrap();
+    match Database::diff_files(&plain, &after_path) {
+        Err(Error::Load { path, cause }) => {
+            assert_eq!(path, plain);
+            assert!(matches!(*cause, Error::UnrecognizedFormat), "{:?}", cause);
+        }
+        result => panic!("expected Load, got {:?}", result),
+    }
+}
@@ -0,0 +1,78 @@
+use std::fs;
+use std::path::PathBuf;
+
+use integrity_checker::config::Config;
+use integrity_checker::database::{BuildOptions, Database, Features};
+use integrity_checker::error::Error;
+
+use tempfile::tempdir;
+
+#[test]
+fn config_parse() {
+    let config = Config::parse(r#"{
+        "hashes": ["sha256", "xxh64"],
+        "exclude": ["cache/**", "*.pid"],
+        "max_depth": 4,
+        "follow_links": true,
+        "buffer_size": 65536
+    }"#).unwrap();
+    assert_eq!(config.features,
+               Some(Features {
+                   sha2: false, blake2b: false, sha256: true, sha512: false, blake3: false, xxh64: true,
+                   crc32: false,
+               }));
+    assert_eq!(config.exclude, vec!["cache/**".to_owned(), "*.pid".to_owned()]);
+    assert_eq!(config.max_depth, Some(4));
+    assert_eq!(config.follow_links, Some(true));
+    assert_eq!(config.buffer_size, Some(65536));
+}
+
+#[test]
+fn config_errors() {
+    match Config::parse("{\"max_depth\": 4,\n\"max_dpeth\": 4}") {
+        Err(Error::Config(2, _)) => (),
+        result => panic!("expected an error on line 2, got {:?}", result),
+    }
+    assert!(Config::parse(r#"{"hashes": ["md5"]}"#).is_err());
+    assert!(Config::parse(r#"{"follow_links": 1}"#).is_err());
+    assert!(Config::parse(r#"{"buffer_size": 0}"#).is_err());
+    assert!(Config::parse(r#"{"exclude": ["a"}"#).is_err());
+}
+
+#[test]
+fn config_apply() {
+    let dir = tempdir().unwrap();
+    fs::create_dir_all(dir.path().join("cache").join("deep")).unwrap();
+    fs::write(dir.path().join("asdf.txt"), b"asdf\n").unwrap();
+    fs::write(dir.path().join("app.pid"), b"123\n").unwrap();
+    fs::write(dir.path().join("cache").join("deep").join("blob"), b"qwer\n").unwrap();
+    let config_dir = tempdir().unwrap();
+    let config_path = config_dir.path().join("options.json");
+    fs::write(&config_path, br#"{"exclude": ["*.pid"], "max_depth": 2}"#).unwrap();
+
+    let mut options = BuildOptions::default();
+    Config::load_outside(&config_path, dir.path()).unwrap().apply(&mut options);
+    let db = Database::build_with_options(dir.path(), &options).unwrap();
+    assert!(db.lookup(&PathBuf::from("asdf.txt")).is_some());
+    assert!(db.lookup(&PathBuf::from("app.pid")).is_none());
+    assert!(db.lookup(&PathBuf::from("cache/deep/blob")).is_none());
+
+    // Explicit options applied afterwards take precedence
+    options.max_depth = None;
+    let db = Database::build_with_options(dir.path(), &options).unwrap();
+    assert!(db.lookup(&PathBuf::from("cache/deep/blob")).is_some());
+}
+
+#[test]
+fn config_inside_checked_tree() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("evil.sh"), b"rm -rf /\n").unwrap();
+    let config_path = dir.path().join("options.json");
+    fs::write(&config_path, br#"{"exclude": ["evil.sh"]}"#).unwrap();
+
+    assert!(Config::load(&config_path).is_ok());
+    match Config::load_outside(&config_path, dir.path()) {
+        Err(Error::ConfigInTree(path)) => assert_eq!(path, config_path),
+        result => panic!("expected ConfigInTree, got {:?}", result),
+    }
+}
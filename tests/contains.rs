@@ -0,0 +1,40 @@
+use std::path::PathBuf;
+
+use integrity_checker::database::{Database, Features};
+
+#[test]
+fn contains() {
+    let threads = 1;
+    let db = Database::build("tests/changes_delete_dir/before", Features::default(), threads, false)
+        .unwrap();
+
+    assert!(db.contains("d/4.txt"));
+    assert!(db.contains("./d/4.txt"));
+    assert!(db.contains("a/b/"));
+    assert!(!db.contains("d/6.txt"));
+    assert!(!db.contains("d/4.txt/x"));
+
+    assert!(db.contains_file("./a/b/c/1.txt"));
+    assert!(!db.contains_file("a/b/c"));
+    assert!(db.contains_dir("a/b/c/"));
+    assert!(!db.contains_dir("d/5.txt"));
+}
+
+#[test]
+fn entry_accessors() {
+    let threads = 1;
+    let db = Database::build("tests/changes_delete_dir/before", Features::default(), threads, false)
+        .unwrap();
+
+    let dir = db.lookup(&PathBuf::from("a/b")).unwrap();
+    assert!(dir.is_dir());
+    assert!(!dir.is_file());
+    assert!(dir.as_metrics().is_none());
+    let children = dir.children().unwrap();
+    assert!(children.contains_key(&PathBuf::from("c")));
+
+    let file = db.lookup(&PathBuf::from("d/4.txt")).unwrap();
+    assert!(file.is_file());
+    assert!(file.children().is_none());
+    assert_eq!(file.as_metrics().unwrap().size(), 2);
+}
@@ -0,0 +1,70 @@
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use integrity_checker::database::{BuildOptions, Database, DiffOptions, DiffSummary};
+use integrity_checker::error::Error;
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+use tempfile::tempdir;
+
+fn write_gz(path: impl AsRef<Path>, contents: &[u8], level: u32) {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::new(level));
+    encoder.write_all(contents).unwrap();
+    fs::write(path, encoder.finish().unwrap()).unwrap();
+}
+
+fn build_pair(options: &BuildOptions) -> (Database, Database) {
+    let contents = b"Oct 15 12:00:00 host sshd[1]: session opened\n".repeat(100);
+
+    let before = tempdir().unwrap();
+    write_gz(before.path().join("auth.log.gz"), &contents, 1);
+    fs::write(before.path().join("plain.txt"), b"asdf\n").unwrap();
+
+    let after = tempdir().unwrap();
+    write_gz(after.path().join("auth.log.gz"), &contents, 9);
+    fs::write(after.path().join("plain.txt"), b"asdf\n").unwrap();
+
+    (Database::build_with_options(before.path(), options).unwrap(),
+     Database::build_with_options(after.path(), options).unwrap())
+}
+
+#[test]
+fn recompressed_is_changed() {
+    let (before, after) = build_pair(&BuildOptions::default());
+    assert_eq!(before.show_diff_to(&after, &mut Vec::new()).unwrap(), DiffSummary::Changes);
+}
+
+#[test]
+fn recompressed_is_unchanged_when_decompressed() {
+    let options = BuildOptions { decompress: vec!["*.gz".to_owned()], ..BuildOptions::default() };
+    let (before, after) = build_pair(&options);
+    assert_eq!(before.show_diff_to(&after, &mut Vec::new()).unwrap(), DiffSummary::NoChanges);
+}
+
+#[test]
+fn decompressed_is_not_compared_with_raw() {
+    let options = BuildOptions { decompress: vec!["*.gz".to_owned()], ..BuildOptions::default() };
+    let (before, _) = build_pair(&options);
+    let (_, after) = build_pair(&BuildOptions::default());
+    match before.ensure_comparable(&after) {
+        Err(Error::DecompressionMismatch { path, recorded: true }) => {
+            assert_eq!(path, Path::new("auth.log.gz"));
+        }
+        result => panic!("expected DecompressionMismatch, got {:?}", result),
+    }
+}
+
+#[test]
+fn decompress_falls_back_to_raw_bytes() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("fake.gz"), b"not actually gzip\n").unwrap();
+
+    let options = BuildOptions { decompress: vec!["*.gz".to_owned()], ..BuildOptions::default() };
+    let decompressed = Database::build_with_options(dir.path(), &options).unwrap();
+    let raw = Database::build_with_options(dir.path(), &BuildOptions::default()).unwrap();
+    let result = raw.show_diff_to_with_options(&decompressed, &DiffOptions::default(), &mut Vec::new());
+    assert_eq!(result.unwrap(), DiffSummary::NoChanges);
+}
@@ -1,6 +1,12 @@
 use std::path::{Path, PathBuf};
 
-use integrity_checker::database::{Database, DiffSummary, Features};
+use globset::{Glob, GlobSetBuilder};
+
+use integrity_checker::database::{
+    ChangeClass, ColorMode, Database, DiffMode, DiffOptions, DiffReport, DiffSummary, EntryDiff, Features,
+    MetricsDiff, NameAnomalies, PathReport, PathStatus,
+};
+use integrity_checker::error::Error;
 
 fn diff(root_dir: impl AsRef<Path>, before_features: Features, after_features: Features) -> DiffSummary {
     let mut before_path = PathBuf::from(root_dir.as_ref());
@@ -15,12 +21,17 @@ fn diff(root_dir: impl AsRef<Path>, before_features: Features, after_features: F
     before_db.show_diff(&after_db)
 }
 
-const NONE:    Features = Features { sha2: false, blake2b: false };
-const SHA2:    Features = Features { sha2:  true, blake2b: false };
-const BLAKE2B: Features = Features { sha2: false, blake2b: true };
-const ALL:     Features = Features { sha2:  true, blake2b: true };
+const NONE:    Features = Features { sha2: false, blake2b: false, sha256: false, sha512: false, blake3: false, xxh64: false, crc32: false };
+const SHA2:    Features = Features { sha2:  true, blake2b: false, sha256: false, sha512: false, blake3: false, xxh64: false, crc32: false };
+const BLAKE2B: Features = Features { sha2: false, blake2b:  true, sha256: false, sha512: false, blake3: false, xxh64: false, crc32: false };
+const SHA256:  Features = Features { sha2: false, blake2b: false, sha256:  true, sha512: false, blake3: false, xxh64: false, crc32: false };
+const SHA512:  Features = Features { sha2: false, blake2b: false, sha256: false, sha512:  true, blake3: false, xxh64: false, crc32: false };
+const BLAKE3:  Features = Features { sha2: false, blake2b: false, sha256: false, sha512: false, blake3:  true, xxh64: false, crc32: false };
+const XXH64:   Features = Features { sha2: false, blake2b: false, sha256: false, sha512: false, blake3: false, xxh64:  true, crc32: false };
+const CRC32:   Features = Features { sha2: false, blake2b: false, sha256: false, sha512: false, blake3: false, xxh64: false, crc32:  true };
+const ALL:     Features = Features { sha2:  true, blake2b:  true, sha256:  true, sha512:  true, blake3:  true, xxh64:  true, crc32:  true };
 
-const ALL_FEATURES: &[Features] = &[NONE, SHA2, BLAKE2B, ALL];
+const ALL_FEATURES: &[Features] = &[NONE, SHA2, BLAKE2B, SHA256, SHA512, BLAKE3, XXH64, CRC32, ALL];
 
 // These pairs of features share at least one hash in common (and
 // therefore can detect changes even when other metrics don't change).
@@ -29,27 +40,75 @@ const VIABLE_FEATURES: &[(Features, Features)] = &[
     (    ALL,    SHA2),
     (BLAKE2B,     ALL),
     (    ALL, BLAKE2B),
+    ( SHA256,     ALL),
+    (    ALL,  SHA256),
+    ( SHA512,     ALL),
+    (    ALL,  SHA512),
+    ( BLAKE3,     ALL),
+    (    ALL,  BLAKE3),
+    (  XXH64,     ALL),
+    (    ALL,   XXH64),
+    (  CRC32,     ALL),
+    (    ALL,   CRC32),
     (    ALL,     ALL),
 ];
 
-// These pairs of features don't share any common hash (and therefore
-// can't detect changes except when another metric changes).
-const NONVIABLE_FEATURES: &[(Features, Features)] = &[
+// These pairs of features don't share any common hash, because one
+// side has none at all (and therefore can't detect changes except when
+// another metric changes).
+const SIZE_ONLY_FEATURES: &[(Features, Features)] = &[
     (   NONE,    NONE),
     (   NONE,    SHA2),
     (   SHA2,    NONE),
     (   NONE, BLAKE2B),
     (BLAKE2B,    NONE),
+    (   NONE,  SHA256),
+    ( SHA256,    NONE),
+    (   NONE,  SHA512),
+    ( SHA512,    NONE),
+    (   NONE,  BLAKE3),
+    ( BLAKE3,    NONE),
+    (   NONE,   XXH64),
+    (  XXH64,    NONE),
+    (   NONE,   CRC32),
+    (  CRC32,    NONE),
+];
+
+// These pairs of features both record hashes, but none in common, so
+// files can't be verified and are reported as changed.
+const UNVERIFIABLE_FEATURES: &[(Features, Features)] = &[
     (   SHA2, BLAKE2B),
     (BLAKE2B,    SHA2),
+    (   SHA2,  SHA256),
+    ( SHA256,    SHA2),
+    (BLAKE2B,  SHA256),
+    ( SHA256, BLAKE2B),
+    ( SHA256,  SHA512),
+    ( SHA512,  SHA256),
+    (   SHA2,  BLAKE3),
+    ( BLAKE3,    SHA2),
+    (   SHA2,   XXH64),
+    (  XXH64,    SHA2),
+    (  XXH64,   CRC32),
+    (  CRC32,   XXH64),
 ];
 
+fn unverifiable(a: Features, b: Features) -> bool {
+    let hashes = |f: Features| [f.sha2, f.blake2b, f.sha256, f.sha512, f.blake3, f.xxh64, f.crc32];
+    let (a, b) = (hashes(a), hashes(b));
+    a.contains(&true) && b.contains(&true) && !a.iter().zip(b.iter()).any(|(x, y)| *x && *y)
+}
+
 #[test]
 fn no_changes() {
     for before_features in ALL_FEATURES {
         for after_features in ALL_FEATURES {
             let result = diff("tests/nochanges", *before_features, *after_features);
-            assert_eq!(result, DiffSummary::NoChanges);
+            if unverifiable(*before_features, *after_features) {
+                assert_eq!(result, DiffSummary::Changes);
+            } else {
+                assert_eq!(result, DiffSummary::NoChanges);
+            }
         }
     }
 }
@@ -70,10 +129,71 @@ fn changes_edit_no_size_change() {
         let result = diff("tests/changes_edit_no_size_change", *before_features, *after_features);
         assert_eq!(result, DiffSummary::Changes);
     }
-    for (before_features, after_features) in NONVIABLE_FEATURES {
+    for (before_features, after_features) in SIZE_ONLY_FEATURES {
         let result = diff("tests/changes_edit_no_size_change", *before_features, *after_features);
         assert_eq!(result, DiffSummary::NoChanges);
     }
+    for (before_features, after_features) in UNVERIFIABLE_FEATURES {
+        let result = diff("tests/changes_edit_no_size_change", *before_features, *after_features);
+        assert_eq!(result, DiffSummary::Changes);
+    }
+}
+
+#[test]
+fn no_common_hash() {
+    let dir = "tests/changes_edit_no_size_change";
+    let report = |before_features, after_features| {
+        let before_db = Database::build(
+            Path::new(dir).join("before"), before_features, 1, false).unwrap();
+        let after_db = Database::build(
+            Path::new(dir).join("after"), after_features, 1, false).unwrap();
+        let mut output = Vec::new();
+        let diff = before_db.diff(&after_db);
+        diff.report(&DiffOptions::default(), &mut output).unwrap();
+        (diff.files_without_common_hash(), String::from_utf8(output).unwrap())
+    };
+
+    let (count, output) = report(SHA2, BLAKE2B);
+    assert!(count > 0);
+    assert!(output.contains(&format!(
+        "warning: {} file(s) not verified: no hash algorithm in common\n", count)), "{}", output);
+    assert!(output.contains("##> "), "{}", output);
+
+    // Databases without any hash are compared by size on purpose
+    assert_eq!(report(NONE, SHA2).0, 0);
+    let (count, output) = report(SHA2, ALL);
+    assert_eq!(count, 0);
+    assert!(!output.contains("warning"), "{}", output);
+}
+
+#[test]
+fn weak_evidence() {
+    let dir = "tests/changes_edit_no_size_change";
+    let report = |before_features, after_features, mode| {
+        let before_db = Database::build(
+            Path::new(dir).join("before"), before_features, 1, false).unwrap();
+        let after_db = Database::build(
+            Path::new(dir).join("after"), after_features, 1, false).unwrap();
+        let options = DiffOptions { mode, ..DiffOptions::default() };
+        let mut output = Vec::new();
+        let diff = before_db.diff_with_options(&after_db, &options);
+        let result = diff.report(&options, &mut output).unwrap();
+        (result, diff.files_with_weak_evidence(), String::from_utf8(output).unwrap())
+    };
+
+    // A fast database against a full one still catches the edit
+    let (result, count, output) = report(Features::fast(), ALL, DiffMode::Full);
+    assert_eq!(result, DiffSummary::Changes);
+    assert!(count > 0);
+    assert!(output.contains(&format!(
+        "warning: {} file(s) compared only by non-cryptographic checksums, which can't detect \
+         deliberate tampering",
+        count)), "{}", output);
+
+    // ... but no warning once a cryptographic hash is shared, or in fast mode
+    assert_eq!(report(ALL, ALL, DiffMode::Full).1, 0);
+    assert_eq!(report(Features::fast(), ALL, DiffMode::Fast).1, 0);
+    assert_eq!(report(SHA2, BLAKE2B, DiffMode::Full).1, 0);
 }
 
 #[test]
@@ -92,10 +212,14 @@ fn changes_edit_bin() {
         let result = diff("tests/changes_edit_bin", *before_features, *after_features);
         assert_eq!(result, DiffSummary::Changes);
     }
-    for (before_features, after_features) in NONVIABLE_FEATURES {
+    for (before_features, after_features) in SIZE_ONLY_FEATURES {
         let result = diff("tests/changes_edit_bin", *before_features, *after_features);
         assert_eq!(result, DiffSummary::NoChanges);
     }
+    for (before_features, after_features) in UNVERIFIABLE_FEATURES {
+        let result = diff("tests/changes_edit_bin", *before_features, *after_features);
+        assert_eq!(result, DiffSummary::Changes);
+    }
 }
 
 #[test]
@@ -153,7 +277,1096 @@ fn suspicious_nonascii() {
     for before_features in ALL_FEATURES {
         for after_features in ALL_FEATURES {
             let result = diff("tests/suspicious_nonascii", *before_features, *after_features);
-            assert_eq!(result, DiffSummary::Suspicious);
+            assert_eq!(result, DiffSummary::Changes);
+        }
+    }
+
+    // Gaining non-ASCII bytes is only suspicious on request
+    let threads = 1;
+    let before_db = Database::build("tests/suspicious_nonascii/before", ALL, threads, false).unwrap();
+    let after_db = Database::build("tests/suspicious_nonascii/after", ALL, threads, false).unwrap();
+    let mut output = Vec::new();
+    before_db.show_diff_to(&after_db, &mut output).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("> non-ASCII bytes were added or removed\n"), "{}", output);
+    assert!(!output.contains("suspicious"), "{}", output);
+
+    let options = DiffOptions { flag_nonascii: true, ..DiffOptions::default() };
+    let mut output = Vec::new();
+    let result = before_db.show_diff_to_with_options(&after_db, &options, &mut output).unwrap();
+    assert_eq!(result, DiffSummary::Suspicious);
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("> suspicious: non-ASCII bytes were added or removed\n"), "{}", output);
+}
+
+#[test]
+fn visit_changes_edit() {
+    let threads = 1;
+    let before_db = Database::build("tests/changes_edit/before", ALL, threads, false).unwrap();
+    let after_db = Database::build("tests/changes_edit/after", ALL, threads, false).unwrap();
+
+    let mut changed = Vec::new();
+    let mut root = None;
+    before_db.diff_visit(&after_db, &DiffOptions::default(), |path, diff| {
+        match diff {
+            EntryDiff::File(stats) if stats.changed_content => changed.push(path.to_owned()),
+            EntryDiff::Directory(entries, stats) if path == Path::new("") => {
+                assert!(entries.is_empty());
+                root = Some(stats.changed);
+            }
+            _ => (),
+        }
+    });
+    assert_eq!(changed, vec![PathBuf::from("zxcv.txt")]);
+    assert_eq!(root, Some(1));
+}
+
+#[test]
+fn fast_mode() {
+    let threads = 1;
+    let before_db = Database::build(
+        "tests/changes_edit_no_size_change/before", ALL, threads, false).unwrap();
+    let after_db = Database::build(
+        "tests/changes_edit_no_size_change/after", ALL, threads, false).unwrap();
+    let options = DiffOptions { mode: DiffMode::Fast, ..DiffOptions::default() };
+    assert_eq!(before_db.show_diff_with_options(&after_db, &options), DiffSummary::Changes);
+
+    // Without XXH64 there is nothing left to detect the edit with
+    let before_db = Database::build(
+        "tests/changes_edit_no_size_change/before", SHA2, threads, false).unwrap();
+    let after_db = Database::build(
+        "tests/changes_edit_no_size_change/after", SHA2, threads, false).unwrap();
+    assert_eq!(before_db.show_diff_with_options(&after_db, &options), DiffSummary::NoChanges);
+}
+
+#[cfg(unix)]
+#[test]
+fn changes_mode() {
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("script.sh");
+    fs::write(&path, b"echo asdf\n").unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+    let before_db = Database::build(dir.path(), Features::default(), 1, false).unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    let after_db = Database::build(dir.path(), Features::default(), 1, false).unwrap();
+
+    match before_db.diff(&after_db) {
+        EntryDiff::Directory(entries, stats) => {
+            assert_eq!(stats.changed, 1);
+            match entries.get(&PathBuf::from("script.sh")) {
+                Some(EntryDiff::File(diff)) => {
+                    assert!(!diff.changed_content);
+                    assert_eq!(diff.changed_mode, Some((0o644, 0o755)));
+                    assert!(diff.privilege_escalation);
+                }
+                entry => panic!("expected a file diff, got {:?}", entry),
+            }
+        }
+        diff => panic!("expected a directory diff, got {:?}", diff),
+    }
+    // Becoming executable is a privilege escalation, dropping bits is not
+    assert_eq!(before_db.show_diff(&after_db), DiffSummary::Suspicious);
+    assert_eq!(after_db.show_diff(&before_db), DiffSummary::Changes);
+}
+
+#[cfg(unix)]
+#[test]
+fn privilege_escalation() {
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("tool");
+    fs::write(&path, b"asdf\n").unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    let before_db = Database::build(dir.path(), Features::default(), 1, false).unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o4757)).unwrap();
+    let after_db = Database::build(dir.path(), Features::default(), 1, false).unwrap();
+
+    let mut output = Vec::new();
+    let result = before_db.show_diff_to(&after_db, &mut output).unwrap();
+    assert_eq!(result, DiffSummary::Suspicious);
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("> suspicious: gained setuid bit\n"), "{}", output);
+    assert!(output.contains("> suspicious: became world-writable\n"), "{}", output);
+    assert!(!output.contains("became executable"), "{}", output);
+
+    // Suppressed, the mode change is still reported, just not as suspicious
+    let options = DiffOptions { ignore_privilege_escalation: true, ..DiffOptions::default() };
+    let mut output = Vec::new();
+    let result = before_db.show_diff_to_with_options(&after_db, &options, &mut output).unwrap();
+    assert_eq!(result, DiffSummary::Changes);
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("> mode changed from rwxr-xr-x to rwsr-xrwx\n"), "{}", output);
+    assert!(!output.contains("suspicious"), "{}", output);
+
+    // More execute bits on a file that was already executable are no
+    // escalation
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o744)).unwrap();
+    let before_db = Database::build(dir.path(), Features::default(), 1, false).unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    let after_db = Database::build(dir.path(), Features::default(), 1, false).unwrap();
+    assert_eq!(before_db.show_diff_to(&after_db, Vec::new()).unwrap(), DiffSummary::Changes);
+}
+
+#[test]
+fn classify() {
+    use std::fs;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    let dir = tempfile::tempdir().unwrap();
+    let set_mtime = |name: &str, secs: u64| {
+        fs::File::options().write(true).open(dir.path().join(name)).unwrap()
+            .set_modified(UNIX_EPOCH + Duration::from_secs(secs)).unwrap();
+    };
+    for name in ["binary.txt", "frozen.txt", "edited.txt", "touched.txt", "same.txt"] {
+        fs::write(dir.path().join(name), b"asdf\n").unwrap();
+        set_mtime(name, 1_000_000_000);
+    }
+    // E.g. saved again within the same second as the build
+    fs::write(dir.path().join("resaved.txt"), b"asdf\n").unwrap();
+    fs::File::options().write(true).open(dir.path().join("resaved.txt")).unwrap()
+        .set_modified(UNIX_EPOCH + Duration::new(1_000_000_000, 100_000_000)).unwrap();
+    let before_db = Database::build(dir.path(), Features::default(), 1, false).unwrap();
+
+    fs::write(dir.path().join("binary.txt"), b"as\0f\n").unwrap();
+    fs::write(dir.path().join("frozen.txt"), b"qwer\n").unwrap();
+    set_mtime("frozen.txt", 1_000_000_000);
+    fs::write(dir.path().join("edited.txt"), b"qwer\n").unwrap();
+    fs::write(dir.path().join("resaved.txt"), b"qwer\n").unwrap();
+    fs::File::options().write(true).open(dir.path().join("resaved.txt")).unwrap()
+        .set_modified(UNIX_EPOCH + Duration::new(1_000_000_000, 900_000_000)).unwrap();
+    set_mtime("touched.txt", 1_100_000_000);
+    fs::write(dir.path().join("added.txt"), b"zxcv\n").unwrap();
+    let after_db = Database::build(dir.path(), Features::default(), 1, false).unwrap();
+
+    let diff = before_db.diff(&after_db);
+    let classes: Vec<_> = match &diff {
+        EntryDiff::Directory(entries, _) => entries.iter()
+            .filter_map(|(path, entry)| match entry {
+                EntryDiff::File(diff) => Some((path.to_str().unwrap(), diff.classify())),
+                _ => None,
+            })
+            .collect(),
+        diff => panic!("expected a directory diff, got {:?}", diff),
+    };
+    assert_eq!(classes, vec![
+        ("binary.txt", ChangeClass::DefiniteTamper),
+        ("edited.txt", ChangeClass::ProbableChange),
+        ("frozen.txt", ChangeClass::DefiniteTamper),
+        ("resaved.txt", ChangeClass::ProbableChange),
+        ("same.txt", ChangeClass::Unchanged),
+        ("touched.txt", ChangeClass::Benign),
+    ]);
+
+    let options = DiffOptions { group_by_confidence: true, ..DiffOptions::default() };
+    let mut output = Vec::new();
+    diff.report(&options, &mut output).unwrap();
+    assert_eq!(String::from_utf8(output).unwrap(), "\
+definite tamper:
+  ./binary.txt
+  > text file gained NUL bytes
+  ./frozen.txt
+  > content changed, but mtime did not
+probable change:
+  ./edited.txt
+  ./resaved.txt
+benign:
+  ./touched.txt
+1 added, 0 removed
+");
+
+    // Without nanoseconds, as in older databases, an unchanged mtime
+    // can't be told from a rewrite within the same second
+    let strip = |db: &Database| {
+        let json = serde_json::to_string(db).unwrap().replace(",\"mtime_nsec\":0", "");
+        assert!(!json.contains("mtime_nsec\":0"), "{}", json);
+        serde_json::from_str::<Database>(&json).unwrap()
+    };
+    match strip(&before_db).diff(&strip(&after_db)) {
+        EntryDiff::Directory(entries, _) => match &entries[Path::new("frozen.txt")] {
+            EntryDiff::File(diff) => assert_eq!(diff.classify(), ChangeClass::ProbableChange),
+            entry => panic!("expected a file diff, got {:?}", entry),
+        },
+        diff => panic!("expected a directory diff, got {:?}", diff),
+    }
+}
+
+#[test]
+fn ignore_changes_edit() {
+    let threads = 1;
+    let before_db = Database::build("tests/changes_edit/before", SHA2, threads, false).unwrap();
+    let after_db = Database::build("tests/changes_edit/after", SHA2, threads, false).unwrap();
+
+    let mut builder = GlobSetBuilder::new();
+    builder.add(Glob::new("*.txt").unwrap());
+    let ignore = builder.build().unwrap();
+    match before_db.diff_ignoring(&after_db, &ignore) {
+        EntryDiff::Directory(entries, stats) => {
+            assert!(!entries.contains_key(Path::new("zxcv.txt")));
+            assert_eq!(stats.changed, 0);
+            assert_eq!(stats.unchanged, 3);
         }
+        diff => panic!("expected a directory diff, got {:?}", diff),
+    }
+}
+
+#[test]
+fn changes_delete_last() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("a.txt"), b"asdf\n").unwrap();
+    std::fs::write(dir.path().join("z.txt"), b"qwer\n").unwrap();
+    let before_db = Database::build(dir.path(), SHA2, 1, false).unwrap();
+    std::fs::remove_file(dir.path().join("z.txt")).unwrap();
+    let after_db = Database::build(dir.path(), SHA2, 1, false).unwrap();
+
+    match before_db.diff(&after_db) {
+        EntryDiff::Directory(_, stats) => assert_eq!(stats.removed, 1),
+        diff => panic!("expected a directory diff, got {:?}", diff),
+    }
+    assert_eq!(before_db.show_diff(&after_db), DiffSummary::Changes);
+}
+
+#[test]
+fn paranoid_hash_disagreement() {
+    let threads = 1;
+    let before_db = Database::build("tests/nochanges/before", ALL, threads, false).unwrap();
+
+    // Simulate a database where only one hash of a file was altered
+    let mut value = serde_json::to_value(&before_db).unwrap();
+    let blake2b = value["Directory"]["qwer.bin"]["File"]["blake2b"].clone();
+    value["Directory"]["asdf.txt"]["File"]["blake2b"] = blake2b;
+    let after_db: Database = serde_json::from_str(&value.to_string()).unwrap();
+
+    let options = DiffOptions { paranoid: true, ..DiffOptions::default() };
+    assert_eq!(before_db.show_diff_with_options(&after_db, &options), DiffSummary::Suspicious);
+    assert_eq!(before_db.show_diff(&after_db), DiffSummary::Changes);
+}
+
+fn file_diff(root_dir: &str, file: &str) -> MetricsDiff {
+    let threads = 1;
+    let root = PathBuf::from(root_dir);
+    let before_db = Database::build(root.join("before"), SHA2, threads, false).unwrap();
+    let after_db = Database::build(root.join("after"), SHA2, threads, false).unwrap();
+    match before_db.diff(&after_db) {
+        EntryDiff::Directory(mut entries, _) => match entries.remove(Path::new(file)) {
+            Some(EntryDiff::File(diff)) => diff,
+            entry => panic!("expected a file diff, got {:?}", entry),
+        },
+        diff => panic!("expected a directory diff, got {:?}", diff),
+    }
+}
+
+#[test]
+fn text_to_binary() {
+    let diff = file_diff("tests/suspicious_nul", "asdf.txt");
+    assert!(diff.text_to_binary && !diff.binary_to_text);
+
+    // Valid UTF-8 is still text
+    let diff = file_diff("tests/suspicious_nonascii", "asdf.txt");
+    assert!(!diff.text_to_binary && !diff.binary_to_text);
+
+    let diff = file_diff("tests/changes_edit_bin", "qwer.bin");
+    assert!(!diff.text_to_binary && !diff.binary_to_text);
+}
+
+#[test]
+fn mtime_went_backward() {
+    use std::fs::{self, File};
+    use std::time::{Duration, SystemTime};
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("log.txt");
+    fs::write(&path, b"asdf\n").unwrap();
+    let now = SystemTime::now();
+    File::options().write(true).open(&path).unwrap().set_modified(now).unwrap();
+    let before_db = Database::build(dir.path(), SHA2, 1, false).unwrap();
+    let earlier = now - Duration::from_secs(86400);
+    File::options().write(true).open(&path).unwrap().set_modified(earlier).unwrap();
+    let after_db = Database::build(dir.path(), SHA2, 1, false).unwrap();
+
+    let options = DiffOptions { check_mtime: true, ..DiffOptions::default() };
+    assert_eq!(before_db.show_diff_with_options(&after_db, &options), DiffSummary::Suspicious);
+    assert_eq!(after_db.show_diff_with_options(&before_db, &options), DiffSummary::NoChanges);
+    assert_eq!(before_db.show_diff(&after_db), DiffSummary::NoChanges);
+
+    // Nothing changed, yet the report still names the suspicious file
+    let mut output = Vec::new();
+    before_db.show_diff_to_with_options(&after_db, &options, &mut output).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("log.txt changed"), "{}", output);
+    assert!(output.contains("> suspicious: mtime moved backward"), "{}", output);
+}
+
+#[test]
+fn show_touched() {
+    use std::fs::{self, File};
+    use std::time::{Duration, SystemTime};
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("log.txt");
+    fs::write(&path, b"asdf\n").unwrap();
+    let now = SystemTime::now();
+    File::options().write(true).open(&path).unwrap().set_modified(now).unwrap();
+    let before_db = Database::build(dir.path(), SHA2, 1, false).unwrap();
+    let later = now + Duration::from_secs(86400);
+    File::options().write(true).open(&path).unwrap().set_modified(later).unwrap();
+    let after_db = Database::build(dir.path(), SHA2, 1, false).unwrap();
+
+    // Content decides whether the file changed, whatever the timestamp
+    let options = DiffOptions { show_touched: true, ..DiffOptions::default() };
+    let mut output = Vec::new();
+    let result = before_db.show_diff_to_with_options(&after_db, &options, &mut output).unwrap();
+    assert_eq!(result, DiffSummary::NoChanges);
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("> content identical, timestamp changed\n"), "{}", output);
+
+    let mut output = Vec::new();
+    before_db.show_diff_to(&after_db, &mut output).unwrap();
+    assert!(output.is_empty());
+}
+
+#[cfg(unix)]
+#[test]
+fn symlinks() {
+    use std::fs;
+    use std::os::unix::fs::symlink;
+
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("asdf.txt"), b"asdf\n").unwrap();
+    symlink("asdf.txt", dir.path().join("link")).unwrap();
+    let before_db = Database::build(dir.path(), SHA2, 1, false).unwrap();
+    let value = serde_json::to_value(&before_db).unwrap();
+    assert_eq!(value["Directory"]["link"]["Symlink"]["target"], "asdf.txt");
+    let round_trip: Database = serde_json::from_str(&value.to_string()).unwrap();
+    assert_eq!(round_trip.show_diff(&before_db), DiffSummary::NoChanges);
+
+    // Databases from before symlinks were recorded see them as added
+    let mut old_value = value.clone();
+    old_value["Directory"].as_object_mut().unwrap().remove("link");
+    let old_db: Database = serde_json::from_str(&old_value.to_string()).unwrap();
+    match old_db.diff(&before_db) {
+        EntryDiff::Directory(_, stats) => {
+            assert_eq!((stats.added, stats.removed, stats.changed), (1, 0, 0));
+        }
+        diff => panic!("expected a directory diff, got {:?}", diff),
+    }
+
+    fs::remove_file(dir.path().join("link")).unwrap();
+    symlink("/etc/shadow", dir.path().join("link")).unwrap();
+    let retargeted_db = Database::build(dir.path(), SHA2, 1, false).unwrap();
+    let mut output = Vec::new();
+    assert_eq!(before_db.show_diff_to(&retargeted_db, &mut output).unwrap(), DiffSummary::Changes);
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("> symlink target changed from asdf.txt to /etc/shadow\n"), "{}", output);
+
+    fs::remove_file(dir.path().join("link")).unwrap();
+    fs::write(dir.path().join("link"), b"asdf\n").unwrap();
+    let replaced_db = Database::build(dir.path(), SHA2, 1, false).unwrap();
+    let mut output = Vec::new();
+    assert_eq!(before_db.show_diff_to(&replaced_db, &mut output).unwrap(), DiffSummary::Changes);
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("kind changed at link: symlink -> file\n"), "{}", output);
+    let mut output = Vec::new();
+    replaced_db.show_diff_to(&before_db, &mut output).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("kind changed at link: file -> symlink\n"), "{}", output);
+}
+
+#[test]
+fn empty_directories() {
+    use std::fs;
+
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir_all(dir.path().join("a/b")).unwrap();
+    fs::write(dir.path().join("a/b/asdf.txt"), b"asdf\n").unwrap();
+    fs::create_dir(dir.path().join("a/empty")).unwrap();
+    fs::create_dir(dir.path().join("spool")).unwrap();
+    for threads in &[1, 4] {
+        let db = Database::build(dir.path(), SHA2, *threads, false).unwrap();
+        let value = serde_json::to_value(&db).unwrap();
+        assert_eq!(value["Directory"]["a"]["Directory"]["empty"], serde_json::json!({"Directory": {}}));
+        assert_eq!(value["Directory"]["spool"], serde_json::json!({"Directory": {}}));
+        assert!(value["Directory"]["a"]["Directory"]["b"]["Directory"]["asdf.txt"]["File"].is_object());
+    }
+    let before_db = Database::build(dir.path(), SHA2, 1, false).unwrap();
+
+    fs::remove_dir(dir.path().join("spool")).unwrap();
+    for name in &["x", "y"] {
+        fs::create_dir(dir.path().join("a").join(name)).unwrap();
+    }
+    let after_db = Database::build(dir.path(), SHA2, 1, false).unwrap();
+    match before_db.diff(&after_db) {
+        EntryDiff::Directory(_, stats) => {
+            assert_eq!((stats.added, stats.removed, stats.changed, stats.unchanged), (2, 1, 0, 1));
+            assert_eq!((stats.added_dirs, stats.removed_dirs), (2, 1));
+        }
+        diff => panic!("expected a directory diff, got {:?}", diff),
+    }
+    let mut output = Vec::new();
+    assert_eq!(before_db.show_diff_to(&after_db, &mut output).unwrap(), DiffSummary::Changes);
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.starts_with(".: 0 changed, 2 added (2 directories), 1 removed (1 directory), 1 unchanged\n"),
+            "{}", output);
+}
+
+#[cfg(unix)]
+#[test]
+fn hardlink_broken() {
+    use std::fs;
+
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("asdf.txt"), b"asdf\n").unwrap();
+    fs::hard_link(dir.path().join("asdf.txt"), dir.path().join("link.txt")).unwrap();
+    let before_db = Database::build(dir.path(), SHA2, 1, false).unwrap();
+    let value = serde_json::to_value(&before_db).unwrap();
+    assert_eq!(value["Directory"]["asdf.txt"], value["Directory"]["link.txt"]);
+    assert_eq!(value["Directory"]["link.txt"]["File"]["nlink"], 2);
+
+    // Replacing one link with a copy leaves both paths unshared
+    fs::remove_file(dir.path().join("link.txt")).unwrap();
+    fs::write(dir.path().join("link.txt"), b"asdf\n").unwrap();
+    let after_db = Database::build(dir.path(), SHA2, 1, false).unwrap();
+    let mut output = Vec::new();
+    assert_eq!(before_db.show_diff_to(&after_db, &mut output).unwrap(), DiffSummary::Changes);
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("| asdf.txt changed\n##> hard link broken (was one of 2 links)\n"), "{}", output);
+    assert!(output.contains("| link.txt changed\n##> hard link broken (was one of 2 links)\n"), "{}", output);
+
+    // Databases without link counts compare cleanly
+    let mut old_value = value.clone();
+    for name in &["asdf.txt", "link.txt"] {
+        old_value["Directory"][name]["File"].as_object_mut().unwrap().remove("nlink");
+    }
+    let old_db: Database = serde_json::from_str(&old_value.to_string()).unwrap();
+    assert_eq!(old_db.show_diff(&after_db), DiffSummary::NoChanges);
+}
+
+#[cfg(unix)]
+#[test]
+fn special_files() {
+    use std::ffi::CString;
+    use std::fs;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::net::UnixListener;
+
+    let mkfifo = |path: &Path| {
+        let path = CString::new(path.as_os_str().as_bytes()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(path.as_ptr(), 0o644) }, 0);
+    };
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("asdf.txt"), b"asdf\n").unwrap();
+    mkfifo(&dir.path().join("pipe"));
+    let _listener = UnixListener::bind(dir.path().join("socket")).unwrap();
+
+    // Building must not open the FIFO, which would block forever
+    for threads in &[1, 4] {
+        let db = Database::build(dir.path(), SHA2, *threads, false).unwrap();
+        let value = serde_json::to_value(&db).unwrap();
+        assert_eq!(value["Directory"]["pipe"], serde_json::json!({"Special": "Fifo"}));
+        assert_eq!(value["Directory"]["socket"], serde_json::json!({"Special": "Socket"}));
+        let round_trip: Database = serde_json::from_str(&value.to_string()).unwrap();
+        assert_eq!(round_trip.show_diff(&db), DiffSummary::NoChanges);
+    }
+    let before_db = Database::build(dir.path(), SHA2, 1, false).unwrap();
+
+    fs::remove_file(dir.path().join("asdf.txt")).unwrap();
+    mkfifo(&dir.path().join("asdf.txt"));
+    let after_db = Database::build(dir.path(), SHA2, 1, false).unwrap();
+    let mut output = Vec::new();
+    assert_eq!(before_db.show_diff_to(&after_db, &mut output).unwrap(), DiffSummary::Suspicious);
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("| kind changed at asdf.txt: file -> fifo\n\
+                             ##> suspicious: regular file swapped with a special file\n"), "{}", output);
+    let mut output = Vec::new();
+    assert_eq!(after_db.show_diff_to(&before_db, &mut output).unwrap(), DiffSummary::Suspicious);
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("kind changed at asdf.txt: fifo -> file\n"), "{}", output);
+}
+
+#[cfg(unix)]
+#[test]
+fn changed_sparseness() {
+    use std::fs::{self, File};
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("disk.img");
+    File::create(&path).unwrap().set_len(1 << 20).unwrap();
+    let sparse_db = Database::build(dir.path(), SHA2, 1, false).unwrap();
+    let metrics = sparse_db.lookup(&PathBuf::from("disk.img")).unwrap().as_metrics().unwrap();
+    if metrics.allocated() != Some(0) {
+        return; // The file system doesn't support holes
+    }
+
+    fs::write(&path, vec![0; 1 << 20]).unwrap();
+    let dense_db = Database::build(dir.path(), SHA2, 1, false).unwrap();
+    let metrics = dense_db.lookup(&PathBuf::from("disk.img")).unwrap().as_metrics().unwrap();
+    assert!(metrics.allocated().unwrap() >= 1 << 20);
+
+    let mut output = Vec::new();
+    assert_eq!(sparse_db.show_diff_to(&dense_db, &mut output).unwrap(), DiffSummary::Changes);
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("| disk.img changed\n##> content identical, but no longer sparse\n"),
+            "{}", output);
+    let mut output = Vec::new();
+    dense_db.show_diff_to(&sparse_db, &mut output).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("##> content identical, but now sparse\n"), "{}", output);
+}
+
+#[test]
+fn show_diff_to_writer() {
+    let threads = 1;
+    let before_db = Database::build("tests/suspicious_truncate/before", SHA2, threads, false).unwrap();
+    let after_db = Database::build("tests/suspicious_truncate/after", SHA2, threads, false).unwrap();
+    let mut output = Vec::new();
+    let result = before_db.show_diff_to(&after_db, &mut output).unwrap();
+    assert_eq!(result, DiffSummary::Suspicious);
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.starts_with(".: 1 changed, 0 added, 0 removed, 2 unchanged\n"));
+    assert!(output.contains("| qwer.bin changed\n"));
+    assert!(output.contains("> suspicious: file was truncated\n"));
+}
+
+#[test]
+fn suspicious_names() {
+    assert!(NameAnomalies::of("report.pdf".as_ref()).is_empty());
+    assert!(NameAnomalies::of("no\u{00E9}l.txt".as_ref()).is_empty());
+    assert_eq!(NameAnomalies::of("report.pdf ".as_ref()), NameAnomalies::WHITESPACE);
+    assert_eq!(NameAnomalies::of("\u{00A0}report.pdf".as_ref()), NameAnomalies::WHITESPACE);
+    assert_eq!(NameAnomalies::of("invoice\u{202E}fdp.exe".as_ref()), NameAnomalies::BIDI);
+    assert_eq!(NameAnomalies::of("a\u{0085}b".as_ref()), NameAnomalies::CONTROL);
+    assert_eq!(NameAnomalies::of("a\nb\n".as_ref()), NameAnomalies::CONTROL | NameAnomalies::WHITESPACE);
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        let name = std::ffi::OsStr::from_bytes(b"\xff\xfe.bin");
+        assert!(NameAnomalies::of(name).is_empty());
+        let name = std::ffi::OsStr::from_bytes(b"\xff\x1b.bin");
+        assert_eq!(NameAnomalies::of(name), NameAnomalies::CONTROL);
+    }
+
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("asdf.txt"), b"asdf\n").unwrap();
+    let before_db = Database::build(dir.path(), SHA2, 1, false).unwrap();
+    std::fs::write(dir.path().join("invoice\u{202E}fdp.exe"), b"MZ").unwrap();
+    std::fs::write(dir.path().join("report.pdf "), b"%PDF").unwrap();
+    std::fs::write(dir.path().join("qwer.txt"), b"qwer\n").unwrap();
+    let after_db = Database::build(dir.path(), SHA2, 1, false).unwrap();
+
+    match before_db.diff(&after_db) {
+        EntryDiff::Directory(entries, stats) => {
+            assert_eq!(stats.added, 3);
+            assert_eq!(stats.suspicious, 2);
+            assert_eq!(entries.len(), 4);
+        }
+        diff => panic!("expected a directory diff, got {:?}", diff),
+    }
+    let mut output = Vec::new();
+    assert_eq!(before_db.show_diff_to(&after_db, &mut output).unwrap(), DiffSummary::Suspicious);
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("| + \"report.pdf \"\n\
+                             ##> suspicious: name has leading or trailing whitespace\n"), "{}", output);
+    assert!(output.contains("| + \"invoice\\u{202e}fdp.exe\"\n\
+                             ##> suspicious: name has bidirectional text controls\n"), "{}", output);
+    assert!(output.contains("fdp.exe\"\n##> suspicious: name has bidirectional text controls\n\
+                             | + qwer.txt\n| + \"report.pdf \"\n"), "{}", output);
+}
+
+#[test]
+fn suspicious_names_in_added_directory() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("asdf.txt"), b"asdf\n").unwrap();
+    let before_db = Database::build(dir.path(), SHA2, 1, false).unwrap();
+    std::fs::create_dir_all(dir.path().join("x").join("nested")).unwrap();
+    std::fs::write(dir.path().join("x").join("invoice\u{202E}fdp.exe"), b"MZ").unwrap();
+    std::fs::write(dir.path().join("x").join("nested").join("report.pdf "), b"%PDF").unwrap();
+    std::fs::write(dir.path().join("x").join("qwer.txt"), b"qwer\n").unwrap();
+    let after_db = Database::build(dir.path(), SHA2, 1, false).unwrap();
+
+    let diff = before_db.diff(&after_db);
+    match diff {
+        EntryDiff::Directory(ref entries, ref stats) => {
+            assert_eq!((stats.added, stats.suspicious), (1, 2));
+            match entries[Path::new("x")] {
+                EntryDiff::Added { anomalies, ref nested_anomalies, .. } => {
+                    assert!(anomalies.is_empty());
+                    assert_eq!(nested_anomalies.len(), 2);
+                }
+                ref entry => panic!("expected an added entry, got {:?}", entry),
+            }
+        }
+        ref diff => panic!("expected a directory diff, got {:?}", diff),
+    }
+    let json = serde_json::to_string(&diff).unwrap();
+    assert_eq!(serde_json::from_str::<EntryDiff>(&json).unwrap(), diff);
+    let report = diff.to_report();
+    assert_eq!(report.suspicious, 2);
+    let paths: Vec<(&Path, Vec<&str>)> = report.paths.iter()
+        .map(|path| (path.path.as_ref(), path.suspicious.iter().map(String::as_str).collect()))
+        .collect();
+    assert_eq!(paths, vec![
+        (Path::new("x"), vec![]),
+        (Path::new("x/invoice\u{202E}fdp.exe"), vec!["bidirectional text controls"]),
+        (Path::new("x/nested/report.pdf "), vec!["leading or trailing whitespace"]),
+    ]);
+
+    let mut output = Vec::new();
+    assert_eq!(before_db.show_diff_to(&after_db, &mut output).unwrap(), DiffSummary::Suspicious);
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("| + x/ (3 files)\n\
+                             ##> suspicious: \"x/invoice\\u{202e}fdp.exe\" has bidirectional text controls\n\
+                             ##> suspicious: \"x/nested/report.pdf \" has leading or trailing whitespace\n"),
+            "{}", output);
+
+    let options = DiffOptions { suspicious_only: true, ..DiffOptions::default() };
+    match diff.suspicious_only(&options) {
+        Some(EntryDiff::Directory(entries, stats)) => {
+            assert_eq!((stats.added, stats.suspicious), (1, 2));
+            assert!(entries.contains_key(Path::new("x")));
+        }
+        diff => panic!("expected a directory diff, got {:?}", diff),
+    }
+}
+
+#[test]
+fn diff_json() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("edit.txt"), b"asdf\n").unwrap();
+    std::fs::write(dir.path().join("keep.txt"), b"qwer\n").unwrap();
+    std::fs::create_dir(dir.path().join("gone")).unwrap();
+    std::fs::write(dir.path().join("gone").join("file.txt"), b"zxcv\n").unwrap();
+    let before_db = Database::build(dir.path(), SHA2, 1, false).unwrap();
+    std::fs::write(dir.path().join("edit.txt"), b"asdf\0\n").unwrap();
+    std::fs::remove_dir_all(dir.path().join("gone")).unwrap();
+    std::fs::write(dir.path().join("new.txt"), b"new\n").unwrap();
+    std::fs::write(dir.path().join("report.pdf "), b"%PDF").unwrap();
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        std::fs::write(dir.path().join(std::ffi::OsStr::from_bytes(b"\xff.bin")), b"").unwrap();
+    }
+    let after_db = Database::build(dir.path(), SHA2, 1, false).unwrap();
+
+    // The diff survives a round trip through JSON
+    let diff = before_db.diff(&after_db);
+    let json = serde_json::to_string(&diff).unwrap();
+    assert_eq!(serde_json::from_str::<EntryDiff>(&json).unwrap(), diff);
+
+    let mut output = Vec::new();
+    let options = DiffOptions::default();
+    assert_eq!(before_db.diff_json(&after_db, &options, &mut output).unwrap(), DiffSummary::Suspicious);
+    let report: DiffReport = serde_json::from_slice(&output).unwrap();
+    assert_eq!(report, diff.to_report());
+    let path = |path: &str, status, suspicious: &[&str]| PathReport {
+        path: PathBuf::from(path),
+        status,
+        suspicious: suspicious.iter().map(|reason| reason.to_string()).collect(),
+    };
+    let mut expected = vec![
+        path("edit.txt", PathStatus::Changed,
+             &["original had no NUL bytes, but now does", "text file became binary"]),
+        path("gone", PathStatus::Removed, &[]),
+        path("new.txt", PathStatus::Added, &[]),
+        path("report.pdf ", PathStatus::Added, &["leading or trailing whitespace"]),
+    ];
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStringExt;
+        let name = PathBuf::from(std::ffi::OsString::from_vec(b"\xff.bin".to_vec()));
+        expected.push(PathReport { path: name, status: PathStatus::Added, suspicious: Vec::new() });
+        let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(json["paths"][4]["path"], serde_json::json!({ "base64": "/y5iaW4=" }));
+    }
+    assert_eq!(report.paths, expected);
+    assert_eq!((report.changed, report.added, report.removed), (1, expected.len() as u64 - 2, 1));
+}
+
+#[test]
+fn show_diff_write_error() {
+    // E.g. stdout piped to `head`, which exited
+    struct ClosedPipe;
+    impl std::io::Write for ClosedPipe {
+        fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::ErrorKind::BrokenPipe.into())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let before_db = Database::build("tests/suspicious_truncate/before", SHA2, 1, false).unwrap();
+    let after_db = Database::build("tests/suspicious_truncate/after", SHA2, 1, false).unwrap();
+    match before_db.show_diff_to(&after_db, ClosedPipe) {
+        Err(Error::Io(err)) => assert_eq!(err.kind(), std::io::ErrorKind::BrokenPipe),
+        result => panic!("expected a broken pipe, got {:?}", result),
+    }
+}
+
+#[test]
+fn show_diff_color() {
+    let before_db = Database::build("tests/suspicious_truncate/before", SHA2, 1, false).unwrap();
+    let after_db = Database::build("tests/suspicious_truncate/after", SHA2, 1, false).unwrap();
+    let report = |color| {
+        let options = DiffOptions { color, ..DiffOptions::default() };
+        let mut output = Vec::new();
+        before_db.show_diff_to_with_options(&after_db, &options, &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    };
+
+    // Only an explicit `Always` colors a sink other than stdout
+    let plain = report(ColorMode::Never);
+    assert_eq!(report(ColorMode::Auto), plain);
+    assert!(!plain.contains('\x1b'));
+    let colored = report(ColorMode::Always);
+    assert!(colored.starts_with(".: \x1b[33m1 changed\x1b[0m, 0 added, 0 removed, 2 unchanged\n"),
+            "{:?}", colored);
+    assert!(colored.contains("| \x1b[33mqwer.bin changed\x1b[0m\n"), "{:?}", colored);
+    assert!(colored.contains("##\x1b[1;31m> suspicious: file was truncated\x1b[0m\n"), "{:?}", colored);
+    let stripped = colored.replace("\x1b[33m", "").replace("\x1b[1;31m", "").replace("\x1b[0m", "");
+    assert_eq!(stripped, plain);
+}
+
+#[test]
+fn suspicious_only() {
+    let dir = tempfile::tempdir().unwrap();
+    for name in ["quiet", "busy"] {
+        std::fs::create_dir(dir.path().join(name)).unwrap();
+        std::fs::write(dir.path().join(name).join("edit.txt"), b"asdf\n").unwrap();
+    }
+    std::fs::write(dir.path().join("busy").join("log.txt"), b"qwer\n").unwrap();
+    std::fs::write(dir.path().join("busy").join("keep.txt"), b"zxcv\n").unwrap();
+    let before_db = Database::build(dir.path(), SHA2, 1, false).unwrap();
+    for name in ["quiet", "busy"] {
+        std::fs::write(dir.path().join(name).join("edit.txt"), b"asdf asdf\n").unwrap();
+    }
+    std::fs::write(dir.path().join("busy").join("log.txt"), b"").unwrap();
+    std::fs::write(dir.path().join("busy").join("new.txt"), b"new\n").unwrap();
+    let after_db = Database::build(dir.path(), SHA2, 1, false).unwrap();
+
+    let options = DiffOptions { suspicious_only: true, ..DiffOptions::default() };
+    let mut output = Vec::new();
+    let result = before_db.show_diff_to_with_options(&after_db, &options, &mut output).unwrap();
+    assert_eq!(result, DiffSummary::Suspicious);
+    assert_eq!(String::from_utf8(output).unwrap(),
+               ".: 1 changed, 0 added, 0 removed, 0 unchanged\n\
+                | busy: 1 changed, 0 added, 0 removed, 0 unchanged\n\
+                | | log.txt changed\n\
+                ####> suspicious: file was truncated\n");
+
+    // Without anything suspicious, nothing is shown, but the summary
+    // still reports the changes
+    std::fs::write(dir.path().join("busy").join("log.txt"), b"qwer\n").unwrap();
+    let after_db = Database::build(dir.path(), SHA2, 1, false).unwrap();
+    let mut output = Vec::new();
+    let result = before_db.show_diff_to_with_options(&after_db, &options, &mut output).unwrap();
+    assert_eq!(result, DiffSummary::Changes);
+    assert!(output.is_empty(), "{}", String::from_utf8_lossy(&output));
+    assert_eq!(before_db.diff(&after_db).suspicious_only(&options), None);
+}
+
+#[test]
+fn added_and_removed_listed() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir_all(dir.path().join("old").join("nested")).unwrap();
+    std::fs::write(dir.path().join("old").join("a.txt"), b"a\n").unwrap();
+    std::fs::write(dir.path().join("old").join("nested").join("b.txt"), b"b\n").unwrap();
+    std::fs::write(dir.path().join("gone.txt"), b"gone\n").unwrap();
+    let before_db = Database::build(dir.path(), SHA2, 1, false).unwrap();
+    std::fs::remove_dir_all(dir.path().join("old")).unwrap();
+    std::fs::remove_file(dir.path().join("gone.txt")).unwrap();
+    std::fs::create_dir(dir.path().join("new")).unwrap();
+    std::fs::write(dir.path().join("new").join("c.txt"), b"c\n").unwrap();
+    std::fs::write(dir.path().join("new.txt"), b"new\n").unwrap();
+    let after_db = Database::build(dir.path(), SHA2, 1, false).unwrap();
+
+    match before_db.diff(&after_db) {
+        EntryDiff::Directory(entries, stats) => {
+            assert_eq!((stats.added, stats.removed), (2, 2));
+            let listed = |added: bool| entries.values().filter(|entry| match entry {
+                EntryDiff::Added { .. } => added,
+                EntryDiff::Removed { .. } => !added,
+                _ => false,
+            }).count() as u64;
+            assert_eq!((listed(true), listed(false)), (stats.added, stats.removed));
+            match entries.get(Path::new("old")) {
+                Some(EntryDiff::Removed { files, .. }) => assert_eq!(*files, 2),
+                entry => panic!("expected a removed directory, got {:?}", entry),
+            }
+        }
+        diff => panic!("expected a directory diff, got {:?}", diff),
+    }
+    let mut output = Vec::new();
+    assert_eq!(before_db.show_diff_to(&after_db, &mut output).unwrap(), DiffSummary::Changes);
+    assert_eq!(String::from_utf8(output).unwrap(),
+               ".: 0 changed, 2 added (1 directory), 2 removed (1 directory), 0 unchanged\n\
+                | - gone.txt\n\
+                | + new/ (1 file)\n\
+                | + new.txt\n\
+                | - old/ (2 files)\n");
+}
+
+#[test]
+fn added_empty() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("asdf.txt"), b"asdf\n").unwrap();
+    let before_db = Database::build(dir.path(), SHA2, 1, false).unwrap();
+    std::fs::write(dir.path().join("marker"), b"").unwrap();
+    std::fs::write(dir.path().join("qwer.txt"), b"qwer\n").unwrap();
+    let after_db = Database::build(dir.path(), SHA2, 1, false).unwrap();
+
+    match before_db.diff(&after_db) {
+        EntryDiff::Directory(_, stats) => {
+            assert_eq!(stats.added, 2);
+            assert_eq!(stats.added_empty, 1);
+        }
+        diff => panic!("expected a directory diff, got {:?}", diff),
+    }
+    assert_eq!(before_db.show_diff(&after_db), DiffSummary::Changes);
+
+    let options = DiffOptions { flag_added_empty: true, ..DiffOptions::default() };
+    let mut output = Vec::new();
+    let result = before_db.show_diff_to_with_options(&after_db, &options, &mut output).unwrap();
+    assert_eq!(result, DiffSummary::Suspicious);
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.starts_with(".: 0 changed, 2 added (1 empty), 0 removed, 1 unchanged\n"));
+    assert!(output.contains("> suspicious: added empty file(s)\n"));
+
+    // An added directory counts once, like in `added`, and ignored
+    // files don't make it empty
+    std::fs::remove_file(dir.path().join("marker")).unwrap();
+    std::fs::create_dir(dir.path().join("markers")).unwrap();
+    for name in &["a", "b", "c"] {
+        std::fs::write(dir.path().join("markers").join(name), b"").unwrap();
+    }
+    let after_db = Database::build(dir.path(), SHA2, 1, false).unwrap();
+    let mut output = Vec::new();
+    before_db.show_diff_to_with_options(&after_db, &options, &mut output).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.starts_with(".: 0 changed, 2 added (1 empty, 1 directory), 0 removed, 1 unchanged\n"));
+
+    let mut ignore = GlobSetBuilder::new();
+    ignore.add(Glob::new("markers/*").unwrap());
+    let options = DiffOptions { ignore: Some(ignore.build().unwrap()), ..options };
+    let mut output = Vec::new();
+    let result = before_db.show_diff_to_with_options(&after_db, &options, &mut output).unwrap();
+    assert_eq!(result, DiffSummary::Changes);
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.starts_with(".: 0 changed, 2 added (1 directory), 0 removed, 1 unchanged\n"));
+}
+
+#[test]
+fn shared_only() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("base.txt"), b"asdf\n").unwrap();
+    std::fs::write(dir.path().join("left.txt"), b"left\n").unwrap();
+    let before_db = Database::build(dir.path(), SHA2, 1, false).unwrap();
+    std::fs::remove_file(dir.path().join("left.txt")).unwrap();
+    std::fs::write(dir.path().join("right.txt"), b"right\n").unwrap();
+    let after_db = Database::build(dir.path(), SHA2, 1, false).unwrap();
+
+    let options = DiffOptions { shared_only: true, ..DiffOptions::default() };
+    match before_db.diff_with_options(&after_db, &options) {
+        EntryDiff::Directory(_, stats) => {
+            assert_eq!((stats.added, stats.removed, stats.changed, stats.unchanged), (0, 0, 0, 1));
+        }
+        diff => panic!("expected a directory diff, got {:?}", diff),
+    }
+    assert_eq!(before_db.show_diff_with_options(&after_db, &options), DiffSummary::NoChanges);
+    assert_eq!(before_db.show_diff(&after_db), DiffSummary::Changes);
+
+    std::fs::write(dir.path().join("base.txt"), b"qwer\n").unwrap();
+    let tampered_db = Database::build(dir.path(), SHA2, 1, false).unwrap();
+    assert_eq!(before_db.show_diff_with_options(&tampered_db, &options), DiffSummary::Changes);
+}
+
+#[test]
+fn path_prefix() {
+    let threads = 1;
+    let before_db = Database::build("tests/suspicious_truncate/before", SHA2, threads, false).unwrap();
+    let after_db = Database::build("tests/suspicious_truncate/after", SHA2, threads, false).unwrap();
+    let options = DiffOptions {
+        path_prefix: Some(PathBuf::from("/opt/app")),
+        ..DiffOptions::default()
+    };
+    let mut output = Vec::new();
+    before_db.show_diff_to_with_options(&after_db, &options, &mut output).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.starts_with("/opt/app: 1 changed, 0 added, 0 removed, 2 unchanged\n"));
+    assert!(output.contains("| qwer.bin changed\n"));
+}
+
+#[test]
+fn summary_line() {
+    let threads = 1;
+    let before_db = Database::build("tests/suspicious_truncate/before", SHA2, threads, false).unwrap();
+    let after_db = Database::build("tests/suspicious_truncate/after", SHA2, threads, false).unwrap();
+    assert_eq!(before_db.diff(&after_db).summary_line(Path::new("/opt/app")),
+               "integrity check of /opt/app: 1 changed, 0 added, 0 removed, 1 suspicious");
+
+    let before_db = Database::build("tests/changes_delete_dir/before", SHA2, threads, false).unwrap();
+    let after_db = Database::build("tests/changes_delete_dir/after", SHA2, threads, false).unwrap();
+    assert_eq!(before_db.diff(&after_db).summary_line(Path::new(".")),
+               "integrity check of .: 0 changed, 0 added, 1 removed, 0 suspicious");
+}
+
+#[test]
+fn changed_subtree_roots() {
+    let dir = tempfile::tempdir().unwrap();
+    for sub in ["a/b/c", "a/d", "e/f"].iter() {
+        std::fs::create_dir_all(dir.path().join(sub)).unwrap();
+        std::fs::write(dir.path().join(sub).join("1.txt"), b"asdf\n").unwrap();
+    }
+    std::fs::write(dir.path().join("a/2.txt"), b"asdf\n").unwrap();
+    std::fs::write(dir.path().join("e/f/2.txt"), b"asdf\n").unwrap();
+    let before_db = Database::build(dir.path(), SHA2, 1, false).unwrap();
+    assert!(before_db.diff(&before_db).changed_subtree_roots().is_empty());
+
+    std::fs::write(dir.path().join("a/b/c/1.txt"), b"qwer\n").unwrap();
+    std::fs::write(dir.path().join("a/d/new.txt"), b"qwer\n").unwrap();
+    std::fs::remove_file(dir.path().join("e/f/1.txt")).unwrap();
+    let after_db = Database::build(dir.path(), SHA2, 1, false).unwrap();
+    assert_eq!(before_db.diff(&after_db).changed_subtree_roots(),
+               vec![PathBuf::from("a/b/c"), PathBuf::from("a/d"), PathBuf::from("e/f")]);
+
+    // A change directly in `a` covers everything below it
+    std::fs::write(dir.path().join("a/2.txt"), b"qwer\n").unwrap();
+    let after_db = Database::build(dir.path(), SHA2, 1, false).unwrap();
+    assert_eq!(before_db.diff(&after_db).changed_subtree_roots(),
+               vec![PathBuf::from("a"), PathBuf::from("e/f")]);
+}
+
+#[test]
+fn kind_changed() {
+    use std::fs;
+
+    let before = tempfile::tempdir().unwrap();
+    let after = tempfile::tempdir().unwrap();
+    fs::write(before.path().join("asdf.txt"), b"asdf\n").unwrap();
+    fs::write(before.path().join("data"), b"qwer\n").unwrap();
+    fs::write(after.path().join("asdf.txt"), b"asdf\n").unwrap();
+    fs::create_dir(after.path().join("data")).unwrap();
+    fs::write(after.path().join("data").join("1.txt"), b"qwer\n").unwrap();
+
+    let threads = 1;
+    let before_db = Database::build(before.path(), SHA2, threads, false).unwrap();
+    let after_db = Database::build(after.path(), SHA2, threads, false).unwrap();
+
+    let mut output = Vec::new();
+    let result = before_db.show_diff_to(&after_db, &mut output).unwrap();
+    assert_eq!(result, DiffSummary::Changes);
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.starts_with(".: 1 changed, 0 added, 0 removed, 1 unchanged\n"));
+    assert!(output.contains("| kind changed at data: file -> directory\n"));
+
+    let mut output = Vec::new();
+    after_db.show_diff_to(&before_db, &mut output).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("| kind changed at data: directory -> file\n"));
+}
+
+#[test]
+fn normalize_paths() {
+    use integrity_checker::database::PathNormalization;
+    use std::fs;
+
+    let (nfc, nfd) = ("caf\u{e9}", "cafe\u{301}");
+    let before = tempfile::tempdir().unwrap();
+    let after = tempfile::tempdir().unwrap();
+    fs::create_dir(before.path().join(nfc)).unwrap();
+    fs::write(before.path().join(nfc).join("menu.txt"), b"asdf\n").unwrap();
+    fs::create_dir(after.path().join(nfd)).unwrap();
+    fs::write(after.path().join(nfd).join("menu.txt"), b"asdf\n").unwrap();
+    let before_db = Database::build(before.path(), Features::default(), 1, false).unwrap();
+    let after_db = Database::build(after.path(), Features::default(), 1, false).unwrap();
+    assert_eq!(before_db.show_diff(&after_db), DiffSummary::Changes);
+
+    for form in [PathNormalization::Nfc, PathNormalization::Nfd] {
+        let options = DiffOptions { normalize_paths: Some(form), ..DiffOptions::default() };
+        assert_eq!(before_db.show_diff_with_options(&after_db, &options), DiffSummary::NoChanges);
+    }
+
+    // Where both forms exist side by side, neither is lost
+    if fs::write(after.path().join(nfc), b"qwer\n").is_err() ||
+        fs::read_dir(after.path()).unwrap().count() < 2
+    {
+        return; // Normalizing file system, can't test collisions
+    }
+    let after_db = Database::build(after.path(), Features::default(), 1, false).unwrap();
+    let options = DiffOptions {
+        normalize_paths: Some(PathNormalization::Nfc),
+        ..DiffOptions::default()
+    };
+    match before_db.diff_with_options(&after_db, &options) {
+        EntryDiff::Directory(_, stats) => {
+            assert_eq!((stats.added, stats.removed, stats.changed), (1, 0, 1));
+        }
+        diff => panic!("expected a directory diff, got {:?}", diff),
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn changed_owner() {
+    let threads = 1;
+    let before_db = Database::build("tests/nochanges/before", SHA2, threads, false).unwrap();
+    let mut value = serde_json::to_value(&before_db).unwrap();
+    assert!(value["Directory"]["asdf.txt"]["File"]["uid"].is_u64());
+
+    // Databases without owners (e.g. from older versions) compare cleanly
+    let file = value["Directory"]["asdf.txt"]["File"].as_object_mut().unwrap();
+    file.remove("uid");
+    file.remove("gid");
+    let old_db: Database = serde_json::from_str(&value.to_string()).unwrap();
+    assert_eq!(old_db.show_diff(&before_db), DiffSummary::NoChanges);
+
+    value["Directory"]["asdf.txt"]["File"]["uid"] = 12345.into();
+    value["Directory"]["asdf.txt"]["File"]["gid"] = 678.into();
+    let after_db: Database = serde_json::from_str(&value.to_string()).unwrap();
+    let mut output = Vec::new();
+    assert_eq!(before_db.show_diff_to(&after_db, &mut output).unwrap(), DiffSummary::Changes);
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("> owner changed from "), "{}", output);
+    assert!(output.contains(" to 12345:678\n"), "{}", output);
+}
+
+#[test]
+fn diff_subtree() {
+    let before = tempfile::tempdir().unwrap();
+    std::fs::create_dir_all(before.path().join("projects/alpha")).unwrap();
+    std::fs::write(before.path().join("projects/alpha/a.txt"), b"asdf\n").unwrap();
+    std::fs::write(before.path().join("projects/alpha/b.log"), b"asdf\n").unwrap();
+    std::fs::write(before.path().join("projects/beta"), b"qwer\n").unwrap();
+    let after = tempfile::tempdir().unwrap();
+    std::fs::create_dir_all(after.path().join("projects/alpha")).unwrap();
+    std::fs::write(after.path().join("projects/alpha/a.txt"), b"ASDF\n").unwrap();
+    std::fs::write(after.path().join("projects/alpha/b.log"), b"ASDF\n").unwrap();
+    std::fs::create_dir_all(after.path().join("projects/beta")).unwrap();
+
+    let before = Database::build(before.path(), Features::default(), 1, false).unwrap();
+    let after = Database::build(after.path(), Features::default(), 1, false).unwrap();
+
+    // Ignore globs match paths from the database root
+    let mut ignore = GlobSetBuilder::new();
+    ignore.add(Glob::new("projects/alpha/*.log").unwrap());
+    let options = DiffOptions { ignore: Some(ignore.build().unwrap()), ..DiffOptions::default() };
+    match before.diff_subtree_with_options(&after, "projects/alpha", &options).unwrap() {
+        EntryDiff::Directory(entries, stats) => {
+            assert_eq!((stats.changed, stats.unchanged), (1, 1));
+            assert!(entries.contains_key(Path::new("a.txt")));
+        }
+        diff => panic!("expected a directory diff, got {:?}", diff),
+    }
+
+    match before.diff_subtree(&after, "projects/beta") {
+        Err(Error::NotADirectory(path)) => assert_eq!(path, Path::new("projects/beta")),
+        result => panic!("expected NotADirectory, got {:?}", result),
+    }
+    match before.diff_subtree(&after, "projects/gamma") {
+        Err(Error::NotInDatabase(path)) => assert_eq!(path, Path::new("projects/gamma")),
+        result => panic!("expected NotInDatabase, got {:?}", result),
     }
 }
@@ -0,0 +1,58 @@
+#![cfg(unix)]
+
+use std::fs;
+use std::os::unix::fs::symlink;
+use std::os::unix::io::AsFd;
+use std::path::PathBuf;
+
+use integrity_checker::database::{BuildOptions, Database, DiffSummary};
+
+use tempfile::tempdir;
+
+#[test]
+fn build_from_dir_fd() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("asdf.txt"), b"asdf\n").unwrap();
+    fs::create_dir(dir.path().join("nested")).unwrap();
+    fs::write(dir.path().join("nested").join("qwer.txt"), b"qwer\n").unwrap();
+    fs::write(dir.path().join(".hidden"), b"zxcv\n").unwrap();
+
+    let options = BuildOptions::default();
+    let expected = Database::build_with_options(dir.path(), &options).unwrap();
+    let handle = fs::File::open(dir.path()).unwrap();
+    let db = Database::build_from_dir_fd(handle.as_fd(), &options).unwrap();
+    assert_eq!(expected.show_diff_to(&db, Vec::new()).unwrap(), DiffSummary::NoChanges);
+    assert!(db.lookup(&PathBuf::from(".hidden")).is_none());
+
+    let options = BuildOptions { max_depth: Some(1), ..BuildOptions::default() };
+    let db = Database::build_from_dir_fd(handle.as_fd(), &options).unwrap();
+    assert!(db.lookup(&PathBuf::from("asdf.txt")).is_some());
+    assert!(db.lookup(&PathBuf::from("nested/qwer.txt")).is_none());
+
+    let options = BuildOptions {
+        strip_prefix_components: Some(PathBuf::from("nested")),
+        ..BuildOptions::default()
+    };
+    let expected = Database::build_with_options(dir.path(), &options).unwrap();
+    let db = Database::build_from_dir_fd(handle.as_fd(), &options).unwrap();
+    assert_eq!(expected.show_diff_to(&db, Vec::new()).unwrap(), DiffSummary::NoChanges);
+    assert!(db.lookup(&PathBuf::from("qwer.txt")).is_some());
+    assert!(db.lookup(&PathBuf::from("asdf.txt")).is_none());
+}
+
+#[test]
+fn build_from_dir_fd_ignores_symlinks() {
+    let outside = tempdir().unwrap();
+    fs::write(outside.path().join("secret.txt"), b"asdf\n").unwrap();
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("asdf.txt"), b"asdf\n").unwrap();
+    symlink(outside.path(), dir.path().join("link")).unwrap();
+    symlink(outside.path().join("secret.txt"), dir.path().join("secret.txt")).unwrap();
+
+    let options = BuildOptions { follow_links: true, ..BuildOptions::default() };
+    let handle = fs::File::open(dir.path()).unwrap();
+    let db = Database::build_from_dir_fd(handle.as_fd(), &options).unwrap();
+    assert!(db.lookup(&PathBuf::from("asdf.txt")).is_some());
+    assert!(db.lookup(&PathBuf::from("link")).is_none());
+    assert!(db.lookup(&PathBuf::from("secret.txt")).is_none());
+}
@@ -0,0 +1,170 @@
+use std::path::PathBuf;
+
+use integrity_checker::database::{BuildOptions, Database, Entry, Features};
+
+#[test]
+fn display_metrics() {
+    let features = Features {
+        sha2: false, blake2b: false, sha256: true, sha512: false, blake3: false, xxh64: false,
+        crc32: false,
+    };
+    let threads = 1;
+    let db = Database::build("tests/nochanges/before", features, threads, false).unwrap();
+    let metrics = match db.lookup(&PathBuf::from("asdf.txt")) {
+        Some(Entry::File(metrics)) => metrics,
+        entry => panic!("expected a file, got {:?}", entry),
+    };
+    let mode = match metrics.mode_symbolic() {
+        Some(mode) => format!("mode:         {}\n", mode),
+        None => String::new(),
+    };
+    assert_eq!(
+        metrics.to_string(),
+        format!("size:         13 B (13 bytes)\n\
+                 sha2-256:     0ba904eae8773b70c75333db4de2f3ac45a8ad4ddba1b242f0b3cfc199391dd8\n\
+                 {}\
+                 nul:          false\n\
+                 nonascii:     false\n\
+                 utf8:         true", mode));
+}
+
+#[cfg(unix)]
+#[test]
+fn mode_symbolic() {
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let cases = [
+        (0o644, "rw-r--r--"),
+        (0o755, "rwxr-xr-x"),
+        (0o4755, "rwsr-xr-x"),
+        (0o2750, "rwxr-s---"),
+        (0o1777, "rwxrwxrwt"),
+        (0o4644, "rwSr--r--"),
+        (0o1644, "rw-r--r-T"),
+    ];
+    for (mode, expected) in cases.iter() {
+        let path = dir.path().join("file");
+        fs::write(&path, b"asdf\n").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(*mode)).unwrap();
+        let db = Database::build(dir.path(), Features::default(), 1, false).unwrap();
+        match db.lookup(&PathBuf::from("file")) {
+            Some(Entry::File(metrics)) => {
+                assert_eq!(metrics.mode_symbolic().as_deref(), Some(*expected))
+            }
+            entry => panic!("expected a file, got {:?}", entry),
+        }
+    }
+}
+
+#[test]
+fn human_json() {
+    let features = Features {
+        sha2: false, blake2b: false, sha256: true, sha512: false, blake3: false, xxh64: false,
+        crc32: false,
+    };
+    let threads = 1;
+    let db = Database::build("tests/changes_delete_dir/before", features, threads, false).unwrap();
+    let json = db.to_human_json();
+    let file = &json["a"]["b"]["c"]["1.txt"];
+    assert!(file["size"].is_u64());
+    assert_eq!(file["sha2-256"].as_str().map(str::len), Some(64));
+    assert_eq!(file["nul"], false);
+    assert!(file.get("blake2b").is_none());
+    assert!(json["d"]["4.txt"].is_object());
+}
+
+#[test]
+fn utf8_across_buffers() {
+    let dir = tempfile::tempdir().unwrap();
+    // Place a two-byte character across the 4096-byte read boundary
+    let mut text = "a".repeat(4095);
+    text.push('λ');
+    std::fs::write(dir.path().join("text.txt"), &text).unwrap();
+    let mut bytes = text.into_bytes();
+    bytes.truncate(4096);
+    std::fs::write(dir.path().join("truncated.txt"), &bytes).unwrap();
+
+    let options = BuildOptions { buffer_size: 4096, ..BuildOptions::default() };
+    let db = Database::build_with_options(dir.path(), &options).unwrap();
+    let json = db.to_human_json();
+    assert_eq!(json["text.txt"]["utf8"], true);
+    assert_eq!(json["text.txt"]["nonascii"], true);
+    assert_eq!(json["truncated.txt"]["utf8"], false);
+}
+
+#[test]
+fn blake3_known_answers() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("empty"), b"").unwrap();
+    std::fs::write(dir.path().join("abc"), b"abc").unwrap();
+    // The official test vectors hash the bytes 0, 1, ..., 250, 0, 1, ...
+    // of these lengths: one full chunk, a second chunk and three chunks
+    for len in &[1024, 1025, 2049] {
+        let input: Vec<u8> = (0..*len).map(|i| (i % 251) as u8).collect();
+        std::fs::write(dir.path().join(len.to_string()), input).unwrap();
+    }
+
+    let features = Features {
+        sha2: false, blake2b: false, sha256: false, sha512: false, blake3: true, xxh64: false,
+        crc32: false,
+    };
+    let db = Database::build(dir.path(), features, 1, false).unwrap();
+    let json = db.to_human_json();
+    assert_eq!(json["empty"]["blake3"],
+               "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262");
+    assert_eq!(json["abc"]["blake3"],
+               "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85");
+    assert_eq!(json["1024"]["blake3"],
+               "42214739f095a406f3fc83deb889744ac00df831c10daa55189b5d121c855af7");
+    assert_eq!(json["1025"]["blake3"],
+               "d00278ae47eb27b34faecf67b4fe263f82d5412916c1ffd97c8cb7fb814b8444");
+    assert_eq!(json["2049"]["blake3"],
+               "5f4d72f40d7a5f82b15ca2b2e44b1de3c2ef86c426c95c1af0b6879522563030");
+}
+
+#[test]
+fn sha512_known_answer() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("abc"), b"abc").unwrap();
+
+    let features = Features {
+        sha2: false, blake2b: false, sha256: false, sha512: true, blake3: false, xxh64: false,
+        crc32: false,
+    };
+    let db = Database::build(dir.path(), features, 1, false).unwrap();
+    assert_eq!(db.to_human_json()["abc"]["sha2-512"],
+               "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a\
+                2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f");
+}
+
+#[test]
+fn crc32_known_answer() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("check"), b"123456789").unwrap();
+
+    let features = Features {
+        sha2: false, blake2b: false, sha256: false, sha512: false, blake3: false, xxh64: false,
+        crc32: true,
+    };
+    let db = Database::build(dir.path(), features, 1, false).unwrap();
+    assert_eq!(db.to_human_json()["check"]["crc32"], "cbf43926");
+    let metrics = db.lookup(&std::path::PathBuf::from("check")).unwrap().as_metrics().unwrap();
+    assert_eq!(metrics.crc32(), Some(0xCBF4_3926));
+}
+
+#[test]
+fn xxh64_known_answers() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("empty"), b"").unwrap();
+    std::fs::write(dir.path().join("abc"), b"abc").unwrap();
+    // Longer than one 32-byte stripe, so the four accumulators are used
+    std::fs::write(dir.path().join("long"), b"Nobody inspects the spammish repetition").unwrap();
+
+    let db = Database::build(dir.path(), Features::fast(), 1, false).unwrap();
+    let json = db.to_human_json();
+    assert_eq!(json["empty"]["xxh64"], "ef46db3751d8e999");
+    assert_eq!(json["abc"]["xxh64"], "44bc2cf5ad770999");
+    assert_eq!(json["long"]["xxh64"], "fbcea83c8a378bf1");
+}
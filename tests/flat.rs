@@ -0,0 +1,82 @@
+use std::path::{Path, PathBuf};
+
+use integrity_checker::database::{BuildOptions, Database, DiffOptions};
+use integrity_checker::flat::{FlatDatabase, FlatDiff};
+
+fn build(path: &str, threads: usize) -> FlatDatabase {
+    let options = BuildOptions { threads, ..BuildOptions::default() };
+    FlatDatabase::build_with_options(path, &options).unwrap()
+}
+
+#[test]
+fn flat_matches_tree() {
+    for threads in &[1, 4] {
+        let options = BuildOptions { threads: *threads, ..BuildOptions::default() };
+        let db = Database::build_with_options("tests/changes_delete_dir/before", &options).unwrap();
+        let flat = build("tests/changes_delete_dir/before", *threads);
+        assert_eq!(flat, FlatDatabase::from_database(&db));
+        assert_eq!(flat.to_database(), db);
+        assert_eq!(flat.len(), 5);
+    }
+}
+
+#[test]
+fn flat_lookup_and_directory() {
+    let flat = build("tests/changes_delete_dir/before", 1);
+    assert!(flat.lookup(Path::new("a/b/c/2.txt")).is_some());
+    assert!(flat.lookup(Path::new("a/b/c")).is_none());
+
+    let paths: Vec<_> = flat.directory(Path::new("a/b")).iter().map(|(p, _)| p.clone()).collect();
+    assert_eq!(paths, vec![
+        PathBuf::from("a/b/c/1.txt"),
+        PathBuf::from("a/b/c/2.txt"),
+        PathBuf::from("a/b/c/3.txt"),
+    ]);
+    assert_eq!(flat.directory(Path::new("d")).len(), 2);
+    assert_eq!(flat.directory(Path::new("")).len(), 5);
+    assert!(flat.directory(Path::new("e")).is_empty());
+}
+
+#[test]
+fn flat_diff() {
+    let before = build("tests/changes_delete_dir/before", 1);
+    let after = build("tests/changes_delete_dir/after", 1);
+    let diff = before.diff(&after, &DiffOptions::default());
+    assert_eq!(diff.len(), 3);
+    assert!(diff.iter().all(|(_, change)| matches!(change, FlatDiff::Removed)));
+
+    let before = build("tests/changes_edit/before", 1);
+    let after = build("tests/changes_edit/after", 1);
+    match &before.diff(&after, &DiffOptions::default())[..] {
+        [(path, FlatDiff::Changed(diff))] => {
+            assert_eq!(path, Path::new("zxcv.txt"));
+            assert!(diff.changed_content);
+        }
+        diff => panic!("unexpected diff {:?}", diff),
+    }
+}
+
+#[test]
+fn flat_diff_keeps_suspicious() {
+    use std::fs::{self, File};
+    use std::time::{Duration, SystemTime};
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("log.txt");
+    fs::write(&path, b"asdf\n").unwrap();
+    let now = SystemTime::now();
+    File::options().write(true).open(&path).unwrap().set_modified(now).unwrap();
+    let before = FlatDatabase::build_with_options(dir.path(), &BuildOptions::default()).unwrap();
+    File::options().write(true).open(&path).unwrap().set_modified(now - Duration::from_secs(86400)).unwrap();
+    let after = FlatDatabase::build_with_options(dir.path(), &BuildOptions::default()).unwrap();
+
+    assert!(before.diff(&after, &DiffOptions::default()).is_empty());
+    let options = DiffOptions { check_mtime: true, ..DiffOptions::default() };
+    match &before.diff(&after, &options)[..] {
+        [(path, FlatDiff::Changed(diff))] => {
+            assert_eq!(path, Path::new("log.txt"));
+            assert!(!diff.is_changed() && diff.is_suspicious());
+        }
+        diff => panic!("unexpected diff {:?}", diff),
+    }
+}
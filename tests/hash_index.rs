@@ -0,0 +1,29 @@
+use std::fs;
+use std::path::PathBuf;
+
+use integrity_checker::database::{BuildOptions, Database, Features};
+
+use tempfile::tempdir;
+
+#[test]
+fn build_hash_index() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("asdf.txt"), b"asdf\n").unwrap();
+    fs::create_dir(dir.path().join("copies")).unwrap();
+    fs::write(dir.path().join("copies").join("asdf.bak"), b"asdf\n").unwrap();
+    fs::write(dir.path().join("qwer.txt"), b"qwer\n").unwrap();
+
+    let db = Database::build(dir.path(), Features::default(), 1, false).unwrap();
+    let index = db.build_hash_index();
+    let mut groups: Vec<_> = index.values().cloned().collect();
+    groups.sort();
+    assert_eq!(groups, vec![
+        vec![PathBuf::from("asdf.txt"), PathBuf::from("copies/asdf.bak")],
+        vec![PathBuf::from("qwer.txt")],
+    ]);
+
+    // Path-bound hashes never match, so such files are left out
+    let options = BuildOptions { bind_paths: true, ..BuildOptions::default() };
+    let bound = Database::build_with_options(dir.path(), &options).unwrap();
+    assert!(bound.build_hash_index().is_empty());
+}
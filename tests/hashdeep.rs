@@ -0,0 +1,71 @@
+use std::fs;
+use std::path::PathBuf;
+
+use integrity_checker::database::{Database, EntryDiff, Features};
+use integrity_checker::error::Error;
+
+use tempfile::tempdir;
+
+#[test]
+fn hashdeep_roundtrip() {
+    let dir = tempdir().unwrap();
+    fs::create_dir(dir.path().join("sub")).unwrap();
+    fs::write(dir.path().join("sub").join("a,b.txt"), b"asdf\n").unwrap();
+    let features = Features { sha256: true, ..Features::default() };
+    let db = Database::build(dir.path(), features, 1, false).unwrap();
+
+    let mut audit = Vec::new();
+    db.write_hashdeep(&mut audit).unwrap();
+    let audit = String::from_utf8(audit).unwrap();
+    assert!(audit.starts_with("%%%% HASHDEEP-1.0\n%%%% size,sha256,filename\n"), "{}", audit);
+    assert!(audit.ends_with(
+        "\n5,d1bc8d3ba4afc7e109612cb73acbdddac052c93025aa1f82942edabb7deb82a1,sub/a,b.txt\n"), "{}", audit);
+
+    let imported = Database::from_hashdeep(audit.as_bytes()).unwrap();
+    assert!(imported.ignored_columns.is_empty());
+    assert!(imported.database.diff(&db).outcome().clean);
+
+    let unhashed = Database::build(dir.path(), Features::default(), 1, false).unwrap();
+    match unhashed.write_hashdeep(Vec::new()) {
+        Err(Error::MissingHash { path, .. }) => assert_eq!(path, PathBuf::from("sub/a,b.txt")),
+        result => panic!("expected MissingHash, got {:?}", result),
+    }
+}
+
+#[test]
+fn hashdeep_import() {
+    let audit = "\
+%%%% HASHDEEP-1.0\r
+%%%% size,md5,sha256,filename\r
+## Invoked from: /home/user\r
+## $ hashdeep -l -r .\r
+##\r
+5,2b00042f7481c7b056c4b410d28f33cf,d1bc8d3ba4afc7e109612cb73acbdddac052c93025aa1f82942edabb7deb82a1,./same.txt\r
+5,2b00042f7481c7b056c4b410d28f33cf,d1bc8d3ba4afc7e109612cb73acbdddac052c93025aa1f82942edabb7deb82a1,edited.txt\r
+";
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("same.txt"), b"asdf\n").unwrap();
+    fs::write(dir.path().join("edited.txt"), b"qwer\n").unwrap();
+    let features = Features { sha256: true, ..Features::default() };
+    let current = Database::build(dir.path(), features, 1, false).unwrap();
+
+    let imported = Database::from_hashdeep(audit.as_bytes()).unwrap();
+    assert_eq!(imported.ignored_columns, vec!["md5".to_owned()]);
+    match imported.database.diff(&current) {
+        EntryDiff::Directory(entries, stats) => {
+            assert_eq!((stats.changed, stats.unchanged), (1, 1));
+            assert!(entries.contains_key(&PathBuf::from("edited.txt")));
+        }
+        diff => panic!("expected a directory diff, got {:?}", diff),
+    }
+
+    let bad = [
+        "5,abc\n", "%%%% filename,size\n", "x,00,/abs\n", "%%%% size,filename\n5,../escape.txt\n",
+    ];
+    for bad in bad.iter() {
+        match Database::from_hashdeep(bad.as_bytes()) {
+            Err(Error::ParseError) => (),
+            result => panic!("expected ParseError for {:?}, got {:?}", bad, result.map(|_| ())),
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use std::fs;
+
+use integrity_checker::database::{Database, DiffFormatter, DiffOptions, Features};
+use integrity_checker::html::HtmlFormatter;
+
+#[test]
+fn html_suspicious() {
+    let threads = 1;
+    let features = Features::default();
+    let before_db = Database::build("tests/suspicious_truncate/before", features, threads, false)
+        .unwrap();
+    let after_db = Database::build("tests/suspicious_truncate/after", features, threads, false)
+        .unwrap();
+    let html = before_db.diff_html(&after_db);
+    assert!(html.starts_with("<!DOCTYPE html>"));
+    assert!(html.contains("Suspicious findings (2)"));
+    assert!(html.contains("<code>qwer.bin</code>: file was truncated"));
+    assert!(html.trim_end().ends_with("</html>"));
+}
+
+#[test]
+fn html_no_changes() {
+    let db = Database::build("tests/nochanges/before", Features::default(), 1, false).unwrap();
+    let html = db.diff_html(&db);
+    assert!(html.contains("<p>No changes.</p>"));
+    assert!(!html.contains("Suspicious findings"));
+}
+
+#[test]
+fn html_escapes_paths() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("<b>&\"x\".txt");
+    fs::write(&path, b"asdf\n").unwrap();
+    let before_db = Database::build(dir.path(), Features::default(), 1, false).unwrap();
+    fs::write(&path, b"asdf asdf\n").unwrap();
+    let after_db = Database::build(dir.path(), Features::default(), 1, false).unwrap();
+
+    let html = before_db.diff_html(&after_db);
+    assert!(html.contains("<code>&lt;b&gt;&amp;&quot;x&quot;.txt</code> changed"));
+    assert!(!html.contains("<b>"));
+}
+
+#[test]
+fn html_suspicious_without_changes() {
+    use std::fs::File;
+    use std::time::{Duration, SystemTime};
+
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir(dir.path().join("logs")).unwrap();
+    let path = dir.path().join("logs").join("log.txt");
+    fs::write(&path, b"asdf\n").unwrap();
+    let now = SystemTime::now();
+    File::options().write(true).open(&path).unwrap().set_modified(now).unwrap();
+    let before_db = Database::build(dir.path(), Features::default(), 1, false).unwrap();
+    File::options().write(true).open(&path).unwrap().set_modified(now - Duration::from_secs(86400)).unwrap();
+    let after_db = Database::build(dir.path(), Features::default(), 1, false).unwrap();
+
+    let options = DiffOptions { check_mtime: true, ..DiffOptions::default() };
+    let mut output = Vec::new();
+    HtmlFormatter.format(&before_db.diff_with_options(&after_db, &options), &mut output).unwrap();
+    let html = String::from_utf8(output).unwrap();
+    assert!(html.contains("Suspicious findings (1)"), "{}", html);
+    assert!(!html.contains("No changes."), "{}", html);
+    assert!(html.contains("<code>logs/</code>"), "{}", html);
+    assert!(html.contains("<code>log.txt</code> suspicious: mtime moved backward"), "{}", html);
+}
@@ -0,0 +1,92 @@
+#![cfg(unix)]
+
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::time::{Duration, UNIX_EPOCH};
+
+use integrity_checker::database::{
+    BuildOptions, Database, DiffOptions, DiffSummary, EntryDiff, ListingFormat,
+};
+use integrity_checker::error::Error;
+
+use tempfile::{tempdir, TempDir};
+
+fn fixture() -> (TempDir, Database) {
+    let dir = tempdir().unwrap();
+    fs::create_dir(dir.path().join("sub")).unwrap();
+    for (name, contents) in [("asdf.txt", "asdf\n"), ("sub/my file.txt", "qwer\n")] {
+        let path = dir.path().join(name);
+        fs::write(&path, contents).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        fs::File::options().write(true).open(&path).unwrap()
+            .set_modified(UNIX_EPOCH + Duration::from_secs(1_600_000_000)).unwrap();
+    }
+    let db = Database::build_with_options(dir.path(), &BuildOptions::default()).unwrap();
+    (dir, db)
+}
+
+fn summary(diff: &EntryDiff) -> DiffSummary {
+    diff.report(&DiffOptions::default(), Vec::new()).unwrap()
+}
+
+#[test]
+fn listing_matches() {
+    let (_dir, db) = fixture();
+    let listing = "5 1600000000.0000000000 644 asdf.txt\n\
+                   5 1600000000.0000000000 644 sub/my file.txt\n";
+    let diff = db.diff_metadata_listing(listing.as_bytes(), ListingFormat::Lines).unwrap();
+    assert_eq!(summary(&diff), DiffSummary::NoChanges);
+
+    let listing = "5 1600000000 644 ./asdf.txt\x005 1600000000 644 ./sub/my file.txt\x00";
+    let diff = db.diff_metadata_listing(listing.as_bytes(), ListingFormat::Nul).unwrap();
+    assert_eq!(summary(&diff), DiffSummary::NoChanges);
+}
+
+#[test]
+fn listing_ignores_non_files() {
+    let (dir, _) = fixture();
+    fs::create_dir(dir.path().join("empty")).unwrap();
+    std::os::unix::fs::symlink("asdf.txt", dir.path().join("link")).unwrap();
+    let db = Database::build_with_options(dir.path(), &BuildOptions::default()).unwrap();
+    assert!(db.lookup(std::path::Path::new("empty")).is_some());
+    assert!(db.lookup(std::path::Path::new("link")).is_some());
+
+    // As listed by `find -type f`
+    let listing = "5 1600000000 644 asdf.txt
+5 1600000000 644 sub/my file.txt
+";
+    let diff = db.diff_metadata_listing(listing.as_bytes(), ListingFormat::Lines).unwrap();
+    assert_eq!(summary(&diff), DiffSummary::NoChanges);
+    let listing = "5 1600000000 644 asdf.txt
+";
+    let diff = db.diff_metadata_listing(listing.as_bytes(), ListingFormat::Lines).unwrap();
+    assert!(diff.summary_line(std::path::Path::new(".")).contains("0 changed, 0 added, 1 removed"));
+}
+
+#[test]
+fn listing_changes() {
+    let (_dir, db) = fixture();
+    let root = std::path::Path::new(".");
+    let cases = [
+        ("6 1600000000 644 asdf.txt\n", "1 changed, 0 added, 1 removed"), // Size
+        ("5 1600000001 644 asdf.txt\n", "1 changed, 0 added, 1 removed"), // Mtime
+        ("5 1600000000 600 asdf.txt\n", "1 changed, 0 added, 1 removed"), // Mode
+        ("5 1600000000 644 asdf.txt\n5 1600000000 644 new.txt\n", "0 changed, 1 added, 1 removed"),
+    ];
+    for (listing, expected) in cases.iter() {
+        let diff = db.diff_metadata_listing(listing.as_bytes(), ListingFormat::Lines).unwrap();
+        assert_eq!(summary(&diff), DiffSummary::Changes);
+        assert!(diff.summary_line(root).contains(expected), "{}", diff.summary_line(root));
+    }
+}
+
+#[test]
+fn listing_malformed() {
+    let (_dir, db) = fixture();
+    for listing in ["5 1600000000 644\n", "five 1600000000 644 asdf.txt\n", "5 1600000000 999 asdf.txt\n"] {
+        match db.diff_metadata_listing(listing.as_bytes(), ListingFormat::Lines) {
+            Err(Error::ParseError) => (),
+            result => panic!("expected ParseError for {:?}, got {:?}", listing, result),
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use std::fs;
+use std::path::PathBuf;
+
+use integrity_checker::database::{Database, DiffSummary, EntryDiff, Features, ManifestAlgorithm};
+use integrity_checker::error::Error;
+
+use tempfile::tempdir;
+
+#[test]
+fn verify_crc32_manifest() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("check.txt"), b"123456789").unwrap();
+    fs::create_dir(dir.path().join("sub")).unwrap();
+    fs::write(dir.path().join("sub").join("edited.txt"), b"asdf\n").unwrap();
+    fs::write(dir.path().join("unlisted.txt"), b"qwer\n").unwrap();
+
+    let features = Features { crc32: true, ..Features::default() };
+    let db = Database::build(dir.path(), features, 1, false).unwrap();
+    let manifest = "\
+# Legacy backup manifest
+check.txt\tCBF43926
+sub/edited.txt\t00000000
+gone.txt\t12345678
+bad.txt\tnot-hex
+no tab here
+";
+    let report = db.verify_crc32_manifest(manifest.as_bytes()).unwrap();
+    assert_eq!(report.matched, vec![PathBuf::from("check.txt")]);
+    assert_eq!(report.mismatched, vec![PathBuf::from("sub/edited.txt")]);
+    assert_eq!(report.only_in_manifest, vec![PathBuf::from("gone.txt")]);
+    assert_eq!(report.only_in_database, vec![PathBuf::from("unlisted.txt")]);
+    assert!(report.unchecked.is_empty());
+    assert_eq!(report.malformed, vec![
+        (5, "bad.txt\tnot-hex".to_owned()),
+        (6, "no tab here".to_owned()),
+    ]);
+
+    // Without recorded CRC-32s, nothing can be checked
+    let db = Database::build(dir.path(), Features::default(), 1, false).unwrap();
+    let report = db.verify_crc32_manifest(manifest.as_bytes()).unwrap();
+    assert!(report.matched.is_empty() && report.mismatched.is_empty());
+    assert_eq!(report.unchecked, vec![PathBuf::from("check.txt"), PathBuf::from("sub/edited.txt")]);
+}
+
+#[test]
+fn from_sha256sum() {
+    let dir = tempdir().unwrap();
+    fs::create_dir(dir.path().join("sub")).unwrap();
+    fs::write(dir.path().join("sub").join("same.txt"), b"asdf\n").unwrap();
+    fs::write(dir.path().join("a\nb.txt"), b"qwer\n").unwrap();
+    fs::write(dir.path().join("edited.txt"), b"asdf\n").unwrap();
+    let features = Features { sha256: true, ..Features::default() };
+    let current = Database::build(dir.path(), features, 1, false).unwrap();
+
+    let manifest = "\
+d1bc8d3ba4afc7e109612cb73acbdddac052c93025aa1f82942edabb7deb82a1  ./sub/same.txt
+\\5F70AE29B3019EC851EF6B664B59D3FD88DDA0DE5EB58212DDBD97C65C3F8198 *a\\nb.txt
+5f70ae29b3019ec851ef6b664b59d3fd88dda0de5eb58212ddbd97c65c3f8198  edited.txt
+";
+    let path = dir.path().join("SHA256SUMS");
+    fs::write(&path, manifest).unwrap();
+    let imported = Database::from_sha256sum_file(&path).unwrap();
+    assert!(imported.contains_file("a\nb.txt"));
+
+    // Manifests saved with CRLF line endings name the same files
+    let crlf = Database::from_sha256sum(manifest.replace('\n', "\r\n").as_bytes()).unwrap();
+    assert!(crlf.contains_file("edited.txt") && !crlf.contains_file("edited.txt\r"));
+    assert!(crlf.diff(&imported).outcome().clean);
+
+    // Only the hashes are compared, not the unknown sizes and flags
+    match imported.diff(&current) {
+        EntryDiff::Directory(entries, stats) => {
+            assert_eq!((stats.changed, stats.unchanged, stats.added), (1, 2, 0));
+            assert!(entries.contains_key(&PathBuf::from("edited.txt")));
+        }
+        diff => panic!("expected a directory diff, got {:?}", diff),
+    }
+
+    // Against a default build, nothing at all could be verified
+    let default = Database::build(dir.path(), Features::default(), 1, false).unwrap();
+    let mut output = Vec::new();
+    let result = imported.show_diff_to(&default, &mut output).unwrap();
+    assert_eq!(result, DiffSummary::Changes);
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("warning: 3 file(s) not verified"), "{}", output);
+    assert!(output.contains("##> sub/same.txt\n"), "{}", output);
+
+    let bad = [
+        "d1bc8d3b  short.txt\n",
+        "d1bc8d3ba4afc7e109612cb73acbdddac052c93025aa1f82942edabb7deb82a1 no-marker.txt\n",
+        "\\d1bc8d3ba4afc7e109612cb73acbdddac052c93025aa1f82942edabb7deb82a1  a\\tb\n",
+        "d1bc8d3ba4afc7e109612cb73acbdddac052c93025aa1f82942edabb7deb82a1  ../escape.txt\n",
+        "d1bc8d3ba4afc7e109612cb73acbdddac052c93025aa1f82942edabb7deb82a1  sub/../../escape.txt\n",
+    ];
+    for bad in bad.iter() {
+        match Database::from_sha256sum(bad.as_bytes()) {
+            Err(Error::ParseError) => (),
+            result => panic!("expected ParseError, got {:?}", result),
+        }
+    }
+}
+
+#[test]
+fn write_manifest() {
+    let dir = tempdir().unwrap();
+    fs::create_dir(dir.path().join("sub")).unwrap();
+    fs::write(dir.path().join("sub").join("b.txt"), b"asdf\n").unwrap();
+    fs::write(dir.path().join("a\\b\nc.txt"), b"qwer\n").unwrap();
+    let features = Features { sha256: true, ..Features::default() };
+    let db = Database::build(dir.path(), features, 1, false).unwrap();
+
+    let mut manifest = Vec::new();
+    db.write_manifest(&mut manifest, ManifestAlgorithm::Sha256).unwrap();
+    assert_eq!(String::from_utf8(manifest.clone()).unwrap(), "\
+\\5f70ae29b3019ec851ef6b664b59d3fd88dda0de5eb58212ddbd97c65c3f8198  a\\\\b\\nc.txt
+d1bc8d3ba4afc7e109612cb73acbdddac052c93025aa1f82942edabb7deb82a1  sub/b.txt
+");
+    assert_eq!(db.to_string().into_bytes(), manifest);
+    let imported = Database::from_sha256sum(&manifest[..]).unwrap();
+    assert!(imported.diff(&db).outcome().clean);
+
+    match db.write_manifest(Vec::new(), ManifestAlgorithm::Sha512) {
+        Err(Error::MissingHash { path, algorithm }) => {
+            assert_eq!((path, algorithm), (PathBuf::from("a\\b\nc.txt"), "sha2-512"));
+        }
+        result => panic!("expected MissingHash, got {:?}", result),
+    }
+    let unhashed = Database::build(dir.path(), Features { sha256: false, ..features }, 1, false).unwrap();
+    assert_eq!(unhashed.to_string(), "");
+}
@@ -0,0 +1,199 @@
+use std::path::PathBuf;
+
+use integrity_checker::database::{
+    BuildOptions, Database, DiffMode, DiffOptions, Features, FileType, HashSum, LineEndings, Metrics,
+};
+
+fn hash(byte: u8) -> Option<HashSum> {
+    Some(HashSum::from(vec![byte; 32]))
+}
+
+#[test]
+fn metrics_unchanged() {
+    let old = Metrics::new(4, hash(1), None, false, false);
+    let new = Metrics::new(4, hash(1), hash(2), false, false);
+    let diff = old.diff(&new, &DiffOptions::default());
+    assert!(!diff.changed_content && !diff.zeroed && !diff.changed_nul);
+}
+
+#[test]
+fn metrics_changed_hash() {
+    let old = Metrics::new(4, hash(1), None, false, false);
+    let new = Metrics::new(4, hash(2), None, false, false);
+    assert!(old.diff(&new, &DiffOptions::default()).changed_content);
+
+    // Fast mode ignores cryptographic hashes
+    let options = DiffOptions { mode: DiffMode::Fast, ..DiffOptions::default() };
+    assert!(!old.diff(&new, &options).changed_content);
+}
+
+#[test]
+fn metrics_suspicious() {
+    let old = Metrics::new(4, hash(1), None, false, false);
+    let diff = old.diff(&Metrics::new(0, hash(2), None, false, false), &DiffOptions::default());
+    assert!(diff.changed_content && diff.zeroed);
+
+    // Whether the file was text is unknown without its UTF-8 validity
+    let diff = old.diff(&Metrics::new(4, hash(2), None, true, false), &DiffOptions::default());
+    assert!(diff.changed_nul && !diff.text_to_binary);
+    let old = Metrics::from_bytes(b"asdf", Features::default());
+    let diff = old.diff(&Metrics::from_bytes(b"as\0f", Features::default()), &DiffOptions::default());
+    assert!(diff.changed_nul && diff.text_to_binary);
+}
+
+#[test]
+fn metrics_hash_disagreement() {
+    let old = Metrics::new(4, hash(1), hash(1), false, false);
+    let new = Metrics::new(4, hash(1), hash(2), false, false);
+    let options = DiffOptions { paranoid: true, ..DiffOptions::default() };
+    assert!(old.diff(&new, &options).hash_disagreement);
+    assert!(!old.diff(&old, &options).hash_disagreement);
+}
+
+#[test]
+fn metrics_from_bytes() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("asdf.txt"), b"asdf\n").unwrap();
+    let features = Features { blake3: true, crc32: true, ..Features::default() };
+    let db = Database::build(dir.path(), features, 1, false).unwrap();
+    let on_disk = db.lookup(&PathBuf::from("asdf.txt")).unwrap().as_metrics().unwrap();
+
+    // Every hash matches, but no file metadata is recorded
+    let in_memory = Metrics::from_bytes(b"asdf\n", features);
+    let options = DiffOptions { paranoid: true, ..DiffOptions::default() };
+    let diff = in_memory.diff(on_disk, &options);
+    assert!(!diff.changed_content && !diff.hash_disagreement);
+    assert_eq!(in_memory.crc32(), on_disk.crc32());
+    assert_eq!(in_memory.size(), 5);
+    assert_eq!(in_memory.mode_symbolic(), None);
+}
+
+#[test]
+fn metrics_entropy_jump() {
+    let line = b"All work and no play makes Jack a dull boy.\n";
+    let text: Vec<u8> = line.iter().cycle().take(1 << 16).cloned().collect();
+    // Stands in for ciphertext (xorshift64)
+    let mut state = 0x2545_f491_4f6c_dd1d_u64;
+    let encrypted: Vec<u8> = (0..text.len()).map(|_| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        (state >> 24) as u8
+    }).collect();
+    let old = Metrics::from_bytes(&text, Features::default());
+    let new = Metrics::from_bytes(&encrypted, Features::default());
+    assert!(old.entropy().unwrap() < 5000);
+    assert!(new.entropy().unwrap() > 7990);
+    assert_eq!(Metrics::from_bytes(b"", Features::default()).entropy(), None);
+
+    let diff = old.diff(&new, &DiffOptions::default());
+    assert!(diff.entropy_jump && diff.is_suspicious());
+    assert!(diff.suspicious_reasons().contains(&"entropy jumped to near maximum (encrypted?)"));
+    assert!(!new.diff(&old, &DiffOptions::default()).entropy_jump);
+
+    // A much larger file with high entropy is a replacement, not an encryption
+    let bigger = Metrics::from_bytes(&encrypted.repeat(2), Features::default());
+    assert!(!old.diff(&bigger, &DiffOptions::default()).entropy_jump);
+
+    // Databases without entropy (e.g. from older versions) never flag it
+    let mut value = serde_json::to_value(&old).unwrap();
+    value.as_object_mut().unwrap().remove("entropy");
+    let older: Metrics = serde_json::from_str(&value.to_string()).unwrap();
+    assert!(!older.diff(&new, &DiffOptions::default()).entropy_jump);
+}
+
+#[test]
+fn metrics_utf8_validity() {
+    // Binary files aren't text either way, but can still lose validity
+    let old = Metrics::from_bytes("\0caf\u{e9}\n".as_bytes(), Features::default());
+    let new = Metrics::from_bytes(b"\0caf\xe9\n", Features::default());
+    let diff = old.diff(&new, &DiffOptions::default());
+    assert!(diff.changed_utf8_validity && !diff.text_to_binary && diff.is_suspicious());
+    assert_eq!(diff.suspicious_reasons(), vec!["was valid UTF-8, but no longer is"]);
+    assert!(!new.diff(&old, &DiffOptions::default()).changed_utf8_validity);
+
+    // For text files it is already reported as becoming binary
+    let old = Metrics::from_bytes("caf\u{e9}\n".as_bytes(), Features::default());
+    let diff = old.diff(&Metrics::from_bytes(b"caf\xe9\n", Features::default()), &DiffOptions::default());
+    assert!(diff.changed_utf8_validity && diff.text_to_binary);
+    assert_eq!(diff.suspicious_reasons(), vec!["text file became binary"]);
+
+    // Databases without the field (e.g. from older versions) never flag it
+    let mut value = serde_json::to_value(&old).unwrap();
+    value.as_object_mut().unwrap().remove("utf8");
+    let older: Metrics = serde_json::from_str(&value.to_string()).unwrap();
+    let diff = older.diff(&Metrics::from_bytes(b"caf\xe9\n", Features::default()), &DiffOptions::default());
+    assert!(!diff.changed_utf8_validity && !diff.text_to_binary);
+
+    // ... nor guess whether the file was text
+    let mut value = serde_json::to_value(Metrics::from_bytes(b"cafe\n", Features::default())).unwrap();
+    value.as_object_mut().unwrap().remove("utf8");
+    let older: Metrics = serde_json::from_str(&value.to_string()).unwrap();
+    let diff = older.diff(&Metrics::from_bytes(b"caf\xe9\n", Features::default()), &DiffOptions::default());
+    assert!(!diff.text_to_binary && !diff.binary_to_text);
+    let diff = old.diff(&older, &DiffOptions::default());
+    assert!(!diff.text_to_binary && !diff.binary_to_text);
+}
+
+#[test]
+fn metrics_line_endings() {
+    let lf = Metrics::from_bytes(b"one\ntwo\nthree\n", Features::default());
+    let crlf = Metrics::from_bytes(b"one\r\ntwo\r\nthree\r\n", Features::default());
+    assert_eq!(lf.line_endings(), Some(LineEndings::Lf));
+    assert_eq!(crlf.line_endings(), Some(LineEndings::Crlf));
+    assert_eq!(Metrics::from_bytes(b"one\r\ntwo\n", Features::default()).line_endings(),
+               Some(LineEndings::Mixed));
+    assert_eq!(Metrics::from_bytes(b"one", Features::default()).line_endings(),
+               Some(LineEndings::NoNewlines));
+    assert_eq!(Metrics::from_bytes(b"\0one\r\n", Features::default()).line_endings(),
+               Some(LineEndings::NotApplicable));
+
+    let diff = lf.diff(&crlf, &DiffOptions::default());
+    assert!(diff.changed_content);
+    assert_eq!(diff.changed_line_endings, Some((LineEndings::Lf, LineEndings::Crlf)));
+    let mixed = Metrics::from_bytes(b"one\r\ntwo\nthree\r\n", Features::default());
+    let diff = crlf.diff(&mixed, &DiffOptions::default());
+    assert_eq!(diff.changed_line_endings, Some((LineEndings::Crlf, LineEndings::Mixed)));
+
+    // Other edits that happen to change the size by as much don't count
+    let edited = Metrics::from_bytes(b"one\ntwo\nthree!!!\n", Features::default());
+    assert_eq!(lf.diff(&edited, &DiffOptions::default()).changed_line_endings, None);
+    assert_eq!(lf.diff(&lf, &DiffOptions::default()).changed_line_endings, None);
+}
+
+#[test]
+fn line_endings_across_buffers() {
+    // The CR ends one read and its LF starts the next
+    let dir = tempfile::tempdir().unwrap();
+    let mut contents = vec![b'x'; 4095];
+    contents.extend_from_slice(b"\r\nyz\r\n");
+    std::fs::write(dir.path().join("asdf.txt"), &contents).unwrap();
+    let options = BuildOptions { buffer_size: 4096, ..BuildOptions::default() };
+    let db = Database::build_with_options(dir.path(), &options).unwrap();
+    let metrics = db.lookup(&PathBuf::from("asdf.txt")).unwrap().as_metrics().unwrap();
+    assert_eq!(metrics.line_endings(), Some(LineEndings::Crlf));
+}
+
+#[test]
+fn metrics_filetype() {
+    let sniff = |data: &[u8]| Metrics::from_bytes(data, Features::default()).filetype().unwrap();
+    assert_eq!(sniff(b"\x7fELF\x02\x01\x01\0"), FileType::Elf);
+    assert_eq!(sniff(b"\xff\xd8\xff\xe0\0\x10JFIF"), FileType::Jpeg);
+    assert_eq!(sniff(b"%PDF-1.7\n"), FileType::Pdf);
+    assert_eq!(sniff(b"hello\n"), FileType::Text);
+    assert_eq!(sniff(b"\0\x01\x02"), FileType::Unknown);
+
+    let jpeg = Metrics::from_bytes(b"\xff\xd8\xff\xe0\0\x10JFIF", Features::default());
+    let elf = Metrics::from_bytes(b"\x7fELF\x02\x01\x01\0", Features::default());
+    let diff = jpeg.diff(&elf, &DiffOptions::default());
+    assert_eq!(diff.changed_filetype, Some((FileType::Jpeg, FileType::Elf)));
+    assert!(diff.is_suspicious());
+
+    // Unknown contents changing, or text becoming binary, don't count
+    let unknown = Metrics::from_bytes(b"\0\x01\x02", Features::default());
+    let other = Metrics::from_bytes(b"\0\x03\x04", Features::default());
+    let diff = unknown.diff(&other, &DiffOptions::default());
+    assert_eq!(diff.changed_filetype, None);
+    let text = Metrics::from_bytes(b"hello\n", Features::default());
+    assert_eq!(text.diff(&unknown, &DiffOptions::default()).changed_filetype, None);
+}
@@ -0,0 +1,31 @@
+use std::path::PathBuf;
+
+use integrity_checker::database::{Database, Features};
+
+fn missing(root_dir: &str) -> Vec<PathBuf> {
+    let threads = 1;
+    let before = PathBuf::from(root_dir).join("before");
+    let after = PathBuf::from(root_dir).join("after");
+    let db = Database::build(&before, Features::default(), threads, false).unwrap();
+    db.missing_files(&after).unwrap()
+}
+
+#[test]
+fn no_missing() {
+    assert!(missing("tests/nochanges").is_empty());
+    assert!(missing("tests/changes_new").is_empty());
+}
+
+#[test]
+fn missing_file() {
+    assert_eq!(missing("tests/changes_delete"), vec![PathBuf::from("asdf.txt")]);
+}
+
+#[test]
+fn missing_dir() {
+    assert_eq!(missing("tests/changes_delete_dir"), vec![
+        PathBuf::from("a/b/c/1.txt"),
+        PathBuf::from("a/b/c/2.txt"),
+        PathBuf::from("a/b/c/3.txt"),
+    ]);
+}
@@ -0,0 +1,77 @@
+use std::fs;
+use std::path::Path;
+
+use integrity_checker::database::{Database, EntryDiff, Features};
+use integrity_checker::error::Error;
+
+use tempfile::tempdir;
+
+#[test]
+fn mtree_roundtrip() {
+    let dir = tempdir().unwrap();
+    fs::create_dir_all(dir.path().join("sub").join("empty")).unwrap();
+    fs::write(dir.path().join("sub").join("a b#c=d\\e.txt"), b"asdf\n").unwrap();
+    fs::write(dir.path().join("plain.txt"), b"qwer\n").unwrap();
+    #[cfg(unix)]
+    std::os::unix::fs::symlink("sub dir/target", dir.path().join("link")).unwrap();
+    let features = Features { sha256: true, ..Features::default() };
+    let db = Database::build(dir.path(), features, 1, false).unwrap();
+
+    let mut spec = Vec::new();
+    db.export_mtree(&mut spec).unwrap();
+    let spec = String::from_utf8(spec).unwrap();
+    assert!(spec.starts_with("#mtree\n/set type=file\n. type=dir\n"), "{}", spec);
+    assert!(spec.contains("\n./sub/a\\040b\\043c\\075d\\134e.txt size=5 sha256digest=\
+                           d1bc8d3ba4afc7e109612cb73acbdddac052c93025aa1f82942edabb7deb82a1"),
+            "{}", spec);
+    assert!(spec.contains("\n./sub/empty type=dir\n"), "{}", spec);
+
+    let imported = Database::from_mtree(spec.as_bytes()).unwrap();
+    assert!(imported.contains_dir("sub/empty"));
+    #[cfg(unix)]
+    assert_eq!(imported.lookup(Path::new("link")), db.lookup(Path::new("link")));
+    match imported.diff(&db) {
+        EntryDiff::Directory(_, stats) => {
+            let unchanged = 2 + cfg!(unix) as u64; // The symlink is unchanged too
+            assert_eq!((stats.changed, stats.added, stats.removed, stats.unchanged), (0, 0, 0, unchanged));
+        }
+        diff => panic!("expected a directory diff, got {:?}", diff),
+    }
+    let mut again = Vec::new();
+    imported.export_mtree(&mut again).unwrap();
+    assert_eq!(String::from_utf8(again).unwrap(), spec);
+}
+
+#[test]
+fn mtree_classic_form() {
+    let spec = "\
+#\t   user: root
+/set type=file uid=0 gid=0 mode=0644
+.               type=dir mode=0755
+    bin         type=dir mode=0755
+        sh      size=5 mode=0555 \\
+                sha256digest=d1bc8d3ba4afc7e109612cb73acbdddac052c93025aa1f82942edabb7deb82a1
+        rsh     type=link link=sh
+    ..
+/unset all
+    a\\sb       md5digest=ignored time=1700000000.5
+";
+    let db = Database::from_mtree(spec.as_bytes()).unwrap();
+    assert!(db.contains_file("bin/sh"));
+    assert!(db.contains_file("a b"));
+    let sh = match db.lookup(Path::new("bin/sh")) {
+        Some(integrity_checker::database::Entry::File(metrics)) => metrics.clone(),
+        entry => panic!("expected a file, got {:?}", entry),
+    };
+    assert_eq!(sh.size(), 5);
+    assert!(sh.to_string().contains("mode:"), "{}", sh);
+
+    for bad in &["./a type=file size=big\n", "./a novalue\n", "./a\\9 type=file\n", "../a type=file\n"] {
+        match Database::from_mtree(bad.as_bytes()) {
+            Err(Error::ParseError) => (),
+            result => panic!("expected ParseError for {:?}, got {:?}", bad, result),
+        }
+    }
+    let nested = Database::from_mtree(&b"/set type=file\n./x/y size=1\n"[..]).unwrap();
+    assert!(nested.contains_dir(Path::new("x")));
+}
@@ -0,0 +1,592 @@
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use integrity_checker::database::{
+    BuildOptions, Database, DiffMode, DiffOptions, DiffSummary, Entry, Features, VerifyStatus,
+    MAX_BUFFER_SIZE, MAX_PREVIEW_LEN,
+};
+use integrity_checker::error::Error;
+
+use tempfile::tempdir;
+
+#[test]
+fn fold_case() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("README.TXT"), b"asdf\n").unwrap();
+    fs::create_dir(dir.path().join("Docs")).unwrap();
+    fs::write(dir.path().join("Docs").join("Guide.md"), b"qwer\n").unwrap();
+
+    let options = BuildOptions { fold_case: true, ..BuildOptions::default() };
+    let db = Database::build_with_options(dir.path(), &options).unwrap();
+    assert!(db.lookup(&PathBuf::from("readme.txt")).is_some());
+    assert!(db.lookup(&PathBuf::from("docs/guide.md")).is_some());
+    assert!(db.lookup(&PathBuf::from("README.TXT")).is_none());
+}
+
+#[test]
+fn fold_case_duplicate() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("readme"), b"asdf\n").unwrap();
+    if fs::write(dir.path().join("README"), b"qwer\n").is_err() ||
+        fs::read_dir(dir.path()).unwrap().count() < 2
+    {
+        return; // Case-insensitive file system, can't test collisions
+    }
+
+    // Errors from worker threads propagate too, rather than panicking
+    for threads in [1, 4] {
+        let options = BuildOptions { fold_case: true, threads, ..BuildOptions::default() };
+        match Database::build_with_options(dir.path(), &options) {
+            Err(Error::DuplicatePath(path)) => {
+                assert_eq!(path.to_str().unwrap().to_lowercase(), "readme")
+            }
+            result => panic!("expected DuplicatePath, got {:?}", result),
+        }
+    }
+}
+
+#[test]
+fn strip_prefix_components() {
+    let dir = tempdir().unwrap();
+    let snapshot = dir.path().join("snapshot").join("daily");
+    fs::create_dir_all(&snapshot).unwrap();
+    fs::write(snapshot.join("asdf.txt"), b"asdf\n").unwrap();
+    fs::write(dir.path().join("live.txt"), b"qwer\n").unwrap();
+
+    let options = BuildOptions {
+        strip_prefix_components: Some(PathBuf::from("snapshot/daily")),
+        ..BuildOptions::default()
+    };
+    let db = Database::build_with_options(dir.path(), &options).unwrap();
+    assert!(db.lookup(&PathBuf::from("asdf.txt")).is_some());
+    // Only the snapshot is stored, rather than a mix of both trees
+    assert!(db.lookup(&PathBuf::from("live.txt")).is_none());
+    assert!(db.lookup(&PathBuf::from("snapshot")).is_none());
+
+    // The keys match those of a database of the snapshot itself, so
+    // checking one against the other finds no changes
+    let live = Database::build_with_options(&snapshot, &BuildOptions::default()).unwrap();
+    assert_eq!(live.show_diff_to(&db, Vec::new()).unwrap(), DiffSummary::NoChanges);
+    let outcome = live.check_outcome(dir.path(), &options, &DiffOptions::default()).unwrap();
+    assert!(outcome.clean);
+}
+
+#[test]
+fn progress() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("asdf.txt"), b"asdf\n").unwrap();
+    fs::write(dir.path().join("qwer.txt"), b"qwer\n").unwrap();
+
+    for threads in [1, 2] {
+        let options = BuildOptions { progress: true, threads, ..BuildOptions::default() };
+        let db = Database::build_with_options(dir.path(), &options).unwrap();
+        assert!(db.lookup(&PathBuf::from("asdf.txt")).is_some());
+        assert!(db.lookup(&PathBuf::from("qwer.txt")).is_some());
+    }
+}
+
+#[test]
+fn bind_paths() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("asdf.txt"), b"asdf\n").unwrap();
+    fs::write(dir.path().join("copy.txt"), b"asdf\n").unwrap();
+
+    let unbound = Database::build_with_options(dir.path(), &BuildOptions::default()).unwrap();
+    let options = BuildOptions { bind_paths: true, ..BuildOptions::default() };
+    let bound = Database::build_with_options(dir.path(), &options).unwrap();
+
+    let same_contents = |db: &Database| {
+        match (db.lookup(&PathBuf::from("asdf.txt")), db.lookup(&PathBuf::from("copy.txt"))) {
+            (Some(Entry::File(asdf)), Some(Entry::File(copy))) => {
+                !asdf.diff(copy, &DiffOptions::default()).changed_content
+            }
+            entries => panic!("expected two files, got {:?}", entries),
+        }
+    };
+    assert!(same_contents(&unbound));
+    assert!(!same_contents(&bound));
+
+    // Path-bound hashes are only comparable with each other
+    let rebuilt = Database::build_with_options(dir.path(), &options).unwrap();
+    assert_eq!(bound.show_diff_to(&rebuilt, Vec::new()).unwrap(), DiffSummary::NoChanges);
+    assert_eq!(unbound.show_diff_to(&bound, Vec::new()).unwrap(), DiffSummary::Changes);
+    match unbound.check_quiet(dir.path(), &options, &DiffOptions::default()) {
+        Err(Error::PathBindingMismatch { recorded: false, .. }) => (),
+        result => panic!("expected PathBindingMismatch, got {:?}", result),
+    }
+    for result in bound.verify_iter(dir.path()) {
+        assert_eq!(result.unwrap().1, VerifyStatus::Ok);
+    }
+}
+
+#[test]
+fn memory_budget() {
+    let dir = tempdir().unwrap();
+    for i in 0..32 {
+        fs::write(dir.path().join(format!("file{}", i)), vec![i as u8; 1000 * i]).unwrap();
+    }
+    let expected = Database::build_with_options(dir.path(), &BuildOptions::default()).unwrap();
+
+    // A budget smaller than one buffer still lets files through one at a time
+    for budget in [100, 4096, 10000] {
+        let options = BuildOptions {
+            threads: 4,
+            memory_budget: Some(budget),
+            ..BuildOptions::default()
+        };
+        let db = Database::build_with_options(dir.path(), &options).unwrap();
+        assert_eq!(expected.show_diff_to(&db, Vec::new()).unwrap(), DiffSummary::NoChanges);
+    }
+}
+
+#[test]
+fn max_read_bytes_per_sec() {
+    let dir = tempdir().unwrap();
+    for i in 0..3 {
+        fs::write(dir.path().join(format!("file{}", i)), vec![b'a'; 100_000]).unwrap();
+    }
+    let expected = Database::build_with_options(dir.path(), &BuildOptions::default()).unwrap();
+
+    // The first second's worth of bytes is available immediately, so
+    // reading 300 KB at 200 KB/s takes at least half a second
+    let options = BuildOptions {
+        max_read_bytes_per_sec: Some(200_000),
+        ..BuildOptions::default()
+    };
+    let start = Instant::now();
+    let db = Database::build_with_options(dir.path(), &options).unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(400));
+    assert_eq!(expected.show_diff_to(&db, Vec::new()).unwrap(), DiffSummary::NoChanges);
+}
+
+#[cfg(windows)]
+#[test]
+fn long_paths() {
+    let dir = tempdir().unwrap();
+    let mut relative = PathBuf::new();
+    for _ in 0..30 {
+        relative.push("directory");
+    }
+    relative.push("asdf.txt");
+    // Creating the tree needs the extended-length prefix too
+    let extended = PathBuf::from(format!(r"\\?\{}", dir.path().join(&relative).display()));
+    fs::create_dir_all(extended.parent().unwrap()).unwrap();
+    fs::write(&extended, b"asdf\n").unwrap();
+
+    let db = Database::build_with_options(dir.path(), &BuildOptions::default()).unwrap();
+    assert!(db.contains_file(&relative));
+}
+
+#[test]
+fn modified_before() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("old.txt"), b"asdf\n").unwrap();
+    fs::write(dir.path().join("new.txt"), b"qwer\n").unwrap();
+    let old = UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+    fs::File::options().write(true).open(dir.path().join("old.txt")).unwrap()
+        .set_modified(old).unwrap();
+
+    for threads in [1, 2] {
+        let options = BuildOptions {
+            threads,
+            modified_before: Some(1_500_000_000),
+            ..BuildOptions::default()
+        };
+        let db = Database::build_with_options(dir.path(), &options).unwrap();
+        assert!(db.lookup(&PathBuf::from("old.txt")).is_some());
+        assert!(db.lookup(&PathBuf::from("new.txt")).is_none());
+    }
+}
+
+#[test]
+fn truncate_hashes() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("asdf.txt"), b"asdf\n").unwrap();
+    fs::write(dir.path().join("qwer.txt"), b"qwer\n").unwrap();
+
+    let features = Features {
+        sha2: true, blake2b: true, sha256: true, sha512: false, blake3: false, xxh64: true,
+        crc32: false,
+    };
+    let options = BuildOptions { features, truncate_hashes: Some(4), ..BuildOptions::default() };
+    let truncated = Database::build_with_options(dir.path(), &options).unwrap();
+    let full = Database::build_with_options(
+        dir.path(), &BuildOptions { features, ..BuildOptions::default() }).unwrap();
+
+    // Truncated hashes survive a round trip and compare with each other
+    let bytes = truncated.dump_json(Vec::new(), features).unwrap();
+    let loaded = Database::load_json(&bytes[..]).unwrap();
+    let rebuilt = Database::build_with_options(dir.path(), &options).unwrap();
+    assert_eq!(loaded.show_diff_to(&rebuilt, Vec::new()).unwrap(), DiffSummary::NoChanges);
+    for result in loaded.verify_iter(dir.path()) {
+        assert_eq!(result.unwrap().1, VerifyStatus::Ok);
+    }
+
+    // ... but never with full-length ones
+    assert_eq!(truncated.show_diff_to(&full, Vec::new()).unwrap(), DiffSummary::Changes);
+    match truncated.ensure_comparable(&full) {
+        Err(Error::TruncationMismatch { recorded: Some(4), current: None, .. }) => (),
+        result => panic!("expected TruncationMismatch, got {:?}", result),
+    }
+    let full_options = BuildOptions { features, ..BuildOptions::default() };
+    match truncated.check_quiet(dir.path(), &full_options, &DiffOptions::default()) {
+        Err(Error::TruncationMismatch { recorded: Some(4), current: None, .. }) => (),
+        result => panic!("expected TruncationMismatch, got {:?}", result),
+    }
+
+    let options = BuildOptions { truncate_hashes: Some(0), ..BuildOptions::default() };
+    match Database::build_with_options(dir.path(), &options) {
+        Err(Error::InvalidHashTruncation(0)) => (),
+        result => panic!("expected InvalidHashTruncation, got {:?}", result),
+    }
+}
+
+#[test]
+fn build_incremental() {
+    use std::fs::OpenOptions;
+    use std::io::{Seek, SeekFrom, Write};
+
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("big.bin");
+    let data: Vec<u8> = (0..10000u32).map(|i| (i % 251) as u8).collect();
+    fs::write(&path, &data).unwrap();
+    let append = |bytes: &[u8]| {
+        OpenOptions::new().append(true).open(&path).unwrap().write_all(bytes).unwrap()
+    };
+
+    let tree_only = BuildOptions {
+        features: Features {
+            sha2: false, blake2b: false, sha256: false, sha512: false, blake3: false, xxh64: false,
+            crc32: false,
+        },
+        tree_hash_threshold: Some(4096),
+        tree_hash_chunk_size: 1000,
+        ..BuildOptions::default()
+    };
+    let full = |options| Database::build_with_options(dir.path(), options).unwrap();
+    let incremental = |previous, options| {
+        Database::build_incremental(dir.path(), previous, options).unwrap().0
+    };
+    let big = PathBuf::from("big.bin");
+
+    // After an append, the result is the same as hashing everything
+    let before = full(&tree_only);
+    append(&[0x80; 2500]);
+    let after = incremental(&before, &tree_only);
+    assert_eq!(after.lookup(&big), full(&tree_only).lookup(&big));
+
+    // Chunks taken from the previous database are not read again, so
+    // an edit inside them goes unseen
+    let mut file = OpenOptions::new().write(true).open(&path).unwrap();
+    file.seek(SeekFrom::Start(0)).unwrap();
+    file.write_all(b"x").unwrap();
+    drop(file);
+    append(b"asdf");
+    let reused = incremental(&after, &tree_only);
+    assert_ne!(reused.lookup(&big), full(&tree_only).lookup(&big));
+
+    // Shrunk files and files with other hashes are hashed in full
+    fs::write(&path, &data[..5000]).unwrap();
+    assert_eq!(incremental(&reused, &tree_only).lookup(&big), full(&tree_only).lookup(&big));
+    let options = BuildOptions { tree_hash_threshold: Some(4096), ..BuildOptions::default() };
+    let before = full(&options);
+    fs::write(&path, &data).unwrap();
+    assert_eq!(incremental(&before, &options).lookup(&big), full(&options).lookup(&big));
+}
+
+#[test]
+fn first_block_hash() {
+    let dir = tempdir().unwrap();
+    let head = vec![b'a'; 4096];
+    fs::write(dir.path().join("log.txt"), &head).unwrap();
+    fs::write(dir.path().join("data.bin"), vec![b'b'; 5000]).unwrap();
+
+    let features = Features {
+        sha2: false, blake2b: false, sha256: false, sha512: false, blake3: false, xxh64: false,
+        crc32: false,
+    };
+    let options = BuildOptions { features, first_block_hash: true, ..BuildOptions::default() };
+    let before_db = Database::build_with_options(dir.path(), &options).unwrap();
+    let bytes = before_db.dump_json(Vec::new(), features).unwrap();
+    let before_db = Database::load_json(&bytes[..]).unwrap();
+
+    // Appending leaves the first block alone, overwriting the start doesn't
+    fs::write(dir.path().join("log.txt"), [&head[..], b"more\n"].concat()).unwrap();
+    let mut data = vec![b'b'; 5000];
+    data[0] = b'c';
+    fs::write(dir.path().join("data.bin"), data).unwrap();
+    let after_db = Database::build_with_options(dir.path(), &options).unwrap();
+
+    // Even without any full hash, the first block catches same-size edits
+    let fast = DiffOptions { mode: DiffMode::Fast, ..DiffOptions::default() };
+    let mut output = Vec::new();
+    let result = before_db.show_diff_to_with_options(&after_db, &fast, &mut output).unwrap();
+    assert_eq!(result, DiffSummary::Changes);
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("2 changed"), "{}", output);
+    assert!(output.contains("log.txt changed\n"), "{}", output);
+    assert!(output.contains("> first block unchanged, but later content differs\n"), "{}", output);
+    assert!(!output.contains("data.bin changed"), "{}", output);
+
+    for result in before_db.verify_iter(dir.path()) {
+        assert_eq!(result.unwrap().1, VerifyStatus::Changed);
+    }
+}
+
+#[test]
+fn parallel_walk() {
+    let dir = tempdir().unwrap();
+    for i in 0..8 {
+        let subdir = dir.path().join(format!("dir{}", i));
+        fs::create_dir(&subdir).unwrap();
+        for j in 0..8 {
+            fs::write(subdir.join(format!("file{}", j)), vec![b'a'; i * j]).unwrap();
+        }
+    }
+
+    let features = Features::default();
+    let serial = Database::build_with_options(dir.path(), &BuildOptions::default()).unwrap();
+    let serial = serial.dump_json(Vec::new(), features).unwrap();
+    for threads in [2, 8] {
+        let options = BuildOptions { threads, ..BuildOptions::default() };
+        let parallel = Database::build_with_options(dir.path(), &options).unwrap();
+        assert_eq!(parallel.dump_json(Vec::new(), features).unwrap(), serial);
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn parallel_walk_error() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("asdf.txt"), b"asdf\n").unwrap();
+    std::os::unix::fs::symlink(dir.path().join("missing"), dir.path().join("broken")).unwrap();
+
+    for threads in [1, 4] {
+        let options = BuildOptions { threads, follow_links: true, ..BuildOptions::default() };
+        match Database::build_with_options(dir.path(), &options) {
+            Err(Error::Ignore(_)) => (),
+            result => panic!("expected a walk error, got {:?}", result),
+        }
+    }
+}
+
+#[test]
+fn tree_hash() {
+    let dir = tempdir().unwrap();
+    let mut data: Vec<u8> = (0..10000u32).map(|i| (i % 251) as u8).collect();
+    fs::write(dir.path().join("big.bin"), &data).unwrap();
+    fs::write(dir.path().join("small.txt"), b"asdf\n").unwrap();
+
+    // Without other hashes, only the tree hash vouches for large files
+    let features = Features {
+        sha2: false, blake2b: false, sha256: false, sha512: false, blake3: false, xxh64: false,
+        crc32: false,
+    };
+    let build = |threads, tree_hash_chunk_size| {
+        let options = BuildOptions {
+            features,
+            threads,
+            tree_hash_threshold: Some(4096),
+            tree_hash_chunk_size,
+            ..BuildOptions::default()
+        };
+        Database::build_with_options(dir.path(), &options).unwrap()
+    };
+    let before_db = build(1, 1000);
+    let bytes = before_db.dump_json(Vec::new(), features).unwrap();
+    let json = serde_json::to_string(&before_db).unwrap();
+    assert_eq!(json.matches("\"tree_hash\"").count(), 1, "{}", json);
+    assert!(json.contains("\"tree_chunk_size\":1000"), "{}", json);
+    assert_eq!(build(4, 1000).dump_json(Vec::new(), features).unwrap(), bytes);
+    let before_db = Database::load_json(&bytes[..]).unwrap();
+
+    // A same-size edit in a later chunk changes the tree hash
+    data[9500] ^= 1;
+    fs::write(dir.path().join("big.bin"), &data).unwrap();
+    let mut output = Vec::new();
+    let result = before_db.show_diff_to_with_options(&build(4, 1000), &DiffOptions::default(), &mut output);
+    assert_eq!(result.unwrap(), DiffSummary::Changes);
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("1 changed"), "{}", output);
+
+    // Tree hashes of different chunk sizes aren't compared, which
+    // leaves the file unverified
+    let mut output = Vec::new();
+    let result = before_db.show_diff_to_with_options(&build(4, 2000), &DiffOptions::default(), &mut output);
+    assert_eq!(result.unwrap(), DiffSummary::Changes);
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("warning: 1 file(s) have tree hashes of different chunk sizes"), "{}", output);
+    assert!(output.contains("warning: 1 file(s) not verified"), "{}", output);
+
+    let options = BuildOptions {
+        tree_hash_threshold: Some(4096),
+        tree_hash_chunk_size: 0,
+        ..BuildOptions::default()
+    };
+    match Database::build_with_options(dir.path(), &options) {
+        Err(Error::InvalidChunkSize(0)) => (),
+        result => panic!("expected an invalid chunk size, got {:?}", result),
+    }
+}
+
+#[test]
+fn buffer_size() {
+    let dir = tempdir().unwrap();
+    for i in 0..8 {
+        let data: Vec<u8> = (0..1000 * i * i).map(|j| (j % 253) as u8).collect();
+        fs::write(dir.path().join(format!("file{}", i)), data).unwrap();
+    }
+    fs::write(dir.path().join("text.txt"), "λ".repeat(5000)).unwrap();
+
+    // Metrics don't depend on how the contents were chunked
+    let features = Features::default();
+    let expected = Database::build_with_options(dir.path(), &BuildOptions::default()).unwrap();
+    let expected = expected.dump_json(Vec::new(), features).unwrap();
+    for buffer_size in [1, 7, 4096, 1 << 20] {
+        let options = BuildOptions { buffer_size, ..BuildOptions::default() };
+        let db = Database::build_with_options(dir.path(), &options).unwrap();
+        assert_eq!(db.dump_json(Vec::new(), features).unwrap(), expected, "{}", buffer_size);
+    }
+
+    for buffer_size in [0, MAX_BUFFER_SIZE + 1] {
+        let options = BuildOptions { buffer_size, ..BuildOptions::default() };
+        match Database::build_with_options(dir.path(), &options) {
+            Err(Error::InvalidBufferSize(size)) => assert_eq!(size, buffer_size),
+            result => panic!("expected an invalid buffer size, got {:?}", result),
+        }
+    }
+}
+
+#[test]
+fn mmap() {
+    let dir = tempdir().unwrap();
+    for i in 0..8 {
+        let data: Vec<u8> = (0..1000 * i * i).map(|j| (j % 251) as u8).collect();
+        fs::write(dir.path().join(format!("file{}", i)), data).unwrap();
+    }
+    fs::write(dir.path().join("text.txt"), "λ".repeat(50000)).unwrap();
+
+    let features = Features::default();
+    for (bind_paths, first_block_hash) in [(false, false), (true, true)] {
+        let options = BuildOptions { bind_paths, first_block_hash, ..BuildOptions::default() };
+        let expected = Database::build_with_options(dir.path(), &options).unwrap();
+        let expected = expected.dump_json(Vec::new(), features).unwrap();
+        for threads in [1, 4] {
+            let options = BuildOptions { mmap: true, threads, ..options.clone() };
+            let db = Database::build_with_options(dir.path(), &options).unwrap();
+            assert_eq!(db.dump_json(Vec::new(), features).unwrap(), expected);
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn mmap_truncated() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("big.bin");
+    fs::write(&path, vec![b'a'; 4 << 20]).unwrap();
+
+    // Slow enough that the file is truncated part way through
+    let options = BuildOptions {
+        mmap: true,
+        max_read_bytes_per_sec: Some(1 << 20),
+        ..BuildOptions::default()
+    };
+    let truncate = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(300));
+        fs::OpenOptions::new().write(true).open(path).unwrap().set_len(0).unwrap();
+    });
+    match Database::build_with_options(dir.path(), &options) {
+        Err(Error::Io(err)) => assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof),
+        result => panic!("expected a truncation error, got {:?}", result),
+    }
+    truncate.join().unwrap();
+}
+
+#[test]
+fn build_stats() {
+    let dir = tempdir().unwrap();
+    fs::create_dir(dir.path().join("sub")).unwrap();
+    fs::write(dir.path().join("asdf.txt"), b"asdf\n").unwrap();
+    fs::write(dir.path().join("sub").join("qwer.txt"), b"qwerqwer\n").unwrap();
+
+    for threads in [1, 4] {
+        let options = BuildOptions { threads, ..BuildOptions::default() };
+        let (db, stats) = Database::build_with_stats(dir.path(), &options).unwrap();
+        assert_eq!((stats.files, stats.directories, stats.bytes, stats.skipped), (2, 2, 14, 0));
+        assert!(stats.bytes_per_sec() > 0.0);
+        // Each file takes up at least one block
+        assert!(cfg!(not(unix)) || stats.allocated >= 2 * 512);
+        let expected = Database::build_with_options(dir.path(), &options).unwrap();
+        assert_eq!(expected.show_diff_to(&db, Vec::new()).unwrap(), DiffSummary::NoChanges);
+
+        let options = BuildOptions { modified_before: Some(0), ..options };
+        let (_, stats) = Database::build_with_stats(dir.path(), &options).unwrap();
+        assert_eq!((stats.files, stats.skipped), (0, 2));
+    }
+
+    let db = Database::build_with_options(dir.path(), &BuildOptions::default()).unwrap();
+    let (summary, stats) = db.check_with_stats(
+        dir.path(), &BuildOptions::default(), &DiffOptions::default()).unwrap();
+    assert_eq!(summary, DiffSummary::NoChanges);
+    assert_eq!(stats.bytes, 14);
+}
+
+#[test]
+fn build_with_progress() {
+    let dir = tempdir().unwrap();
+    for i in 0..2500 {
+        fs::write(dir.path().join(format!("file{}", i)), b"asdf\n").unwrap();
+    }
+
+    for threads in [1, 4] {
+        let options = BuildOptions { threads, ..BuildOptions::default() };
+        let calls = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = calls.clone();
+        let (_, stats) = Database::build_with_progress(dir.path(), &options, move |progress| {
+            assert!(progress.path.to_str().unwrap().starts_with("file"));
+            sink.lock().unwrap().push((progress.files, progress.bytes));
+        }).unwrap();
+        assert_eq!(stats.files, 2500);
+        assert_eq!(*calls.lock().unwrap(), vec![(1000, 5000), (2000, 10000)]);
+    }
+
+    // The panic reaches the caller instead of a partial database
+    for threads in [1, 4] {
+        let options = BuildOptions { threads, ..BuildOptions::default() };
+        let result = std::panic::catch_unwind(|| {
+            Database::build_with_progress(dir.path(), &options, |_| panic!("callback failed"))
+        });
+        assert!(result.is_err());
+    }
+}
+
+#[test]
+fn preview() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("log.txt"), b"\tfirst line\nsecond line\n").unwrap();
+
+    let options = BuildOptions { preview_len: 12, ..BuildOptions::default() };
+    let before = Database::build_with_options(dir.path(), &options).unwrap();
+    let without = Database::build_with_options(dir.path(), &BuildOptions::default()).unwrap();
+    match before.lookup(&PathBuf::from("log.txt")) {
+        Some(Entry::File(metrics)) => assert_eq!(metrics.preview(), Some(&b"\tfirst line\n"[..])),
+        entry => panic!("expected a file, got {:?}", entry),
+    }
+    // Previews are never compared
+    assert_eq!(before.show_diff_to(&without, Vec::new()).unwrap(), DiffSummary::NoChanges);
+
+    fs::write(dir.path().join("log.txt"), b"").unwrap();
+    let after = Database::build_with_options(dir.path(), &options).unwrap();
+    let mut output = Vec::new();
+    assert_eq!(before.show_diff_to(&after, &mut output).unwrap(), DiffSummary::Suspicious);
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("##> original began with: \\tfirst line\\n\n"), "{}", output);
+
+    let options = BuildOptions { preview_len: MAX_PREVIEW_LEN + 1, ..BuildOptions::default() };
+    match Database::build_with_options(dir.path(), &options) {
+        Err(Error::InvalidPreviewLength(len)) => assert_eq!(len, MAX_PREVIEW_LEN + 1),
+        result => panic!("expected an invalid preview length, got {:?}", result),
+    }
+}
@@ -0,0 +1,155 @@
+use std::fs;
+use std::path::PathBuf;
+
+use integrity_checker::database::{BuildOptions, Database, DiffOptions, DiffSummary, EntryDiff, Rename};
+
+use tempfile::tempdir;
+
+#[test]
+fn rename_by_content() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("old.txt"), b"asdf\n").unwrap();
+    fs::write(dir.path().join("other.txt"), b"qwer\n").unwrap();
+    let before = Database::build_with_options(dir.path(), &BuildOptions::default()).unwrap();
+
+    fs::rename(dir.path().join("old.txt"), dir.path().join("new.txt")).unwrap();
+    fs::remove_file(dir.path().join("other.txt")).unwrap();
+    let after = Database::build_with_options(dir.path(), &BuildOptions::default()).unwrap();
+
+    assert_eq!(before.renames(&after, &DiffOptions::default()), vec![Rename {
+        from: PathBuf::from("old.txt"),
+        to: PathBuf::from("new.txt"),
+        same_inode: false,
+    }]);
+}
+
+#[cfg(unix)]
+#[test]
+fn rename_by_inode() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("old.txt"), b"asdf\n").unwrap();
+    let options = BuildOptions { record_inode: true, ..BuildOptions::default() };
+    let before = Database::build_with_options(dir.path(), &options).unwrap();
+
+    // Renamed and modified, so only the inode ties the two together
+    fs::rename(dir.path().join("old.txt"), dir.path().join("new.txt")).unwrap();
+    fs::OpenOptions::new().append(true).open(dir.path().join("new.txt")).unwrap()
+        .set_len(2).unwrap();
+    let after = Database::build_with_options(dir.path(), &options).unwrap();
+
+    assert_eq!(before.renames(&after, &DiffOptions::default()), vec![Rename {
+        from: PathBuf::from("old.txt"),
+        to: PathBuf::from("new.txt"),
+        same_inode: true,
+    }]);
+
+    let unrecorded = Database::build_with_options(dir.path(), &BuildOptions::default()).unwrap();
+    assert!(before.renames(&unrecorded, &DiffOptions::default()).is_empty());
+}
+
+#[test]
+fn show_renames() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("old.txt"), b"asdf\n").unwrap();
+    let before = Database::build_with_options(dir.path(), &BuildOptions::default()).unwrap();
+    fs::rename(dir.path().join("old.txt"), dir.path().join("new.txt")).unwrap();
+    let after = Database::build_with_options(dir.path(), &BuildOptions::default()).unwrap();
+
+    let options = DiffOptions { detect_renames: true, ..DiffOptions::default() };
+    let mut output = Vec::new();
+    before.show_diff_to_with_options(&after, &options, &mut output).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("old.txt renamed to new.txt\n"), "{}", output);
+
+    let options = DiffOptions { path_prefix: Some(PathBuf::from("/srv")), ..options };
+    let mut output = Vec::new();
+    before.show_diff_to_with_options(&after, &options, &mut output).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("/srv/old.txt renamed to /srv/new.txt\n"), "{}", output);
+}
+
+#[test]
+fn swapped_contents() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("a.txt"), b"asdf\n").unwrap();
+    fs::write(dir.path().join("b.txt"), b"qwer\n").unwrap();
+    fs::write(dir.path().join("c.txt"), b"zxcv\n").unwrap();
+    let before = Database::build_with_options(dir.path(), &BuildOptions::default()).unwrap();
+
+    fs::write(dir.path().join("a.txt"), b"qwer\n").unwrap();
+    fs::write(dir.path().join("b.txt"), b"asdf\n").unwrap();
+    fs::write(dir.path().join("c.txt"), b"ZXCV\n").unwrap();
+    let after = Database::build_with_options(dir.path(), &BuildOptions::default()).unwrap();
+
+    assert_eq!(before.swaps(&after, &DiffOptions::default()),
+               vec![(PathBuf::from("a.txt"), PathBuf::from("b.txt"))]);
+
+    let options = DiffOptions { detect_renames: true, ..DiffOptions::default() };
+    let mut output = Vec::new();
+    let result = before.show_diff_to_with_options(&after, &options, &mut output).unwrap();
+    assert_eq!(result, DiffSummary::Suspicious);
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("> suspicious: content swapped between a.txt and b.txt\n"), "{}", output);
+}
+
+#[test]
+fn renames_leave_counts() {
+    let dir = tempdir().unwrap();
+    fs::create_dir(dir.path().join("src")).unwrap();
+    fs::write(dir.path().join("src/a.txt"), b"asdf\n").unwrap();
+    fs::write(dir.path().join("src/b.txt"), b"asdf\n").unwrap();
+    fs::write(dir.path().join("kept.txt"), b"qwer\n").unwrap();
+    fs::write(dir.path().join("gone.txt"), b"zxcv\n").unwrap();
+    let before = Database::build_with_options(dir.path(), &BuildOptions::default()).unwrap();
+
+    fs::rename(dir.path().join("src"), dir.path().join("lib")).unwrap();
+    fs::remove_file(dir.path().join("gone.txt")).unwrap();
+    // A copy of content that is still in place is not a rename
+    fs::write(dir.path().join("copy.txt"), b"qwer\n").unwrap();
+    let after = Database::build_with_options(dir.path(), &BuildOptions::default()).unwrap();
+
+    let options = DiffOptions { detect_renames: true, ..DiffOptions::default() };
+    let (diff, renames) = before.diff_with_renames(&after, &options);
+    assert_eq!(renames, vec![
+        Rename { from: PathBuf::from("src/a.txt"), to: PathBuf::from("lib/a.txt"), same_inode: false },
+        Rename { from: PathBuf::from("src/b.txt"), to: PathBuf::from("lib/b.txt"), same_inode: false },
+    ]);
+    match diff {
+        EntryDiff::Directory(_, stats) => {
+            // `lib` and `src` stay one-sided directories, now without files
+            assert_eq!((stats.added, stats.removed), (2, 2));
+        }
+        _ => panic!("expected a directory diff"),
+    }
+
+    let mut output = Vec::new();
+    before.show_diff_to_with_options(&after, &options, &mut output).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("| + copy.txt\n"), "{}", output);
+    assert!(output.contains("| - gone.txt\n"), "{}", output);
+    assert!(output.contains("| + lib/ (0 files)\n"), "{}", output);
+    assert!(output.contains("src/a.txt renamed to lib/a.txt\n"), "{}", output);
+}
+
+#[test]
+fn renamed_file_not_counted() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("old.txt"), b"asdf\n").unwrap();
+    let before = Database::build_with_options(dir.path(), &BuildOptions::default()).unwrap();
+    fs::rename(dir.path().join("old.txt"), dir.path().join("new.txt")).unwrap();
+    let after = Database::build_with_options(dir.path(), &BuildOptions::default()).unwrap();
+
+    let options = DiffOptions { detect_renames: true, ..DiffOptions::default() };
+    let (diff, _) = before.diff_with_renames(&after, &options);
+    match diff {
+        EntryDiff::Directory(entries, stats) => {
+            assert!(entries.is_empty());
+            assert_eq!((stats.added, stats.removed), (0, 0));
+        }
+        _ => panic!("expected a directory diff"),
+    }
+    let mut output = Vec::new();
+    let summary = before.show_diff_to_with_options(&after, &options, &mut output).unwrap();
+    assert_eq!(summary, DiffSummary::Changes);
+    assert_eq!(String::from_utf8(output).unwrap(), "old.txt renamed to new.txt\n");
+}
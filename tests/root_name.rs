@@ -0,0 +1,55 @@
+use std::fs;
+use std::path::PathBuf;
+
+use integrity_checker::database::{Database, DiffSummary, Features};
+
+use tempfile::tempdir;
+
+#[test]
+fn root_as_dir() {
+    let threads = 1;
+    let features = Features::default();
+    let db = Database::build("tests/nochanges/before", features, threads, false).unwrap();
+    assert_eq!(db.root_name(), Some("before"));
+    assert!(db.lookup(&PathBuf::from("asdf.txt")).is_some());
+
+    // The name survives a round trip through the header
+    let bytes = db.dump_json(Vec::new(), features).unwrap();
+    let loaded = Database::load_json(&bytes[..]).unwrap();
+    assert_eq!(loaded.root_name(), Some("before"));
+}
+
+#[test]
+fn root_as_file() {
+    let threads = 1;
+    let features = Features::default();
+    let db = Database::build("tests/nochanges/before/asdf.txt", features, threads, false).unwrap();
+    assert_eq!(db.root_name(), Some("asdf.txt"));
+    assert!(db.lookup(&PathBuf::from("asdf.txt")).is_some());
+
+    let bytes = db.dump_json(Vec::new(), features).unwrap();
+    let loaded = Database::load_json(&bytes[..]).unwrap();
+    assert_eq!(loaded.root_name(), Some("asdf.txt"));
+}
+
+#[test]
+fn root_name_mismatch_warning() {
+    let dir = tempdir().unwrap();
+    for name in ["first", "second"] {
+        fs::create_dir(dir.path().join(name)).unwrap();
+        fs::write(dir.path().join(name).join("asdf.txt"), b"asdf\n").unwrap();
+    }
+    let threads = 1;
+    let features = Features::default();
+    let first = Database::build(dir.path().join("first"), features, threads, false).unwrap();
+    let second = Database::build(dir.path().join("second"), features, threads, false).unwrap();
+
+    let mut output = Vec::new();
+    assert_eq!(first.show_diff_to(&second, &mut output).unwrap(), DiffSummary::NoChanges);
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("differently named roots first and second"));
+
+    let mut output = Vec::new();
+    first.show_diff_to(&first, &mut output).unwrap();
+    assert!(!String::from_utf8(output).unwrap().contains("warning"));
+}
@@ -0,0 +1,55 @@
+use std::fs;
+
+use integrity_checker::database::{BuildOptions, Database, DiffSummary, Features};
+use integrity_checker::error::Error;
+
+use tempfile::tempdir;
+
+const SHA256: Features = Features {
+    sha2: false, blake2b: false, sha256: true, sha512: false, blake3: false, xxh64: false,
+    crc32: false,
+};
+
+fn live(root: &std::path::Path) -> Database {
+    let options = BuildOptions {
+        features: SHA256,
+        exclude: vec!["*.sha256".to_owned()],
+        ..BuildOptions::default()
+    };
+    Database::build_with_options(root, &options).unwrap()
+}
+
+#[test]
+fn sidecars() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("asdf.txt"), b"asdf\n").unwrap();
+    fs::write(dir.path().join("asdf.txt.sha256"),
+              "d1bc8d3ba4afc7e109612cb73acbdddac052c93025aa1f82942edabb7deb82a1  asdf.txt\n").unwrap();
+    fs::create_dir(dir.path().join("sub")).unwrap();
+    fs::write(dir.path().join("sub").join("qwer.bin"), b"qwer\n").unwrap();
+    fs::write(dir.path().join("sub").join("qwer.bin.sha256"),
+              "5F70AE29B3019EC851EF6B664B59D3FD88DDA0DE5EB58212DDBD97C65C3F8198\n").unwrap();
+
+    let expected = Database::from_sidecars(dir.path(), ".sha256").unwrap();
+    assert_eq!(expected.show_diff_to(&live(dir.path()), Vec::new()).unwrap(),
+               DiffSummary::NoChanges);
+
+    fs::write(dir.path().join("sub").join("qwer.bin"), b"QWER\n").unwrap();
+    assert_eq!(expected.show_diff_to(&live(dir.path()), Vec::new()).unwrap(),
+               DiffSummary::Changes);
+}
+
+#[test]
+fn sidecar_missing_and_malformed() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("asdf.txt"), b"asdf\n").unwrap();
+    let expected = Database::from_sidecars(dir.path(), ".sha256").unwrap();
+    assert_eq!(expected.show_diff_to(&live(dir.path()), Vec::new()).unwrap(),
+               DiffSummary::Changes); // asdf.txt has no sidecar, so it counts as added
+
+    fs::write(dir.path().join("asdf.txt.sha256"), "not a hash\n").unwrap();
+    match Database::from_sidecars(dir.path(), ".sha256") {
+        Err(Error::ParseError) => (),
+        result => panic!("expected ParseError, got {:?}", result),
+    }
+}
@@ -0,0 +1,32 @@
+use std::path::PathBuf;
+
+use integrity_checker::database::{Database, Features};
+
+#[test]
+fn directory_sizes() {
+    let threads = 1;
+    let db = Database::build("tests/changes_delete_dir/before", Features::default(), threads, false)
+        .unwrap();
+
+    let sizes = db.directory_sizes();
+    let expected: Vec<(PathBuf, u64)> = vec![
+        (PathBuf::from(""), 10),
+        (PathBuf::from("a"), 6),
+        (PathBuf::from("a/b"), 6),
+        (PathBuf::from("a/b/c"), 6),
+        (PathBuf::from("d"), 4),
+    ];
+    assert_eq!(sizes.into_iter().collect::<Vec<_>>(), expected);
+}
+
+#[test]
+fn directory_sizes_single_file() {
+    let threads = 1;
+    let db = Database::build("tests/nochanges/before/asdf.txt", Features::default(), threads, false)
+        .unwrap();
+
+    let sizes = db.directory_sizes();
+    assert_eq!(sizes.len(), 1);
+    assert_eq!(sizes[&PathBuf::from("")], db.lookup(&PathBuf::from("asdf.txt")).unwrap()
+        .as_metrics().unwrap().size());
+}
@@ -0,0 +1,34 @@
+use std::fs;
+
+use integrity_checker::database::{BuildOptions, Database, DiffSummary};
+use integrity_checker::flat::FlatDatabase;
+use integrity_checker::testutil::make_tree;
+
+use tempfile::tempdir;
+
+#[test]
+fn make_tree_is_deterministic() {
+    let first = tempdir().unwrap();
+    let second = tempdir().unwrap();
+    make_tree(first.path(), 200, 3, 512).unwrap();
+    make_tree(second.path(), 200, 3, 512).unwrap();
+
+    let first_db = Database::build_with_options(first.path(), &BuildOptions::default()).unwrap();
+    let second_db = Database::build_with_options(second.path(), &BuildOptions::default()).unwrap();
+    let result = first_db.show_diff_to(&second_db, Vec::new()).unwrap();
+    assert_eq!(result, DiffSummary::NoChanges);
+    assert_eq!(FlatDatabase::from_database(&first_db).len(), 200);
+}
+
+#[test]
+fn make_tree_parallel_build() {
+    let dir = tempdir().unwrap();
+    make_tree(dir.path(), 1000, 4, 256).unwrap();
+    assert!(fs::read_dir(dir.path()).unwrap().count() > 1);
+
+    let serial = FlatDatabase::build_with_options(dir.path(), &BuildOptions::default()).unwrap();
+    let options = BuildOptions { threads: 4, ..BuildOptions::default() };
+    let parallel = FlatDatabase::build_with_options(dir.path(), &options).unwrap();
+    assert_eq!(serial.len(), 1000);
+    assert_eq!(serial, parallel);
+}
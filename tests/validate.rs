@@ -50,12 +50,17 @@ fn validate(path: impl AsRef<Path>, features: Features) -> Result<bool, Error> {
        validate_schema(&bytes[index+1..], "schema/database.json")?)
 }
 
-const NONE:    Features = Features { sha2: false, blake2b: false };
-const SHA2:    Features = Features { sha2:  true, blake2b: false };
-const BLAKE2B: Features = Features { sha2: false, blake2b: true };
-const ALL:     Features = Features { sha2:  true, blake2b: true };
-
-const ALL_FEATURES: &[Features] = &[NONE, SHA2, BLAKE2B, ALL];
+const NONE:    Features = Features { sha2: false, blake2b: false, sha256: false, sha512: false, blake3: false, xxh64: false, crc32: false };
+const SHA2:    Features = Features { sha2:  true, blake2b: false, sha256: false, sha512: false, blake3: false, xxh64: false, crc32: false };
+const BLAKE2B: Features = Features { sha2: false, blake2b:  true, sha256: false, sha512: false, blake3: false, xxh64: false, crc32: false };
+const SHA256:  Features = Features { sha2: false, blake2b: false, sha256:  true, sha512: false, blake3: false, xxh64: false, crc32: false };
+const SHA512:  Features = Features { sha2: false, blake2b: false, sha256: false, sha512:  true, blake3: false, xxh64: false, crc32: false };
+const BLAKE3:  Features = Features { sha2: false, blake2b: false, sha256: false, sha512: false, blake3:  true, xxh64: false, crc32: false };
+const XXH64:   Features = Features { sha2: false, blake2b: false, sha256: false, sha512: false, blake3: false, xxh64:  true, crc32: false };
+const CRC32:   Features = Features { sha2: false, blake2b: false, sha256: false, sha512: false, blake3: false, xxh64: false, crc32:  true };
+const ALL:     Features = Features { sha2:  true, blake2b:  true, sha256:  true, sha512:  true, blake3:  true, xxh64:  true, crc32:  true };
+
+const ALL_FEATURES: &[Features] = &[NONE, SHA2, BLAKE2B, SHA256, SHA512, BLAKE3, XXH64, CRC32, ALL];
 
 #[test]
 fn no_changes() {
@@ -144,3 +149,26 @@ fn suspicious_nonascii() {
         assert!(validate("tests/suspicious_nonascii/after", *features).unwrap());
     }
 }
+
+#[test]
+fn invalid_hash_length() {
+    let threads = 1;
+    let db = Database::build("tests/nochanges/before", ALL, threads, false).unwrap();
+    assert!(db.check_hash_lengths().is_ok());
+
+    // Truncate one hash; the database still parses and the checksum
+    // (computed over the tampered contents) still matches
+    let mut value = serde_json::to_value(&db).unwrap();
+    value["Directory"]["asdf.txt"]["File"]["blake2b"] = Value::from("AAAA");
+    let tampered: Database = serde_json::from_str(&value.to_string()).unwrap();
+    let bytes = tampered.dump_json(Vec::new(), ALL).unwrap();
+    match Database::load_json(&bytes[..]) {
+        Err(Error::InvalidHashLength { path, algorithm, expected, got }) => {
+            assert_eq!(path, Path::new("asdf.txt"));
+            assert_eq!(algorithm, "blake2b");
+            assert_eq!(expected, 32);
+            assert_eq!(got, 3);
+        }
+        result => panic!("expected InvalidHashLength, got {:?}", result),
+    }
+}
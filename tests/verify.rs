@@ -0,0 +1,60 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use integrity_checker::database::{Database, Features};
+
+const SHA256: Features = Features {
+    sha2: false, blake2b: false, sha256: true, sha512: false, blake3: false, xxh64: false,
+    crc32: false,
+};
+
+fn expected() -> BTreeMap<PathBuf, String> {
+    // Generated with: sha256sum tests/nochanges/before/*
+    let mut expected = BTreeMap::new();
+    expected.insert(PathBuf::from("asdf.txt"),
+                    "0ba904eae8773b70c75333db4de2f3ac45a8ad4ddba1b242f0b3cfc199391dd8".to_owned());
+    expected.insert(PathBuf::from("qwer.bin"),
+                    "40aff2e9d2d8922e47afd4648e6967497158785fbd1da870e7110266bf944880".to_owned());
+    expected.insert(PathBuf::from("zxcv.txt"),
+                    "961735e430de24e0c475c580b9cc34bb6af35e26238eab26ca7bfd3a54e07bbe".to_owned());
+    expected
+}
+
+fn build(path: impl AsRef<Path>, features: Features) -> Database {
+    let threads = 1;
+    Database::build(&path, features, threads, false).unwrap()
+}
+
+#[test]
+fn expected_matches() {
+    let db = build("tests/nochanges/before", SHA256);
+    assert!(db.verify_against_expected(&expected()).is_ok());
+}
+
+#[test]
+fn expected_mismatched() {
+    let db = build("tests/changes_edit/after", SHA256);
+    let result = db.verify_against_expected(&expected());
+    assert_eq!(result.mismatched, vec![PathBuf::from("zxcv.txt")]);
+    assert!(result.missing.is_empty() && result.extra.is_empty());
+}
+
+#[test]
+fn expected_missing_and_extra() {
+    let mut expected = expected();
+    expected.insert(PathBuf::from("./gone.txt"), "00".to_owned());
+    expected.remove(Path::new("qwer.bin"));
+
+    let db = build("tests/nochanges/before", SHA256);
+    let result = db.verify_against_expected(&expected);
+    assert_eq!(result.missing, vec![PathBuf::from("gone.txt")]);
+    assert_eq!(result.extra, vec![PathBuf::from("qwer.bin")]);
+    assert!(result.mismatched.is_empty());
+}
+
+#[test]
+fn expected_unhashed() {
+    let db = build("tests/nochanges/before", Features::default());
+    let result = db.verify_against_expected(&expected());
+    assert_eq!(result.unhashed.len(), 3);
+}
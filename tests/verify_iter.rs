@@ -0,0 +1,35 @@
+use std::path::PathBuf;
+
+use integrity_checker::database::{Database, Features, VerifyStatus};
+
+fn verify(root_dir: &str) -> Vec<(PathBuf, VerifyStatus)> {
+    let threads = 1;
+    let before = PathBuf::from(root_dir).join("before");
+    let after = PathBuf::from(root_dir).join("after");
+    let db = Database::build(&before, Features::default(), threads, false).unwrap();
+    let mut results: Vec<_> = db.verify_iter(&after).map(Result::unwrap).collect();
+    results.sort_by(|a, b| a.0.cmp(&b.0));
+    results
+}
+
+#[test]
+fn verify_iter_nochanges() {
+    assert!(verify("tests/nochanges").iter().all(|(_, status)| *status == VerifyStatus::Ok));
+}
+
+#[test]
+fn verify_iter_changes() {
+    assert!(verify("tests/changes_edit_no_size_change").contains(
+        &(PathBuf::from("zxcv.txt"), VerifyStatus::Changed)));
+    assert!(verify("tests/changes_delete").contains(
+        &(PathBuf::from("asdf.txt"), VerifyStatus::Missing)));
+    assert!(verify("tests/changes_new").contains(
+        &(PathBuf::from("new.txt"), VerifyStatus::Added)));
+}
+
+#[test]
+fn verify_iter_stop_early() {
+    let db = Database::build("tests/nochanges/before", Features::default(), 1, false).unwrap();
+    let first = db.verify_iter("tests/nochanges/after").next();
+    assert!(first.unwrap().is_ok());
+}